@@ -8,9 +8,12 @@ SECTIONS
     KEEP(*(.text.startup));
     *(.text .text.*);
   }
-  .data : { *(.data) }
+  .data : { *(.data .data.*) }
   .sdata : { *(.sdata) }
-  .rodata : { *(.rodata) }
+  .rodata : { *(.rodata .rodata.*) }
+
+  /* Space for the symbol table patched in by tools/mksymtab. */
+  .symbols : { KEEP(*(.symbols)) }
 
   .dynsym : { *(.dynsym) }
   .gnu.hash : { *(.gnu.hash) }
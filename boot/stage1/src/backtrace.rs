@@ -0,0 +1,87 @@
+//! This module walks the stack to produce backtraces for panics and crashes.
+//!
+//! Each ELFv2 stack frame starts with a back-chain pointer to the caller's frame, and a
+//! function saves its return address 16 bytes into its caller's frame. The walk stays
+//! within the stack the first frame lies in, so a corrupted stack ends the backtrace early
+//! rather than faulting.
+
+use core::{fmt::Write, ops::Range};
+
+use crate::{memmap, symbols};
+
+/// The most frames a backtrace will show.
+const MAX_FRAMES: usize = 32;
+
+/// The offset of the saved return address in a frame.
+const LR_SAVE: u64 = 16;
+
+/// An iterator over the return addresses of the frames on a stack.
+#[derive(Clone)]
+pub struct Frames {
+    /// The current frame's physical address.
+    frame: u64,
+    stack: Range<u64>,
+    count: usize,
+}
+
+impl Frames {
+    /// Walk the stack starting at the frame `sp` points to.
+    pub fn new(sp: u64) -> Self {
+        Self {
+            frame: sp & !memmap::REAL_MODE_BASE,
+            stack: memmap::stack_containing(sp).unwrap_or(0..0),
+            count: 0,
+        }
+    }
+
+    /// Walk the calling function's stack.
+    #[inline(always)]
+    pub fn current() -> Self {
+        let sp: u64;
+        unsafe {
+            asm!("mr {}, %r1", out(reg) sp, options(nomem, nostack, preserves_flags));
+        }
+
+        Self::new(sp)
+    }
+
+    /// Check that an 8-byte slot is within the stack, so it can be read.
+    fn readable(&self, addr: u64) -> bool {
+        addr & 7 == 0 && addr >= self.stack.start && addr + 8 <= self.stack.end
+    }
+
+    fn read(addr: u64) -> u64 {
+        unsafe { (memmap::real(addr) as *const u64).read_volatile() }
+    }
+}
+
+impl Iterator for Frames {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        if self.count >= MAX_FRAMES || !self.readable(self.frame) {
+            return None;
+        }
+
+        // The stack grows down, so the caller's frame must be above ours.
+        let caller = Self::read(self.frame) & !memmap::REAL_MODE_BASE;
+        if caller <= self.frame || !self.readable(caller + LR_SAVE) {
+            return None;
+        }
+
+        self.frame = caller;
+        self.count += 1;
+
+        Some(Self::read(caller + LR_SAVE))
+    }
+}
+
+/// Print a backtrace, one return address and its function per line.
+pub fn print(out: &mut dyn Write, frames: Frames) {
+    for (i, addr) in frames.enumerate() {
+        let _ = match symbols::lookup(addr) {
+            Some(loc) => writeln!(out, "    #{:<2} {:016X}  {}", i, addr, loc),
+            None => writeln!(out, "    #{:<2} {:016X}", i, addr),
+        };
+    }
+}
@@ -0,0 +1,94 @@
+//! This module connects the terminal to its consoles.
+//!
+//! Terminal output goes to the UART, the telnet clients and the network console, and is kept for
+//! the crash log. Input is read from the UART and the telnet clients. Anything else that
+//! implements [Console] can be attached with [CONSOLE].
+//!
+//! N.B: Output is also written while reporting a panic or a crash, possibly by a thread that
+//! was stopped holding a lock. So output paths, and the crash reporting they feed, never block:
+//! the state they share ([netcon::NETCON], [telnetd::OUTPUT], the crash log's output history and
+//! the crash dump settings) is only ever taken with `try_lock` there.
+
+use alloc::boxed::Box;
+use console::Mux;
+use sync::mutex::SpinMutex;
+use xenon_soc::uart;
+
+use crate::{crashlog, gdb, net, netcon, telnetd};
+
+pub use console::{Console, Writer};
+
+/// The terminal's consoles.
+pub static CONSOLE: SpinMutex<Mux> = SpinMutex::new(Mux::EMPTY);
+
+/// The serial port.
+struct Uart {
+    /// The last byte written, so line endings are only translated once.
+    last: u8,
+}
+
+impl Console for Uart {
+    fn write(&mut self, data: &[u8]) {
+        uart::UART.lock(|uart| {
+            for &b in data {
+                // Prepend newline characters with a carriage return.
+                if b == b'\n' && self.last != b'\r' {
+                    uart.write_byte(b'\r');
+                }
+
+                uart.write_byte(b);
+                self.last = b;
+            }
+        });
+    }
+
+    fn poll(&mut self) -> Option<u8> {
+        // N.B: While GDB is using the UART, it belongs to GDB.
+        if gdb::owns_uart() {
+            return None;
+        }
+
+        uart::UART.lock(|uart| uart.try_read_byte())
+    }
+}
+
+/// The telnet clients.
+struct Telnet;
+
+impl Console for Telnet {
+    fn write(&mut self, data: &[u8]) {
+        telnetd::write(data);
+    }
+
+    fn poll(&mut self) -> Option<u8> {
+        net::try_with(|net| net.read_telnet()).flatten()
+    }
+}
+
+/// The network console. This is output only.
+struct NetCon;
+
+impl Console for NetCon {
+    fn write(&mut self, data: &[u8]) {
+        netcon::write(data);
+    }
+}
+
+/// Recent output, kept for crash records. This is output only.
+struct CrashLog;
+
+impl Console for CrashLog {
+    fn write(&mut self, data: &[u8]) {
+        crashlog::write(data);
+    }
+}
+
+/// Attach the default consoles.
+pub fn init() {
+    CONSOLE.lock(|console| {
+        console.add(Box::new(Uart { last: 0 }));
+        console.add(Box::new(Telnet));
+        console.add(Box::new(NetCon));
+        console.add(Box::new(CrashLog));
+    });
+}
@@ -0,0 +1,374 @@
+//! This module sends a dump of the whole system to a host when a thread crashes.
+//!
+//! On an unhandled exception, the other threads are stopped and the registers of every thread,
+//! the memory the bootloader uses and any ranges added with `crashdump add` are uploaded over
+//! TFTP as an ELF core file. The server must allow new files to be created (e.g. `in.tftpd -c`).
+//! The file can then be examined with GDB:
+//!
+//! ```text
+//! gdb -ex 'set osabi GNU/Linux' stage1 xenon.core
+//! ```
+//!
+//! Everything the dump needs is set up by `crashdump`, so nothing is allocated after a crash.
+//! Each dump is sent over a fresh socket, whose buffers are static.
+
+use alloc::string::String;
+use core::{fmt::Write, ops::Range, str::FromStr};
+use elf::coredump::{self, Registers, Segment, Thread};
+use ppc_fault::{Fault, SIGTRAP};
+use smoltcp::{
+    socket::{SocketHandle, UdpPacketMetadata, UdpSocket, UdpSocketBuffer},
+    wire::{IpEndpoint, Ipv4Address},
+};
+use sync::mutex::SpinMutex;
+use xenon_soc::uart;
+
+use crate::{
+    except::{self, CpuContext, ExceptionType, EXCEPTION_SAVE_AREA},
+    memmap, net,
+    terminal::{Args, Command, CommandRef, Error, Terminal},
+};
+
+/// The file a dump is uploaded as, if none is given.
+const DEFAULT_FILENAME: &str = "xenon.core";
+
+/// The number of ranges that can be added to a dump.
+const MAX_RANGES: usize = 4;
+
+/// The space for the headers of the core file.
+const HEADER_SIZE: usize = 8192;
+
+/// The number of packets the dump socket can buffer in either direction.
+const SOCKET_PACKETS: usize = 2;
+
+static mut RX_METADATA: [UdpPacketMetadata; SOCKET_PACKETS] =
+    [UdpPacketMetadata::EMPTY; SOCKET_PACKETS];
+static mut RX_PAYLOAD: [u8; SOCKET_PACKETS * tftp::MAX_PACKET] =
+    [0; SOCKET_PACKETS * tftp::MAX_PACKET];
+static mut TX_METADATA: [UdpPacketMetadata; SOCKET_PACKETS] =
+    [UdpPacketMetadata::EMPTY; SOCKET_PACKETS];
+static mut TX_PAYLOAD: [u8; SOCKET_PACKETS * tftp::MAX_PACKET] =
+    [0; SOCKET_PACKETS * tftp::MAX_PACKET];
+
+/// Where dumps are sent.
+struct Target {
+    server: IpEndpoint,
+    filename: String,
+    /// A socket without buffers, holding a place in the socket set for the one the dump is sent
+    /// over. Adding that socket after a crash then never grows the set.
+    placeholder: SocketHandle,
+}
+
+struct Config {
+    target: Option<Target>,
+    /// Physical memory to dump, besides the reserved regions.
+    ranges: [Option<Range<u64>>; MAX_RANGES],
+}
+
+/// Where crash dumps are sent, and what they contain.
+static CONFIG: SpinMutex<Config> = SpinMutex::new(Config {
+    target: None,
+    ranges: [None, None, None, None],
+});
+
+/// The signal a thread stopped by `fault` is reported with.
+fn signal(fault: &Fault) -> u16 {
+    fault.diagnose().map(|d| d.signal()).unwrap_or(SIGTRAP) as u16
+}
+
+fn thread(pir: u64, id: ExceptionType, signal: u16, ctx: &CpuContext, fault: &Fault) -> Thread {
+    let mut gpr = [0u64; 32];
+    gpr.copy_from_slice(&ctx.r);
+
+    Thread {
+        tid: pir as u32 + 1,
+        signal,
+        regs: Registers {
+            gpr,
+            pc: ctx.pc,
+            msr: ctx.msr,
+            ctr: ctx.ctr,
+            lr: ctx.lr,
+            cr: ctx.cr,
+            trap: (id as u32 as u64) << 4,
+            dar: fault.dar,
+            dsisr: fault.dsisr as u64,
+            ..Registers::default()
+        },
+    }
+}
+
+/// The headers of the core file, followed by the contents of its segments.
+struct Dump<'a> {
+    header: &'a [u8],
+    segments: &'a [Segment],
+    /// The offset of the next byte to read.
+    pos: u64,
+}
+
+impl tftp::Source for Dump<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> usize {
+        let mut len = 0;
+
+        while len < buf.len() {
+            let out = &mut buf[len..];
+            let mut start = self.header.len() as u64;
+
+            if self.pos < start {
+                let data = &self.header[self.pos as usize..];
+                let n = data.len().min(out.len());
+                out[..n].copy_from_slice(&data[..n]);
+
+                len += n;
+                self.pos += n as u64;
+                continue;
+            }
+
+            let seg = self.segments.iter().find(|seg| {
+                start += seg.size;
+                self.pos < start
+            });
+
+            let seg = match seg {
+                Some(seg) => seg,
+                None => break,
+            };
+
+            let off = seg.size - (start - self.pos);
+            let n = (seg.size - off).min(out.len() as u64) as usize;
+
+            // SAFETY: The segments only cover RAM, which is always mapped in real mode.
+            let data = unsafe {
+                core::slice::from_raw_parts(memmap::real(seg.paddr + off) as *const u8, n)
+            };
+            out[..n].copy_from_slice(data);
+
+            len += n;
+            self.pos += n as u64;
+        }
+
+        len
+    }
+}
+
+/// A fresh socket to send a dump over.
+///
+/// # Safety
+/// Only one such socket may exist at a time.
+unsafe fn socket() -> UdpSocket<'static> {
+    UdpSocket::new(
+        UdpSocketBuffer::new(&mut RX_METADATA[..], &mut RX_PAYLOAD[..]),
+        UdpSocketBuffer::new(&mut TX_METADATA[..], &mut TX_PAYLOAD[..]),
+    )
+}
+
+/// A socket that can't send or receive anything, to hold a place in the socket set.
+fn placeholder() -> UdpSocket<'static> {
+    let buffer = || {
+        let metadata: &mut [UdpPacketMetadata] = &mut [];
+        let payload: &mut [u8] = &mut [];
+        UdpSocketBuffer::new(metadata, payload)
+    };
+
+    UdpSocket::new(buffer(), buffer())
+}
+
+/// Write to the UART, taking it by force if it's locked.
+fn report(f: impl Fn(&mut dyn Write)) {
+    if uart::UART.try_lock(|uart| f(uart)).is_err() {
+        f(unsafe { uart::UART.get_mut_unchecked() });
+    }
+}
+
+/// Send a dump after the current thread took the unhandled exception `id`, if dumps are
+/// enabled. The other threads are stopped while the dump is sent.
+pub fn on_crash(id: ExceptionType, ctx: &CpuContext) {
+    let _ = CONFIG.try_lock(|config| {
+        if let Some(target) = &mut config.target {
+            send(target, &config.ranges, id, ctx);
+        }
+    });
+}
+
+fn send(target: &mut Target, ranges: &[Option<Range<u64>>], id: ExceptionType, ctx: &CpuContext) {
+    let pir = xenon_cpu::intrin::pir();
+
+    // N.B: If another thread already stopped the others, it's dumping them itself.
+    let stopped = crate::smp::stop_others().unwrap_or(0);
+
+    // The crashed thread goes first, so GDB selects it.
+    let fault = except::last_fault(pir);
+    let mut threads = [thread(pir, id, signal(&fault), ctx, &fault); 6];
+    let mut num_threads = 1;
+
+    for other in (0..6).filter(|p| stopped & (1 << p) != 0) {
+        // SAFETY: The thread is stopped, and won't touch its save area until resumed.
+        let ctx = unsafe { &EXCEPTION_SAVE_AREA[other as usize] };
+
+        let fault = Fault::default();
+        threads[num_threads] = thread(other, ExceptionType::ExternalInterrupt, 0, ctx, &fault);
+        num_threads += 1;
+    }
+
+    let threads = &threads[..num_threads];
+
+    let mut segments = [Segment {
+        vaddr: 0,
+        paddr: 0,
+        size: 0,
+    }; 6 + MAX_RANGES];
+    let mut num_segments = 0;
+
+    let regions = memmap::reserved_regions();
+    let regions = regions.iter().map(|(_, range)| range);
+
+    for range in regions.chain(ranges.iter().flatten()) {
+        segments[num_segments] = Segment {
+            vaddr: memmap::real(range.start),
+            paddr: range.start,
+            size: range.end - range.start,
+        };
+        num_segments += 1;
+    }
+
+    let segments = &segments[..num_segments];
+
+    let mut header = [0u8; HEADER_SIZE];
+    let len = coredump::write_header(&mut header, threads, segments).unwrap();
+    let size = coredump::file_size(threads, segments);
+
+    report(|out| {
+        let _ = writeln!(
+            out,
+            "Sending a crash dump of {} threads ({} bytes) to {} as {}...",
+            threads.len(),
+            size,
+            target.server,
+            target.filename
+        );
+    });
+
+    let mut dump = Dump {
+        header: &header[..len],
+        segments,
+        pos: 0,
+    };
+
+    // N.B: This fails if we crashed with the network stack locked.
+    let res = net::try_with(|net| {
+        // The dump socket takes the placeholder's slot, which is taken back afterwards.
+        net.sockets.remove(target.placeholder);
+
+        // SAFETY: Dumps are sent with `CONFIG` locked, so this is the only dump socket.
+        let res = tftp::upload(
+            &mut net.iface,
+            &mut net.sockets,
+            unsafe { socket() },
+            target.server,
+            &target.filename,
+            Some(size as usize),
+            &tftp::Config::default(),
+            &mut dump,
+            &mut net::now,
+        );
+
+        target.placeholder = net.sockets.add(placeholder());
+        res
+    });
+
+    report(|out| {
+        let _ = match res {
+            Some(Ok(_)) => writeln!(out, "Crash dump sent."),
+            Some(Err(e)) => writeln!(out, "Crash dump failed: {}", e),
+            None => writeln!(out, "Crash dump failed: network unavailable"),
+        };
+    });
+
+    crate::smp::resume_others();
+}
+
+pub static COMMANDS: [CommandRef<Terminal>; 1] = [&CRASHDUMP];
+
+static CRASHDUMP: Command = Command {
+    name: "crashdump",
+    usage: "[off | <server>[:<port>] [file] | add <address> <length> | clear]",
+    help: "Show or set where crash dumps are sent, and what they contain",
+    handler: crashdump,
+};
+
+fn crashdump(term: &mut Terminal, args: &mut Args) -> Result<(), Error> {
+    match args.next() {
+        None => CONFIG.lock(|config| {
+            match &config.target {
+                Some(t) => writeln!(
+                    term,
+                    "sending crash dumps to {} as {}",
+                    t.server, t.filename
+                )
+                .unwrap(),
+                None => writeln!(term, "crash dumps disabled").unwrap(),
+            }
+
+            for range in config.ranges.iter().flatten() {
+                writeln!(term, "  also dumping {:#x}..{:#x}", range.start, range.end).unwrap();
+            }
+        }),
+
+        Some("off") => set_target(None),
+
+        Some("add") => {
+            let addr = args.num("address")? & !memmap::REAL_MODE_BASE;
+            let len = args.num("length")?;
+
+            let range = match addr.checked_add(len) {
+                Some(end) if len != 0 && end <= memmap::RAM_SIZE => addr..end,
+                _ => return Err(Error::Invalid("length")),
+            };
+
+            let added = CONFIG.lock(|config| {
+                let slot = config.ranges.iter_mut().find(|r| r.is_none());
+                slot.map(|slot| *slot = Some(range)).is_some()
+            });
+
+            if !added {
+                writeln!(term, "at most {} ranges can be added", MAX_RANGES).unwrap();
+            }
+        }
+
+        Some("clear") => CONFIG.lock(|config| config.ranges = [None, None, None, None]),
+
+        Some(arg) => {
+            let (addr, port) = match arg.split_once(':') {
+                Some((addr, port)) => (addr, port.parse::<u16>().ok()),
+                None => (arg, Some(tftp::SERVER_PORT)),
+            };
+
+            let addr = Ipv4Address::from_str(addr).map_err(|_| Error::Invalid("address"))?;
+            let port = port.ok_or(Error::Invalid("port"))?;
+            let filename = args.next().unwrap_or(DEFAULT_FILENAME);
+
+            let placeholder = match net::with(|net| net.sockets.add(placeholder())) {
+                Some(handle) => handle,
+                None => {
+                    writeln!(term, "network not initialized").unwrap();
+                    return Ok(());
+                }
+            };
+
+            set_target(Some(Target {
+                server: (addr, port).into(),
+                filename: filename.into(),
+                placeholder,
+            }));
+        }
+    }
+
+    Ok(())
+}
+
+/// Replace the target, removing the old one's placeholder socket.
+fn set_target(target: Option<Target>) {
+    if let Some(old) = CONFIG.lock(|config| core::mem::replace(&mut config.target, target)) {
+        net::with(|net| net.sockets.remove(old.placeholder));
+    }
+}
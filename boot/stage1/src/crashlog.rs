@@ -0,0 +1,173 @@
+//! This module keeps a log of crashes that survives a warm reboot.
+//!
+//! Unhandled exceptions and panics are recorded at [memmap::CRASHLOG_BASE] along with their
+//! registers, backtrace and the most recent console output. Records left by earlier crashes are
+//! printed at boot, and can be shown with `crashlog` or downloaded from `/crashlog` on the web
+//! interface. The format is described by the `crashlog` crate, and `tools/crashreport` prints a
+//! downloaded log on the host.
+
+use core::fmt::Write;
+use crashlog::{Fault, Kind, Log, Record, Registers};
+use sync::mutex::SpinMutex;
+
+use crate::{
+    backtrace::Frames,
+    except::{self, CpuContext, ExceptionType},
+    memmap, symbols,
+    terminal::{Args, Command, CommandRef, Error, Terminal},
+};
+
+/// The amount of recent console output kept for crash records.
+const HISTORY_SIZE: usize = 4096;
+
+/// Recent console output, as a ring buffer.
+struct History {
+    buf: [u8; HISTORY_SIZE],
+    pos: usize,
+    full: bool,
+}
+
+impl History {
+    fn write(&mut self, data: &[u8]) {
+        for &b in data {
+            self.buf[self.pos] = b;
+            self.pos = (self.pos + 1) % HISTORY_SIZE;
+            self.full |= self.pos == 0;
+        }
+    }
+
+    /// Copy the output into `out`, oldest first. Returns the length copied.
+    fn copy_to(&self, out: &mut [u8; HISTORY_SIZE]) -> usize {
+        if !self.full {
+            out[..self.pos].copy_from_slice(&self.buf[..self.pos]);
+            return self.pos;
+        }
+
+        let (new, old) = self.buf.split_at(self.pos);
+        out[..old.len()].copy_from_slice(old);
+        out[old.len()..].copy_from_slice(new);
+        HISTORY_SIZE
+    }
+}
+
+/// The most recent terminal output, kept for crash records.
+static HISTORY: SpinMutex<History> = SpinMutex::new(History {
+    buf: [0; HISTORY_SIZE],
+    pos: 0,
+    full: false,
+});
+
+/// Held while the log is being modified.
+static LOCK: SpinMutex<()> = SpinMutex::new(());
+
+/// Keep console output for crash records.
+pub fn write(data: &[u8]) {
+    let _ = HISTORY.try_lock(|history| history.write(data));
+}
+
+/// The log, for reading.
+pub fn region() -> &'static [u8] {
+    unsafe {
+        core::slice::from_raw_parts(
+            memmap::real(memmap::CRASHLOG_BASE) as *const u8,
+            memmap::CRASHLOG_SIZE as usize,
+        )
+    }
+}
+
+/// Modify the log.
+fn with_log<R>(f: impl FnOnce(&mut Log<&mut [u8]>) -> R) -> R {
+    LOCK.lock(|_| {
+        // SAFETY: The region is reserved for the log, and only modified with the lock held.
+        let data = unsafe {
+            core::slice::from_raw_parts_mut(
+                memmap::real(memmap::CRASHLOG_BASE) as *mut u8,
+                memmap::CRASHLOG_SIZE as usize,
+            )
+        };
+
+        f(&mut Log::new(data))
+    })
+}
+
+/// Record a crash on the current thread.
+fn record(kind: Kind, regs: Registers, frames: Frames, message: &str, fault: Option<Fault>) {
+    // N.B: The history is left out if we crashed while writing to it.
+    let mut history = [0u8; HISTORY_SIZE];
+    let len = HISTORY.try_lock(|h| h.copy_to(&mut history)).unwrap_or(0);
+
+    let record = Record {
+        kind,
+        thread: xenon_cpu::intrin::pir() as u8,
+        timebase: xenon_cpu::intrin::mftb() as u64,
+        regs,
+        backtrace: frames.collect(),
+        message,
+        fault,
+        log: &history[..len],
+    };
+
+    with_log(|log| log.push(&record));
+}
+
+/// Record an unhandled exception.
+pub fn record_exception(id: ExceptionType, ctx: &CpuContext) {
+    let regs = Registers {
+        r: ctx.r,
+        cr: ctx.cr,
+        lr: ctx.lr,
+        ctr: ctx.ctr,
+        pc: ctx.pc,
+        msr: ctx.msr,
+    };
+
+    let kind = Kind::Exception(id as u32 as u16);
+    let fault = except::last_fault(xenon_cpu::intrin::pir());
+    record(kind, regs, Frames::new(ctx.r[1]), "", Some(fault));
+}
+
+/// Record a panic. There is no saved context, so only the backtrace says where it happened.
+/// `fault` is the exception being handled when the panic happened, if any.
+pub fn record_panic(message: &str, frames: Frames, fault: Option<Fault>) {
+    record(Kind::Panic, Registers::default(), frames, message, fault);
+}
+
+/// The number of records in the log.
+pub fn len() -> usize {
+    Log::new(region()).len()
+}
+
+/// Print every record, oldest first.
+pub fn print(out: &mut dyn Write) {
+    let symbol = |out: &mut dyn Write, addr| match symbols::lookup(addr) {
+        Some(loc) => write!(out, "  {}", loc),
+        None => Ok(()),
+    };
+
+    for entry in Log::new(region()).iter() {
+        let _ = writeln!(out, "==== Crash record {}:", entry.sequence);
+        let _ = entry.record.report(out, &symbol);
+    }
+}
+
+pub static COMMANDS: [CommandRef<Terminal>; 1] = [&CRASHLOG];
+
+static CRASHLOG: Command = Command {
+    name: "crashlog",
+    usage: "[clear]",
+    help: "Show or clear the records of earlier crashes",
+    handler: crashlog,
+};
+
+fn crashlog(term: &mut Terminal, args: &mut Args) -> Result<(), Error> {
+    match args.next() {
+        None => match len() {
+            0 => writeln!(term, "No crashes recorded").unwrap(),
+            _ => print(term),
+        },
+        Some("clear") => with_log(|log| log.clear()),
+        Some(_) => return Err(Error::Usage),
+    }
+
+    Ok(())
+}
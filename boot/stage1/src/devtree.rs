@@ -0,0 +1,185 @@
+//! This module builds the flattened device tree handed off to a loaded kernel.
+
+use alloc::{string::String, vec::Vec};
+use fdt::{DeviceTree, Reservation};
+use sync::mutex::SpinMutex;
+
+use crate::{
+    memmap, smp,
+    terminal::{Args, Command, CommandRef, Error, Terminal},
+};
+
+/// The Xenon CPU clock frequency, in Hz.
+const CPU_FREQ: u32 = 3_192_000_000;
+
+/// The number of hardware threads per core.
+const THREADS_PER_CORE: u32 = 2;
+/// The number of cores.
+const NUM_CORES: u32 = 3;
+
+/// Parameters for the next kernel boot, set from the terminal.
+pub struct BootParams {
+    /// The kernel command line.
+    pub bootargs: String,
+    /// The physical address range of the initial ramdisk, if any.
+    pub initrd: Option<(u64, u64)>,
+}
+
+pub static BOOT_PARAMS: SpinMutex<BootParams> = SpinMutex::new(BootParams {
+    bootargs: String::new(),
+    initrd: None,
+});
+
+pub static COMMANDS: [CommandRef<Terminal>; 2] = [&BOOTARGS, &INITRD];
+
+static BOOTARGS: Command = Command {
+    name: "bootargs",
+    usage: "[args...]",
+    help: "Set the kernel command line",
+    handler: bootargs,
+};
+
+fn bootargs(_term: &mut Terminal, args: &mut Args) -> Result<(), Error> {
+    // Take the remainder of the line as the command line.
+    let bootargs = String::from(args.rest());
+
+    BOOT_PARAMS.lock(|params| params.bootargs = bootargs);
+    Ok(())
+}
+
+static INITRD: Command = Command {
+    name: "initrd",
+    usage: "<address> <len>",
+    help: "Pass an initial ramdisk to the kernel (a length of 0 removes it)",
+    handler: initrd,
+};
+
+fn initrd(_term: &mut Terminal, args: &mut Args) -> Result<(), Error> {
+    let addr = args.num("address")?;
+    let len = args.num("length")?;
+
+    // N.B: The device tree expects physical addresses.
+    let start = addr & !memmap::REAL_MODE_BASE;
+    BOOT_PARAMS.lock(|params| {
+        params.initrd = if len != 0 { Some((start, start + len)) } else { None };
+    });
+
+    Ok(())
+}
+
+/// Fill out a device tree with the information Linux needs to boot on this system.
+/// If `base` is specified, it is used as the starting point (e.g. a DTB describing the SoC).
+pub fn build(base: Option<&[u8]>, params: &BootParams) -> Result<DeviceTree, fdt::Error> {
+    let mut dt = match base {
+        Some(blob) => DeviceTree::from_bytes(blob)?,
+        None => DeviceTree::new(),
+    };
+
+    dt.boot_cpuid_phys = xenon_cpu::intrin::pir() as u32;
+
+    // Keep the kernel away from the bootloader's memory.
+    for (_, range) in memmap::reserved_regions().iter() {
+        // The heap is not needed by the kernel after handoff.
+        if range.start == memmap::HEAP_BASE {
+            continue;
+        }
+
+        dt.reservations.push(Reservation {
+            address: range.start,
+            size: range.end - range.start,
+        });
+    }
+
+    let root = &mut dt.root;
+    if root.property("#address-cells").is_none() {
+        root.set_u32("#address-cells", 2);
+        root.set_u32("#size-cells", 2);
+    }
+
+    if root.property("model").is_none() {
+        root.set_str("model", "Xenon Game Console");
+        root.set_str("compatible", "XENON");
+    }
+
+    // N.B: This assumes #address-cells = #size-cells = 2, which is what Linux uses on Xenon.
+    let memory = dt.node_or_insert("/memory@0");
+    memory.set_str("device_type", "memory");
+    memory.set_cells("reg", &[0, 0, 0, memmap::RAM_SIZE as u32]);
+
+    let chosen = dt.node_or_insert("/chosen");
+    chosen.set_str("bootargs", &params.bootargs);
+
+    match params.initrd {
+        Some((start, end)) => {
+            chosen.set_u64("linux,initrd-start", start);
+            chosen.set_u64("linux,initrd-end", end);
+        }
+
+        None => {
+            chosen.remove_property("linux,initrd-start");
+            chosen.remove_property("linux,initrd-end");
+        }
+    }
+
+    let cpus = dt.node_or_insert("/cpus");
+    cpus.set_u32("#address-cells", 1);
+    cpus.set_u32("#size-cells", 0);
+
+    // Secondary threads parked in the holding loop can be released through the spin table.
+    let parked = smp::parked();
+
+    for core in 0..NUM_CORES {
+        let first = core * THREADS_PER_CORE;
+        let name = alloc::format!("PowerPC,Xenon@{}", first);
+
+        let cpu = cpus.child_or_insert(&name);
+        cpu.set_str("device_type", "cpu");
+        cpu.set_u32("reg", first);
+        cpu.set_cells("ibm,ppc-interrupt-server#s", &[first, first + 1]);
+        cpu.set_u32("clock-frequency", CPU_FREQ);
+        cpu.set_u32("timebase-frequency", xenon_cpu::time::TIMEBASE_FREQ as u32);
+        cpu.set_u32("d-cache-line-size", xenon_cpu::intrin::CACHE_LINE_SIZE as u32);
+        cpu.set_u32("i-cache-line-size", xenon_cpu::intrin::CACHE_LINE_SIZE as u32);
+        cpu.set_empty("64-bit");
+
+        // One release address per thread, or 0 if the thread is not parked.
+        let release: Vec<u8> = (first..first + THREADS_PER_CORE)
+            .map(|t| match parked & (1 << t) {
+                0 => 0,
+                _ => smp::release_addr(t as u64),
+            })
+            .flat_map(|a| a.to_be_bytes())
+            .collect();
+
+        if parked & (1 << first) != 0 {
+            cpu.set_str("enable-method", "spin-table");
+            cpu.set_u64("cpu-release-addr", smp::release_addr(first as u64));
+        } else {
+            cpu.remove_property("enable-method");
+            cpu.remove_property("cpu-release-addr");
+        }
+
+        cpu.set_property("xell,cpu-release-addrs", &release);
+    }
+
+    Ok(dt)
+}
+
+/// Serialize a device tree into the handoff area, returning its physical address.
+///
+/// # Safety
+/// The caller must ensure nothing else is using the handoff area.
+pub unsafe fn install(dt: &DeviceTree) -> Result<u64, ()> {
+    let blob = dt.to_bytes();
+    if blob.len() as u64 > memmap::FDT_SIZE {
+        return Err(());
+    }
+
+    core::ptr::copy_nonoverlapping(
+        blob.as_ptr(),
+        memmap::real(memmap::FDT_BASE) as *mut u8,
+        blob.len(),
+    );
+
+    Ok(memmap::FDT_BASE)
+}
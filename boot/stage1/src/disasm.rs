@@ -0,0 +1,81 @@
+//! This module implements the terminal's `dis` command, and the disassembly shown in crash
+//! output.
+
+use core::fmt::{self, Write};
+use ppc_disasm::Instruction;
+
+use crate::{
+    memmap, symbols,
+    terminal::{Args, Command, CommandRef, Error, Terminal},
+};
+
+/// The number of instructions `dis` shows if no count is given.
+const DIS_COUNT: u64 = 16;
+
+/// The number of instructions shown on either side of a crashing instruction.
+const CRASH_CONTEXT: u64 = 4;
+
+pub static COMMANDS: [CommandRef<Terminal>; 1] = [&DIS];
+
+static DIS: Command = Command {
+    name: "dis",
+    usage: "<address> [count]",
+    help: "Disassemble instructions",
+    handler: dis,
+};
+
+fn dis(term: &mut Terminal, args: &mut Args) -> Result<(), Error> {
+    let addr = args.num("address")?;
+    let count = args.opt_num("count")?.unwrap_or(DIS_COUNT);
+
+    if addr % 4 != 0 {
+        return Err(Error::Invalid("address"));
+    }
+
+    for i in 0..count {
+        let at = addr.wrapping_add(i * 4);
+        let word = unsafe { (at as *const u32).read_volatile() };
+
+        write_line(term, "  ", at, word, i == 0).unwrap();
+    }
+
+    Ok(())
+}
+
+/// Write an instruction as a line of a listing, after a marker. The function it's in is
+/// labelled at its start, or anywhere if `label` is set.
+fn write_line(out: &mut dyn Write, marker: &str, addr: u64, word: u32, label: bool) -> fmt::Result {
+    match symbols::lookup(addr) {
+        Some(loc) if label || loc.offset == 0 => writeln!(out, "<{}>:", loc)?,
+        _ => {}
+    }
+
+    let insn = Instruction::new(addr, word);
+    write!(out, "{}{:016X}: {:08X}  {}", marker, addr, word, insn)?;
+
+    // Name the destination of branches.
+    match insn.target().and_then(symbols::lookup) {
+        Some(loc) => writeln!(out, "  <{}>", loc),
+        None => writeln!(out),
+    }
+}
+
+/// Print the instructions around `pc`, for crash output. Nothing is printed unless they are all
+/// in RAM, so this can't fault.
+pub fn print_around(out: &mut dyn Write, pc: u64) {
+    let base = pc & memmap::REAL_MODE_BASE;
+    let pc = pc & !memmap::REAL_MODE_BASE & !3;
+
+    let start = pc.saturating_sub(CRASH_CONTEXT * 4);
+    let end = pc + (CRASH_CONTEXT + 1) * 4;
+    if end > memmap::RAM_SIZE {
+        return;
+    }
+
+    for at in (start..end).step_by(4) {
+        let word = unsafe { (memmap::real(at) as *const u32).read_volatile() };
+        let marker = if at == pc { "=> " } else { "   " };
+
+        let _ = write_line(out, marker, base | at, word, at == start);
+    }
+}
@@ -1,9 +1,20 @@
 //! This module defines exception handlers.
+//!
+//! Each exception vector has a chain of handlers, added with [register]. They run in the order
+//! they were added, and each one handles the exception, passes it on to the next, or declares it
+//! fatal. Exceptions nobody handles end up in the default handler, which reports the crash.
 
-use atomic::{Atomic, Ordering};
 use core::fmt::{self, Debug, Write};
+use handler_chain::Chain;
+use sync::mutex::SpinMutex;
 
-use crate::{smc, uart, util::make_arithaddr};
+use crate::{memmap, smc, uart};
+
+use ppc_fault::{Fault, VECTOR_INSTRUCTION_SEGMENT, VECTOR_ISI};
+
+use ppc_asm::{
+    addi, bctr, li, mfctr, mtctr, mtspr, ori, oris, rotldi, split_ha, Builder, Gpr, Spr, R3, R4,
+};
 
 use xenon_cpu::mfspr;
 
@@ -181,18 +192,176 @@ impl CpuContext {
 /// This is a per-processor area where context information is saved when
 /// an exception is encountered.
 #[no_mangle]
-static mut EXCEPTION_SAVE_AREA: [CpuContext; 6] = [CpuContext::new(); 6];
+pub static mut EXCEPTION_SAVE_AREA: [CpuContext; 6] = [CpuContext::new(); 6];
 
 /// This area contains context information for per-process exception handlers.
 /// This is generally static and unmodified.
 #[no_mangle]
 static mut EXCEPTION_LOAD_AREA: [CpuContext; 6] = [CpuContext::new(); 6];
 
-/// The definition of the application-defined exception handler.
-pub type ExceptionHandler = fn(ExceptionType, &mut CpuContext) -> Result<(), ()>;
+/// The fault state of the last exception taken by each processor.
+static mut LAST_FAULT: [Fault; 6] = [Fault {
+    vector: 0,
+    pc: 0,
+    srr1: 0,
+    dar: 0,
+    dsisr: 0,
+    hdar: 0,
+    hdsisr: 0,
+    insn: None,
+}; 6];
+
+/// Capture the fault state of the exception `id` that was just taken by the current processor.
+fn capture_fault(id: ExceptionType, ctx: &CpuContext) -> Fault {
+    let vector = ((id as u32) << 4) as u16;
+
+    // N.B: Fetching the instruction is what failed for instruction storage exceptions.
+    let addr = ctx.pc & !memmap::REAL_MODE_BASE;
+    let insn = if vector != VECTOR_ISI
+        && vector != VECTOR_INSTRUCTION_SEGMENT
+        && addr % 4 == 0
+        && addr + 4 <= memmap::RAM_SIZE
+    {
+        // SAFETY: The address is in RAM, which is always mapped in real mode.
+        Some(unsafe { (memmap::real(addr) as *const u32).read_volatile() })
+    } else {
+        None
+    };
+
+    unsafe {
+        Fault {
+            vector,
+            pc: ctx.pc,
+            srr1: ctx.msr,
+            dar: mfspr!(19),
+            dsisr: mfspr!(18) as u32,
+            hdar: mfspr!(307),
+            hdsisr: mfspr!(306) as u32,
+            insn,
+        }
+    }
+}
+
+/// The fault state of the last exception taken by the processor `pir`.
+pub fn last_fault(pir: u64) -> Fault {
+    // SAFETY: Only the processor itself writes its entry, when it takes an exception.
+    unsafe { LAST_FAULT[pir as usize] }
+}
+
+/// The fault state of the exception the current processor is handling, if it's running on its
+/// exception stack.
+pub fn handling() -> Option<Fault> {
+    let pir = xenon_cpu::intrin::pir();
+    let top = memmap::exception_stack(pir) & !memmap::REAL_MODE_BASE;
+
+    let marker = 0u8;
+    let sp = &marker as *const u8 as u64 & !memmap::REAL_MODE_BASE;
+
+    if (top - memmap::STACK_SIZE..top).contains(&sp) {
+        Some(last_fault(pir))
+    } else {
+        None
+    }
+}
+
+/// What an exception handler did with an exception.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Disposition {
+    /// The exception was dealt with. The interrupted context is resumed.
+    Handled,
+    /// The exception is not for this handler. The next handler in the chain runs.
+    Pass,
+    /// The exception can't be recovered from. The rest of the chain is skipped.
+    Fatal,
+}
+
+/// An exception handler. Handlers run on the exception stack of the processor that took the
+/// exception, and may modify the context that is resumed.
+pub type ExceptionHandler = fn(ExceptionType, &mut CpuContext) -> Disposition;
+
+/// Every processor (bit N = PIR N).
+pub const ALL_CPUS: u32 = 0x3F;
+
+/// The most handlers in a single chain.
+const MAX_HANDLERS: usize = 8;
+
+/// The chain run for every exception, before the chain for its vector.
+const ANY_CHAIN: usize = EXCEPTION_VECTORS.len();
+
+type HandlerChain = Chain<ExceptionHandler, MAX_HANDLERS>;
+
+/// The handler chains, one per exception vector and [ANY_CHAIN]. The masks are the processors
+/// each handler runs on.
+static CHAINS: [HandlerChain; EXCEPTION_VECTORS.len() + 1] =
+    [HandlerChain::EMPTY; EXCEPTION_VECTORS.len() + 1];
+
+/// Held while handlers are being registered or unregistered, and holds the next registration ID.
+/// Exceptions never take this.
+static CHAINS_LOCK: SpinMutex<u32> = SpinMutex::new(1);
+
+/// A registered handler, which can be removed with [unregister].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Registration {
+    chain: usize,
+    id: u32,
+}
+
+/// The chain for the exception `id`, if it has a vector.
+fn chain_index(id: ExceptionType) -> Option<usize> {
+    EXCEPTION_VECTORS
+        .iter()
+        .position(|vec| (*vec >> 4) as u32 == id as u32)
+}
+
+fn add_handler(chain: usize, cpus: u32, handler: ExceptionHandler) -> Option<Registration> {
+    CHAINS_LOCK.lock(|next_id| {
+        let id = *next_id;
+        if !CHAINS[chain].insert(id, cpus, handler) {
+            return None;
+        }
+
+        *next_id += 1;
+        Some(Registration { chain, id })
+    })
+}
+
+/// Add `handler` to the end of the chain for the exception `id`, on the processors in `cpus`
+/// (bit N = PIR N). Returns `None` if the chain is full.
+pub fn register(id: ExceptionType, cpus: u32, handler: ExceptionHandler) -> Option<Registration> {
+    add_handler(chain_index(id)?, cpus, handler)
+}
+
+/// Add `handler` to the end of the chain run for every exception, before the chain for the
+/// exception's vector. Returns `None` if the chain is full.
+pub fn register_any(cpus: u32, handler: ExceptionHandler) -> Option<Registration> {
+    add_handler(ANY_CHAIN, cpus, handler)
+}
+
+/// Remove a handler. Processors may still be running it when this returns.
+pub fn unregister(reg: Registration) {
+    CHAINS_LOCK.lock(|_| CHAINS[reg.chain].remove(reg.id));
+}
+
+/// Run the handlers for the exception `id` on the processor `pir`, in order, until one of them
+/// doesn't pass. An exception that every handler passes on is fatal.
+fn dispatch(id: ExceptionType, pir: u64, ctx: &mut CpuContext) -> Disposition {
+    let chains = core::iter::once(ANY_CHAIN).chain(chain_index(id));
+
+    for chain in chains {
+        for (handler, cpus) in CHAINS[chain].iter() {
+            if cpus & (1 << pir) == 0 {
+                continue;
+            }
+
+            match handler(id, ctx) {
+                Disposition::Pass => {}
+                disposition => return disposition,
+            }
+        }
+    }
 
-/// The application-defined exception handler.
-static EXCEPTION_HANDLER: Atomic<Option<ExceptionHandler>> = Atomic::new(None);
+    Disposition::Fatal
+}
 
 #[no_mangle]
 extern "C" fn handle_exception() -> ! {
@@ -207,30 +376,55 @@ extern "C" fn handle_exception() -> ! {
         &mut EXCEPTION_SAVE_AREA[pir as usize]
     };
 
-    match EXCEPTION_HANDLER.load(Ordering::Relaxed) {
-        Some(ex) => {
-            // If the handler successfully handles the exception, reload the calling context.
-            if ex(id, save_area).is_ok() {
-                unsafe {
-                    load_context(save_area);
-                }
-            }
-        }
+    // SAFETY: Only this processor writes its entry.
+    let fault = unsafe {
+        let pir = mfspr!(1023) as usize;
+        LAST_FAULT[pir] = capture_fault(id, save_area);
+        LAST_FAULT[pir]
+    };
 
-        // Fallback and handle the exception here.
-        None => {}
+    let pir = unsafe { mfspr!(1023) };
+
+    // If a handler deals with the exception, reload the calling context.
+    if let Disposition::Handled = dispatch(id, pir, save_area) {
+        unsafe {
+            load_context(save_area);
+        }
     }
 
-    let pir = unsafe { mfspr!(1023) };
+    fatal(id, pir, save_area, fault)
+}
 
-    let closure = |uart: &mut uart::UART| {
+/// The default handler at the end of every chain, for exceptions nothing else handled.
+fn fatal(id: ExceptionType, pir: u64, save_area: &mut CpuContext, fault: Fault) -> ! {
+    // Keep a record that survives the reset below.
+    crate::crashlog::record_exception(id, save_area);
+
+    let closure = |uart: &mut dyn Write| {
         core::writeln!(uart, "UNHANDLED EXCEPTION! Hit exception vector {:?}", id).unwrap();
+        if let Some(diagnosis) = fault.diagnose() {
+            core::writeln!(uart, "    {}", diagnosis).unwrap();
+        }
         core::writeln!(uart, "MSR:   {:#?}", xenon_cpu::intrin::mfmsr()).unwrap();
         core::writeln!(uart, "PIR:   {:#?}", pir).unwrap();
         core::writeln!(uart, "---- Saved registers:").unwrap();
         core::writeln!(uart, "    MSR:   {:#?}", save_area.msr).unwrap();
+        core::writeln!(uart, "    DAR:   {:#?}", fault.dar).unwrap();
+        core::writeln!(uart, "    DSISR: {:#?}", fault.dsisr).unwrap();
+        core::writeln!(uart, "    HDAR:  {:#?}", fault.hdar).unwrap();
+        core::writeln!(uart, "    HDSISR: {:#?}", fault.hdsisr).unwrap();
         core::writeln!(uart, "    LR:    {:#?}", save_area.lr).unwrap();
+        if let Some(loc) = crate::symbols::lookup(save_area.lr) {
+            core::writeln!(uart, "           {}", loc).unwrap();
+        }
         core::writeln!(uart, "    PC:    {:#?}", save_area.pc).unwrap();
+        if let Some(loc) = crate::symbols::lookup(save_area.pc) {
+            core::writeln!(uart, "           {}", loc).unwrap();
+        }
+        core::writeln!(uart, "---- Code:").unwrap();
+        crate::disasm::print_around(uart, save_area.pc);
+        core::writeln!(uart, "---- Backtrace:").unwrap();
+        crate::backtrace::print(uart, crate::backtrace::Frames::new(save_area.r[1]));
     };
 
     // Attempt to lock the UART. If that fails (for example, because we took an exception
@@ -239,7 +433,7 @@ extern "C" fn handle_exception() -> ! {
         let mut tries = 0u64;
 
         loop {
-            match uart::UART.try_lock(&closure) {
+            match uart::UART.try_lock(|uart| closure(uart)) {
                 Ok(_) => break Ok(()),
                 Err(_) => {
                     if tries > 50 {
@@ -254,10 +448,22 @@ extern "C" fn handle_exception() -> ! {
     };
 
     if res.is_err() {
-        let mut uart = unsafe { uart::UART.get_mut_unchecked() };
-        closure(&mut uart);
+        let uart = unsafe { uart::UART.get_mut_unchecked() };
+        closure(uart);
     }
 
+    // Mirror the error text to the network console, if it's free.
+    let _ = crate::netcon::NETCON.try_lock(|con| {
+        closure(con);
+        con.flush();
+    });
+
+    // Send every thread's state to the host, if asked to.
+    crate::crashdump::on_crash(id, save_area);
+
+    // Let GDB have a look, or report the crash to it as a stop.
+    crate::gdb::enter(id, save_area);
+
     if pir == 0 {
         // Not good. Auto-reset the system.
         smc::SMC.lock(|smc| {
@@ -320,6 +526,45 @@ pub unsafe extern "C" fn load_context(_ctx: &CpuContext) -> ! {
     );
 }
 
+/// Save the non-volatile state of the caller into `save`, and switch to `load`.
+/// This returns to the caller once `save` is loaded with [load_context].
+#[naked]
+pub unsafe extern "C" fn switch_context(_save: &mut CpuContext, _load: &CpuContext) {
+    asm!(
+        "std    %r1, 0x08(%r3)",
+        "std    %r2, 0x10(%r3)",
+        "std    %r13, 0x68(%r3)",
+        "std    %r14, 0x70(%r3)",
+        "std    %r15, 0x78(%r3)",
+        "std    %r16, 0x80(%r3)",
+        "std    %r17, 0x88(%r3)",
+        "std    %r18, 0x90(%r3)",
+        "std    %r19, 0x98(%r3)",
+        "std    %r20, 0xA0(%r3)",
+        "std    %r21, 0xA8(%r3)",
+        "std    %r22, 0xB0(%r3)",
+        "std    %r23, 0xB8(%r3)",
+        "std    %r24, 0xC0(%r3)",
+        "std    %r25, 0xC8(%r3)",
+        "std    %r26, 0xD0(%r3)",
+        "std    %r27, 0xD8(%r3)",
+        "std    %r28, 0xE0(%r3)",
+        "std    %r29, 0xE8(%r3)",
+        "std    %r30, 0xF0(%r3)",
+        "std    %r31, 0xF8(%r3)",
+        "mfcr   %r0",
+        "std    %r0, 0x100(%r3)",
+        "mflr   %r0",
+        "std    %r0, 0x108(%r3)",
+        "std    %r0, 0x118(%r3)", // Resume at our return address.
+        "mfmsr  %r0",
+        "std    %r0, 0x120(%r3)",
+        "mr     %r3, %r4",
+        "b      load_context",
+        options(noreturn)
+    );
+}
+
 #[naked]
 unsafe extern "C" fn except_thunk() -> ! {
     asm!(
@@ -405,19 +650,36 @@ unsafe extern "C" fn except_load_thunk() -> ! {
 /// r3 will be loaded with the constant specified in the `id` parameter.
 /// r4 will be loaded with the value of CTR.
 const fn make_longjmp_exc(id: u16, target: usize) -> [u32; 11] {
-    [
-        0x7C704BA6,                                      // mtspr   HSPRG0, %r3
-        0x7C914BA6,                                      // mtspr   HSPRG1, %r4
-        (0x3C600000 | ((target >> 48) & 0xFFFF)) as u32, // lis     %r3, target[64:48]
-        (0x60630000 | ((target >> 32) & 0xFFFF)) as u32, // ori     %r3, %r3, target[48:32]
-        0x786307C6,                                      // rldicr  %r3, %r3, 32, 31
-        (0x64630000 | ((target >> 16) & 0xFFFF)) as u32, // oris    %r3, %r3, target[32:16]
-        (0x60630000 | ((target >> 00) & 0xFFFF)) as u32, // ori     %r3, %r3, target[16:0]
-        0x7C8902A6,                                      // mfctr   %r4
-        0x7C6903A6,                                      // mtctr   %r3
-        (0x38600000 | (id as u32)),                      // li      %r3, id
-        0x4E800420,                                      // bctr
-    ]
+    Builder::new()
+        .push(mtspr(Spr::HSPRG0, R3))
+        .push(mtspr(Spr::HSPRG1, R4))
+        .load_u64(R3, target as u64)
+        .push(mfctr(R4))
+        .push(mtctr(R3))
+        .push(li(R3, id as i16))
+        .push(bctr())
+        .finish()
+}
+
+/// Patch the load of `area` into `reg` that starts at instruction `at` of a thunk.
+///
+/// The register is expected to already hold an offset in bits 32..64, so the upper half is ORed
+/// in and the lower half is added.
+unsafe fn patch_area_load(thunk: *mut u32, at: usize, reg: Gpr, area: usize) {
+    // We only have to use addition on the lowest chunk, because the highest
+    // offset is `0xA00` (5 << 9).
+    let (arith_hi, arith_lo) = split_ha(area as u32);
+    let code = [
+        oris(reg, reg, (area >> 48) as u16), // oris   %rX, %rX, area@highest
+        ori(reg, reg, (area >> 32) as u16),  // ori    %rX, %rX, area@higher
+        rotldi(reg, reg, 32),                // rotldi %rX, %rX, 32
+        oris(reg, reg, arith_hi),            // oris   %rX, %rX, area@ha
+        addi(reg, reg, arith_lo as i16),     // addi   %rX, %rX, area@l
+    ];
+
+    for (i, insn) in code.iter().enumerate() {
+        thunk.add(at + i).write_volatile(*insn);
+    }
 }
 
 pub unsafe fn cause_exception() -> ! {
@@ -426,91 +688,44 @@ pub unsafe fn cause_exception() -> ! {
 }
 
 /// This function initializes the exception handler subsystem.
+/// Handlers are added afterwards with [register] and [register_any].
 ///
 /// # Safety
 /// This function should only be called once during startup.
 /// This will place jump stubs at the PowerPC exception vectors.
 ///
 /// Unsafe for obvious reasons.
-pub unsafe fn init_except(handler: Option<ExceptionHandler>) {
-    EXCEPTION_HANDLER.store(handler, Ordering::Relaxed);
-
+pub unsafe fn init_except() {
     // Set up the load area.
     EXCEPTION_LOAD_AREA = [
-        CpuContext::with_hvcall(handle_exception, 0x8000_0000_1EFF_0000),
-        CpuContext::with_hvcall(handle_exception, 0x8000_0000_1EFE_0000),
-        CpuContext::with_hvcall(handle_exception, 0x8000_0000_1EFD_0000),
-        CpuContext::with_hvcall(handle_exception, 0x8000_0000_1EFC_0000),
-        CpuContext::with_hvcall(handle_exception, 0x8000_0000_1EFB_0000),
-        CpuContext::with_hvcall(handle_exception, 0x8000_0000_1EFA_0000),
+        CpuContext::with_hvcall(handle_exception, memmap::exception_stack(0)),
+        CpuContext::with_hvcall(handle_exception, memmap::exception_stack(1)),
+        CpuContext::with_hvcall(handle_exception, memmap::exception_stack(2)),
+        CpuContext::with_hvcall(handle_exception, memmap::exception_stack(3)),
+        CpuContext::with_hvcall(handle_exception, memmap::exception_stack(4)),
+        CpuContext::with_hvcall(handle_exception, memmap::exception_stack(5)),
     ];
 
     // N.B: We have to patch the exception thunk to deal with PIE.
-    {
-        let save_area = &mut EXCEPTION_SAVE_AREA[0] as *mut _ as usize;
-        let thunk_area = except_thunk as usize as *mut u32;
-
-        // We have to use addition here because the PIR is pre-loaded into r4 by
-        // the thunk, and a bitwise OR will not properly add it as an offset.
-        // We only have to use addition on the lowest chunk, because the highest
-        // offset is `0xA00` (5 << 9).
-        let (arith_hi, arith_lo) = make_arithaddr(save_area as u32);
-
-        // "oris   %r4, %r4, EXCEPTION_SAVE_AREA@highest"
-        thunk_area
-            .offset(3)
-            .write_volatile(0x64840000 | ((save_area >> 48) & 0xFFFF) as u32);
-        // "ori    %r4, %r4, EXCEPTION_SAVE_AREA@higher"
-        thunk_area
-            .offset(4)
-            .write_volatile(0x60840000 | ((save_area >> 32) & 0xFFFF) as u32);
-        // "oris   %r4, %r4, EXCEPTION_SAVE_AREA@ha"
-        thunk_area
-            .offset(6)
-            .write_volatile(0x64840000 | arith_hi as u32);
-        // "addi   %r4, %r4, EXCEPTION_SAVE_AREA@l"
-        thunk_area
-            .offset(7)
-            .write_volatile(0x38840000 | arith_lo as u32);
-    }
+    // We have to use addition here because the PIR is pre-loaded into r4 by
+    // the thunk, and a bitwise OR will not properly add it as an offset.
+    patch_area_load(
+        except_thunk as usize as *mut u32,
+        3,
+        R4,
+        &mut EXCEPTION_SAVE_AREA[0] as *mut _ as usize,
+    );
 
     // Ditto for the load thunk.
-    {
-        let load_area = &mut EXCEPTION_LOAD_AREA[0] as *mut _ as usize;
-        let thunk_area = except_load_thunk as usize as *mut u32;
-
-        let (arith_hi, arith_lo) = make_arithaddr(load_area as u32);
-
-        // "oris   %r3, %r3, EXCEPTION_LOAD_AREA@highest"
-        thunk_area
-            .offset(2)
-            .write_volatile(0x64630000 | ((load_area >> 48) & 0xFFFF) as u32);
-        // "ori    %r3, %r3, EXCEPTION_LOAD_AREA@higher"
-        thunk_area
-            .offset(3)
-            .write_volatile(0x60630000 | ((load_area >> 32) & 0xFFFF) as u32);
-        // "oris   %r3, %r3, EXCEPTION_LOAD_AREA@ha"
-        thunk_area
-            .offset(5)
-            .write_volatile(0x64630000 | arith_hi as u32);
-        // "addi   %r3, %r3, EXCEPTION_LOAD_AREA@l"
-        thunk_area
-            .offset(6)
-            .write_volatile(0x38630000 | arith_lo as u32);
-    }
+    patch_area_load(
+        except_load_thunk as usize as *mut u32,
+        2,
+        R3,
+        &mut EXCEPTION_LOAD_AREA[0] as *mut _ as usize,
+    );
 
     for vec in EXCEPTION_VECTORS.iter() {
         let buf = make_longjmp_exc((*vec >> 4) as u16, except_thunk as usize);
         core::ptr::copy_nonoverlapping(buf.as_ptr(), *vec as *mut u32, buf.len());
     }
 }
-
-#[cfg(test)]
-mod test {
-    use crate::except::make_arithaddr;
-
-    #[test]
-    fn test_arithaddr() {
-        assert_eq!(make_arithaddr(0x0B0B8018), 0x0B0C8018);
-    }
-}
@@ -1,109 +1,845 @@
-use core::num::NonZeroUsize;
+//! This module implements a GDB remote stub, over TCP or the SMC UART.
+//!
+//! A debugging session starts when a thread takes an unhandled exception, or when [breakpoint]
+//! is called. That thread (the "controller") then runs the stub on its own stack until GDB
+//! detaches. The stub talks to the GDB client connected to [net::GDB_PORT] if there is one, and
+//! takes over the UART otherwise.
+//!
+//! Each hardware thread is exposed to GDB as a thread, with the thread ID `PIR + 1`. Debugging is
+//! all-stop: when any thread stops, the others are halted by sending them an IPI through the IIC.
+//! A halted thread spins in its external interrupt handler (see [on_ipi]) with its state in its
+//! slot of [except::EXCEPTION_SAVE_AREA], until the stub gives it a resume action.
+//!
+//! While the threads are running, the stub is suspended in [Debugger::resume]. The next
+//! exception any thread takes (a breakpoint, a single-step trace, or a crash) brings the
+//! controller back to the stub, which reports it to GDB as a stop.
+//!
+//! Threads that don't respond to the IPI (e.g. parked with external interrupts disabled) are left
+//! alone, and are not reported to GDB.
+//!
+//! N.B: The network stack can't be used by the stub while a stopped thread holds it, so code that
+//! runs with it locked can only be debugged over the UART.
 
+use core::{
+    fmt::Write,
+    num::NonZeroUsize,
+    sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, Ordering},
+};
+
+use gdb_ppc64::{PowerPc64, PowerPc64Regs};
 use gdbstub::{
+    common::Tid,
     target::{
-        ext::base::multithread::{MultiThreadOps, ThreadStopReason},
-        Target,
+        ext::{
+            base::{
+                multithread::{GdbInterrupt, MultiThreadOps, ResumeAction, ThreadStopReason},
+                BaseOps,
+            },
+            breakpoints::{Breakpoints, BreakpointsOps, SwBreakpoint, SwBreakpointOps},
+        },
+        Target, TargetError, TargetResult,
     },
-    GdbStubBuilder,
+    Connection, GdbStubBuilder,
+};
+use ppc_fault::SIGTRAP;
+use xenon_soc::iic::{Iic, Interrupt};
+
+use crate::{
+    except::{self, CpuContext, Disposition, ExceptionType},
+    memmap, net,
+    terminal::{Args, Command, CommandRef, Error, Terminal},
+    uart,
+};
+
+/// `tw 31, 0, 0`
+const TRAP: u32 = 0x7FE00008;
+
+/// MSR[SE]: take a trace exception after each instruction.
+const MSR_SE: u64 = 1 << 10;
+
+const MAX_BREAKPOINTS: usize = 32;
+
+const MAX_THREADS: usize = 6;
+
+const NO_THREAD: u64 = u64::MAX;
+
+/// How long to wait for the other threads to respond to a halt IPI.
+const HALT_TIMEOUT_MS: u64 = 100;
+
+// Resume actions handed to halted threads.
+const ACTION_NONE: u8 = 0;
+const ACTION_CONTINUE: u8 = 1;
+const ACTION_STEP: u8 = 2;
+
+/// The thread running the stub (the controller), or [NO_THREAD] if there is no session.
+static SESSION: AtomicU64 = AtomicU64::new(NO_THREAD);
+
+/// The thread that asked to trap into the debugger with [breakpoint] or [poll_interrupt], if any.
+static TRAP_REQUEST: AtomicU64 = AtomicU64::new(NO_THREAD);
+
+/// Set while the session is over TCP, rather than the UART.
+static TCP: AtomicBool = AtomicBool::new(false);
+
+/// Set while the controller is running and the stub is waiting for a stop.
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Set while a stop is in progress, and every thread should halt.
+static HALT_REQUEST: AtomicBool = AtomicBool::new(false);
+
+/// The threads (bit N = PIR N) halted in [park].
+static HALTED: AtomicU32 = AtomicU32::new(0);
+
+/// The resume action for each halted thread.
+static ACTIONS: [AtomicU8; MAX_THREADS] = [
+    AtomicU8::new(ACTION_NONE),
+    AtomicU8::new(ACTION_NONE),
+    AtomicU8::new(ACTION_NONE),
+    AtomicU8::new(ACTION_NONE),
+    AtomicU8::new(ACTION_NONE),
+    AtomicU8::new(ACTION_NONE),
+];
+
+/// The thread whose stop is reported to GDB, or [NO_THREAD] if none has stopped yet.
+static STOPPED: AtomicU64 = AtomicU64::new(NO_THREAD);
+
+/// Why each thread last stopped. Written by the thread itself before it publishes [STOPPED].
+static mut STOPS: [Stop; MAX_THREADS] = [Stop::Interrupt; MAX_THREADS];
+
+/// The stub's context, saved while the threads are running.
+static mut STUB_CONTEXT: CpuContext = CpuContext::new();
+
+#[repr(C, align(16))]
+struct Stack([u8; 0x1_0000]);
+
+/// The stub runs on its own stack, since the exception stack is reused by every exception.
+static mut STUB_STACK: Stack = Stack([0; 0x1_0000]);
+
+#[derive(Clone, Copy)]
+enum Stop {
+    /// The thread took an exception.
+    Exception(ExceptionType),
+    /// The thread was interrupted from GDB (Ctrl-C).
+    Interrupt,
+}
+
+/// Returns true while a debugging session is active.
+pub fn is_active() -> bool {
+    SESSION.load(Ordering::Acquire) != NO_THREAD
+}
+
+/// Returns true while a debugging session owns the UART.
+pub fn owns_uart() -> bool {
+    is_active() && !TCP.load(Ordering::Acquire)
+}
+
+/// Stop the calling thread and wait for GDB to attach.
+/// Returns once GDB continues or detaches.
+pub fn breakpoint() {
+    trap();
+}
+
+pub static COMMANDS: [CommandRef<Terminal>; 1] = [&GDB];
+
+static GDB: Command = Command {
+    name: "gdb",
+    usage: "",
+    help: "Stop for the debugger",
+    handler: gdb,
 };
-use gdbstub_arch::ppc;
 
-struct BLTarget {}
+fn gdb(term: &mut Terminal, _args: &mut Args) -> Result<(), Error> {
+    if net::with(|net| net.gdb_connected()) == Some(true) {
+        writeln!(term, "Stopping for GDB on port {}...", net::GDB_PORT).unwrap();
+    } else {
+        writeln!(term, "Waiting for GDB on the UART...").unwrap();
+    }
+
+    breakpoint();
+    writeln!(term, "GDB detached.").unwrap();
+
+    Ok(())
+}
+
+/// Check for an interrupt request (Ctrl-C) from GDB while the threads are running.
+///
+/// N.B: GDB's interrupts only reach the target while a thread calls this. The serial terminal
+/// does so while it waits for input.
+pub fn poll_interrupt() {
+    if !is_active() || HALT_REQUEST.load(Ordering::Acquire) {
+        return;
+    }
+
+    let interrupted = if TCP.load(Ordering::Acquire) {
+        net::try_with(|net| net.gdb_peek() == Ok(Some(0x03)) && net.gdb_recv(&mut [0]).is_ok())
+            .unwrap_or(false)
+    } else {
+        unsafe { uart::UART.get_mut_unchecked() }.try_read_byte() == Some(0x03)
+    };
+
+    if interrupted {
+        trap();
+    }
+}
+
+/// Trap into the debugger on the calling thread. See [on_exception].
+fn trap() {
+    TRAP_REQUEST.store(xenon_cpu::intrin::pir(), Ordering::Relaxed);
+
+    unsafe {
+        asm!("trap");
+    }
+}
+
+/// The threads that have checked in.
+fn online() -> u32 {
+    crate::PROCESSORS.load(Ordering::Relaxed) & ((1 << MAX_THREADS) - 1)
+}
+
+/// Ask the threads in `targets` (bit N = PIR N) to halt. See [on_ipi].
+fn send_halt(targets: u32) {
+    if targets != 0 {
+        Iic::local().send_ipi(targets as u8, Interrupt::Ipi1);
+    }
+}
+
+/// Halt every thread other than the controller, and wait (for a while) until they have.
+fn halt_all(controller: u64) {
+    HALT_REQUEST.store(true, Ordering::Release);
+
+    let others = online() & !(1 << controller);
+    send_halt(others & !HALTED.load(Ordering::Acquire));
+
+    let timeout = (xenon_cpu::time::TIMEBASE_FREQ * HALT_TIMEOUT_MS / 1000) as u128;
+    let deadline = xenon_cpu::intrin::mftb() + timeout;
+
+    while HALTED.load(Ordering::Acquire) & others != others && xenon_cpu::intrin::mftb() < deadline
+    {
+        core::hint::spin_loop();
+    }
+}
+
+/// Halt the current thread until the stub gives it a resume action, or the session ends.
+fn park(pir: u64, ctx: &mut CpuContext) {
+    ctx.msr &= !MSR_SE;
+    HALTED.fetch_or(1 << pir, Ordering::AcqRel);
 
-impl Target for BLTarget {
-    type Arch = ppc::PowerPcAltivec32<()>;
+    loop {
+        match ACTIONS[pir as usize].swap(ACTION_NONE, Ordering::Acquire) {
+            ACTION_STEP => {
+                ctx.msr |= MSR_SE;
+                break;
+            }
+            ACTION_CONTINUE => break,
+            _ if !is_active() => break,
+            _ => core::hint::spin_loop(),
+        }
+    }
+
+    HALTED.fetch_and(!(1 << pir), Ordering::AcqRel);
+}
+
+/// Register the debugger's handlers for breakpoints and single-steps.
+pub fn init() {
+    for id in [ExceptionType::Program, ExceptionType::Trace] {
+        except::register(id, except::ALL_CPUS, on_exception).unwrap();
+    }
+}
+
+/// Claim breakpoints and single-steps for the debugger. This does not return if the exception
+/// was a stop in the current session, or a request from [breakpoint] to enter the debugger, and
+/// passes on anything else.
+fn on_exception(id: ExceptionType, ctx: &mut CpuContext) -> Disposition {
+    let pir = xenon_cpu::intrin::pir();
+
+    if let ExceptionType::Program = id {
+        if TRAP_REQUEST
+            .compare_exchange(pir, NO_THREAD, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+        {
+            // Step over the trap, so the caller carries on once GDB continues.
+            ctx.pc += 4;
+
+            if !is_active() {
+                enter(id, ctx);
+            }
+
+            if in_session(pir) {
+                stop(pir, Stop::Interrupt, ctx);
+            }
+        }
+    }
+
+    if !in_session(pir) {
+        return Disposition::Pass;
+    }
+
+    stop(pir, Stop::Exception(id), ctx)
+}
+
+/// Whether an exception on the thread `pir` is a stop in the current session. It isn't if there
+/// is no session, or if the stub itself crashed.
+fn in_session(pir: u64) -> bool {
+    let controller = SESSION.load(Ordering::Acquire);
+
+    controller != NO_THREAD && (controller != pir || RUNNING.load(Ordering::Acquire))
+}
+
+/// Report a stop of the current thread to the stub, and halt it until GDB resumes it.
+fn stop(pir: u64, stop: Stop, ctx: &mut CpuContext) -> ! {
+    ctx.msr &= !MSR_SE;
+
+    unsafe {
+        STOPS[pir as usize] = stop;
+    }
+
+    // If several threads stop at once, the first one is reported.
+    let _ = STOPPED.compare_exchange(NO_THREAD, pir, Ordering::AcqRel, Ordering::Relaxed);
+    HALT_REQUEST.store(true, Ordering::Release);
+
+    if SESSION.load(Ordering::Acquire) == pir {
+        // Wake up the stub, which is waiting in `resume`.
+        RUNNING.store(false, Ordering::Release);
+
+        unsafe {
+            except::load_context(&STUB_CONTEXT);
+        }
+    }
+
+    // Bring the controller back to the stub, and halt everyone else on the way.
+    send_halt(online() & !(1 << pir));
+    park(pir, ctx);
+
+    unsafe {
+        except::load_context(ctx);
+    }
+}
+
+/// Handle the debugger's halt IPI ([Interrupt::Ipi1]) on the current thread.
+/// Returns once the thread is resumed.
+pub fn on_ipi(ctx: &mut CpuContext) {
+    // The IPI may be stale (e.g. sent to a thread that was already halted).
+    if !is_active() || !HALT_REQUEST.load(Ordering::Acquire) {
+        return;
+    }
+
+    let pir = xenon_cpu::intrin::pir();
+
+    if SESSION.load(Ordering::Acquire) == pir {
+        if RUNNING.swap(false, Ordering::AcqRel) {
+            ctx.msr &= !MSR_SE;
+
+            unsafe {
+                except::load_context(&STUB_CONTEXT);
+            }
+        }
+
+        return;
+    }
+
+    park(pir, ctx);
+}
+
+/// Start a debugging session on the current thread, which just took the exception `id`, or
+/// report the exception to the current session as a stop.
+/// This returns only if the stub itself took the exception.
+pub fn enter(id: ExceptionType, ctx: &mut CpuContext) {
+    let pir = xenon_cpu::intrin::pir();
+
+    if SESSION
+        .compare_exchange(NO_THREAD, pir, Ordering::AcqRel, Ordering::Relaxed)
+        .is_err()
+    {
+        if in_session(pir) {
+            stop(pir, Stop::Exception(id), ctx);
+        }
+
+        return;
+    }
+
+    unsafe {
+        STOPS[pir as usize] = Stop::Exception(id);
+        STOPPED.store(pir, Ordering::Release);
+
+        // Leave room at the top of the stack for the back chain.
+        let top = &STUB_STACK as *const _ as u64 + core::mem::size_of::<Stack>() as u64;
+        except::load_context(&CpuContext::with_hvcall(session, top - 0x100));
+    }
+}
+
+/// The stub's entry point, on the stub stack.
+extern "C" fn session() -> ! {
+    let pir = SESSION.load(Ordering::Acquire);
+    halt_all(pir);
+
+    let mut target = Debugger {
+        controller: pir,
+        actions: [None; MAX_THREADS],
+        breakpoints: [None; MAX_BREAKPOINTS],
+    };
+
+    // Prefer a network client, if one is connected (and the network stack is available).
+    let tcp = net::try_with(|net| net.take_gdb_client() || net.gdb_connected()).unwrap_or(false);
+    TCP.store(tcp, Ordering::Release);
+
+    // However the session ends, let the threads carry on as if nothing happened.
+    if tcp {
+        run(
+            Tcp {
+                buf: [0; 1024],
+                len: 0,
+            },
+            &mut target,
+        );
+    } else {
+        run(Serial { peeked: None }, &mut target);
+    }
+
+    target.remove_breakpoints();
+
+    let ctx = context(pir);
+    ctx.msr &= !MSR_SE;
+
+    // This releases the halted threads.
+    STOPPED.store(NO_THREAD, Ordering::Relaxed);
+    HALT_REQUEST.store(false, Ordering::Release);
+    TCP.store(false, Ordering::Release);
+    SESSION.store(NO_THREAD, Ordering::Release);
+
+    unsafe {
+        except::load_context(ctx);
+    }
+}
+
+/// Run the stub over `conn` until GDB detaches, or the connection fails.
+fn run<C: Connection>(conn: C, target: &mut Debugger) {
+    let mut buf = [0u8; 4096];
+    let mut gdb = GdbStubBuilder::new(conn)
+        .with_packet_buffer(&mut buf)
+        .build()
+        .unwrap();
+
+    let _ = gdb.run(target);
+}
+
+/// The state of a thread, as saved when it stopped.
+fn context(pir: u64) -> &'static mut CpuContext {
+    unsafe { &mut except::EXCEPTION_SAVE_AREA[pir as usize] }
+}
+
+fn tid(pir: u64) -> Tid {
+    NonZeroUsize::new(pir as usize + 1).unwrap()
+}
+
+fn pir(tid: Tid) -> Option<u64> {
+    match tid.get() - 1 {
+        pir if pir < MAX_THREADS => Some(pir as u64),
+        _ => None,
+    }
+}
+
+/// The UART, as used by the stub.
+///
+/// N.B: The UART is not locked, since the thread that holds the lock may be the one that is stopped.
+/// The stub owns the UART for the duration of the session.
+struct Serial {
+    peeked: Option<u8>,
+}
+
+impl Serial {
+    fn uart(&mut self) -> &mut uart::UART {
+        unsafe { uart::UART.get_mut_unchecked() }
+    }
+}
+
+impl Connection for Serial {
+    type Error = ();
+
+    fn read(&mut self) -> Result<u8, Self::Error> {
+        if let Some(byte) = self.peeked.take() {
+            return Ok(byte);
+        }
+
+        Ok(self.uart().read_byte())
+    }
+
+    fn write(&mut self, byte: u8) -> Result<(), Self::Error> {
+        self.uart().write_byte(byte);
+        Ok(())
+    }
+
+    fn peek(&mut self) -> Result<Option<u8>, Self::Error> {
+        if self.peeked.is_none() {
+            self.peeked = self.uart().try_read_byte();
+        }
+
+        Ok(self.peeked)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// The connection to a GDB client over TCP (see [net::GDB_PORT]).
+///
+/// Output is buffered until GDB's stub flushes it at the end of each packet.
+struct Tcp {
+    buf: [u8; 1024],
+    len: usize,
+}
+
+impl Connection for Tcp {
+    type Error = ();
+
+    fn read(&mut self) -> Result<u8, Self::Error> {
+        let mut byte = [0u8];
+
+        loop {
+            // N.B: This fails if the client disconnects, or if a stopped thread holds the stack.
+            match net::try_with(|net| net.gdb_recv(&mut byte)) {
+                Some(Ok(1)) => return Ok(byte[0]),
+                Some(Ok(_)) => core::hint::spin_loop(),
+                _ => return Err(()),
+            }
+        }
+    }
+
+    fn write(&mut self, byte: u8) -> Result<(), Self::Error> {
+        if self.len == self.buf.len() {
+            self.flush()?;
+        }
+
+        self.buf[self.len] = byte;
+        self.len += 1;
+
+        // Acknowledgements are sent right away, since the reply may take a while (e.g. while the
+        // threads are running).
+        if byte == b'+' && self.len == 1 {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    fn peek(&mut self) -> Result<Option<u8>, Self::Error> {
+        net::try_with(|net| net.gdb_peek()).unwrap_or(Err(()))
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        let mut sent = 0;
+
+        while sent < self.len {
+            sent +=
+                net::try_with(|net| net.gdb_send(&self.buf[sent..self.len])).unwrap_or(Err(()))?;
+        }
+
+        self.len = 0;
+        Ok(())
+    }
+}
+
+/// The kinds of memory GDB may access.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Memory {
+    Ram,
+    Mmio,
+}
+
+struct Debugger {
+    /// The thread running the stub.
+    controller: u64,
+    /// Resume actions GDB gave to specific threads.
+    actions: [Option<ResumeAction>; MAX_THREADS],
+    /// Software breakpoints, with the instructions they replaced.
+    breakpoints: [Option<(u64, u32)>; MAX_BREAKPOINTS],
+}
+
+impl Debugger {
+    /// The threads GDB can see: the controller, and those halted in the debugger.
+    fn threads(&self) -> u32 {
+        HALTED.load(Ordering::Acquire) | 1 << self.controller
+    }
+
+    /// The state of the thread `tid`, if it is stopped.
+    fn thread_context(&self, tid: Tid) -> TargetResult<&'static mut CpuContext, Self> {
+        match pir(tid) {
+            Some(pir) if self.threads() & (1 << pir) != 0 => Ok(context(pir)),
+            _ => Err(TargetError::NonFatal),
+        }
+    }
+
+    /// The kind of memory at `addr..addr + len`. GDB may only access ranges that are all RAM or
+    /// all MMIO.
+    fn memory(addr: u64, len: usize) -> Option<Memory> {
+        let start = addr & !memmap::REAL_MODE_BASE;
+        let end = start.checked_add(len as u64)?;
+
+        [
+            (Memory::Ram, 0..memmap::RAM_SIZE),
+            (
+                Memory::Mmio,
+                memmap::MMIO_BASE..memmap::MMIO_BASE + memmap::MMIO_SIZE,
+            ),
+        ]
+        .iter()
+        .find(|(_, region)| region.start <= start && end <= region.end)
+        .map(|(memory, _)| *memory)
+    }
+
+    fn read_insn(addr: u64) -> u32 {
+        unsafe { (memmap::real(addr) as *const u32).read_volatile() }
+    }
+
+    fn write_insn(addr: u64, insn: u32) {
+        let ptr = memmap::real(addr) as *mut u32;
+
+        unsafe {
+            ptr.write_volatile(insn);
+            xenon_cpu::intrin::sync_icache(ptr as usize, 4);
+        }
+    }
+
+    fn remove_breakpoints(&mut self) {
+        for (addr, insn) in self.breakpoints.iter_mut().filter_map(|bp| bp.take()) {
+            Self::write_insn(addr, insn);
+        }
+    }
+
+    fn stop_reason(&mut self, pir: u64, stop: Stop) -> ThreadStopReason<u64> {
+        let id = match stop {
+            Stop::Exception(id) => id,
+            Stop::Interrupt => return ThreadStopReason::GdbInterrupt,
+        };
+
+        let pc = context(pir).pc;
+
+        match id {
+            ExceptionType::Trace => ThreadStopReason::DoneStep,
+            ExceptionType::Program if self.breakpoints.iter().flatten().any(|bp| bp.0 == pc) => {
+                ThreadStopReason::SwBreak(tid(pir))
+            }
+            // Anything else is reported with the signal Linux would send for the fault.
+            _ => {
+                let diagnosis = except::last_fault(pir).diagnose();
+                ThreadStopReason::Signal(diagnosis.map(|d| d.signal()).unwrap_or(SIGTRAP))
+            }
+        }
+    }
+}
+
+impl Target for Debugger {
+    type Arch = PowerPc64;
     type Error = &'static str;
 
-    fn base_ops(&mut self) -> gdbstub::target::ext::base::BaseOps<Self::Arch, Self::Error> {
-        gdbstub::target::ext::base::BaseOps::MultiThread(self)
+    fn base_ops(&mut self) -> BaseOps<Self::Arch, Self::Error> {
+        BaseOps::MultiThread(self)
+    }
+
+    fn breakpoints(&mut self) -> Option<BreakpointsOps<Self>> {
+        Some(self)
     }
 }
 
-impl MultiThreadOps for BLTarget {
+fn is_step(action: ResumeAction) -> bool {
+    matches!(action, ResumeAction::Step | ResumeAction::StepWithSignal(_))
+}
+
+impl MultiThreadOps for Debugger {
     fn resume(
         &mut self,
-        _default_resume_action: gdbstub::target::ext::base::ResumeAction,
-        _gdb_interrupt: gdbstub::target::ext::base::GdbInterrupt<'_>,
-    ) -> Result<
-        gdbstub::target::ext::base::multithread::ThreadStopReason<
-            <Self::Arch as gdbstub::arch::Arch>::Usize,
-        >,
-        Self::Error,
-    > {
-        // Does nothing for now...
-        Ok(ThreadStopReason::DoneStep)
+        default_resume_action: ResumeAction,
+        _gdb_interrupt: GdbInterrupt<'_>,
+    ) -> Result<ThreadStopReason<u64>, Self::Error> {
+        let threads = self.threads();
+        let controller = self.controller;
+
+        STOPPED.store(NO_THREAD, Ordering::Relaxed);
+        HALT_REQUEST.store(false, Ordering::Release);
+
+        // Release the halted threads.
+        let mut released = 0;
+        for pir in (0..MAX_THREADS as u64).filter(|&pir| threads & (1 << pir) != 0) {
+            if pir == controller {
+                continue;
+            }
+
+            let action = self.actions[pir as usize].unwrap_or(default_resume_action);
+            let action = if is_step(action) {
+                ACTION_STEP
+            } else {
+                ACTION_CONTINUE
+            };
+
+            ACTIONS[pir as usize].store(action, Ordering::Release);
+            released |= 1 << pir;
+        }
+
+        // Wait for them to leave, so they can't be mistaken for halted after the next stop.
+        while HALTED.load(Ordering::Acquire) & released != 0 {
+            core::hint::spin_loop();
+        }
+
+        let ctx = context(controller);
+        if is_step(self.actions[controller as usize].unwrap_or(default_resume_action)) {
+            ctx.msr |= MSR_SE;
+        } else {
+            ctx.msr &= !MSR_SE;
+        }
+
+        // Run the controller. We get back here when any thread stops (see `on_exception`).
+        RUNNING.store(true, Ordering::Release);
+        unsafe {
+            except::switch_context(&mut STUB_CONTEXT, ctx);
+        }
+
+        // All threads must be stopped before the stop is reported.
+        halt_all(controller);
+
+        let pir = STOPPED.load(Ordering::Acquire);
+        if pir == NO_THREAD {
+            return Ok(ThreadStopReason::GdbInterrupt);
+        }
+
+        let stop = unsafe { STOPS[pir as usize] };
+        Ok(self.stop_reason(pir, stop))
     }
 
     fn clear_resume_actions(&mut self) -> Result<(), Self::Error> {
+        self.actions = [None; MAX_THREADS];
         Ok(())
     }
 
-    fn set_resume_action(
-        &mut self,
-        _tid: gdbstub::common::Tid,
-        _action: gdbstub::target::ext::base::ResumeAction,
-    ) -> Result<(), Self::Error> {
+    fn set_resume_action(&mut self, tid: Tid, action: ResumeAction) -> Result<(), Self::Error> {
+        if let Some(pir) = pir(tid) {
+            self.actions[pir as usize] = Some(action);
+        }
+
         Ok(())
     }
 
-    fn read_registers(
-        &mut self,
-        _regs: &mut <Self::Arch as gdbstub::arch::Arch>::Registers,
-        _tid: gdbstub::common::Tid,
-    ) -> gdbstub::target::TargetResult<(), Self> {
+    fn read_registers(&mut self, regs: &mut PowerPc64Regs, tid: Tid) -> TargetResult<(), Self> {
+        let ctx = self.thread_context(tid)?;
+
+        // N.B: Floating-point and vector state isn't saved on exceptions, so it is unavailable.
+        *regs = PowerPc64Regs {
+            r: ctx.r,
+            pc: ctx.pc,
+            msr: ctx.msr,
+            cr: ctx.cr as u32,
+            lr: ctx.lr,
+            ctr: ctx.ctr,
+            ..Default::default()
+        };
+
         Ok(())
     }
 
-    fn write_registers(
-        &mut self,
-        _regs: &<Self::Arch as gdbstub::arch::Arch>::Registers,
-        _tid: gdbstub::common::Tid,
-    ) -> gdbstub::target::TargetResult<(), Self> {
+    fn write_registers(&mut self, regs: &PowerPc64Regs, tid: Tid) -> TargetResult<(), Self> {
+        let ctx = self.thread_context(tid)?;
+
+        ctx.r = regs.r;
+        ctx.pc = regs.pc;
+        ctx.msr = regs.msr;
+        ctx.cr = regs.cr as u64;
+        ctx.lr = regs.lr;
+        ctx.ctr = regs.ctr;
+
         Ok(())
     }
 
     fn read_addrs(
         &mut self,
-        start_addr: <Self::Arch as gdbstub::arch::Arch>::Usize,
+        start_addr: u64,
         data: &mut [u8],
-        tid: gdbstub::common::Tid,
-    ) -> gdbstub::target::TargetResult<(), Self> {
-        let src = unsafe { core::slice::from_raw_parts(start_addr as *const _, data.len()) };
+        _tid: Tid,
+    ) -> TargetResult<(), Self> {
+        if Self::memory(start_addr, data.len()).is_none() {
+            return Err(TargetError::NonFatal);
+        }
+
+        let src = memmap::real(start_addr) as *const u8;
+
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = unsafe { src.add(i).read_volatile() };
+        }
 
         Ok(())
     }
 
-    fn write_addrs(
-        &mut self,
-        start_addr: <Self::Arch as gdbstub::arch::Arch>::Usize,
-        data: &[u8],
-        tid: gdbstub::common::Tid,
-    ) -> gdbstub::target::TargetResult<(), Self> {
+    fn write_addrs(&mut self, start_addr: u64, data: &[u8], _tid: Tid) -> TargetResult<(), Self> {
+        let memory = Self::memory(start_addr, data.len()).ok_or(TargetError::NonFatal)?;
+
+        let dst = memmap::real(start_addr) as *mut u8;
+
+        for (i, byte) in data.iter().enumerate() {
+            unsafe { dst.add(i).write_volatile(*byte) };
+        }
+
+        // GDB may be patching code. MMIO is cache-inhibited, so it has no cache blocks to sync.
+        if memory == Memory::Ram {
+            unsafe {
+                xenon_cpu::intrin::sync_icache(dst as usize, data.len());
+            }
+        }
+
         Ok(())
     }
 
     fn list_active_threads(
         &mut self,
-        thread_is_active: &mut dyn FnMut(gdbstub::common::Tid),
+        thread_is_active: &mut dyn FnMut(Tid),
     ) -> Result<(), Self::Error> {
-        thread_is_active(NonZeroUsize::new(1).unwrap());
+        let threads = self.threads();
+
+        for pir in (0..MAX_THREADS as u64).filter(|&pir| threads & (1 << pir) != 0) {
+            thread_is_active(tid(pir));
+        }
 
         Ok(())
     }
 }
 
-pub fn entry(uart: &mut crate::uart::UART) {
-    let mut target = BLTarget {};
+impl Breakpoints for Debugger {
+    fn sw_breakpoint(&mut self) -> Option<SwBreakpointOps<Self>> {
+        Some(self)
+    }
+}
 
-    let mut buf = [0u8; 4096];
-    let mut gdb = GdbStubBuilder::new(uart)
-        .with_packet_buffer(&mut buf)
-        .build()
-        .unwrap();
+impl SwBreakpoint for Debugger {
+    fn add_sw_breakpoint(&mut self, addr: u64, _kind: usize) -> TargetResult<bool, Self> {
+        // Code only runs from RAM.
+        if addr % 4 != 0
+            || Self::memory(addr, 4) != Some(Memory::Ram)
+            || self.breakpoints.iter().flatten().any(|bp| bp.0 == addr)
+        {
+            return Ok(false);
+        }
+
+        let slot = match self.breakpoints.iter_mut().find(|bp| bp.is_none()) {
+            Some(slot) => slot,
+            None => return Ok(false),
+        };
+
+        *slot = Some((addr, Self::read_insn(addr)));
+        Self::write_insn(addr, TRAP);
+
+        Ok(true)
+    }
+
+    fn remove_sw_breakpoint(&mut self, addr: u64, _kind: usize) -> TargetResult<bool, Self> {
+        match self
+            .breakpoints
+            .iter_mut()
+            .find(|bp| matches!(bp, Some((a, _)) if *a == addr))
+        {
+            Some(slot) => {
+                let (addr, insn) = slot.take().unwrap();
+                Self::write_insn(addr, insn);
+
+                Ok(true)
+            }
 
-    match gdb.run(&mut target) {
-        Ok(_) => {}
-        Err(_) => {}
+            None => Ok(false),
+        }
     }
 }
@@ -2,8 +2,10 @@ use buddyalloc::Heap;
 use core::alloc::{GlobalAlloc, Layout};
 use sync::mutex::SpinMutex;
 
-const HEAP_START: *mut u8 = 0x8000_0000_0800_0000 as *mut u8;
-const HEAP_SIZE: usize = 0x0100_0000;
+use crate::memmap;
+
+const HEAP_START: *mut u8 = memmap::real(memmap::HEAP_BASE) as *mut u8;
+const HEAP_SIZE: usize = memmap::HEAP_SIZE as usize;
 
 /// Declare a simple heap locked behind a Mutex.
 struct LockedHeap<const N: usize>(SpinMutex<Heap<N>>);
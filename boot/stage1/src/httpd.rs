@@ -0,0 +1,285 @@
+//! This module implements the web interface used to upload and boot kernels.
+//!
+//! Images are uploaded to [memmap::UPLOAD_BASE] by POSTing to `/upload`, either
+//! from the form on the status page (`multipart/form-data`) or as a raw body:
+//!
+//! ```text
+//! curl --data-binary @vmlinux http://<address>/upload
+//! curl -X POST http://<address>/boot
+//! ```
+//!
+//! The crash log (see [crate::crashlog]) can be downloaded from `/crashlog`.
+
+use alloc::{format, vec, vec::Vec};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use http::{Connection, Handler, Head, Method, Response, Status};
+use smoltcp::{
+    socket::{SocketHandle, SocketSet, TcpSocket, TcpSocketBuffer},
+    time::Duration,
+    wire::Ipv4Cidr,
+};
+
+use crate::memmap;
+
+const PORT: u16 = 80;
+
+/// The number of sockets accepting connections. More than one lets a new client
+/// connect while the previous connection is still closing.
+const SOCKETS: usize = 2;
+
+const BUFFER_SIZE: usize = 16 * 1024;
+
+/// Connections idle for longer than this are dropped.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The length of the uploaded image, or 0 if there is none.
+static UPLOAD_LEN: AtomicUsize = AtomicUsize::new(0);
+
+/// Set while a client is uploading an image.
+static UPLOAD_BUSY: AtomicBool = AtomicBool::new(false);
+
+/// Set once a client has asked to boot the uploaded image.
+static BOOT_REQUEST: AtomicBool = AtomicBool::new(false);
+
+/// The uploaded image, if any.
+pub fn uploaded_image() -> Option<&'static [u8]> {
+    match UPLOAD_LEN.load(Ordering::Acquire) {
+        0 => None,
+        len => Some(unsafe {
+            core::slice::from_raw_parts(memmap::real(memmap::UPLOAD_BASE) as *const u8, len)
+        }),
+    }
+}
+
+/// Returns the uploaded image if a client has asked to boot it.
+pub fn take_boot_request() -> Option<&'static [u8]> {
+    if BOOT_REQUEST.swap(false, Ordering::AcqRel) {
+        uploaded_image()
+    } else {
+        None
+    }
+}
+
+/// Per-connection request state.
+#[derive(Default)]
+struct Request {
+    /// Set if this connection holds [UPLOAD_BUSY].
+    uploading: bool,
+    /// The number of bytes uploaded so far.
+    received: usize,
+    /// Set if the boot action should be triggered once the response is sent.
+    boot: bool,
+    /// The interface address, for the status page.
+    addr: Option<Ipv4Cidr>,
+}
+
+impl Request {
+    fn release(&mut self) {
+        if self.uploading {
+            UPLOAD_BUSY.store(false, Ordering::Release);
+            self.uploading = false;
+        }
+    }
+
+    fn status_page(&self) -> Response {
+        let addr = match self.addr {
+            Some(addr) => format!("{}", addr),
+            None => "none".into(),
+        };
+
+        let upload = match UPLOAD_LEN.load(Ordering::Acquire) {
+            0 => "none".into(),
+            len => format!("{} bytes at {:08X}", len, memmap::UPLOAD_BASE),
+        };
+
+        let crashes = match crate::crashlog::len() {
+            0 => "none".into(),
+            n => format!("{} (<a href=\"/crashlog\">download</a>)", n),
+        };
+
+        let page = format!(
+            "<!DOCTYPE html>\n<html><head><title>xell-rs</title></head><body>\n\
+            <h1>xell-rs</h1>\n<table>\n\
+            <tr><td>Processors</td><td>{:02X}</td></tr>\n\
+            <tr><td>Address</td><td>{}</td></tr>\n\
+            <tr><td>Uploaded image</td><td>{}</td></tr>\n\
+            <tr><td>Crash records</td><td>{}</td></tr>\n\
+            </table>\n\
+            <form method=\"post\" action=\"/upload\" enctype=\"multipart/form-data\">\n\
+            <input type=\"file\" name=\"image\"> <input type=\"submit\" value=\"Upload\">\n\
+            </form>\n\
+            <form method=\"post\" action=\"/boot\">\n\
+            <input type=\"submit\" value=\"Boot uploaded image\">\n\
+            </form>\n</body></html>\n",
+            crate::PROCESSORS.load(Ordering::Relaxed),
+            addr,
+            upload,
+            crashes,
+        );
+
+        Response::new(Status::Ok).body("text/html; charset=utf-8", page)
+    }
+}
+
+impl Handler for Request {
+    fn begin(&mut self, head: &Head) -> Result<(), Response> {
+        match (head.method, head.path.as_str()) {
+            (Method::Get | Method::Head, "/" | "/crashlog") => Ok(()),
+
+            (Method::Post, "/upload") => {
+                if head.content_length.unwrap_or(0) as u64 > memmap::UPLOAD_SIZE {
+                    return Err(Response::text(Status::PayloadTooLarge, "Image too large\n"));
+                }
+
+                if UPLOAD_BUSY.swap(true, Ordering::AcqRel) {
+                    return Err(Response::text(Status::Conflict, "Upload in progress\n"));
+                }
+
+                // The previous image is about to be overwritten.
+                self.uploading = true;
+                self.received = 0;
+                UPLOAD_LEN.store(0, Ordering::Release);
+
+                Ok(())
+            }
+
+            (Method::Post, "/boot") => match UPLOAD_LEN.load(Ordering::Acquire) {
+                0 => Err(Response::text(Status::Conflict, "No image uploaded\n")),
+                _ => Ok(()),
+            },
+
+            (_, "/" | "/upload" | "/boot" | "/crashlog") => Err(Response::text(
+                Status::MethodNotAllowed,
+                "Method not allowed\n",
+            )),
+
+            _ => Err(Response::text(Status::NotFound, "Not found\n")),
+        }
+    }
+
+    fn data(&mut self, _head: &Head, data: &[u8]) -> Result<(), Response> {
+        // Only uploads take a body.
+        if !self.uploading {
+            return Ok(());
+        }
+
+        if (self.received + data.len()) as u64 > memmap::UPLOAD_SIZE {
+            self.release();
+            return Err(Response::text(Status::PayloadTooLarge, "Image too large\n"));
+        }
+
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                data.as_ptr(),
+                (memmap::real(memmap::UPLOAD_BASE) as *mut u8).add(self.received),
+                data.len(),
+            );
+        }
+
+        self.received += data.len();
+        Ok(())
+    }
+
+    fn finish(&mut self, head: &Head) -> Response {
+        match head.path.as_str() {
+            "/upload" => {
+                UPLOAD_LEN.store(self.received, Ordering::Release);
+                self.release();
+
+                match self.received {
+                    0 => Response::text(Status::BadRequest, "No image received\n"),
+                    _ => Response::redirect("/"),
+                }
+            }
+
+            "/boot" => {
+                self.boot = true;
+                Response::text(Status::Accepted, "Booting...\n")
+            }
+
+            "/crashlog" => Response::new(Status::Ok)
+                .header(
+                    "Content-Disposition",
+                    "attachment; filename=\"crashlog.bin\"",
+                )
+                .body("application/octet-stream", crate::crashlog::region()),
+
+            _ => self.status_page(),
+        }
+    }
+}
+
+struct Client {
+    handle: SocketHandle,
+    conn: Connection,
+    request: Request,
+}
+
+pub struct Server {
+    clients: Vec<Client>,
+}
+
+impl Server {
+    /// Create the server's sockets. These are allocated once and reused for every client.
+    pub fn new(sockets: &mut SocketSet<'static>) -> Self {
+        let clients = (0..SOCKETS)
+            .map(|_| {
+                let rx = TcpSocketBuffer::new(vec![0u8; BUFFER_SIZE]);
+                let tx = TcpSocketBuffer::new(vec![0u8; BUFFER_SIZE]);
+
+                Client {
+                    handle: sockets.add(TcpSocket::new(rx, tx)),
+                    conn: Connection::new(),
+                    request: Request::default(),
+                }
+            })
+            .collect();
+
+        Self { clients }
+    }
+
+    /// Service all connections. `addr` is the interface address shown on the status page.
+    pub fn poll(&mut self, sockets: &mut SocketSet<'static>, addr: Option<Ipv4Cidr>) {
+        for client in self.clients.iter_mut() {
+            let mut socket = sockets.get::<TcpSocket>(client.handle);
+
+            if !socket.is_open() {
+                // The previous connection (if any) is gone. Wait for the next client.
+                client.request.release();
+                client.conn = Connection::new();
+                client.request = Request::default();
+
+                if socket.listen(PORT).is_ok() {
+                    socket.set_timeout(Some(TIMEOUT));
+                }
+
+                continue;
+            }
+
+            client.request.addr = addr;
+
+            let Client { conn, request, .. } = client;
+            if socket.can_recv() {
+                let _ = socket.recv(|data| {
+                    conn.feed(data, request);
+                    (data.len(), ())
+                });
+            }
+
+            if socket.can_send() && !conn.output().is_empty() {
+                if let Ok(n) = socket.send_slice(conn.output()) {
+                    conn.consume(n);
+                }
+            }
+
+            if conn.is_done() && socket.may_send() {
+                socket.close();
+
+                if request.boot {
+                    request.boot = false;
+                    BOOT_REQUEST.store(true, Ordering::Release);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,115 @@
+//! This module loads ELF executables into memory and transfers control to them.
+
+use core::{fmt, ops::Range};
+
+use crate::{except, memmap};
+
+/// MSR[SF/HV/ME]: 64-bit hypervisor real mode, machine checks enabled.
+pub const ENTRY_MSR: u64 = 0x9000_0000_0000_1000;
+
+#[derive(Debug)]
+pub enum LoadError {
+    /// The image failed validation.
+    Elf(elf::Error),
+    /// A segment does not fit within physical memory.
+    OutOfMemory(usize),
+    /// A segment overlaps memory that is in use by the bootloader.
+    Overlap(usize, &'static str),
+}
+
+impl From<elf::Error> for LoadError {
+    fn from(e: elf::Error) -> Self {
+        Self::Elf(e)
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Elf(e) => write!(f, "invalid ELF: {}", e),
+            LoadError::OutOfMemory(i) => write!(f, "segment {} outside of RAM", i),
+            LoadError::Overlap(i, r) => write!(f, "segment {} overlaps {}", i, r),
+        }
+    }
+}
+
+/// An image that has been placed in memory and is ready to be executed.
+#[derive(Debug, Clone, Copy)]
+pub struct LoadedImage {
+    /// The physical address of the entry point.
+    pub entry: u64,
+    /// The physical address range occupied by the image.
+    pub extent: (u64, u64),
+}
+
+/// Validate an ELF image and copy its loadable segments to their physical addresses.
+/// Any memory not backed by the file is zero-filled.
+///
+/// # Safety
+/// This will overwrite arbitrary physical memory outside of the bootloader's own regions,
+/// including the exception vectors.
+pub unsafe fn load(data: &[u8]) -> Result<LoadedImage, LoadError> {
+    let file = elf::ElfFile::parse(data)?;
+    let regions = memmap::reserved_regions();
+
+    // The source buffer must survive until all segments have been copied.
+    let src = data.as_ptr() as u64 & !memmap::REAL_MODE_BASE;
+    let src = src..src + data.len() as u64;
+
+    // Validate the placement of all segments before touching any memory.
+    for (i, ph) in file.load_segments().enumerate() {
+        let range = ph.phys_range();
+        if range.end > memmap::RAM_SIZE {
+            return Err(LoadError::OutOfMemory(i));
+        }
+
+        let overlaps = |r: &Range<u64>| range.start < r.end && r.start < range.end;
+
+        if let Some((name, _)) = regions.iter().find(|(_, r)| overlaps(r)) {
+            return Err(LoadError::Overlap(i, name));
+        }
+
+        if overlaps(&src) {
+            return Err(LoadError::Overlap(i, "source image"));
+        }
+    }
+
+    for ph in file.load_segments() {
+        let dst = memmap::real(ph.p_paddr) as *mut u8;
+        let contents = file.segment_data(&ph);
+
+        core::ptr::copy_nonoverlapping(contents.as_ptr(), dst, contents.len());
+        core_reqs::memset(
+            dst.add(contents.len()),
+            0x00,
+            (ph.p_memsz - ph.p_filesz) as usize,
+        );
+
+        xenon_cpu::intrin::sync_icache(dst as usize, ph.p_memsz as usize);
+    }
+
+    let extent = file.phys_extent();
+    Ok(LoadedImage {
+        entry: file.entry_phys(),
+        extent: (extent.start, extent.end),
+    })
+}
+
+/// Transfer control to a loaded image on the current processor.
+/// `args` are passed in r3-r5, following the PowerPC64 Linux boot protocol:
+/// r3 = device tree, r4 = kernel base, r5 = 0 (no OpenFirmware).
+///
+/// # Safety
+/// The image must have been loaded by [load], and the bootloader's state will
+/// be considered lost after this call.
+pub unsafe fn boot(image: &LoadedImage, args: [u64; 3]) -> ! {
+    let mut ctx = except::CpuContext::new();
+
+    ctx.r[3] = args[0];
+    ctx.r[4] = args[1];
+    ctx.r[5] = args[2];
+    ctx.pc = memmap::real(image.entry);
+    ctx.msr = ENTRY_MSR;
+
+    except::load_context(&ctx);
+}
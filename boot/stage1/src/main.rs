@@ -4,12 +4,12 @@
     global_asm,
     lang_items,
     naked_functions,
+    panic_info_message,
     asm
 )]
 #![no_std]
 #![no_main]
 
-use atomic::Atomic;
 use core::{
     fmt::Write,
     sync::atomic::{AtomicU32, Ordering},
@@ -18,190 +18,289 @@ use xenon_cpu::{
     intrin::{mfmsr, mtmsrl},
     mfspr,
 };
-use xenon_soc::{smc, uart};
-use crate::util::bit;
+use xenon_soc::{
+    iic::{Iic, Interrupt},
+    smc, uart,
+};
+use crate::{
+    console::Console,
+    terminal::{Args, Command, CommandRef, Error, Terminal},
+    util::bit,
+};
 
 extern crate alloc;
 extern crate core_reqs;
 
 mod glballoc;
+mod backtrace;
+mod console;
+mod crashdump;
+mod crashlog;
+mod devtree;
+mod disasm;
 mod except;
+mod gdb;
+mod httpd;
+mod loader;
+mod memmap;
+mod memory;
+mod net;
+mod netcon;
 mod panic;
+mod smp;
+mod symbols;
+mod telnetd;
+mod terminal;
 mod util;
 
-use except::ExceptionType;
+use except::{Disposition, ExceptionType};
 
 global_asm!(include_str!("startup.s"));
 
 static PROCESSORS: AtomicU32 = AtomicU32::new(0);
 
 macro_rules! println {
+    () => {
+        print!("\n")
+    };
     ($($tts:tt)*) => {
-        uart::UART.lock(|mut uart| {
-            core::writeln!(&mut uart, $($tts)*).unwrap();
-        });
+        $crate::print_fmt(core::format_args!("{}\n", core::format_args!($($tts)*)))
     };
 }
 
 macro_rules! print {
     ($($tts:tt)*) => {
-        uart::UART.lock(|mut uart| {
-            core::write!(&mut uart, $($tts)*).unwrap();
-        });
+        $crate::print_fmt(core::format_args!($($tts)*))
     };
 }
 
-fn read_line(uart: &mut uart::UART, line: &mut [u8]) -> usize {
-    let mut n = 0usize;
+/// Write formatted text to every console.
+fn print_fmt(args: core::fmt::Arguments) {
+    crate::console::CONSOLE.lock(|console| {
+        let _ = console.write_fmt(args);
+    });
+}
 
-    while n < line.len() {
-        match uart.read_byte() {
-            b'\r' => {
-                uart.write(b"\r\n");
-                break;
-            }
+/// Wait for the next byte of terminal input, keeping everything else running meanwhile.
+fn read_byte() -> u8 {
+    loop {
+        if gdb::is_active() {
+            gdb::poll_interrupt();
+        }
 
-            // Backspace.
-            0x08 => {
-                if n != 0 {
-                    // Clear the character from the screen.
-                    uart.write(b"\x08 \x08");
+        if let Some(byte) = crate::console::CONSOLE.lock(|console| console.poll()) {
+            return byte;
+        }
 
-                    line[n] = b'\0';
-                    n -= 1;
-                }
-            }
+        // Keep the network stack (e.g. DHCP lease renewal) running while idle.
+        net::with(|net| net.poll());
 
-            byte => {
-                uart.write_byte(byte);
+        // A new GDB client stops everything, as `target remote` expects.
+        if net::with(|net| net.take_gdb_client()) == Some(true) && !gdb::is_active() {
+            println!("GDB connected on port {}.", net::GDB_PORT);
+            gdb::breakpoint();
+        }
 
-                line[n] = byte;
-                n += 1;
+        if let Some(image) = httpd::take_boot_request() {
+            // Give the client's connection a moment to finish closing.
+            let deadline = net::now() + smoltcp::time::Duration::from_millis(500);
+            while net::now() < deadline {
+                net::with(|net| net.poll());
             }
+
+            println!("Booting uploaded image ({} bytes)...", image.len());
+            boot_kernel(image, None);
         }
     }
+}
 
-    n
+/// Return the parked threads to the bootloader after a failed boot, reporting any that are stuck.
+fn unpark_secondaries() {
+    if let Err(stuck) = smp::unpark_secondaries() {
+        println!("Threads failed to unpark: {:02X}", stuck);
+    }
 }
 
-fn serial_terminal() {
-    let mut buf = [0u8; 1024];
-    loop {
-        print!("\n> ");
+/// Hand the system off to a kernel image. Returns only if the image could not be booted.
+fn boot_kernel(data: &[u8], base: Option<&[u8]>) {
+    // Hold all other threads in the spin table, so the kernel can release them.
+    let parked = match smp::park_secondaries(PROCESSORS.load(Ordering::Relaxed)) {
+        Ok(parked) => parked,
+        Err(missing) => {
+            println!("Threads failed to park: {:02X}", missing);
+            unpark_secondaries();
+            return;
+        }
+    };
+    println!("Parked threads: {:02X}", parked);
+
+    let dt = devtree::BOOT_PARAMS.lock(|params| devtree::build(base, params));
+    let dt = match dt {
+        Ok(dt) => dt,
+        Err(e) => {
+            println!("Failed to build device tree: {}", e);
+            unpark_secondaries();
+            return;
+        }
+    };
 
-        let n = uart::UART.lock(|mut uart| read_line(&mut uart, &mut buf));
+    let fdt = match unsafe { devtree::install(&dt) } {
+        Ok(fdt) => fdt,
+        Err(_) => {
+            println!("Device tree too large");
+            unpark_secondaries();
+            return;
+        }
+    };
 
-        let line = match core::str::from_utf8(&buf[..n]) {
-            Ok(l) => l,
-            Err(_) => continue,
-        };
+    let image = match unsafe { loader::load(data) } {
+        Ok(image) => image,
+        Err(e) => {
+            println!("Failed to load image: {}", e);
+            unpark_secondaries();
+            return;
+        }
+    };
 
-        let mut args = line.split(' ');
-        match args.next() {
-            Some("r64") => {
-                let addr = {
-                    let addr_str = match args.next() {
-                        Some(a) => a,
-                        None => {
-                            println!("r64 <address>");
-                            continue;
-                        }
-                    };
-
-                    match u64::from_str_radix(addr_str, 16) {
-                        Ok(n) => n,
-                        Err(_) => {
-                            println!("invalid address");
-                            continue;
-                        }
-                    }
-                };
+    println!(
+        "Loaded image at {:08X}-{:08X}, entry {:08X}",
+        image.extent.0, image.extent.1, image.entry
+    );
 
-                let val = unsafe { core::ptr::read_volatile(addr as *const u64) };
-                println!("{:016X}", val);
-            }
+    unsafe {
+        loader::boot(&image, [fdt, image.extent.0, 0]);
+    }
+}
 
-            Some("w64") => {
-                let addr = {
-                    let addr_str = match args.next() {
-                        Some(a) => a,
-                        None => {
-                            println!("w64 <address> <val>");
-                            continue;
-                        }
-                    };
-
-                    match u64::from_str_radix(addr_str, 16) {
-                        Ok(n) => n,
-                        Err(_) => {
-                            println!("invalid address");
-                            continue;
-                        }
-                    }
-                };
-
-                let val = {
-                    let val_str = match args.next() {
-                        Some(a) => a,
-                        None => {
-                            println!("w64 <address> <val>");
-                            continue;
-                        }
-                    };
-
-                    match u64::from_str_radix(val_str, 16) {
-                        Ok(n) => n,
-                        Err(_) => {
-                            println!("invalid value");
-                            continue;
-                        }
-                    }
-                };
+/// The commands implemented here. Other modules bring their own.
+static COMMANDS: [CommandRef<Terminal>; 7] =
+    [&R64, &W64, &BOOT, &REBOOT, &EXCEPT, &PING, &EGGPLANT];
 
-                unsafe {
-                    core::ptr::write_volatile(addr as *mut u64, val);
-                }
-            }
+static R64: Command = Command {
+    name: "r64",
+    usage: "<address>",
+    help: "Read a 64-bit value from memory",
+    handler: r64,
+};
 
-            Some("reboot") => {
-                println!("Rebooting system...");
-                smc::SMC.lock(|smc| {
-                    smc.restart_system();
-                });
-            }
+fn r64(term: &mut Terminal, args: &mut Args) -> Result<(), Error> {
+    let addr = args.num("address")?;
 
-            Some("except") => {
-                println!("If you say so...");
-                unsafe {
-                    except::cause_exception();
-                }
-            }
+    let val = unsafe { core::ptr::read_volatile(addr as *const u64) };
+    writeln!(term, "{:016X}", val).unwrap();
 
-            Some("ping") => {
-                println!("pong");
-            }
+    Ok(())
+}
 
-            Some("🍆") => {
-                println!(";)");
-            }
+static W64: Command = Command {
+    name: "w64",
+    usage: "<address> <val>",
+    help: "Write a 64-bit value to memory",
+    handler: w64,
+};
 
-            Some("exit") => {
-                println!("Goodbye!");
-                return;
-            }
+fn w64(_term: &mut Terminal, args: &mut Args) -> Result<(), Error> {
+    let addr = args.num("address")?;
+    let val = args.num("value")?;
 
-            Some("") => {}
+    unsafe {
+        core::ptr::write_volatile(addr as *mut u64, val);
+    }
+
+    Ok(())
+}
 
-            Some(cmd) => {
-                println!("Unknown command \"{}\"!", cmd);
+static BOOT: Command = Command {
+    name: "boot",
+    usage: "<address> <len> [dtb]",
+    help: "Boot a kernel image from memory",
+    handler: boot,
+};
+
+fn boot(_term: &mut Terminal, args: &mut Args) -> Result<(), Error> {
+    let addr = args.num("address")?;
+    let len = args.num("length")? as usize;
+
+    // An optional base device tree may follow the image.
+    let base = match args.opt_num("device tree address")? {
+        Some(dtb) => {
+            // N.B: The total size of the blob is stored in the header, after the magic.
+            let (magic, len) = unsafe {
+                (
+                    core::ptr::read_volatile(dtb as *const u32),
+                    core::ptr::read_volatile((dtb + 4) as *const u32),
+                )
+            };
+
+            if magic != fdt::FDT_MAGIC || len as u64 > memmap::RAM_SIZE {
+                return Err(Error::Invalid("device tree address"));
             }
 
-            None => {}
+            Some(unsafe { core::slice::from_raw_parts(dtb as *const u8, len as usize) })
         }
-    }
+        None => None,
+    };
+
+    let data = unsafe { core::slice::from_raw_parts(addr as *const u8, len) };
+    boot_kernel(data, base);
+
+    Ok(())
+}
+
+static REBOOT: Command = Command {
+    name: "reboot",
+    usage: "",
+    help: "Restart the system",
+    handler: reboot,
+};
+
+fn reboot(term: &mut Terminal, _args: &mut Args) -> Result<(), Error> {
+    writeln!(term, "Rebooting system...").unwrap();
+    smc::SMC.lock(|smc| {
+        smc.restart_system();
+    });
+
+    Ok(())
+}
+
+static EXCEPT: Command = Command {
+    name: "except",
+    usage: "",
+    help: "Cause an exception",
+    handler: cause_exception,
+};
+
+fn cause_exception(term: &mut Terminal, _args: &mut Args) -> Result<(), Error> {
+    writeln!(term, "If you say so...").unwrap();
+    unsafe { except::cause_exception() }
+}
+
+static PING: Command = Command {
+    name: "ping",
+    usage: "",
+    help: "Reply with pong",
+    handler: ping,
+};
+
+fn ping(term: &mut Terminal, _args: &mut Args) -> Result<(), Error> {
+    writeln!(term, "pong").unwrap();
+    Ok(())
 }
 
-fn startup_exception_handler(ex: ExceptionType, ctx: &mut except::CpuContext) -> Result<(), ()> {
+static EGGPLANT: Command = Command {
+    name: "🍆",
+    usage: "",
+    help: "",
+    handler: eggplant,
+};
+
+fn eggplant(term: &mut Terminal, _args: &mut Args) -> Result<(), Error> {
+    writeln!(term, ";)").unwrap();
+    Ok(())
+}
+
+/// Captures processors coming from the OS, on any exception, and sends them to [cpu_startup].
+fn startup_exception_handler(ex: ExceptionType, ctx: &mut except::CpuContext) -> Disposition {
     let pir = xenon_cpu::intrin::pir();
     uart::UART.lock(|uart| {
         let sp = unsafe {
@@ -237,35 +336,30 @@ fn startup_exception_handler(ex: ExceptionType, ctx: &mut except::CpuContext) ->
     });
 
     // Branch to thread entry.
-    let context = except::CpuContext::with_hvcall(cpu_startup, 0x8000_0000_1E00_0000 - (pir << 16));
+    let context = except::CpuContext::with_hvcall(cpu_startup, memmap::thread_stack(pir));
     unsafe {
         except::load_context(&context);
     }
 }
 
-fn normal_exception_handler(ex: ExceptionType, _ctx: &mut except::CpuContext) -> Result<(), ()> {
-    match ex {
-        ExceptionType::ExternalInterrupt => Ok(()),
+/// Dispatches external interrupts from the IIC.
+fn external_interrupt(_ex: ExceptionType, ctx: &mut except::CpuContext) -> Disposition {
+    let iic = Iic::local();
 
-        _ => Err(()),
-    }
-}
+    while let Some(int) = iic.acknowledge() {
+        let halt = matches!(int, Interrupt::Ipi1);
+        iic.eoi(int);
 
-fn exception_handler(ex: ExceptionType, ctx: &mut except::CpuContext) -> Result<(), ()> {
-    match EXCEPTION_HANDLER_MODE.load(Ordering::Relaxed) {
-        ExceptionMode::Startup => startup_exception_handler(ex, ctx),
-        ExceptionMode::Normal => normal_exception_handler(ex, ctx),
+        // Panics, crash dumps and the debugger halt threads with an IPI.
+        if halt {
+            smp::on_ipi(ctx);
+            gdb::on_ipi(ctx);
+        }
     }
-}
 
-#[derive(Clone, Copy)]
-enum ExceptionMode {
-    Startup,
-    Normal,
+    Disposition::Handled
 }
 
-static EXCEPTION_HANDLER_MODE: Atomic<ExceptionMode> = Atomic::new(ExceptionMode::Startup);
-
 extern "C" fn cpu_startup() -> ! {
     let pir = xenon_cpu::intrin::pir();
     PROCESSORS.fetch_or(1 << pir, Ordering::Relaxed);
@@ -273,7 +367,8 @@ extern "C" fn cpu_startup() -> ! {
     // Loop until all processors check in.
     while PROCESSORS.load(Ordering::Relaxed) != 0x3F {}
 
-    // Enable external interrupts.
+    // Only take the IPIs used by panics and the debugger, then enable external interrupts.
+    Iic::local().set_priority(Interrupt::Clock);
     unsafe {
         mtmsrl(bit(48));
     }
@@ -284,7 +379,16 @@ extern "C" fn cpu_startup() -> ! {
 fn cpu_idle() -> ! {
     let pir = xenon_cpu::intrin::pir();
 
-    loop {}
+    if pir == 0 {
+        terminal::run();
+    }
+
+    loop {
+        // Secondary threads are handed off to a kernel from here.
+        if smp::park_requested() {
+            smp::park();
+        }
+    }
 }
 
 #[no_mangle]
@@ -346,9 +450,11 @@ pub extern "C" fn __start_rust(
     });
 
     unsafe {
-        except::init_except(Some(exception_handler));
+        except::init_except();
     }
 
+    let startup = except::register_any(except::ALL_CPUS, startup_exception_handler).unwrap();
+
     match src {
         // Startup from ROM
         /*
@@ -376,6 +482,7 @@ pub extern "C" fn __start_rust(
         // Startup from OS (1)
         // HACK: Also going to apply this path for startup from ROM for development.
         0 | 1 => {
+            crate::console::init();
             println!("Startup from OS.");
 
             // We'll need to catch all other cores that may still be running the OS.
@@ -420,13 +527,56 @@ pub extern "C" fn __start_rust(
         smc.set_led(true, 0xF0);
     });
 
-    EXCEPTION_HANDLER_MODE.store(ExceptionMode::Normal, Ordering::Relaxed);
+    except::unregister(startup);
+    except::register(
+        ExceptionType::ExternalInterrupt,
+        except::ALL_CPUS,
+        external_interrupt,
+    )
+    .unwrap();
+    gdb::init();
     println!("System captured.");
 
+    // Report any crashes from before the last reboot.
+    let crashes = crashlog::len();
+    if crashes != 0 {
+        println!(
+            "{} crash record(s) found. Download them from /crashlog, or discard them with `crashlog clear`.",
+            crashes
+        );
+        crate::console::CONSOLE.lock(|console| {
+            crashlog::print(&mut crate::console::Writer(console));
+        });
+    }
+
+    unsafe {
+        net::init();
+    }
+
+    // Acquire an address in the background while at the prompt, and serve the web interface.
+    net::with(|net| {
+        net.start_dhcp();
+        net.start_httpd();
+        net.start_telnetd();
+        net.start_gdb();
+    });
+
+    terminal::register(&COMMANDS);
+    terminal::register(&crashdump::COMMANDS);
+    terminal::register(&crashlog::COMMANDS);
+    terminal::register(&devtree::COMMANDS);
+    terminal::register(&disasm::COMMANDS);
+    terminal::register(&gdb::COMMANDS);
+    terminal::register(&memory::COMMANDS);
+    terminal::register(&net::COMMANDS);
+    terminal::register(&netcon::COMMANDS);
+    terminal::register(&panic::COMMANDS);
+    terminal::register(&symbols::COMMANDS);
+
     PROCESSORS.fetch_or(1 << pir, Ordering::Relaxed);
 
     // Branch to thread entry.
-    let context = except::CpuContext::with_hvcall(cpu_startup, 0x8000_0000_1E00_0000 - (pir << 16));
+    let context = except::CpuContext::with_hvcall(cpu_startup, memmap::thread_stack(pir));
     unsafe {
         except::load_context(&context);
     }
@@ -0,0 +1,114 @@
+//! This module describes the physical memory layout used by the bootloader.
+//!
+//! All addresses here are physical. Use [real] to get an address usable
+//! from real mode with HRMOR disabled.
+//!
+//! N.B: `startup.s` sets up the initial thread stacks independently, and must be
+//! kept in sync with [thread_stack].
+
+use core::ops::Range;
+
+/// The base of the real-mode address space with HRMOR disabled.
+pub const REAL_MODE_BASE: u64 = 0x8000_0000_0000_0000;
+
+/// The amount of physical memory installed in the system.
+pub const RAM_SIZE: u64 = 0x2000_0000;
+
+/// Memory-mapped I/O space.
+pub const MMIO_BASE: u64 = 0x0200_0000_0000;
+pub const MMIO_SIZE: u64 = 0x1_0000_0000;
+
+/// The global allocator's heap.
+pub const HEAP_BASE: u64 = 0x0800_0000;
+pub const HEAP_SIZE: u64 = 0x0100_0000;
+
+/// Images uploaded over the network are staged here.
+/// N.B: This is not reserved, so a loaded kernel is free to reuse it.
+pub const UPLOAD_BASE: u64 = 0x1000_0000;
+pub const UPLOAD_SIZE: u64 = 0x0800_0000;
+
+/// The size of each per-thread stack.
+pub const STACK_SIZE: u64 = 0x1_0000;
+
+/// Thread stacks grow down from this address, one [STACK_SIZE] apart.
+const THREAD_STACK_TOP: u64 = 0x1E00_0000;
+
+/// Exception stacks grow down from this address, one [STACK_SIZE] apart.
+const EXCEPTION_STACK_TOP: u64 = 0x1EFF_0000;
+
+/// Left free above [EXCEPTION_STACK_TOP], since the exception handler's prologue saves its
+/// return address into the (nonexistent) caller's frame there.
+const EXCEPTION_STACK_GUARD: u64 = 0x1000;
+
+/// Crash records (see [crate::crashlog]). Nothing clears this at boot, so the records
+/// survive a warm reboot.
+pub const CRASHLOG_BASE: u64 = EXCEPTION_STACK_TOP + EXCEPTION_STACK_GUARD;
+pub const CRASHLOG_SIZE: u64 = 0x1_0000;
+
+/// Data handed off to a loaded kernel (device tree, etc.) lives here.
+pub const HANDOFF_BASE: u64 = 0x1E00_0000;
+pub const HANDOFF_SIZE: u64 = 0x10_0000;
+
+/// The flattened device tree passed to a loaded kernel.
+pub const FDT_BASE: u64 = HANDOFF_BASE;
+pub const FDT_SIZE: u64 = 0x1_0000;
+
+/// The spin table secondary threads are parked on (see [crate::smp]).
+pub const SPIN_TABLE_BASE: u64 = FDT_BASE + FDT_SIZE;
+
+extern "C" {
+    static _start: u8;
+    static __bss_end: u8;
+}
+
+/// Convert a physical address into a pointer usable from real mode.
+pub const fn real(addr: u64) -> u64 {
+    REAL_MODE_BASE | addr
+}
+
+/// The physical address range occupied by the bootloader image, including BSS.
+pub fn image() -> Range<u64> {
+    // SAFETY: We only take the address of these linker-defined symbols.
+    unsafe {
+        (&_start as *const u8 as u64 & !REAL_MODE_BASE)
+            ..(&__bss_end as *const u8 as u64 & !REAL_MODE_BASE)
+    }
+}
+
+/// The initial stack pointer for the specified hardware thread.
+pub const fn thread_stack(pir: u64) -> u64 {
+    real(THREAD_STACK_TOP - (pir * STACK_SIZE))
+}
+
+/// The initial stack pointer used by the exception handler on the specified hardware thread.
+pub const fn exception_stack(pir: u64) -> u64 {
+    real(EXCEPTION_STACK_TOP - (pir * STACK_SIZE))
+}
+
+/// The physical address range of the thread or exception stack containing `addr`, if any.
+pub fn stack_containing(addr: u64) -> Option<Range<u64>> {
+    let addr = addr & !REAL_MODE_BASE;
+
+    [THREAD_STACK_TOP, EXCEPTION_STACK_TOP]
+        .iter()
+        .flat_map(|top| (1..=6).map(move |n| top - n * STACK_SIZE..top - (n - 1) * STACK_SIZE))
+        .find(|stack| stack.contains(&addr))
+}
+
+/// Retrieve the list of physical memory regions the bootloader is using.
+pub fn reserved_regions() -> [(&'static str, Range<u64>); 6] {
+    [
+        ("stage1 image", image()),
+        ("heap", HEAP_BASE..HEAP_BASE + HEAP_SIZE),
+        (
+            "thread stacks",
+            THREAD_STACK_TOP - 6 * STACK_SIZE..THREAD_STACK_TOP,
+        ),
+        ("handoff area", HANDOFF_BASE..HANDOFF_BASE + HANDOFF_SIZE),
+        (
+            "exception stacks",
+            EXCEPTION_STACK_TOP - 6 * STACK_SIZE..EXCEPTION_STACK_TOP + EXCEPTION_STACK_GUARD,
+        ),
+        ("crash log", CRASHLOG_BASE..CRASHLOG_BASE + CRASHLOG_SIZE),
+    ]
+}
@@ -0,0 +1,387 @@
+//! This module implements the terminal's memory commands.
+//!
+//! Addresses are used as given, as with `r64` and `w64`. Accesses to MMIO space
+//! (`0x8000_0200_xxxx_xxxx`) are volatile. `md` and `mw` access it with the size they are asked
+//! for; the other commands use the largest size the addresses and length are aligned to.
+//! Elsewhere, memory is treated as plain RAM.
+
+use core::fmt::Write;
+
+use crate::{
+    memmap::{self, MMIO_SIZE},
+    terminal::{Args, Command, CommandRef, Error, Terminal},
+};
+
+/// The start of MMIO space.
+const MMIO_BASE: u64 = memmap::real(memmap::MMIO_BASE);
+
+/// The number of bytes `md` dumps if no length is given.
+const DUMP_LEN: u64 = 0x100;
+/// The number of bytes on each line of a dump.
+const DUMP_LINE: u64 = 16;
+
+/// The number of differences or matches reported before giving up.
+const REPORT_MAX: usize = 32;
+
+/// The longest pattern `msearch` takes, in bytes.
+const PATTERN_MAX: usize = 64;
+
+pub static COMMANDS: [CommandRef<Terminal>; 13] = [
+    &MD, &MD_B, &MD_H, &MD_W, &MD_D, &MW_B, &MW_H, &MW_W, &MW_D, &MFILL, &MCOPY, &MCMP, &MSEARCH,
+];
+
+/// A command which accesses memory in units of a given size.
+struct SizedCommand {
+    name: &'static str,
+    usage: &'static str,
+    help: &'static str,
+    size: usize,
+    handler: fn(&mut Terminal, &mut Args, usize) -> Result<(), Error>,
+}
+
+impl shell::Command<Terminal> for SizedCommand {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn usage(&self) -> &'static str {
+        self.usage
+    }
+
+    fn help(&self) -> &'static str {
+        self.help
+    }
+
+    fn run(&self, term: &mut Terminal, args: &mut Args) -> Result<(), Error> {
+        (self.handler)(term, args, self.size)
+    }
+}
+
+macro_rules! sized_commands {
+    ($handler:ident, $usage:literal, $($cmd:ident: $name:literal, $size:literal, $help:literal;)*) => {
+        $(
+            static $cmd: SizedCommand = SizedCommand {
+                name: $name,
+                usage: $usage,
+                help: $help,
+                size: $size,
+                handler: $handler,
+            };
+        )*
+    };
+}
+
+sized_commands! {
+    md, "<address> [len]",
+    // `md` on its own dumps bytes.
+    MD: "md", 1, "";
+    MD_B: "md.b", 1, "Dump memory as bytes";
+    MD_H: "md.h", 2, "Dump memory as 16-bit halfwords";
+    MD_W: "md.w", 4, "Dump memory as 32-bit words";
+    MD_D: "md.d", 8, "Dump memory as 64-bit doublewords";
+}
+
+sized_commands! {
+    mw, "<address> <value> [count]",
+    MW_B: "mw.b", 1, "Write bytes to memory";
+    MW_H: "mw.h", 2, "Write 16-bit halfwords to memory";
+    MW_W: "mw.w", 4, "Write 32-bit words to memory";
+    MW_D: "mw.d", 8, "Write 64-bit doublewords to memory";
+}
+
+/// Returns true if any of `addr..addr + len` is in MMIO space.
+fn is_mmio(addr: u64, len: u64) -> bool {
+    let last = addr.wrapping_add(len.max(1) - 1);
+    addr < MMIO_BASE + MMIO_SIZE && last >= MMIO_BASE
+}
+
+/// The largest access size that `addr` and `len` are both aligned to.
+fn unit(addr: u64, len: u64) -> usize {
+    let bits = addr | len;
+    [8, 4, 2, 1]
+        .iter()
+        .copied()
+        .find(|&n| bits % n as u64 == 0)
+        .unwrap()
+}
+
+unsafe fn read<T: Into<u64>>(addr: u64, volatile: bool) -> u64 {
+    let ptr = addr as *const T;
+
+    if volatile {
+        ptr.read_volatile().into()
+    } else {
+        ptr.read().into()
+    }
+}
+
+unsafe fn write<T>(addr: u64, value: T, volatile: bool) {
+    let ptr = addr as *mut T;
+
+    if volatile {
+        ptr.write_volatile(value);
+    } else {
+        ptr.write(value);
+    }
+}
+
+/// Load `size` bytes from a naturally aligned address.
+unsafe fn load(addr: u64, size: usize) -> u64 {
+    let volatile = is_mmio(addr, size as u64);
+
+    match size {
+        1 => read::<u8>(addr, volatile),
+        2 => read::<u16>(addr, volatile),
+        4 => read::<u32>(addr, volatile),
+        _ => read::<u64>(addr, volatile),
+    }
+}
+
+/// Store the low `size` bytes of `value` to a naturally aligned address.
+unsafe fn store(addr: u64, size: usize, value: u64) {
+    let volatile = is_mmio(addr, size as u64);
+
+    match size {
+        1 => write(addr, value as u8, volatile),
+        2 => write(addr, value as u16, volatile),
+        4 => write(addr, value as u32, volatile),
+        _ => write(addr, value, volatile),
+    }
+}
+
+/// Take an address which must be aligned to `size`.
+fn aligned(args: &mut Args, what: &'static str, size: usize) -> Result<u64, Error> {
+    let addr = args.num(what)?;
+
+    if addr % size as u64 != 0 {
+        return Err(Error::Invalid(what));
+    }
+
+    Ok(addr)
+}
+
+fn md(term: &mut Terminal, args: &mut Args, size: usize) -> Result<(), Error> {
+    let addr = aligned(args, "address", size)?;
+    let len = args.opt_num("length")?.unwrap_or(DUMP_LEN);
+
+    // Round up to whole units.
+    let size = size as u64;
+    let end = addr.saturating_add((len + size - 1) / size * size);
+
+    for line in (addr..end).step_by(DUMP_LINE as usize) {
+        let line_end = end.min(line + DUMP_LINE);
+        let mut text = [b' '; DUMP_LINE as usize];
+
+        write!(term, "{:016X}:", line).unwrap();
+
+        for at in (line..line_end).step_by(size as usize) {
+            let value = unsafe { load(at, size as usize) };
+            write!(term, " {:0w$X}", value, w = size as usize * 2).unwrap();
+
+            let bytes = value.to_be_bytes();
+            for (i, &b) in bytes[8 - size as usize..].iter().enumerate() {
+                let printable = b.is_ascii_graphic() || b == b' ';
+                text[(at - line) as usize + i] = if printable { b } else { b'.' };
+            }
+        }
+
+        // Line up the text of a short last line with the lines above it.
+        let missing = (line + DUMP_LINE - line_end) / size;
+        for _ in 0..missing * (size * 2 + 1) {
+            term.write_char(' ').unwrap();
+        }
+
+        let text = &text[..(line_end - line) as usize];
+        writeln!(term, "  {}", core::str::from_utf8(text).unwrap()).unwrap();
+    }
+
+    Ok(())
+}
+
+fn mw(_term: &mut Terminal, args: &mut Args, size: usize) -> Result<(), Error> {
+    let addr = aligned(args, "address", size)?;
+    let value = args.num("value")?;
+    let count = args.opt_num("count")?.unwrap_or(1);
+
+    if size < 8 && value >> (size * 8) != 0 {
+        return Err(Error::Invalid("value"));
+    }
+
+    for i in 0..count {
+        unsafe { store(addr + i * size as u64, size, value) };
+    }
+
+    Ok(())
+}
+
+static MFILL: Command = Command {
+    name: "mfill",
+    usage: "<address> <len> <byte>",
+    help: "Fill memory with a byte",
+    handler: mfill,
+};
+
+fn mfill(_term: &mut Terminal, args: &mut Args) -> Result<(), Error> {
+    let addr = args.num("address")?;
+    let len = args.num("length")?;
+    let byte = args.num("byte")?;
+
+    if byte > 0xFF {
+        return Err(Error::Invalid("byte"));
+    }
+
+    if is_mmio(addr, len) {
+        let size = unit(addr, len);
+        let value = u64::from_ne_bytes([byte as u8; 8]);
+
+        for at in (addr..addr + len).step_by(size) {
+            unsafe { store(at, size, value) };
+        }
+    } else {
+        unsafe { core::ptr::write_bytes(addr as *mut u8, byte as u8, len as usize) };
+    }
+
+    Ok(())
+}
+
+static MCOPY: Command = Command {
+    name: "mcopy",
+    usage: "<src> <dst> <len>",
+    help: "Copy memory",
+    handler: mcopy,
+};
+
+fn mcopy(_term: &mut Terminal, args: &mut Args) -> Result<(), Error> {
+    let src = args.num("source")?;
+    let dst = args.num("destination")?;
+    let len = args.num("length")?;
+
+    if is_mmio(src, len) || is_mmio(dst, len) {
+        let size = unit(src | dst, len);
+
+        for offset in (0..len).step_by(size) {
+            unsafe { store(dst + offset, size, load(src + offset, size)) };
+        }
+    } else {
+        unsafe { core::ptr::copy(src as *const u8, dst as *mut u8, len as usize) };
+    }
+
+    Ok(())
+}
+
+static MCMP: Command = Command {
+    name: "mcmp",
+    usage: "<address> <address> <len>",
+    help: "Compare two ranges of memory",
+    handler: mcmp,
+};
+
+fn mcmp(term: &mut Terminal, args: &mut Args) -> Result<(), Error> {
+    let a = args.num("address")?;
+    let b = args.num("address")?;
+    let len = args.num("length")?;
+
+    let size = unit(a | b, len);
+    let mut differences = 0;
+
+    for offset in (0..len).step_by(size) {
+        let (x, y) = unsafe { (load(a + offset, size), load(b + offset, size)) };
+        if x == y {
+            continue;
+        }
+
+        differences += 1;
+        if differences <= REPORT_MAX {
+            writeln!(
+                term,
+                "{:016X}: {:0w$X} != {:016X}: {:0w$X}",
+                a + offset,
+                x,
+                b + offset,
+                y,
+                w = size * 2
+            )
+            .unwrap();
+        }
+    }
+
+    match differences {
+        0 => writeln!(term, "{:X} bytes are identical", len).unwrap(),
+        n if n > REPORT_MAX => {
+            writeln!(term, "...and {} more differences", n - REPORT_MAX).unwrap()
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+static MSEARCH: Command = Command {
+    name: "msearch",
+    usage: "<address> <len> <bytes...>",
+    help: "Search memory for a sequence of bytes, given in hex",
+    handler: msearch,
+};
+
+fn msearch(term: &mut Terminal, args: &mut Args) -> Result<(), Error> {
+    let addr = args.num("address")?;
+    let len = args.num("length")?;
+
+    // The pattern may be given as one string of hex digits, or several.
+    let mut pattern = [0u8; PATTERN_MAX];
+    let mut pattern_len = 0;
+
+    for arg in args {
+        if arg.len() % 2 != 0 || arg.len() / 2 > PATTERN_MAX - pattern_len {
+            return Err(Error::Invalid("pattern"));
+        }
+
+        for i in (0..arg.len()).step_by(2) {
+            let byte = arg
+                .get(i..i + 2)
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+                .ok_or(Error::Invalid("pattern"))?;
+
+            pattern[pattern_len] = byte;
+            pattern_len += 1;
+        }
+    }
+
+    let pattern = &pattern[..pattern_len];
+    if pattern.is_empty() {
+        return Err(Error::Usage);
+    }
+
+    // Read memory a unit at a time, keeping the last bytes seen in a window.
+    let size = unit(addr, len);
+    let mut window = [0u8; PATTERN_MAX];
+    let mut seen = 0u64;
+    let mut matches = 0;
+
+    for at in (addr..addr + len).step_by(size) {
+        let value = unsafe { load(at, size) };
+
+        for &b in &value.to_be_bytes()[8 - size..] {
+            window.copy_within(1.., 0);
+            window[PATTERN_MAX - 1] = b;
+            seen += 1;
+
+            if seen < pattern.len() as u64 || &window[PATTERN_MAX - pattern.len()..] != pattern {
+                continue;
+            }
+
+            matches += 1;
+            if matches <= REPORT_MAX {
+                writeln!(term, "{:016X}", addr + seen - pattern.len() as u64).unwrap();
+            }
+        }
+    }
+
+    match matches {
+        0 => writeln!(term, "not found").unwrap(),
+        n if n > REPORT_MAX => writeln!(term, "...and {} more matches", n - REPORT_MAX).unwrap(),
+        _ => {}
+    }
+
+    Ok(())
+}
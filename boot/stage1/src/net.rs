@@ -0,0 +1,615 @@
+//! This module owns the network interface and the sockets bound to it.
+
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+use core::{fmt::Write, str::FromStr};
+use dhcp::packet::{Datagram, CLIENT_PORT, SERVER_PORT};
+use smoltcp::{
+    iface::{EthernetInterface, EthernetInterfaceBuilder, NeighborCache, Routes},
+    socket::{
+        RawPacketMetadata, RawSocket, RawSocketBuffer, SocketHandle, SocketRef, SocketSet,
+        TcpSocket, TcpSocketBuffer, UdpPacketMetadata, UdpSocket, UdpSocketBuffer,
+    },
+    time::Instant,
+    wire::{EthernetAddress, IpCidr, IpEndpoint, IpProtocol, IpVersion, Ipv4Address, Ipv4Cidr},
+};
+use sync::mutex::SpinMutex;
+use xenon_cpu::intrin::{mfmsr, mtmsrl};
+use xenon_enet::EthernetDevice;
+
+use crate::{
+    httpd, netcon, telnetd,
+    terminal::{Args, Command, CommandRef, Error, Terminal},
+    util::bit,
+};
+
+/// The ethernet device, with 32 RX and TX descriptors.
+pub type Device = EthernetDevice<32, 32>;
+
+/// The number of packets each UDP socket can buffer in either direction.
+const UDP_PACKETS: usize = 8;
+
+/// The largest IPv4 packet we will send or receive.
+const MTU: usize = 1500;
+
+/// The local port network console output is sent from, as with Linux netconsole.
+const NETCON_PORT: u16 = 6665;
+
+/// The port GDB connects to, with `target remote <address>:1234`.
+pub const GDB_PORT: u16 = 1234;
+
+/// The size of each of the GDB socket's buffers.
+const GDB_BUFFER_SIZE: usize = 16 * 1024;
+
+/// A running DHCP client, and the raw socket it exchanges messages over.
+struct Dhcp {
+    client: dhcp::Client,
+    handle: SocketHandle,
+}
+
+pub struct Net {
+    pub iface: EthernetInterface<'static, Device>,
+    pub sockets: SocketSet<'static>,
+    dhcp: Option<Dhcp>,
+    httpd: Option<httpd::Server>,
+    telnetd: Option<telnetd::Server>,
+    netcon: Option<SocketHandle>,
+    gdb: Option<Gdb>,
+}
+
+/// The socket GDB clients connect to.
+struct Gdb {
+    handle: SocketHandle,
+    /// Set while a client is connected.
+    connected: bool,
+    /// Set when a client connects, until the connection is claimed with [Net::take_gdb_client].
+    new_client: bool,
+}
+
+/// The network stack. `None` until [init] is called.
+pub static NET: SpinMutex<Option<Net>> = SpinMutex::new(None);
+
+/// The current time, as seen by the network stack.
+pub fn now() -> Instant {
+    let ticks = xenon_cpu::intrin::mftb() as u64;
+    Instant::from_millis((ticks / (xenon_cpu::time::TIMEBASE_FREQ / 1000)) as i64)
+}
+
+/// Reset the ethernet controller and bring up the network stack with no address assigned.
+///
+/// # Safety
+/// This must only be called once.
+pub unsafe fn init() {
+    let device = Device::new();
+
+    let iface = EthernetInterfaceBuilder::new(device)
+        .ethernet_addr(EthernetAddress(xenon_enet::MAC_ADDRESS))
+        .neighbor_cache(NeighborCache::new(BTreeMap::new()))
+        .ip_addrs(vec![IpCidr::new(Ipv4Address::UNSPECIFIED.into(), 0)])
+        .routes(Routes::new(BTreeMap::new()))
+        .finalize();
+
+    NET.lock(|net| {
+        *net = Some(Net {
+            iface,
+            sockets: SocketSet::new(Vec::new()),
+            dhcp: None,
+            httpd: None,
+            telnetd: None,
+            netcon: None,
+            gdb: None,
+        });
+    });
+}
+
+/// Run a closure with exclusive access to the network stack.
+/// Returns `None` if the stack has not been initialized.
+///
+/// N.B: External interrupts are disabled while the stack is locked, so the debugger never halts a
+/// thread that holds it, and can use the stack itself.
+pub fn with<R>(f: impl FnOnce(&mut Net) -> R) -> Option<R> {
+    without_interrupts(|| NET.lock(|net| net.as_mut().map(f)))
+}
+
+/// Like [with], but returns `None` right away if the stack is locked.
+pub fn try_with<R>(f: impl FnOnce(&mut Net) -> R) -> Option<R> {
+    without_interrupts(|| NET.try_lock(|net| net.as_mut().map(f)).ok().flatten())
+}
+
+fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let msr = mfmsr();
+
+    unsafe {
+        mtmsrl(msr & !bit(48));
+    }
+
+    let r = f();
+
+    unsafe {
+        mtmsrl(msr);
+    }
+
+    r
+}
+
+impl Net {
+    /// Process any pending packets and socket state, keep the DHCP lease (if any) up to date,
+    /// and service web interface and telnet clients.
+    pub fn poll(&mut self) {
+        // N.B: Errors here are per-packet (e.g. malformed frames) and are not fatal.
+        let _ = self.iface.poll(&mut self.sockets, now());
+
+        self.poll_dhcp();
+
+        let addr = self.ipv4_addr();
+        if let Some(httpd) = &mut self.httpd {
+            httpd.poll(&mut self.sockets, addr);
+        }
+
+        if let Some(telnetd) = &mut self.telnetd {
+            telnetd.poll(&mut self.sockets);
+        }
+
+        self.poll_gdb();
+
+        // Send any console output that was produced while we were busy.
+        let _ = netcon::NETCON.try_lock(|con| con.flush_to(self));
+        let _ = telnetd::OUTPUT.try_lock(|out| out.flush_to(self));
+    }
+
+    /// Start serving the web interface, if it isn't already running.
+    pub fn start_httpd(&mut self) {
+        if self.httpd.is_none() {
+            self.httpd = Some(httpd::Server::new(&mut self.sockets));
+        }
+    }
+
+    /// Start serving the terminal over telnet, if it isn't already running.
+    pub fn start_telnetd(&mut self) {
+        if self.telnetd.is_none() {
+            self.telnetd = Some(telnetd::Server::new(&mut self.sockets));
+        }
+    }
+
+    /// Take the next byte of terminal input from the telnet clients, if any.
+    pub fn read_telnet(&mut self) -> Option<u8> {
+        self.telnetd.as_mut().and_then(|telnetd| telnetd.read())
+    }
+
+    /// Send terminal output to the telnet clients, and transmit it right away.
+    pub fn send_telnet(&mut self, data: &[u8]) {
+        if let Some(telnetd) = &mut self.telnetd {
+            telnetd.send(&mut self.sockets, data);
+            let _ = self.iface.poll(&mut self.sockets, now());
+        }
+    }
+
+    /// Start accepting GDB connections, if we aren't already.
+    pub fn start_gdb(&mut self) {
+        if self.gdb.is_none() {
+            let rx = TcpSocketBuffer::new(vec![0u8; GDB_BUFFER_SIZE]);
+            let tx = TcpSocketBuffer::new(vec![0u8; GDB_BUFFER_SIZE]);
+
+            self.gdb = Some(Gdb {
+                handle: self.sockets.add(TcpSocket::new(rx, tx)),
+                connected: false,
+                new_client: false,
+            });
+        }
+    }
+
+    fn poll_gdb(&mut self) {
+        let gdb = match &mut self.gdb {
+            Some(gdb) => gdb,
+            None => return,
+        };
+
+        let mut socket = self.sockets.get::<TcpSocket>(gdb.handle);
+
+        if !socket.is_open() {
+            // The previous client (if any) is gone. Wait for the next one.
+            gdb.connected = false;
+            gdb.new_client = false;
+
+            let _ = socket.listen(GDB_PORT);
+        } else if socket.may_send() && !gdb.connected {
+            gdb.connected = true;
+            gdb.new_client = true;
+        }
+    }
+
+    /// Returns true if a GDB client is connected.
+    pub fn gdb_connected(&mut self) -> bool {
+        self.poll_gdb();
+        matches!(&self.gdb, Some(gdb) if gdb.connected)
+    }
+
+    /// Returns true once for each new GDB client, unless its connection has already been claimed.
+    pub fn take_gdb_client(&mut self) -> bool {
+        self.poll_gdb();
+
+        match &mut self.gdb {
+            Some(gdb) => core::mem::take(&mut gdb.new_client),
+            None => false,
+        }
+    }
+
+    /// Process pending packets, and return the GDB socket if a client is connected.
+    fn gdb_socket(&mut self) -> Result<SocketRef<'_, TcpSocket<'static>>, ()> {
+        let _ = self.iface.poll(&mut self.sockets, now());
+
+        match &self.gdb {
+            Some(gdb) if self.sockets.get::<TcpSocket>(gdb.handle).may_recv() => {
+                Ok(self.sockets.get::<TcpSocket>(gdb.handle))
+            }
+            _ => Err(()),
+        }
+    }
+
+    /// Receive data from the GDB client, returning the number of bytes received.
+    /// Fails if the client has disconnected.
+    pub fn gdb_recv(&mut self, buf: &mut [u8]) -> Result<usize, ()> {
+        self.gdb_socket()?.recv_slice(buf).map_err(|_| ())
+    }
+
+    /// Look at the next byte from the GDB client, without receiving it.
+    pub fn gdb_peek(&mut self) -> Result<Option<u8>, ()> {
+        let mut byte = [0u8];
+
+        match self.gdb_socket()?.peek_slice(&mut byte) {
+            Ok(1) => Ok(Some(byte[0])),
+            Ok(_) => Ok(None),
+            Err(_) => Err(()),
+        }
+    }
+
+    /// Send data to the GDB client, returning the number of bytes queued.
+    pub fn gdb_send(&mut self, data: &[u8]) -> Result<usize, ()> {
+        let n = self.gdb_socket()?.send_slice(data).map_err(|_| ())?;
+
+        let _ = self.iface.poll(&mut self.sockets, now());
+        Ok(n)
+    }
+
+    /// Start acquiring an address over DHCP, dropping any address already assigned.
+    pub fn start_dhcp(&mut self) {
+        let now = now();
+
+        match &mut self.dhcp {
+            Some(dhcp) => {
+                dhcp.client.reset(now);
+            }
+
+            None => {
+                let rx =
+                    RawSocketBuffer::new(vec![RawPacketMetadata::EMPTY; 4], vec![0u8; 4 * MTU]);
+                let tx =
+                    RawSocketBuffer::new(vec![RawPacketMetadata::EMPTY; 4], vec![0u8; 4 * MTU]);
+                let socket = RawSocket::new(IpVersion::Ipv4, IpProtocol::Udp, rx, tx);
+
+                self.dhcp = Some(Dhcp {
+                    // The timebase is as good a source of entropy as any for the transaction ID.
+                    client: dhcp::Client::new(
+                        xenon_enet::MAC_ADDRESS,
+                        xenon_cpu::intrin::mftb() as u32,
+                    ),
+                    handle: self.sockets.add(socket),
+                });
+            }
+        }
+
+        self.set_ipv4(Ipv4Cidr::new(Ipv4Address::UNSPECIFIED, 0), None);
+    }
+
+    /// Stop the DHCP client. The current address (if any) is left assigned.
+    pub fn stop_dhcp(&mut self) {
+        if let Some(dhcp) = self.dhcp.take() {
+            self.sockets.remove(dhcp.handle);
+        }
+    }
+
+    /// The current DHCP lease, if any.
+    pub fn lease(&self) -> Option<&dhcp::Lease> {
+        self.dhcp.as_ref().and_then(|dhcp| dhcp.client.lease())
+    }
+
+    fn poll_dhcp(&mut self) {
+        let now = now();
+        let state = match &mut self.dhcp {
+            Some(state) => state,
+            None => return,
+        };
+
+        let mut socket = self.sockets.get::<RawSocket>(state.handle);
+        let mut changed = false;
+
+        while let Ok(pkt) = socket.recv() {
+            match dhcp::packet::parse_ipv4_udp(pkt) {
+                Ok(datagram) if datagram.dst_port == CLIENT_PORT => {
+                    match state.client.handle(now, datagram.payload) {
+                        dhcp::Step::Configured | dhcp::Step::Deconfigured => changed = true,
+                        _ => {}
+                    }
+                }
+
+                _ => {}
+            }
+        }
+
+        let mut msg = [0u8; dhcp::MAX_MESSAGE];
+        let mut pkt = [0u8; MTU];
+
+        loop {
+            match state.client.poll(now, &mut msg) {
+                Ok(dhcp::Step::Send(t)) => {
+                    let datagram = Datagram {
+                        src: t.src,
+                        dst: t.dst,
+                        src_port: CLIENT_PORT,
+                        dst_port: SERVER_PORT,
+                        payload: &msg[..t.len],
+                    };
+
+                    // If the socket is full, the client will retransmit later.
+                    if let Ok(n) = dhcp::packet::write_ipv4_udp(&mut pkt, &datagram) {
+                        let _ = socket.send_slice(&pkt[..n]);
+                    }
+                }
+
+                Ok(dhcp::Step::Configured | dhcp::Step::Deconfigured) => changed = true,
+                Ok(dhcp::Step::Idle) | Err(_) => break,
+            }
+        }
+
+        drop(socket);
+
+        if changed {
+            let config = state
+                .client
+                .lease()
+                .map(|lease| (lease.address, lease.router));
+
+            match config {
+                Some((address, router)) => self.set_ipv4(address, router),
+                None => self.set_ipv4(Ipv4Cidr::new(Ipv4Address::UNSPECIFIED, 0), None),
+            }
+        }
+    }
+
+    /// Create an unbound UDP socket. The caller must remove it from the socket set when done.
+    pub fn add_udp_socket(&mut self) -> SocketHandle {
+        let rx = UdpSocketBuffer::new(
+            vec![UdpPacketMetadata::EMPTY; UDP_PACKETS],
+            vec![0u8; UDP_PACKETS * 1536],
+        );
+        let tx = UdpSocketBuffer::new(
+            vec![UdpPacketMetadata::EMPTY; UDP_PACKETS],
+            vec![0u8; UDP_PACKETS * 1536],
+        );
+
+        self.sockets.add(UdpSocket::new(rx, tx))
+    }
+
+    /// Send a network console datagram to `target`, and transmit it right away.
+    pub fn send_console(&mut self, target: IpEndpoint, data: &[u8]) -> Result<(), ()> {
+        let handle = match self.netcon {
+            Some(handle) => handle,
+            None => {
+                let handle = self.add_udp_socket();
+
+                // N.B: Binding only fails for port 0.
+                let _ = self.sockets.get::<UdpSocket>(handle).bind(NETCON_PORT);
+
+                *self.netcon.insert(handle)
+            }
+        };
+
+        self.sockets
+            .get::<UdpSocket>(handle)
+            .send_slice(data, target)
+            .map_err(|_| ())?;
+
+        // N.B: The caller may be about to crash, so don't wait for the next poll.
+        let _ = self.iface.poll(&mut self.sockets, now());
+        Ok(())
+    }
+
+    /// The IPv4 address currently assigned to the interface, if any.
+    pub fn ipv4_addr(&self) -> Option<Ipv4Cidr> {
+        self.iface.ip_addrs().iter().find_map(|cidr| match cidr {
+            IpCidr::Ipv4(cidr) if !cidr.address().is_unspecified() => Some(*cidr),
+            _ => None,
+        })
+    }
+
+    /// Assign an IPv4 address to the interface, and optionally a default gateway.
+    pub fn set_ipv4(&mut self, cidr: Ipv4Cidr, gateway: Option<Ipv4Address>) {
+        self.iface.update_ip_addrs(|addrs| {
+            if let Some(addr) = addrs.iter_mut().next() {
+                *addr = IpCidr::Ipv4(cidr);
+            }
+        });
+
+        match gateway {
+            Some(gateway) => {
+                let _ = self.iface.routes_mut().add_default_ipv4_route(gateway);
+            }
+            None => {
+                // The default route is the only one we add, so start over with no routes.
+                *self.iface.routes_mut() = Routes::new(BTreeMap::new());
+            }
+        }
+    }
+}
+
+/// Download a file over TFTP into `buf`, returning the number of bytes received.
+///
+/// The stack is only locked for a poll at a time, so the other services (and the debugger) keep
+/// running during the transfer.
+pub fn tftp_download(
+    server: Ipv4Address,
+    filename: &str,
+    buf: &mut [u8],
+) -> Result<usize, tftp::Error> {
+    let mut sink = tftp::BufferSink::new(buf);
+
+    let (handle, download) = with(|net| {
+        let handle = net.add_udp_socket();
+        let download = tftp::Download::new(
+            &mut net.sockets,
+            handle,
+            (server, tftp::SERVER_PORT).into(),
+            filename,
+            &tftp::Config::default(),
+            &mut sink,
+        );
+
+        (handle, download)
+    })
+    .ok_or(tftp::Error::Network(smoltcp::Error::Illegal))?;
+
+    let res = download.and_then(|mut download| loop {
+        let res = try_with(|net| {
+            net.poll();
+            download.poll(&mut net.sockets, now())
+        });
+
+        match res {
+            Some(Ok(Some(n))) => break Ok(n),
+            Some(Err(e)) => break Err(e),
+            Some(Ok(None)) | None => {}
+        }
+    });
+
+    with(|net| {
+        // Flush out any final ACK or error before removing the socket.
+        net.poll();
+        net.sockets.remove(handle);
+    });
+
+    res
+}
+
+pub static COMMANDS: [CommandRef<Terminal>; 3] = [&IP, &DHCP, &TFTP];
+
+static IP: Command = Command {
+    name: "ip",
+    usage: "[<address>/<prefix> [gateway]]",
+    help: "Show or set the IPv4 address",
+    handler: ip,
+};
+
+fn ip(term: &mut Terminal, args: &mut Args) -> Result<(), Error> {
+    let cidr = match args.next() {
+        Some(cidr) => Ipv4Cidr::from_str(cidr).map_err(|_| Error::Usage)?,
+        None => {
+            match with(|net| net.ipv4_addr()).flatten() {
+                Some(cidr) => writeln!(term, "{}", cidr).unwrap(),
+                None => writeln!(term, "no address assigned").unwrap(),
+            }
+
+            return Ok(());
+        }
+    };
+
+    let gateway = args.opt_parse::<Ipv4Address>("gateway")?;
+
+    with(|net| {
+        net.stop_dhcp();
+        net.set_ipv4(cidr, gateway);
+    });
+
+    Ok(())
+}
+
+static DHCP: Command = Command {
+    name: "dhcp",
+    usage: "",
+    help: "Acquire an address with DHCP",
+    handler: dhcp,
+};
+
+fn dhcp(term: &mut Terminal, _args: &mut Args) -> Result<(), Error> {
+    if with(|net| net.start_dhcp()).is_none() {
+        writeln!(term, "network not initialized").unwrap();
+        return Ok(());
+    }
+
+    // Wait for a lease. The client keeps trying in the background if this times out.
+    let deadline = now() + smoltcp::time::Duration::from_secs(10);
+    let lease = loop {
+        let lease = with(|net| {
+            net.poll();
+            net.lease().cloned()
+        })
+        .flatten();
+
+        if lease.is_some() || now() >= deadline {
+            break lease;
+        }
+    };
+
+    match lease {
+        Some(lease) => print_lease(term, &lease),
+        None => writeln!(term, "no lease acquired yet").unwrap(),
+    }
+
+    Ok(())
+}
+
+fn print_lease(term: &mut Terminal, lease: &dhcp::Lease) {
+    writeln!(term, "Address:     {}", lease.address).unwrap();
+
+    if let Some(router) = lease.router {
+        writeln!(term, "Router:      {}", router).unwrap();
+    }
+
+    for dns in lease.dns_servers.iter() {
+        writeln!(term, "DNS:         {}", dns).unwrap();
+    }
+
+    writeln!(term, "DHCP server: {}", lease.server).unwrap();
+
+    if let Some(server) = lease.next_server {
+        writeln!(term, "Next server: {}", server).unwrap();
+    }
+
+    if let Some(name) = &lease.tftp_server_name {
+        writeln!(term, "TFTP server: {}", name).unwrap();
+    }
+
+    if let Some(file) = &lease.boot_file {
+        writeln!(term, "Boot file:   {}", file).unwrap();
+    }
+
+    writeln!(term, "Lease time:  {}s", lease.lease_time.secs()).unwrap();
+}
+
+static TFTP: Command = Command {
+    name: "tftp",
+    usage: "[server] <file> <address> <maxlen>",
+    help: "Download a file over TFTP",
+    handler: tftp,
+};
+
+fn tftp(term: &mut Terminal, args: &mut Args) -> Result<(), Error> {
+    // The server may be omitted if DHCP provided one.
+    let server = if args.clone().count() == 4 {
+        args.parse::<Ipv4Address>("server")?
+    } else {
+        with(|net| net.lease().and_then(|l| l.tftp_server()))
+            .flatten()
+            .ok_or(Error::Usage)?
+    };
+
+    let file = args.arg()?;
+    let addr = args.num("address")?;
+    let len = args.num("length")? as usize;
+
+    let buf = unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, len) };
+    match tftp_download(server, file, buf) {
+        Ok(n) => writeln!(term, "Received {:X} bytes", n).unwrap(),
+        Err(e) => writeln!(term, "TFTP failed: {}", e).unwrap(),
+    }
+
+    Ok(())
+}
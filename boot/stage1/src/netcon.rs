@@ -0,0 +1,149 @@
+//! This module implements a network console, which mirrors console output to a UDP host.
+//!
+//! The output is plain text, one or more lines per datagram, so it can be received with
+//! `nc -u -l 6666` or any Linux netconsole listener.
+//!
+//! Console output may be produced anywhere, including from exception handlers and while the
+//! network stack is locked. Neither the console nor the network stack is ever waited on here:
+//! text that can't be sent immediately stays buffered until the next attempt, and is dropped if
+//! the buffer fills up in the meantime.
+
+use core::{
+    fmt::{self, Write},
+    str::FromStr,
+};
+use smoltcp::wire::{IpEndpoint, Ipv4Address};
+use sync::mutex::SpinMutex;
+
+use crate::{
+    net::{self, Net},
+    terminal::{Args, Command, CommandRef, Error, Terminal},
+};
+
+/// The port netconsole listeners conventionally use.
+pub const DEFAULT_PORT: u16 = 6666;
+
+/// The amount of text buffered before it is sent.
+const BUFFER_SIZE: usize = 1024;
+
+pub struct NetConsole {
+    target: Option<IpEndpoint>,
+    buf: [u8; BUFFER_SIZE],
+    len: usize,
+}
+
+/// The network console.
+pub static NETCON: SpinMutex<NetConsole> = SpinMutex::new(NetConsole {
+    target: None,
+    buf: [0; BUFFER_SIZE],
+    len: 0,
+});
+
+/// Mirror text to the network console, if it is available.
+pub fn write(data: &[u8]) {
+    let _ = NETCON.try_lock(|con| con.write(data));
+}
+
+pub static COMMANDS: [CommandRef<Terminal>; 1] = [&NETCON_CMD];
+
+static NETCON_CMD: Command = Command {
+    name: "netcon",
+    usage: "[off | <address>[:<port>]]",
+    help: "Show or set where console output is mirrored",
+    handler: netcon,
+};
+
+fn netcon(term: &mut Terminal, args: &mut Args) -> Result<(), Error> {
+    let target = match args.next() {
+        Some("off") => None,
+        Some(arg) => {
+            let (addr, port) = match arg.split_once(':') {
+                Some((addr, port)) => (addr, port.parse::<u16>().ok()),
+                None => (arg, Some(DEFAULT_PORT)),
+            };
+
+            let addr = Ipv4Address::from_str(addr).map_err(|_| Error::Invalid("address"))?;
+            let port = port.ok_or(Error::Invalid("port"))?;
+
+            Some((addr, port).into())
+        }
+        None => {
+            match NETCON.try_lock(|con| con.target()) {
+                Ok(Some(target)) => writeln!(term, "mirroring output to {}", target).unwrap(),
+                _ => writeln!(term, "network console disabled").unwrap(),
+            }
+
+            return Ok(());
+        }
+    };
+
+    NETCON.lock(|con| con.set_target(target));
+    Ok(())
+}
+
+impl NetConsole {
+    /// The host output is mirrored to, if any.
+    pub fn target(&self) -> Option<IpEndpoint> {
+        self.target
+    }
+
+    /// Start mirroring output to `target`, or stop if it is `None`.
+    pub fn set_target(&mut self, target: Option<IpEndpoint>) {
+        self.target = target;
+        self.len = 0;
+    }
+
+    /// Attempt to send any buffered text.
+    pub fn flush(&mut self) {
+        if self.len == 0 {
+            return;
+        }
+
+        // If the network stack is busy (or belongs to our caller), try again later.
+        net::try_with(|net| self.flush_to(net));
+    }
+
+    /// Attempt to send any buffered text, with the network stack already locked.
+    pub fn flush_to(&mut self, net: &mut Net) {
+        let target = match self.target {
+            Some(target) => target,
+            None => return,
+        };
+
+        if self.len != 0 && net.send_console(target, &self.buf[..self.len]).is_ok() {
+            self.len = 0;
+        }
+    }
+
+    /// Buffer text, sending it once a line is complete.
+    pub fn write(&mut self, data: &[u8]) {
+        if self.target.is_none() {
+            return;
+        }
+
+        for &b in data {
+            if self.len == self.buf.len() {
+                self.flush();
+
+                if self.len == self.buf.len() {
+                    // Still no way out. Make room for the new text.
+                    self.len = 0;
+                }
+            }
+
+            self.buf[self.len] = b;
+            self.len += 1;
+        }
+
+        if data.contains(&b'\n') {
+            self.flush();
+        }
+    }
+}
+
+impl Write for NetConsole {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write(s.as_bytes());
+        Ok(())
+    }
+}
@@ -1,20 +1,199 @@
-use core::{ffi::c_void, panic::PanicInfo};
+//! This module implements the Rust panic handler.
+//!
+//! A panic prints its message, location, thread and timebase along with a backtrace, and stops
+//! the other threads with an IPI. What happens next is set by the [Policy], which can be changed
+//! from the terminal with `panic`. Every panic is also kept in the crash log.
+//!
+//! A panic in an exception handler also reports the exception being handled.
+//!
+//! A thread that panics while handling a panic only prints a short notice and stops, and a
+//! thread that panics while another one is handling a panic stops quietly. Before the terminal
+//! is entered after a panic, the other threads are resumed and the panic state is cleared, so
+//! the next panic is handled in full.
 
-use xenon_soc::uart;
+use atomic::Atomic;
+use core::{
+    ffi::c_void,
+    fmt::Write,
+    panic::PanicInfo,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+    time::Duration,
+};
+
+use xenon_cpu::time::TIMEBASE_FREQ;
+use xenon_soc::{smc, uart};
+
+use crate::{
+    backtrace::{self, Frames},
+    terminal::{Args, Command, CommandRef, Error, Terminal},
+};
+
+/// What to do once a panic has been reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    /// Stop the thread forever.
+    Spin,
+    /// Run the terminal on the panicking thread.
+    Terminal,
+    /// Restart the system after this many seconds.
+    Reboot(u32),
+    /// Stop for the debugger.
+    Gdb,
+}
+
+const NO_THREAD: u64 = u64::MAX;
+
+static POLICY: Atomic<Policy> = Atomic::new(Policy::Spin);
+
+/// The thread handling a panic, or [NO_THREAD].
+static PANICKING: AtomicU64 = AtomicU64::new(NO_THREAD);
+
+/// The threads (bit N = PIR N) that have entered the panic handler.
+static ENTERED: AtomicU32 = AtomicU32::new(0);
 
 #[lang = "eh_personality"]
 extern "C" fn rust_eh_personality() {}
 
+/// Set what happens after a panic.
+pub fn set_policy(policy: Policy) {
+    POLICY.store(policy, Ordering::Relaxed);
+}
+
+pub fn policy() -> Policy {
+    POLICY.load(Ordering::Relaxed)
+}
+
+/// Stop the current thread forever.
+fn halt() -> ! {
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+/// Run `f` on the UART, taking it by force if it can't be locked (e.g. we panicked with it
+/// locked).
+fn with_uart(f: impl Fn(&mut dyn Write)) {
+    for _ in 0..10 {
+        if uart::UART.try_lock(|uart| f(uart)).is_ok() {
+            return;
+        }
+
+        xenon_cpu::time::delay(Duration::from_millis(10));
+    }
+
+    f(unsafe { uart::UART.get_mut_unchecked() });
+}
+
+/// Write to the UART and the network console.
+fn report(f: impl Fn(&mut dyn Write)) {
+    with_uart(&f);
+
+    let _ = crate::netcon::NETCON.try_lock(|con| {
+        f(con);
+        con.flush();
+    });
+}
+
 /// This is the global Rust panic handler.
-/// Currently it does nothing, though eventually it should print a backtrace.
 #[panic_handler]
 #[no_mangle]
-pub fn panic(_info: &PanicInfo) -> ! {
-    let uart = unsafe { uart::UART.get_mut_unchecked() };
+pub fn panic(info: &PanicInfo) -> ! {
+    let pir = xenon_cpu::intrin::pir();
 
-    uart.write(b"RUST PANIC!\r\n");
+    if ENTERED.fetch_or(1 << pir, Ordering::AcqRel) & (1 << pir) != 0 {
+        // Avoid anything that could panic again.
+        let uart = unsafe { uart::UART.get_mut_unchecked() };
+        uart.write(b"\r\nRECURSIVE PANIC! Thread stopped.\r\n");
+        halt();
+    }
 
-    loop {}
+    if PANICKING
+        .compare_exchange(NO_THREAD, pir, Ordering::AcqRel, Ordering::Acquire)
+        .is_err()
+    {
+        // Another thread is handling a panic, and will stop us.
+        halt();
+    }
+
+    let tb = xenon_cpu::intrin::mftb();
+    let frames = Frames::current();
+    let fault = crate::except::handling();
+    let policy = policy();
+
+    // The debugger halts the other threads itself. Otherwise, they are never resumed.
+    if policy != Policy::Gdb {
+        crate::smp::stop_others();
+    }
+
+    report(|out| {
+        let _ = writeln!(out, "RUST PANIC on thread {}!", pir);
+        if let Some(msg) = info.message() {
+            let _ = writeln!(out, "    {}", msg);
+        }
+        if let Some(loc) = info.location() {
+            let _ = writeln!(out, "    at {}:{}:{}", loc.file(), loc.line(), loc.column());
+        }
+        if let Some(diagnosis) = fault.and_then(|f| f.diagnose()) {
+            let _ = writeln!(out, "    while handling an exception: {}", diagnosis);
+        }
+        let _ = writeln!(
+            out,
+            "TB:    {:016X} ({} ms)",
+            tb,
+            tb * 1000 / TIMEBASE_FREQ as u128
+        );
+        let _ = writeln!(out, "---- Backtrace:");
+        backtrace::print(out, frames.clone());
+    });
+
+    let mut message = crashlog::Message::new();
+    if let Some(msg) = info.message() {
+        let _ = write!(message, "{}", msg);
+    }
+    if let Some(loc) = info.location() {
+        let _ = write!(
+            message,
+            " at {}:{}:{}",
+            loc.file(),
+            loc.line(),
+            loc.column()
+        );
+    }
+    crate::crashlog::record_panic(message.as_str().trim_start(), frames, fault);
+
+    match policy {
+        Policy::Spin => {}
+        Policy::Terminal => {
+            // N.B: This hangs if we panicked with the console or the network stack locked.
+            report(|out| {
+                let _ = writeln!(out, "Entering the terminal. Type `reboot` to restart.");
+            });
+
+            // The terminal needs the other threads (e.g. to boot a kernel or debug them), and a
+            // later panic should be reported in full.
+            crate::smp::resume_others();
+            ENTERED.fetch_and(!(1 << pir), Ordering::AcqRel);
+            PANICKING.store(NO_THREAD, Ordering::Release);
+
+            crate::terminal::run();
+        }
+        Policy::Reboot(seconds) => {
+            for left in (1..=seconds).rev() {
+                report(|out| {
+                    let _ = writeln!(out, "Rebooting in {}...", left);
+                });
+
+                xenon_cpu::time::delay(Duration::from_secs(1));
+            }
+
+            if smc::SMC.try_lock(|smc| smc.restart_system()).is_err() {
+                unsafe { smc::SMC.get_mut_unchecked() }.restart_system();
+            }
+        }
+        Policy::Gdb => crate::gdb::breakpoint(),
+    }
+
+    halt();
 }
 
 #[allow(non_snake_case)]
@@ -22,3 +201,35 @@ pub fn panic(_info: &PanicInfo) -> ! {
 pub extern "C" fn _Unwind_Resume(_exception: *mut c_void) -> ! {
     loop {}
 }
+
+pub static COMMANDS: [CommandRef<Terminal>; 1] = [&PANIC];
+
+static PANIC: Command = Command {
+    name: "panic",
+    usage: "[spin|terminal|reboot [seconds]|gdb]",
+    help: "Show or set what happens after a panic",
+    handler: panic_policy,
+};
+
+/// The countdown before a reboot, if none is given.
+const REBOOT_SECONDS: u32 = 10;
+
+fn panic_policy(term: &mut Terminal, args: &mut Args) -> Result<(), Error> {
+    let policy = match args.next() {
+        None => {
+            writeln!(term, "{:?}", policy()).unwrap();
+            return Ok(());
+        }
+        Some("spin") => Policy::Spin,
+        Some("terminal") => Policy::Terminal,
+        Some("reboot") => {
+            let seconds = args.opt_parse("seconds")?;
+            Policy::Reboot(seconds.unwrap_or(REBOOT_SECONDS))
+        }
+        Some("gdb") => Policy::Gdb,
+        Some(_) => return Err(Error::Invalid("policy")),
+    };
+
+    set_policy(policy);
+    Ok(())
+}
@@ -0,0 +1,264 @@
+//! This module implements the protocol used to hand secondary threads to a loaded kernel.
+//!
+//! Before a kernel is started, every secondary thread that checked in during startup
+//! (see `PROCESSORS`) is parked in a holding loop, spinning on its own entry of a spin
+//! table in the handoff area. The entry layout follows the ePAPR spin table:
+//!
+//! | Offset | Size | Field        |
+//! |--------|------|--------------|
+//! | 0x00   | 8    | `entry_addr` |
+//! | 0x08   | 8    | `r3`         |
+//! | 0x10   | 4    | reserved     |
+//! | 0x14   | 4    | `pir`        |
+//!
+//! Entries are indexed by PIR and spaced one cache line apart, starting at
+//! [memmap::SPIN_TABLE_BASE]. `entry_addr` reads as [SPIN_TABLE_HOLD] while the
+//! thread is held. To release a thread, the kernel writes `r3` and then the physical
+//! address to branch to into `entry_addr`. The thread branches there in 64-bit
+//! hypervisor real mode with MSR[EE] clear, `r3` loaded from the table and `r4`
+//! holding its PIR. No stack is provided.
+//!
+//! The physical address of each parked thread's entry is published in the device tree
+//! (see [crate::devtree]).
+//!
+//! Separately, a thread can stop all the others in their IPI handler with [stop_others], e.g. to
+//! examine them after a crash, and let them carry on with [resume_others].
+
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+
+use xenon_cpu::time::TIMEBASE_FREQ;
+use xenon_soc::iic::{Iic, Interrupt};
+
+use crate::{except, loader, memmap};
+
+/// The value of `entry_addr` while a thread is being held.
+pub const SPIN_TABLE_HOLD: u64 = 1;
+
+/// Internal value of `entry_addr` that returns a parked thread to the bootloader.
+const SPIN_TABLE_UNPARK: u64 = u64::MAX;
+
+/// The distance between two spin table entries.
+const SPIN_TABLE_STRIDE: u64 = xenon_cpu::intrin::CACHE_LINE_SIZE as u64;
+
+#[repr(C)]
+struct SpinTableEntry {
+    entry_addr: u64,
+    r3: u64,
+    rsvd1: u32,
+    pir: u32,
+}
+
+/// Set when idle secondary threads should park themselves.
+static PARK_REQUEST: AtomicBool = AtomicBool::new(false);
+
+/// Bitmap of threads currently spinning in the holding loop.
+static PARKED: AtomicU32 = AtomicU32::new(0);
+
+const NO_THREAD: u64 = u64::MAX;
+
+/// How long to wait for the other threads to respond to the stop IPI.
+const STOP_TIMEOUT_MS: u64 = 100;
+
+/// How long to wait for secondary threads to enter or leave the holding loop.
+const PARK_TIMEOUT_MS: u64 = 100;
+
+/// The thread that stopped the others with [stop_others], or [NO_THREAD].
+static STOPPER: AtomicU64 = AtomicU64::new(NO_THREAD);
+
+/// Bitmap of threads stopped in [on_ipi].
+static STOPPED: AtomicU32 = AtomicU32::new(0);
+
+/// The physical address of the spin table entry for a thread.
+pub const fn release_addr(pir: u64) -> u64 {
+    memmap::SPIN_TABLE_BASE + (pir * SPIN_TABLE_STRIDE)
+}
+
+fn spin_entry(pir: u64) -> *mut SpinTableEntry {
+    memmap::real(release_addr(pir)) as *mut SpinTableEntry
+}
+
+/// Retrieve the bitmap of threads currently parked in the holding loop.
+pub fn parked() -> u32 {
+    PARKED.load(Ordering::Acquire)
+}
+
+/// Returns true if idle secondary threads have been asked to park.
+pub fn park_requested() -> bool {
+    PARK_REQUEST.load(Ordering::Acquire)
+}
+
+/// Park the current thread in the holding loop until released.
+/// This returns only if the bootloader cancels the handoff with [unpark_secondaries].
+pub fn park() {
+    let pir = xenon_cpu::intrin::pir();
+    let entry = spin_entry(pir);
+
+    // Interrupts will be delivered to the kernel's vectors once it is loaded.
+    let msr = xenon_cpu::intrin::mfmsr();
+    unsafe {
+        xenon_cpu::intrin::mtmsrl(0);
+
+        core::ptr::write_volatile(&mut (*entry).r3, 0);
+        core::ptr::write_volatile(&mut (*entry).rsvd1, 0);
+        core::ptr::write_volatile(&mut (*entry).pir, pir as u32);
+        core::ptr::write_volatile(&mut (*entry).entry_addr, SPIN_TABLE_HOLD);
+    }
+
+    PARKED.fetch_or(1 << pir, Ordering::AcqRel);
+
+    let addr = loop {
+        let addr = unsafe { core::ptr::read_volatile(&(*entry).entry_addr) };
+        if addr != SPIN_TABLE_HOLD {
+            break addr;
+        }
+
+        // Drop our thread priority while spinning.
+        unsafe {
+            asm!("or %r1, %r1, %r1", "or %r2, %r2, %r2");
+        }
+    };
+
+    if addr == SPIN_TABLE_UNPARK {
+        PARKED.fetch_and(!(1 << pir), Ordering::AcqRel);
+
+        unsafe {
+            xenon_cpu::intrin::mtmsrl(msr);
+        }
+
+        return;
+    }
+
+    let mut ctx = except::CpuContext::new();
+    ctx.r[3] = unsafe { core::ptr::read_volatile(&(*entry).r3) };
+    ctx.r[4] = pir;
+    ctx.pc = memmap::real(addr);
+    ctx.msr = loader::ENTRY_MSR;
+
+    unsafe {
+        except::load_context(&ctx);
+    }
+}
+
+/// Ask all idle secondary threads to park, and wait (for a while) until every thread that
+/// checked in during startup has done so. Returns the bitmap of parked threads.
+///
+/// If some threads don't park in time, this fails with the bitmap of the missing threads. The
+/// others must then be returned to the bootloader with [unpark_secondaries].
+pub fn park_secondaries(processors: u32) -> Result<u32, u32> {
+    let pir = xenon_cpu::intrin::pir();
+    let secondaries = processors & !(1 << pir);
+
+    PARK_REQUEST.store(true, Ordering::Release);
+
+    let timeout = (TIMEBASE_FREQ * PARK_TIMEOUT_MS / 1000) as u128;
+    let deadline = xenon_cpu::intrin::mftb() + timeout;
+
+    while parked() & secondaries != secondaries && xenon_cpu::intrin::mftb() < deadline {
+        core::hint::spin_loop();
+    }
+
+    let missing = secondaries & !parked();
+    if missing != 0 {
+        return Err(missing);
+    }
+
+    Ok(parked())
+}
+
+/// Return all parked threads to the bootloader, e.g. after a failed kernel load, and wait (for a
+/// while) until they have left the holding loop.
+///
+/// Fails with the bitmap of the threads still in the holding loop, if any.
+pub fn unpark_secondaries() -> Result<(), u32> {
+    PARK_REQUEST.store(false, Ordering::Release);
+
+    let parked = parked();
+    for pir in 0..6u64 {
+        if parked & (1 << pir) != 0 {
+            unsafe {
+                core::ptr::write_volatile(&mut (*spin_entry(pir)).entry_addr, SPIN_TABLE_UNPARK);
+            }
+        }
+    }
+
+    let timeout = (TIMEBASE_FREQ * PARK_TIMEOUT_MS / 1000) as u128;
+    let deadline = xenon_cpu::intrin::mftb() + timeout;
+
+    while self::parked() != 0 && xenon_cpu::intrin::mftb() < deadline {
+        core::hint::spin_loop();
+    }
+
+    match self::parked() {
+        0 => Ok(()),
+        stuck => Err(stuck),
+    }
+}
+
+/// Stop every other thread with an IPI, and wait (for a while) until they have. A stopped
+/// thread's state can be read from its exception save area until [resume_others] is called.
+///
+/// Returns the bitmap of threads that stopped, or `None` if another thread has already stopped
+/// the others.
+pub fn stop_others() -> Option<u32> {
+    let pir = xenon_cpu::intrin::pir();
+
+    match STOPPER.compare_exchange(NO_THREAD, pir, Ordering::AcqRel, Ordering::Acquire) {
+        Ok(_) => {}
+        Err(stopper) if stopper == pir => return Some(STOPPED.load(Ordering::Acquire)),
+        Err(_) => return None,
+    }
+
+    let others = crate::PROCESSORS.load(Ordering::Relaxed) & 0x3F & !(1 << pir);
+    if others == 0 {
+        return Some(0);
+    }
+
+    Iic::local().send_ipi(others as u8, Interrupt::Ipi1);
+
+    let timeout = (TIMEBASE_FREQ * STOP_TIMEOUT_MS / 1000) as u128;
+    let deadline = xenon_cpu::intrin::mftb() + timeout;
+
+    while STOPPED.load(Ordering::Acquire) & others != others && xenon_cpu::intrin::mftb() < deadline
+    {
+        core::hint::spin_loop();
+    }
+
+    Some(STOPPED.load(Ordering::Acquire) & others)
+}
+
+/// Let the threads stopped by [stop_others] carry on, and wait until they have.
+pub fn resume_others() {
+    let pir = xenon_cpu::intrin::pir();
+
+    if STOPPER
+        .compare_exchange(pir, NO_THREAD, Ordering::AcqRel, Ordering::Relaxed)
+        .is_err()
+    {
+        return;
+    }
+
+    while STOPPED.load(Ordering::Acquire) != 0 {
+        core::hint::spin_loop();
+    }
+}
+
+/// Handle a stop IPI ([Interrupt::Ipi1]) on the current thread.
+/// This does not return while another thread has the others stopped.
+pub fn on_ipi(_ctx: &mut except::CpuContext) {
+    let pir = xenon_cpu::intrin::pir();
+    let stopper = STOPPER.load(Ordering::Acquire);
+
+    // The debugger uses the same IPI.
+    if stopper == NO_THREAD || stopper == pir {
+        return;
+    }
+
+    // N.B: Our state has already been saved to the exception save area.
+    STOPPED.fetch_or(1 << pir, Ordering::AcqRel);
+
+    while STOPPER.load(Ordering::Acquire) != NO_THREAD {
+        core::hint::spin_loop();
+    }
+
+    STOPPED.fetch_and(!(1 << pir), Ordering::AcqRel);
+}
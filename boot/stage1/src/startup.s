@@ -227,8 +227,10 @@ init_regs:
 // R11 = clobber
 // CTR = clobber
 init_bss:
-	ld		%r10, __bss_start@toc(%r2)
-	ld		%r11, __bss_end@toc(%r2)
+	addis	%r10, %r2, __bss_start@toc@ha
+	addi	%r10, %r10, __bss_start@toc@l
+	addis	%r11, %r2, __bss_end@toc@ha
+	addi	%r11, %r11, __bss_end@toc@l
 	sub		%r11, %r11, %r10 // r11 = (end - start)
 	srdi	%r11, %r11, 2    // r11 /= 4
 	subi	%r10, %r10, 4
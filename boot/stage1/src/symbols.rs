@@ -0,0 +1,64 @@
+//! This module resolves addresses to the names of stage1's functions.
+//!
+//! The table lives in space reserved in the `.symbols` section, which is empty until
+//! `tools/mksymtab` patches it into the linked ELF. Without it, nothing resolves.
+
+use core::fmt::Write;
+use symtab::{Location, Table};
+
+use crate::{
+    memmap,
+    terminal::{Args, Command, CommandRef, Error, Terminal},
+};
+
+/// The space reserved for the table.
+const SYMBOLS_SIZE: usize = 0x4_0000;
+
+#[used]
+#[link_section = ".symbols"]
+static SYMBOLS: [u8; SYMBOLS_SIZE] = symtab::placeholder();
+
+/// The embedded symbol table, if it's intact.
+pub fn table() -> Option<Table<'static>> {
+    // N.B: The table is patched after linking, so hide its contents from the optimizer.
+    let mut ptr = SYMBOLS.as_ptr();
+    unsafe {
+        asm!("/* {} */", inout(reg) ptr, options(nostack, preserves_flags));
+    }
+
+    let data = unsafe { core::slice::from_raw_parts(ptr, SYMBOLS_SIZE) };
+    Table::parse(data).ok()
+}
+
+/// Resolve an address (real mode or not) to the function containing it.
+pub fn lookup(addr: u64) -> Option<Location<'static>> {
+    table()?.lookup(memmap::real(addr & !memmap::REAL_MODE_BASE))
+}
+
+/// The address of the function called `name`.
+pub fn find(name: &str) -> Option<u64> {
+    table()?.find(name)
+}
+
+pub static COMMANDS: [CommandRef<Terminal>; 1] = [&SYM];
+
+static SYM: Command = Command {
+    name: "sym",
+    usage: "<address>",
+    help: "Show the function containing an address",
+    handler: sym,
+};
+
+fn sym(term: &mut Terminal, args: &mut Args) -> Result<(), Error> {
+    let addr = args.num("address")?;
+
+    match table() {
+        Some(table) if !table.is_empty() => match lookup(addr) {
+            Some(loc) => writeln!(term, "{:016X}  {}", addr, loc).unwrap(),
+            None => writeln!(term, "{:016X}  ?", addr).unwrap(),
+        },
+        _ => writeln!(term, "No symbol table embedded").unwrap(),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,221 @@
+//! This module implements the telnet server, which gives network clients the serial terminal.
+//!
+//! Telnet clients share the terminal with the UART: console output is sent to every client, and
+//! input from any of them is read by the terminal as if it had been typed on the UART.
+//!
+//! ```text
+//! telnet <address>
+//! ```
+//!
+//! Like the network console, output may be produced anywhere, so it is buffered here and only
+//! sent when the network stack is available.
+
+use alloc::{collections::VecDeque, vec, vec::Vec};
+use core::sync::atomic::{AtomicBool, Ordering};
+use smoltcp::socket::{SocketHandle, SocketSet, TcpSocket, TcpSocketBuffer};
+use sync::mutex::SpinMutex;
+use telnet::{Decoder, Encoder, Event};
+
+use crate::net::{self, Net};
+
+pub const PORT: u16 = 23;
+
+/// The number of clients that may be connected at once.
+const CLIENTS: usize = 4;
+
+const BUFFER_SIZE: usize = 4096;
+
+/// The amount of input buffered for the terminal. Input beyond this is dropped.
+const INPUT_SIZE: usize = 256;
+
+/// Set while any client is connected.
+static CONNECTED: AtomicBool = AtomicBool::new(false);
+
+pub struct Output {
+    buf: [u8; BUFFER_SIZE],
+    len: usize,
+}
+
+/// Console output waiting to be sent.
+pub static OUTPUT: SpinMutex<Output> = SpinMutex::new(Output {
+    buf: [0; BUFFER_SIZE],
+    len: 0,
+});
+
+/// Send console output to the connected clients, if any.
+pub fn write(data: &[u8]) {
+    if !CONNECTED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let _ = OUTPUT.try_lock(|out| {
+        for &b in data {
+            if out.len == out.buf.len() {
+                out.flush();
+
+                if out.len == out.buf.len() {
+                    // Nobody is draining the buffer. Drop the oldest output.
+                    out.len = 0;
+                }
+            }
+
+            out.buf[out.len] = b;
+            out.len += 1;
+        }
+
+        out.flush();
+    });
+}
+
+impl Output {
+    /// Attempt to send any buffered output.
+    fn flush(&mut self) {
+        if self.len != 0 {
+            net::try_with(|net| self.flush_to(net));
+        }
+    }
+
+    /// Attempt to send any buffered output, with the network stack already locked.
+    pub fn flush_to(&mut self, net: &mut Net) {
+        if self.len != 0 {
+            net.send_telnet(&self.buf[..self.len]);
+            self.len = 0;
+        }
+    }
+}
+
+struct Client {
+    handle: SocketHandle,
+    decoder: Decoder,
+    encoder: Encoder,
+    /// Set once the client has been sent the greeting.
+    greeted: bool,
+}
+
+pub struct Server {
+    clients: Vec<Client>,
+    /// Input received from all clients, waiting to be read by the terminal.
+    input: VecDeque<u8>,
+}
+
+impl Server {
+    /// Create the server's sockets. These are allocated once and reused for every client.
+    pub fn new(sockets: &mut SocketSet<'static>) -> Self {
+        let clients = (0..CLIENTS)
+            .map(|_| {
+                let rx = TcpSocketBuffer::new(vec![0u8; BUFFER_SIZE]);
+                let tx = TcpSocketBuffer::new(vec![0u8; BUFFER_SIZE]);
+
+                Client {
+                    handle: sockets.add(TcpSocket::new(rx, tx)),
+                    decoder: Decoder::new(),
+                    encoder: Encoder::new(),
+                    greeted: false,
+                }
+            })
+            .collect();
+
+        Self {
+            clients,
+            input: VecDeque::with_capacity(INPUT_SIZE),
+        }
+    }
+
+    /// Accept clients, and receive their input.
+    pub fn poll(&mut self, sockets: &mut SocketSet<'static>) {
+        let mut connected = false;
+
+        for client in self.clients.iter_mut() {
+            let mut socket = sockets.get::<TcpSocket>(client.handle);
+
+            if !socket.is_open() {
+                // The previous client (if any) is gone. Wait for the next one.
+                client.decoder = Decoder::new();
+                client.encoder = Encoder::new();
+                client.greeted = false;
+
+                let _ = socket.listen(PORT);
+                continue;
+            }
+
+            if !socket.may_send() {
+                // Still connecting, or closing.
+                continue;
+            }
+
+            connected = true;
+
+            if !client.greeted {
+                client.greeted = true;
+
+                let _ = socket.send_slice(&telnet::GREETING);
+                let _ = socket.send_slice(b"Connected to the xell-rs terminal.\r\n");
+            }
+
+            // Replies to option negotiation are sent once the input has been read.
+            let decoder = &mut client.decoder;
+            let input = &mut self.input;
+            let mut replies = Vec::new();
+
+            while socket.can_recv() {
+                let res = socket.recv(|data| {
+                    for &b in data.iter() {
+                        match decoder.feed(b) {
+                            Event::Data(b) if input.len() < INPUT_SIZE => input.push_back(b),
+                            Event::Data(_) | Event::None => {}
+                            Event::Reply(reply) => replies.extend_from_slice(&reply),
+                        }
+                    }
+
+                    (data.len(), ())
+                });
+
+                if res.is_err() {
+                    break;
+                }
+            }
+
+            if !replies.is_empty() {
+                let _ = socket.send_slice(&replies);
+            }
+        }
+
+        CONNECTED.store(connected, Ordering::Relaxed);
+    }
+
+    /// Take the next byte of terminal input, if any.
+    pub fn read(&mut self) -> Option<u8> {
+        self.input.pop_front()
+    }
+
+    /// Send console output to every connected client.
+    /// Output that doesn't fit in a client's transmit buffer is dropped.
+    pub fn send(&mut self, sockets: &mut SocketSet<'static>, data: &[u8]) {
+        for client in self.clients.iter_mut().filter(|c| c.greeted) {
+            let mut socket = sockets.get::<TcpSocket>(client.handle);
+
+            if !socket.may_send() {
+                continue;
+            }
+
+            let encoder = &mut client.encoder;
+            let _ = socket.send(|buf| {
+                let mut n = 0;
+
+                for &b in data {
+                    // Leave room for the largest encoding of each byte.
+                    if n + 2 > buf.len() {
+                        break;
+                    }
+
+                    encoder.encode(b, |b| {
+                        buf[n] = b;
+                        n += 1;
+                    });
+                }
+
+                (n, ())
+            });
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! This module implements the serial terminal's command registry.
+//!
+//! Lines are typed into a [shell::Editor], with history, and Tab completes command names.
+//!
+//! Numeric arguments are [expressions](shell::expr), e.g. `md $lr-0x40 0x80`. Their variables
+//! are the registers saved by the last exception taken on this thread (`$r0`-`$r31`, `$sp`,
+//! `$cr`, `$lr`, `$ctr`, `$pc` and `$msr`), then the variables defined with `set`. Bare names
+//! are looked up in the [embedded symbol table](crate::symbols), e.g. `dis stage1::main`.
+//!
+//! Any module can add commands to the terminal with [register]. Commands are [Command]s run on
+//! a [Terminal], which is where their output goes:
+//!
+//! ```ignore
+//! pub static COMMANDS: [CommandRef<Terminal>; 1] = [&PING];
+//!
+//! static PING: Command = Command {
+//!     name: "ping",
+//!     usage: "",
+//!     help: "Reply with pong",
+//!     handler: ping,
+//! };
+//!
+//! fn ping(term: &mut Terminal, _args: &mut Args) -> Result<(), Error> {
+//!     writeln!(term, "pong").unwrap();
+//!     Ok(())
+//! }
+//! ```
+
+use alloc::{string::String, vec::Vec};
+use core::fmt::{self, Write};
+use shell::{Editor, Registry, Resolve};
+use sync::mutex::SpinMutex;
+
+use crate::{
+    console::{Console, CONSOLE},
+    except::EXCEPTION_SAVE_AREA,
+};
+
+pub use shell::{Args, CommandRef, Error};
+
+/// A command implemented by a function.
+pub type Command = shell::FnCommand<Terminal>;
+
+/// The terminal commands run on. Output written to it is sent to every console.
+pub struct Terminal {
+    /// Set to leave the terminal.
+    exit: bool,
+}
+
+impl Write for Terminal {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        CONSOLE.lock(|console| console.write(s.as_bytes()));
+        Ok(())
+    }
+}
+
+/// The number of lines kept in the history.
+const HISTORY_LEN: usize = 32;
+
+static COMMANDS: SpinMutex<Registry<Terminal>> = SpinMutex::new(Registry::EMPTY);
+
+/// The variables defined with `set`, sorted by name.
+static VARS: SpinMutex<Vec<(String, u64)>> = SpinMutex::new(Vec::new());
+
+/// The names expressions can refer to.
+struct Names;
+
+impl Names {
+    /// The value of a register saved by the last exception on this thread.
+    fn register(name: &str) -> Option<u64> {
+        let ctx = unsafe { &EXCEPTION_SAVE_AREA[xenon_cpu::intrin::pir() as usize] };
+
+        match name {
+            "sp" => Some(ctx.r[1]),
+            "cr" => Some(ctx.cr),
+            "lr" => Some(ctx.lr),
+            "ctr" => Some(ctx.ctr),
+            "pc" => Some(ctx.pc),
+            "msr" => Some(ctx.msr),
+            _ => {
+                let n: usize = name.strip_prefix('r')?.parse().ok()?;
+                ctx.r.get(n).copied()
+            }
+        }
+    }
+}
+
+impl Resolve for Names {
+    fn variable(&self, name: &str) -> Option<u64> {
+        Self::register(name).or_else(|| {
+            VARS.lock(|vars| {
+                vars.binary_search_by(|(n, _)| n.as_str().cmp(name))
+                    .ok()
+                    .map(|idx| vars[idx].1)
+            })
+        })
+    }
+
+    fn symbol(&self, name: &str) -> Option<u64> {
+        crate::symbols::find(name)
+    }
+}
+
+static EXIT: Command = Command {
+    name: "exit",
+    usage: "",
+    help: "Leave the terminal",
+    handler: exit,
+};
+
+fn exit(term: &mut Terminal, _args: &mut Args) -> Result<(), Error> {
+    writeln!(term, "Goodbye!").unwrap();
+    term.exit = true;
+    Ok(())
+}
+
+static SET: Command = Command {
+    name: "set",
+    usage: "[name [value]]",
+    help: "Set, clear or list variables",
+    handler: set,
+};
+
+fn set(term: &mut Terminal, args: &mut Args) -> Result<(), Error> {
+    let name = match args.next() {
+        Some(name) => name.strip_prefix('$').unwrap_or(name),
+        None => {
+            return VARS.lock(|vars| {
+                for (name, value) in vars.iter() {
+                    writeln!(term, "${} = {:016X}", name, value).unwrap();
+                }
+
+                Ok(())
+            });
+        }
+    };
+
+    let valid = name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if name.is_empty() || !valid || Names::register(name).is_some() {
+        return Err(Error::Invalid("name"));
+    }
+
+    let value = args.opt_num("value")?;
+
+    VARS.lock(|vars| {
+        let idx = vars.binary_search_by(|(n, _)| n.as_str().cmp(name));
+
+        match (idx, value) {
+            (Ok(idx), Some(value)) => vars[idx].1 = value,
+            (Err(idx), Some(value)) => vars.insert(idx, (String::from(name), value)),
+            (Ok(idx), None) => {
+                vars.remove(idx);
+            }
+            (Err(_), None) => {}
+        }
+    });
+
+    Ok(())
+}
+
+/// Add commands to the terminal.
+pub fn register(commands: &[CommandRef<Terminal>]) {
+    COMMANDS.lock(|registry| registry.register_all(commands));
+}
+
+/// Run commands until `exit`.
+pub fn run() {
+    let mut term = Terminal { exit: false };
+    let mut editor = Editor::new("> ", HISTORY_LEN);
+
+    register(&[&EXIT, &SET]);
+
+    while !term.exit {
+        // N.B: Commands may take a long time, or register commands themselves, so they run
+        // without the registry locked.
+        let commands = COMMANDS.lock(|registry| registry.clone());
+
+        writeln!(term).unwrap();
+        editor.start(&mut term);
+
+        let line = loop {
+            if let Some(line) = editor.feed(crate::read_byte(), &mut term, &commands) {
+                break line;
+            }
+        };
+
+        commands.run(&mut term, &line, &Names);
+    }
+}
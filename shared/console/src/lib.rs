@@ -0,0 +1,185 @@
+//! Transport-agnostic console I/O.
+//!
+//! A [Console] is anything the terminal can write text to and read keystrokes from: a UART, a
+//! TCP session, a network console, a framebuffer... A [Mux] combines several consoles into one,
+//! sending output to all of them and merging their input.
+//!
+//! [pipe] creates an in-memory console, which is handy for tests.
+#![no_std]
+
+extern crate alloc;
+
+pub mod pipe;
+
+use alloc::{boxed::Box, vec::Vec};
+use core::fmt;
+
+pub use pipe::{pipe, PipeEnd};
+
+/// A source of input and a sink for output.
+pub trait Console {
+    /// Write bytes to the console.
+    ///
+    /// This must not wait indefinitely (e.g. on a disconnected client). A console that can't keep
+    /// up may drop output instead.
+    fn write(&mut self, data: &[u8]);
+
+    /// Take the next byte of input, if one is available. This never blocks.
+    ///
+    /// Output-only consoles can rely on the default, which never has any input.
+    fn poll(&mut self) -> Option<u8> {
+        None
+    }
+
+    /// Wait for the next byte of input.
+    fn read(&mut self) -> u8 {
+        loop {
+            if let Some(byte) = self.poll() {
+                return byte;
+            }
+
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Write formatted text to the console. This allows consoles to be used with `write!`.
+    fn write_fmt(&mut self, args: fmt::Arguments) -> fmt::Result {
+        fmt::write(&mut Writer(self), args)
+    }
+}
+
+/// Adapts a [Console] to [fmt::Write].
+pub struct Writer<'a, C: ?Sized>(pub &'a mut C);
+
+impl<C: Console + ?Sized> fmt::Write for Writer<'_, C> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// A console multiplexer. Output is sent to every console, and input is taken from all of them.
+#[derive(Default)]
+pub struct Mux {
+    consoles: Vec<Box<dyn Console + Send>>,
+    /// The console polled first next time, so a busy one can't starve the others.
+    next: usize,
+}
+
+impl Mux {
+    /// An empty multiplexer, for initializing statics.
+    ///
+    /// N.B: This is a constant rather than a `const fn`, because trait objects aren't allowed in
+    /// `const fn`s on the toolchain we build with.
+    pub const EMPTY: Self = Self {
+        consoles: Vec::new(),
+        next: 0,
+    };
+
+    /// Add a console.
+    pub fn add(&mut self, console: Box<dyn Console + Send>) {
+        self.consoles.push(console);
+    }
+
+    /// The number of consoles.
+    pub fn len(&self) -> usize {
+        self.consoles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.consoles.is_empty()
+    }
+}
+
+impl Console for Mux {
+    fn write(&mut self, data: &[u8]) {
+        for console in self.consoles.iter_mut() {
+            console.write(data);
+        }
+    }
+
+    fn poll(&mut self) -> Option<u8> {
+        let len = self.consoles.len();
+
+        for i in 0..len {
+            let idx = (self.next + i) % len;
+
+            if let Some(byte) = self.consoles[idx].poll() {
+                self.next = (idx + 1) % len;
+                return Some(byte);
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Read everything available from a console.
+    fn drain(console: &mut impl Console) -> Vec<u8> {
+        core::iter::from_fn(|| console.poll()).collect()
+    }
+
+    /// An output-only console, which counts what is written to it.
+    struct Counter(alloc::sync::Arc<sync::mutex::SpinMutex<usize>>);
+
+    impl Console for Counter {
+        fn write(&mut self, data: &[u8]) {
+            self.0.lock(|n| *n += data.len());
+        }
+    }
+
+    #[test]
+    fn test_fan_out() {
+        let (mut a, a_dev) = pipe();
+        let (mut b, b_dev) = pipe();
+        let count = alloc::sync::Arc::new(sync::mutex::SpinMutex::new(0));
+
+        let mut mux = Mux::default();
+        mux.add(Box::new(a_dev));
+        mux.add(Box::new(b_dev));
+        mux.add(Box::new(Counter(count.clone())));
+
+        writeln!(mux, "{} + {} = {}", 1, 2, 3).unwrap();
+
+        assert_eq!(drain(&mut a), b"1 + 2 = 3\n");
+        assert_eq!(drain(&mut b), b"1 + 2 = 3\n");
+        assert_eq!(count.lock(|n| *n), 10);
+    }
+
+    #[test]
+    fn test_merge() {
+        let (mut a, a_dev) = pipe();
+        let (mut b, b_dev) = pipe();
+
+        let mut mux = Mux::default();
+        mux.add(Box::new(a_dev));
+        mux.add(Box::new(b_dev));
+        assert_eq!(mux.poll(), None);
+
+        // Input is interleaved, so neither console can starve the other.
+        a.write(b"aaa");
+        b.write(b"b");
+        assert_eq!(drain(&mut mux), b"abaa");
+
+        b.write(b"x");
+        assert_eq!(mux.read(), b'x');
+        assert_eq!(mux.poll(), None);
+    }
+
+    #[test]
+    fn test_dyn() {
+        let (mut host, mut dev) = pipe();
+        let console: &mut dyn Console = &mut dev;
+
+        host.write(b"hi");
+        assert_eq!(console.read(), b'h');
+        assert_eq!(console.read(), b'i');
+
+        write!(console, "{:02X}", 0xAu8).unwrap();
+        assert_eq!(drain(&mut host), b"0A");
+    }
+}
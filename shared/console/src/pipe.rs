@@ -0,0 +1,59 @@
+//! An in-memory console.
+
+use alloc::{collections::VecDeque, sync::Arc};
+use sync::mutex::SpinMutex;
+
+use crate::Console;
+
+type Buffer = Arc<SpinMutex<VecDeque<u8>>>;
+
+/// One end of a pipe. Bytes written to one end are read from the other.
+pub struct PipeEnd {
+    rx: Buffer,
+    tx: Buffer,
+}
+
+/// Create a pipe, returning its two ends.
+pub fn pipe() -> (PipeEnd, PipeEnd) {
+    let a = Buffer::new(SpinMutex::new(VecDeque::new()));
+    let b = Buffer::new(SpinMutex::new(VecDeque::new()));
+
+    (
+        PipeEnd {
+            rx: a.clone(),
+            tx: b.clone(),
+        },
+        PipeEnd { rx: b, tx: a },
+    )
+}
+
+impl Console for PipeEnd {
+    fn write(&mut self, data: &[u8]) {
+        self.tx.lock(|buf| buf.extend(data));
+    }
+
+    fn poll(&mut self) -> Option<u8> {
+        self.rx.lock(|buf| buf.pop_front())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pipe() {
+        let (mut a, mut b) = pipe();
+
+        a.write(b"ping");
+        b.write(b"pong");
+
+        assert_eq!(b.poll(), Some(b'p'));
+        assert_eq!(b.poll(), Some(b'i'));
+        assert_eq!(a.poll(), Some(b'p'));
+        assert_eq!(a.poll(), Some(b'o'));
+        assert_eq!(b.poll(), Some(b'n'));
+        assert_eq!(b.poll(), Some(b'g'));
+        assert_eq!(b.poll(), None);
+    }
+}
@@ -0,0 +1,323 @@
+//! Crash records kept in RAM across a warm reboot.
+//!
+//! A log is a region of memory divided into [SLOT_SIZE] slots, used as a ring: a new record
+//! goes in an empty slot, or replaces the oldest one. Each slot starts with a header that
+//! identifies and checksums the record, so whatever a cold boot leaves in the region is
+//! ignored. A log downloaded from the bootloader is parsed the same way on the host.
+//!
+//! All fields are big-endian. A slot's header:
+//!
+//! | Offset | Size | Field                                  |
+//! |--------|------|----------------------------------------|
+//! | 0      | 4    | Magic, `CRSH`                          |
+//! | 4      | 2    | Version ([VERSION])                    |
+//! | 6      | 2    | Reserved, zero                         |
+//! | 8      | 4    | Sequence number, counting up from 1    |
+//! | 12     | 4    | Length of the record                   |
+//! | 16     | 4    | CRC-32 of the record                   |
+//! | 20     | 4    | Reserved, zero                         |
+//! | 24     | ...  | Record (see [Record])                  |
+#![no_std]
+
+mod record;
+
+pub use ppc_fault::Fault;
+pub use record::{Frames, Kind, Message, Record, Registers, MAX_FRAMES, MAX_MESSAGE};
+
+use core::fmt;
+
+/// The magic number at the beginning of every slot holding a record.
+pub const MAGIC: [u8; 4] = *b"CRSH";
+
+/// The record version we read and write.
+pub const VERSION: u16 = 2;
+
+/// The size of each slot, including its header.
+pub const SLOT_SIZE: usize = 0x4000;
+
+const HEADER_SIZE: usize = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The slot does not hold a record.
+    Empty,
+    /// The record version is unknown.
+    BadVersion(u16),
+    /// The record does not match its checksum.
+    BadChecksum,
+    /// The record is shorter than its fields claim.
+    Truncated,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Empty => write!(f, "empty slot"),
+            Error::BadVersion(v) => write!(f, "unknown record version {}", v),
+            Error::BadChecksum => write!(f, "bad record checksum"),
+            Error::Truncated => write!(f, "record truncated"),
+        }
+    }
+}
+
+/// A record, and its place in the log.
+#[derive(Debug, Clone)]
+pub struct Entry<'a> {
+    /// The sequence number. Later records have higher numbers.
+    pub sequence: u32,
+    pub record: Record<'a>,
+}
+
+/// A crash log, held in `T`.
+pub struct Log<T> {
+    data: T,
+}
+
+impl<T: AsRef<[u8]>> Log<T> {
+    /// Use `data` as a log. Any space after the last whole slot is unused.
+    pub fn new(data: T) -> Self {
+        Self { data }
+    }
+
+    /// The number of slots in the log.
+    pub fn slots(&self) -> usize {
+        self.data.as_ref().len() / SLOT_SIZE
+    }
+
+    fn slot(&self, idx: usize) -> &[u8] {
+        &self.data.as_ref()[idx * SLOT_SIZE..(idx + 1) * SLOT_SIZE]
+    }
+
+    /// The record in slot `idx`.
+    pub fn get(&self, idx: usize) -> Result<Entry<'_>, Error> {
+        let slot = self.slot(idx);
+        if slot[..4] != MAGIC {
+            return Err(Error::Empty);
+        }
+
+        let version = read_u16(slot, 4);
+        if version != VERSION {
+            return Err(Error::BadVersion(version));
+        }
+
+        let len = read_u32(slot, 12) as usize;
+        if len > SLOT_SIZE - HEADER_SIZE {
+            return Err(Error::Truncated);
+        }
+
+        let data = &slot[HEADER_SIZE..HEADER_SIZE + len];
+        if crc32(data) != read_u32(slot, 16) {
+            return Err(Error::BadChecksum);
+        }
+
+        Ok(Entry {
+            sequence: read_u32(slot, 8),
+            record: Record::decode(data)?,
+        })
+    }
+
+    /// The number of records in the log.
+    pub fn len(&self) -> usize {
+        (0..self.slots()).filter(|&i| self.get(i).is_ok()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Iterate over the records, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = Entry<'_>> + '_ {
+        let mut last = None;
+
+        core::iter::from_fn(move || {
+            let next = (0..self.slots())
+                .filter_map(|i| self.get(i).ok())
+                .filter(|e| !matches!(last, Some(l) if e.sequence <= l))
+                .min_by_key(|e| e.sequence)?;
+
+            last = Some(next.sequence);
+            Some(next)
+        })
+    }
+}
+
+impl<T: AsRef<[u8]> + AsMut<[u8]>> Log<T> {
+    /// Add a record, replacing the oldest one if the log is full. Returns its sequence number,
+    /// or `None` if the log has no slots.
+    ///
+    /// If the record doesn't fit in a slot, the oldest part of its log is dropped.
+    pub fn push(&mut self, record: &Record) -> Option<u32> {
+        let mut free = None;
+        let mut oldest: Option<(usize, u32)> = None;
+        let mut newest = 0;
+
+        for idx in 0..self.slots() {
+            match self.get(idx) {
+                Ok(e) => {
+                    newest = newest.max(e.sequence);
+                    if !matches!(oldest, Some((_, seq)) if seq <= e.sequence) {
+                        oldest = Some((idx, e.sequence));
+                    }
+                }
+                Err(_) => {
+                    free.get_or_insert(idx);
+                }
+            }
+        }
+
+        let idx = free.or(oldest.map(|(idx, _)| idx))?;
+        let sequence = newest.wrapping_add(1);
+
+        let slot = &mut self.data.as_mut()[idx * SLOT_SIZE..(idx + 1) * SLOT_SIZE];
+
+        // Invalidate the slot first, so a record that's only partly written is never read.
+        slot[..HEADER_SIZE].fill(0);
+        let len = record.encode(&mut slot[HEADER_SIZE..]);
+        let crc = crc32(&slot[HEADER_SIZE..HEADER_SIZE + len]);
+
+        slot[4..6].copy_from_slice(&VERSION.to_be_bytes());
+        slot[8..12].copy_from_slice(&sequence.to_be_bytes());
+        slot[12..16].copy_from_slice(&(len as u32).to_be_bytes());
+        slot[16..20].copy_from_slice(&crc.to_be_bytes());
+        slot[..4].copy_from_slice(&MAGIC);
+
+        Some(sequence)
+    }
+
+    /// Remove every record.
+    pub fn clear(&mut self) {
+        for idx in 0..self.slots() {
+            self.data.as_mut()[idx * SLOT_SIZE..idx * SLOT_SIZE + HEADER_SIZE].fill(0);
+        }
+    }
+}
+
+/// The CRC-32 (IEEE 802.3) of `data`.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+
+    !crc
+}
+
+fn read_u16(data: &[u8], off: usize) -> u16 {
+    u16::from_be_bytes([data[off], data[off + 1]])
+}
+
+fn read_u32(data: &[u8], off: usize) -> u32 {
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(&data[off..off + 4]);
+    u32::from_be_bytes(buf)
+}
+
+fn read_u64(data: &[u8], off: usize) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&data[off..off + 8]);
+    u64::from_be_bytes(buf)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn record(thread: u8, log: &[u8]) -> Record<'_> {
+        let mut regs = Registers::default();
+        regs.r[1] = 0x1DFF_FF00;
+        regs.pc = 0x8000_0000_0000_1234;
+
+        Record {
+            kind: Kind::Exception(0x30),
+            thread,
+            timebase: 0x1234_5678,
+            regs,
+            backtrace: [0x8000_0000_0000_2000u64, 0x8000_0000_0000_3000]
+                .iter()
+                .copied()
+                .collect(),
+            message: "",
+            fault: Some(Fault {
+                vector: 0x300,
+                pc: 0x8000_0000_0000_1234,
+                dar: 0x10,
+                dsisr: 0x4200_0000,
+                ..Fault::default()
+            }),
+            log,
+        }
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_ring() {
+        let mut log = Log::new([0xA5u8; SLOT_SIZE * 3]);
+        assert!(log.is_empty());
+        assert_eq!(log.get(0).unwrap_err(), Error::Empty);
+
+        for thread in 0..5 {
+            assert_eq!(
+                log.push(&record(thread, b"hello\n")),
+                Some(thread as u32 + 1)
+            );
+        }
+
+        // The two oldest records were replaced.
+        let threads: [u8; 3] = {
+            let mut it = log.iter().map(|e| e.record.thread);
+            [it.next().unwrap(), it.next().unwrap(), it.next().unwrap()]
+        };
+        assert_eq!(threads, [2, 3, 4]);
+        assert_eq!(log.iter().count(), 3);
+
+        let e = log.iter().last().unwrap();
+        assert_eq!(e.sequence, 5);
+        assert_eq!(e.record.regs.pc, 0x8000_0000_0000_1234);
+        assert_eq!(
+            e.record.backtrace.as_slice(),
+            &[0x8000_0000_0000_2000, 0x8000_0000_0000_3000]
+        );
+        assert_eq!(e.record.log, b"hello\n");
+        assert_eq!(e.record.fault.unwrap().dsisr, 0x4200_0000);
+
+        log.clear();
+        assert!(log.is_empty());
+    }
+
+    #[test]
+    fn test_corrupt() {
+        let mut log = Log::new([0u8; SLOT_SIZE]);
+        log.push(&record(0, b""));
+
+        log.data[HEADER_SIZE + 8] ^= 1;
+        assert_eq!(log.get(0).unwrap_err(), Error::BadChecksum);
+
+        log.data[5] = 9;
+        assert_eq!(log.get(0).unwrap_err(), Error::BadVersion(9));
+    }
+
+    #[test]
+    fn test_truncate_log() {
+        let mut text = [0u8; SLOT_SIZE];
+        for (i, b) in text.iter_mut().enumerate() {
+            *b = if i % 10 == 9 { b'\n' } else { b'x' };
+        }
+
+        let mut log = Log::new([0u8; SLOT_SIZE]);
+        log.push(&record(0, &text));
+
+        // The start of the log is dropped, on a line boundary.
+        let e = log.get(0).unwrap();
+        assert!(e.record.log.len() < text.len());
+        assert!(text.ends_with(e.record.log));
+        assert_eq!(text[text.len() - e.record.log.len() - 1], b'\n');
+    }
+}
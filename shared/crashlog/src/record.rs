@@ -0,0 +1,548 @@
+//! The crash record format.
+
+use core::fmt::{self, Write};
+
+use crate::{read_u16, read_u32, read_u64, Error, Fault};
+
+/// The most backtrace frames a record holds.
+pub const MAX_FRAMES: usize = 32;
+
+/// The longest message a record holds, in bytes.
+pub const MAX_MESSAGE: usize = 512;
+
+const FIXED_SIZE: usize = 368;
+
+/// Flags for the fault fields.
+const HAS_FAULT: u32 = 1 << 0;
+const HAS_INSN: u32 = 1 << 1;
+
+/// Names of the exception IDs used by stage1 (the vector, shifted right by 4).
+const EXCEPTIONS: [(u16, &str); 14] = [
+    (0x10, "Reset"),
+    (0x20, "MachineCheck"),
+    (0x30, "Dsi"),
+    (0x38, "DataSegment"),
+    (0x40, "Isi"),
+    (0x48, "InstructionSegment"),
+    (0x50, "ExternalInterrupt"),
+    (0x60, "Alignment"),
+    (0x70, "Program"),
+    (0x80, "FloatingPoint"),
+    (0x90, "Decrementer"),
+    (0xC0, "SystemCall"),
+    (0xD0, "Trace"),
+    (0xF0, "Performance"),
+];
+
+/// What caused a crash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// An unhandled exception, by ID (the vector, shifted right by 4).
+    Exception(u16),
+    /// A Rust panic.
+    Panic,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Kind::Exception(id) => match EXCEPTIONS.iter().find(|(i, _)| i == id) {
+                Some((_, name)) => write!(f, "Exception {} ({:#x})", name, (*id as u32) << 4),
+                None => write!(f, "Exception {:#x}", (*id as u32) << 4),
+            },
+            Kind::Panic => write!(f, "Panic"),
+        }
+    }
+}
+
+/// The registers of the crashed thread.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Registers {
+    pub r: [u64; 32],
+    pub cr: u64,
+    pub lr: u64,
+    pub ctr: u64,
+    pub pc: u64,
+    pub msr: u64,
+}
+
+/// Return addresses from a backtrace, innermost first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frames {
+    addrs: [u64; MAX_FRAMES],
+    len: usize,
+}
+
+impl Frames {
+    pub const fn new() -> Self {
+        Self {
+            addrs: [0; MAX_FRAMES],
+            len: 0,
+        }
+    }
+
+    /// Add a frame. Returns false if there's no room for it.
+    pub fn push(&mut self, addr: u64) -> bool {
+        if self.len == MAX_FRAMES {
+            return false;
+        }
+
+        self.addrs[self.len] = addr;
+        self.len += 1;
+        true
+    }
+
+    pub fn as_slice(&self) -> &[u64] {
+        &self.addrs[..self.len]
+    }
+}
+
+impl Default for Frames {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Collects up to [MAX_FRAMES] frames. The rest are dropped.
+impl FromIterator<u64> for Frames {
+    fn from_iter<I: IntoIterator<Item = u64>>(iter: I) -> Self {
+        let mut frames = Self::new();
+        for addr in iter.into_iter().take(MAX_FRAMES) {
+            frames.push(addr);
+        }
+
+        frames
+    }
+}
+
+/// A buffer to format a record's message into, without allocating. Text that doesn't fit is
+/// dropped.
+#[derive(Clone)]
+pub struct Message {
+    buf: [u8; MAX_MESSAGE],
+    len: usize,
+}
+
+impl Message {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; MAX_MESSAGE],
+            len: 0,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        // N.B: Only whole strs are ever copied in.
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or("")
+    }
+}
+
+impl Default for Message {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Write for Message {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let s = truncate(s, MAX_MESSAGE - self.len);
+        self.buf[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+        self.len += s.len();
+        Ok(())
+    }
+}
+
+/// The longest prefix of `s` that fits in `len` bytes.
+fn truncate(s: &str, mut len: usize) -> &str {
+    if len >= s.len() {
+        return s;
+    }
+
+    while !s.is_char_boundary(len) {
+        len -= 1;
+    }
+
+    &s[..len]
+}
+
+/// A crash record.
+///
+/// All fields are big-endian:
+///
+/// | Offset | Size    | Field                                           |
+/// |--------|---------|-------------------------------------------------|
+/// | 0      | 1       | Kind: 0 for an exception, 1 for a panic         |
+/// | 1      | 1       | Thread (PIR)                                    |
+/// | 2      | 2       | Exception ID, or zero for a panic               |
+/// | 4      | 2       | Number of backtrace frames                      |
+/// | 6      | 2       | Length of the message                           |
+/// | 8      | 8       | Timebase                                        |
+/// | 16     | 8 * 37  | r0-r31, CR, LR, CTR, PC, MSR                    |
+/// | 312    | 4       | Length of the log                               |
+/// | 316    | 4       | Fault flags: 1 if present, 2 if insn is valid   |
+/// | 320    | 8 * 4   | Fault PC, SRR1, DAR, HDAR                       |
+/// | 352    | 4 * 3   | Fault DSISR, HDSISR, insn                       |
+/// | 364    | 2       | Fault vector                                    |
+/// | 366    | 2       | Reserved, zero                                  |
+/// | 368    | 8 * n   | Backtrace frames, innermost first               |
+/// | ...    | ...     | Message (UTF-8), then the log                   |
+#[derive(Debug, Clone)]
+pub struct Record<'a> {
+    pub kind: Kind,
+    pub thread: u8,
+    pub timebase: u64,
+    /// The registers when an exception was taken. Panics leave these zero.
+    pub regs: Registers,
+    pub backtrace: Frames,
+    /// The panic message, if any.
+    pub message: &'a str,
+    /// The state of the exception that crashed, or that was being handled when a panic
+    /// happened.
+    pub fault: Option<Fault>,
+    /// The most recent console output.
+    pub log: &'a [u8],
+}
+
+impl<'a> Record<'a> {
+    /// Encode the record into `out`, returning the length used. The message is cut to
+    /// [MAX_MESSAGE], and the start of the log is dropped if it doesn't fit.
+    pub(crate) fn encode(&self, out: &mut [u8]) -> usize {
+        let frames = self.backtrace.as_slice();
+        let message = truncate(self.message, MAX_MESSAGE);
+
+        let frames_end = FIXED_SIZE + frames.len() * 8;
+        let log_start = frames_end + message.len();
+        assert!(log_start <= out.len(), "no room for a record");
+
+        let mut log = self.log;
+        if log.len() > out.len() - log_start {
+            log = &log[log.len() - (out.len() - log_start)..];
+
+            // Start on a whole line, if there is one.
+            if let Some(pos) = log.iter().position(|&b| b == b'\n') {
+                log = &log[pos + 1..];
+            }
+        }
+
+        let (kind, id) = match self.kind {
+            Kind::Exception(id) => (0, id),
+            Kind::Panic => (1, 0),
+        };
+
+        out[..FIXED_SIZE].fill(0);
+        out[0] = kind;
+        out[1] = self.thread;
+        out[2..4].copy_from_slice(&id.to_be_bytes());
+        out[4..6].copy_from_slice(&(frames.len() as u16).to_be_bytes());
+        out[6..8].copy_from_slice(&(message.len() as u16).to_be_bytes());
+        out[8..16].copy_from_slice(&self.timebase.to_be_bytes());
+
+        let regs = &self.regs;
+        let specials = [regs.cr, regs.lr, regs.ctr, regs.pc, regs.msr];
+        for (i, val) in regs.r.iter().chain(specials.iter()).enumerate() {
+            out[16 + i * 8..24 + i * 8].copy_from_slice(&val.to_be_bytes());
+        }
+
+        out[312..316].copy_from_slice(&(log.len() as u32).to_be_bytes());
+
+        if let Some(fault) = &self.fault {
+            let mut flags = HAS_FAULT;
+            if fault.insn.is_some() {
+                flags |= HAS_INSN;
+            }
+
+            out[316..320].copy_from_slice(&flags.to_be_bytes());
+            out[320..328].copy_from_slice(&fault.pc.to_be_bytes());
+            out[328..336].copy_from_slice(&fault.srr1.to_be_bytes());
+            out[336..344].copy_from_slice(&fault.dar.to_be_bytes());
+            out[344..352].copy_from_slice(&fault.hdar.to_be_bytes());
+            out[352..356].copy_from_slice(&fault.dsisr.to_be_bytes());
+            out[356..360].copy_from_slice(&fault.hdsisr.to_be_bytes());
+            out[360..364].copy_from_slice(&fault.insn.unwrap_or(0).to_be_bytes());
+            out[364..366].copy_from_slice(&fault.vector.to_be_bytes());
+        }
+
+        for (i, addr) in frames.iter().enumerate() {
+            out[FIXED_SIZE + i * 8..FIXED_SIZE + (i + 1) * 8].copy_from_slice(&addr.to_be_bytes());
+        }
+
+        out[frames_end..log_start].copy_from_slice(message.as_bytes());
+        out[log_start..log_start + log.len()].copy_from_slice(log);
+
+        log_start + log.len()
+    }
+
+    /// Decode a record.
+    pub fn decode(data: &'a [u8]) -> Result<Self, Error> {
+        if data.len() < FIXED_SIZE {
+            return Err(Error::Truncated);
+        }
+
+        let nframes = read_u16(data, 4) as usize;
+        let message_len = read_u16(data, 6) as usize;
+        let log_len = read_u32(data, 312) as usize;
+        if nframes > MAX_FRAMES {
+            return Err(Error::Truncated);
+        }
+
+        let frames_end = FIXED_SIZE + nframes * 8;
+        let log_start = frames_end + message_len;
+        if log_start + log_len > data.len() {
+            return Err(Error::Truncated);
+        }
+
+        let kind = match data[0] {
+            0 => Kind::Exception(read_u16(data, 2)),
+            _ => Kind::Panic,
+        };
+
+        let reg = |i: usize| read_u64(data, 16 + i * 8);
+        let mut regs = Registers {
+            r: [0; 32],
+            cr: reg(32),
+            lr: reg(33),
+            ctr: reg(34),
+            pc: reg(35),
+            msr: reg(36),
+        };
+        for (i, r) in regs.r.iter_mut().enumerate() {
+            *r = reg(i);
+        }
+
+        let flags = read_u32(data, 316);
+        let fault = if flags & HAS_FAULT != 0 {
+            Some(Fault {
+                vector: read_u16(data, 364),
+                pc: read_u64(data, 320),
+                srr1: read_u64(data, 328),
+                dar: read_u64(data, 336),
+                dsisr: read_u32(data, 352),
+                hdar: read_u64(data, 344),
+                hdsisr: read_u32(data, 356),
+                insn: if flags & HAS_INSN != 0 {
+                    Some(read_u32(data, 360))
+                } else {
+                    None
+                },
+            })
+        } else {
+            None
+        };
+
+        Ok(Self {
+            kind,
+            thread: data[1],
+            timebase: read_u64(data, 8),
+            regs,
+            backtrace: (0..nframes)
+                .map(|i| read_u64(data, FIXED_SIZE + i * 8))
+                .collect(),
+            message: core::str::from_utf8(&data[frames_end..log_start]).unwrap_or("<invalid>"),
+            fault,
+            log: &data[log_start..log_start + log_len],
+        })
+    }
+
+    /// Write a readable report of the record. `symbol` may append the name of the function
+    /// containing an address.
+    pub fn report(
+        &self,
+        out: &mut dyn Write,
+        symbol: &dyn Fn(&mut dyn Write, u64) -> fmt::Result,
+    ) -> fmt::Result {
+        writeln!(
+            out,
+            "{} on thread {}, TB {:016X}",
+            self.kind, self.thread, self.timebase
+        )?;
+        if !self.message.is_empty() {
+            writeln!(out, "    {}", self.message)?;
+        }
+
+        if let Some(diagnosis) = self.fault.as_ref().and_then(|f| f.diagnose()) {
+            match self.kind {
+                Kind::Panic => writeln!(out, "    while handling an exception: {}", diagnosis)?,
+                Kind::Exception(_) => writeln!(out, "    {}", diagnosis)?,
+            }
+        }
+
+        // N.B: Panics have no saved registers.
+        if self.kind != Kind::Panic {
+            self.write_registers(out, symbol)?;
+        }
+
+        writeln!(out, "---- Backtrace:")?;
+        for (i, &addr) in self.backtrace.as_slice().iter().enumerate() {
+            write!(out, "    #{:<2} {:016X}", i, addr)?;
+            symbol(out, addr)?;
+            writeln!(out)?;
+        }
+
+        if !self.log.is_empty() {
+            writeln!(out, "---- Log:")?;
+            let log = self.log.strip_suffix(b"\n").unwrap_or(self.log);
+            for line in log.split(|&b| b == b'\n') {
+                write!(out, "    ")?;
+                write_line(out, line)?;
+                writeln!(out)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_registers(
+        &self,
+        out: &mut dyn Write,
+        symbol: &dyn Fn(&mut dyn Write, u64) -> fmt::Result,
+    ) -> fmt::Result {
+        let regs = &self.regs;
+        writeln!(out, "---- Registers:")?;
+        for (i, row) in regs.r.chunks(4).enumerate() {
+            write!(out, "   ")?;
+            for (j, val) in row.iter().enumerate() {
+                write!(out, " r{:02}: {:016X}", i * 4 + j, val)?;
+            }
+            writeln!(out)?;
+        }
+        writeln!(
+            out,
+            "    cr:  {:016X} ctr: {:016X} msr: {:016X}",
+            regs.cr, regs.ctr, regs.msr
+        )?;
+        write!(out, "    lr:  {:016X}", regs.lr)?;
+        symbol(out, regs.lr)?;
+        writeln!(out)?;
+        write!(out, "    pc:  {:016X}", regs.pc)?;
+        symbol(out, regs.pc)?;
+        writeln!(out)?;
+
+        if let Some(fault) = &self.fault {
+            writeln!(
+                out,
+                "    dar: {:016X} dsisr: {:08X} hdar: {:016X} hdsisr: {:08X}",
+                fault.dar, fault.dsisr, fault.hdar, fault.hdsisr
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Reports the record without symbols.
+impl fmt::Display for Record<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.report(f, &|_, _| Ok(()))
+    }
+}
+
+/// Write a line of console output, dropping control characters and escape sequences.
+fn write_line(out: &mut dyn Write, line: &[u8]) -> fmt::Result {
+    let text = core::str::from_utf8(line).unwrap_or("<invalid UTF-8>");
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            // Skip a CSI sequence, up to its final byte.
+            '\x1b' => {
+                if chars.next() == Some('[') {
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+            }
+            '\t' => out.write_char(c)?,
+            c if c.is_control() => {}
+            c => out.write_char(c)?,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::*;
+    use std::string::String;
+
+    #[test]
+    fn test_roundtrip() {
+        let mut message = Message::new();
+        write!(message, "assertion failed at {}", 42).unwrap();
+
+        let mut regs = Registers::default();
+        for (i, r) in regs.r.iter_mut().enumerate() {
+            *r = i as u64 * 0x0101_0101;
+        }
+        regs.msr = 0x9000_0000_0000_1032;
+
+        let record = Record {
+            kind: Kind::Panic,
+            thread: 3,
+            timebase: 0xDEAD_BEEF,
+            regs,
+            backtrace: [0x8000_0000_0000_4000; 40].iter().copied().collect(),
+            message: message.as_str(),
+            fault: Some(Fault {
+                vector: 0x700,
+                pc: 0x8000_0000_0000_2000,
+                srr1: 1 << (63 - 45),
+                insn: Some(0x7C60_0164),
+                ..Fault::default()
+            }),
+            log: b"one\r\n\x1b[Ktwo\n",
+        };
+
+        let mut buf = [0u8; 1024];
+        let len = record.encode(&mut buf);
+        let decoded = Record::decode(&buf[..len]).unwrap();
+
+        assert_eq!(decoded.kind, Kind::Panic);
+        assert_eq!(decoded.thread, 3);
+        assert_eq!(decoded.timebase, 0xDEAD_BEEF);
+        assert_eq!(decoded.regs, regs);
+        assert_eq!(decoded.backtrace.as_slice().len(), MAX_FRAMES);
+        assert_eq!(decoded.message, "assertion failed at 42");
+        assert_eq!(decoded.log, b"one\r\n\x1b[Ktwo\n");
+        assert_eq!(decoded.fault, record.fault);
+
+        let mut text = String::new();
+        write!(text, "{}", decoded).unwrap();
+        assert!(text.ends_with("---- Log:\n    one\n    two\n"));
+        assert!(
+            text.contains("\n    while handling an exception: privileged instruction 7C600164\n")
+        );
+
+        assert!(Record::decode(&buf[..len - 1]).is_err());
+    }
+
+    #[test]
+    fn test_message() {
+        let mut message = Message::new();
+        for _ in 0..MAX_MESSAGE {
+            message.write_str("é").unwrap();
+        }
+
+        // A character that doesn't fit is dropped whole.
+        assert_eq!(message.as_str().len(), MAX_MESSAGE);
+        assert!(message.as_str().chars().all(|c| c == 'é'));
+    }
+
+    #[test]
+    fn test_kind() {
+        let mut s = Message::new();
+        write!(
+            s,
+            "{}/{}/{}",
+            Kind::Exception(0x30),
+            Kind::Exception(0x160),
+            Kind::Panic
+        )
+        .unwrap();
+        assert_eq!(s.as_str(), "Exception Dsi (0x300)/Exception 0x1600/Panic");
+    }
+}
@@ -0,0 +1,635 @@
+//! A `no_std` DHCPv4 client (RFC 2131).
+//!
+//! Besides the usual address, router and DNS configuration, the client records
+//! the next-server address (`siaddr`) and boot file name (option 67) so that
+//! the bootloader can netboot without any manual configuration.
+//!
+//! [Client] is independent of the network stack. It consumes DHCP messages and
+//! produces messages to send along with the IPv4 addresses to send them from and to.
+//! Because the interface has no address while a lease is being acquired, messages
+//! must be framed with [packet::write_ipv4_udp] and sent over a raw socket.
+#![no_std]
+
+extern crate alloc;
+
+pub mod packet;
+
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+
+use packet::{opt, Message, MessageType, Request};
+use smoltcp::{
+    time::{Duration, Instant},
+    wire::{Ipv4Address, Ipv4Cidr},
+};
+
+/// The largest message we will send or accept.
+pub const MAX_MESSAGE: usize = 576;
+
+/// The number of REQUEST retransmissions before restarting discovery.
+const REQUEST_RETRIES: u32 = 4;
+
+/// The initial retransmission timeout (RFC 2131 section 4.1).
+const INITIAL_TIMEOUT: u64 = 4;
+/// The maximum retransmission timeout.
+const MAX_TIMEOUT: u64 = 64;
+/// The minimum retransmission timeout while renewing or rebinding.
+const MIN_RENEW_TIMEOUT: u64 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The message is malformed or not a DHCP message.
+    Malformed,
+    /// The output buffer is too small for the message.
+    BufferTooSmall,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Malformed => write!(f, "malformed message"),
+            Error::BufferTooSmall => write!(f, "buffer too small"),
+        }
+    }
+}
+
+/// The configuration handed out by a DHCP server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lease {
+    pub address: Ipv4Cidr,
+    pub router: Option<Ipv4Address>,
+    pub dns_servers: Vec<Ipv4Address>,
+    /// The server that granted the lease.
+    pub server: Ipv4Address,
+    /// The server to boot from (`siaddr`), if any.
+    pub next_server: Option<Ipv4Address>,
+    /// The TFTP server name (option 66), if any.
+    pub tftp_server_name: Option<String>,
+    /// The boot file name (option 67, or the `file` field), if any.
+    pub boot_file: Option<String>,
+    pub lease_time: Duration,
+    /// When the lease was granted.
+    pub acquired: Instant,
+    /// When to start renewing the lease with the server that granted it (T1).
+    pub renew_at: Instant,
+    /// When to start rebinding the lease with any server (T2).
+    pub rebind_at: Instant,
+    pub expires_at: Instant,
+}
+
+impl Lease {
+    fn from_ack(msg: &Message, now: Instant) -> Option<Self> {
+        let mask = msg
+            .options
+            .get_addr(opt::SUBNET_MASK)
+            .unwrap_or(Ipv4Address::new(255, 255, 255, 0));
+        let mask = u32::from_be_bytes(mask.0);
+
+        // Reject non-contiguous masks.
+        if mask.leading_ones() + mask.trailing_zeros() != 32 {
+            return None;
+        }
+
+        let server = msg.options.get_addr(opt::SERVER_ID).unwrap_or(msg.siaddr);
+
+        // A missing lease time means the lease never expires.
+        let lease_time = msg.options.get_u32(opt::LEASE_TIME).unwrap_or(u32::MAX) as u64;
+        let renew = msg
+            .options
+            .get_u32(opt::RENEWAL_TIME)
+            .map(|t| t as u64)
+            .unwrap_or(lease_time / 2);
+        let rebind = msg
+            .options
+            .get_u32(opt::REBINDING_TIME)
+            .map(|t| t as u64)
+            .unwrap_or(lease_time * 7 / 8);
+
+        let string = |s: &[u8]| {
+            core::str::from_utf8(s)
+                .ok()
+                .map(|s| s.trim_end_matches('\0'))
+                .filter(|s| !s.is_empty())
+                .map(String::from)
+        };
+
+        Some(Self {
+            address: Ipv4Cidr::new(msg.yiaddr, mask.leading_ones() as u8),
+            router: msg.options.get_addrs(opt::ROUTER).next(),
+            dns_servers: msg.options.get_addrs(opt::DNS_SERVER).collect(),
+            server,
+            next_server: Some(msg.siaddr).filter(|a| !a.is_unspecified()),
+            tftp_server_name: msg.options.get(opt::TFTP_SERVER).and_then(string),
+            boot_file: msg
+                .options
+                .get(opt::BOOTFILE)
+                .and_then(string)
+                .or_else(|| string(msg.file)),
+            lease_time: Duration::from_secs(lease_time),
+            acquired: now,
+            renew_at: now + Duration::from_secs(renew.min(lease_time)),
+            rebind_at: now + Duration::from_secs(rebind.min(lease_time)),
+            expires_at: now + Duration::from_secs(lease_time),
+        })
+    }
+
+    /// The address of the server to fetch the boot file from.
+    /// This is `siaddr` if set, otherwise option 66 if it holds an address.
+    pub fn tftp_server(&self) -> Option<Ipv4Address> {
+        self.next_server.or_else(|| {
+            self.tftp_server_name
+                .as_deref()
+                .and_then(|s| s.parse().ok())
+        })
+    }
+}
+
+/// A message to send, written into the caller's buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transmit {
+    /// The length of the DHCP message.
+    pub len: usize,
+    /// The source address to use. This is unspecified until a lease is acquired.
+    pub src: Ipv4Address,
+    pub dst: Ipv4Address,
+}
+
+/// The result of polling or handling a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// Send a message.
+    Send(Transmit),
+    /// A lease was acquired or renewed. See [Client::lease].
+    Configured,
+    /// The lease was lost, and the interface must be deconfigured.
+    Deconfigured,
+    /// Nothing to do.
+    Idle,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Start discovery at the deadline.
+    Init,
+    /// Waiting for an offer.
+    Selecting,
+    /// Waiting for the server to confirm an offer.
+    Requesting {
+        server: Ipv4Address,
+        address: Ipv4Address,
+    },
+    /// Holding a lease.
+    Bound,
+    /// Extending the lease with the server that granted it.
+    Renewing,
+    /// Extending the lease with any server.
+    Rebinding,
+}
+
+pub struct Client {
+    mac: [u8; 6],
+    state: State,
+    xid: u32,
+    rng: u32,
+    lease: Option<Lease>,
+    /// When the next message is due.
+    deadline: Instant,
+    retries: u32,
+}
+
+impl Client {
+    /// Create a new client for an interface with the specified MAC address.
+    /// `seed` is used to generate transaction IDs, and should differ between boots.
+    pub fn new(mac: [u8; 6], seed: u32) -> Self {
+        Self {
+            mac,
+            state: State::Init,
+            xid: 0,
+            // Xorshift gets stuck at zero.
+            rng: seed | 1,
+            lease: None,
+            deadline: Instant::from_millis(0),
+            retries: 0,
+        }
+    }
+
+    /// The current lease, if any.
+    pub fn lease(&self) -> Option<&Lease> {
+        self.lease.as_ref()
+    }
+
+    /// Drop any lease and restart discovery.
+    /// Returns [Step::Deconfigured] if a lease was held.
+    pub fn reset(&mut self, now: Instant) -> Step {
+        self.state = State::Init;
+        self.deadline = now;
+        self.retries = 0;
+
+        match self.lease.take() {
+            Some(_) => Step::Deconfigured,
+            None => Step::Idle,
+        }
+    }
+
+    /// The time at which [Client::poll] next needs to be called.
+    pub fn poll_at(&self) -> Instant {
+        match (self.state, &self.lease) {
+            (State::Bound, Some(lease)) => lease.renew_at,
+            (State::Renewing, Some(lease)) => self.deadline.min(lease.rebind_at),
+            (State::Rebinding, Some(lease)) => self.deadline.min(lease.expires_at),
+            _ => self.deadline,
+        }
+    }
+
+    fn next_xid(&mut self) -> u32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng
+    }
+
+    /// Exponential backoff for the current retry.
+    fn backoff(&self) -> Duration {
+        Duration::from_secs((INITIAL_TIMEOUT << self.retries.min(4)).min(MAX_TIMEOUT))
+    }
+
+    /// While renewing or rebinding, retransmit after half the time remaining until `end`.
+    fn renew_deadline(now: Instant, end: Instant) -> Instant {
+        let half = Duration::from_millis((end - now).total_millis() / 2);
+        now + half.max(Duration::from_secs(MIN_RENEW_TIMEOUT))
+    }
+
+    /// Advance timers, writing any message that needs to be sent into `out`.
+    /// This should be called until it returns [Step::Idle].
+    pub fn poll(&mut self, now: Instant, out: &mut [u8]) -> Result<Step, Error> {
+        let lease = self
+            .lease
+            .as_ref()
+            .map(|l| (l.renew_at, l.rebind_at, l.expires_at));
+
+        match self.state {
+            State::Init if now >= self.deadline => {
+                self.xid = self.next_xid();
+                self.retries = 0;
+                self.state = State::Selecting;
+                self.send_discover(now, out)
+            }
+
+            State::Selecting if now >= self.deadline => {
+                self.retries += 1;
+                self.send_discover(now, out)
+            }
+
+            State::Requesting { .. } if now >= self.deadline => {
+                if self.retries >= REQUEST_RETRIES {
+                    return Ok(self.reset(now));
+                }
+
+                self.retries += 1;
+                self.send_request(now, out)
+            }
+
+            State::Bound => match lease {
+                Some((renew_at, _, _)) if now >= renew_at => {
+                    self.xid = self.next_xid();
+                    self.state = State::Renewing;
+                    self.deadline = now;
+                    self.poll(now, out)
+                }
+
+                _ => Ok(Step::Idle),
+            },
+
+            State::Renewing => match lease {
+                Some((_, rebind_at, _)) if now >= rebind_at => {
+                    self.state = State::Rebinding;
+                    self.deadline = now;
+                    self.poll(now, out)
+                }
+
+                Some((_, rebind_at, _)) if now >= self.deadline => {
+                    self.deadline = Self::renew_deadline(now, rebind_at);
+                    self.send_request(now, out)
+                }
+
+                _ => Ok(Step::Idle),
+            },
+
+            State::Rebinding => match lease {
+                Some((_, _, expires_at)) if now >= expires_at => Ok(self.reset(now)),
+
+                Some((_, _, expires_at)) if now >= self.deadline => {
+                    self.deadline = Self::renew_deadline(now, expires_at);
+                    self.send_request(now, out)
+                }
+
+                _ => Ok(Step::Idle),
+            },
+
+            _ => Ok(Step::Idle),
+        }
+    }
+
+    fn send_discover(&mut self, now: Instant, out: &mut [u8]) -> Result<Step, Error> {
+        self.deadline = now + self.backoff();
+
+        let len = packet::write_request(
+            out,
+            &Request {
+                message_type: MessageType::Discover,
+                xid: self.xid,
+                broadcast: true,
+                chaddr: self.mac,
+                ciaddr: Ipv4Address::UNSPECIFIED,
+                requested_ip: None,
+                server_id: None,
+            },
+        )?;
+
+        Ok(Step::Send(Transmit {
+            len,
+            src: Ipv4Address::UNSPECIFIED,
+            dst: Ipv4Address::BROADCAST,
+        }))
+    }
+
+    fn send_request(&mut self, now: Instant, out: &mut [u8]) -> Result<Step, Error> {
+        let mut req = Request {
+            message_type: MessageType::Request,
+            xid: self.xid,
+            broadcast: false,
+            chaddr: self.mac,
+            ciaddr: Ipv4Address::UNSPECIFIED,
+            requested_ip: None,
+            server_id: None,
+        };
+
+        let ciaddr = self
+            .lease
+            .as_ref()
+            .map(|l| l.address.address())
+            .unwrap_or(Ipv4Address::UNSPECIFIED);
+
+        let (src, dst) = match self.state {
+            State::Requesting { server, address } => {
+                self.deadline = now + self.backoff();
+
+                req.broadcast = true;
+                req.requested_ip = Some(address);
+                req.server_id = Some(server);
+
+                (Ipv4Address::UNSPECIFIED, Ipv4Address::BROADCAST)
+            }
+
+            State::Renewing => {
+                req.ciaddr = ciaddr;
+
+                let server = self.lease.as_ref().map(|l| l.server);
+                (ciaddr, server.unwrap_or(Ipv4Address::BROADCAST))
+            }
+
+            _ => {
+                req.ciaddr = ciaddr;
+                (ciaddr, Ipv4Address::BROADCAST)
+            }
+        };
+
+        let len = packet::write_request(out, &req)?;
+        Ok(Step::Send(Transmit { len, src, dst }))
+    }
+
+    /// Handle a DHCP message received on the client port.
+    /// Messages that are not meant for us are ignored.
+    pub fn handle(&mut self, now: Instant, msg: &[u8]) -> Step {
+        let msg = match Message::parse(msg) {
+            Ok(msg) => msg,
+            Err(_) => return Step::Idle,
+        };
+
+        if msg.op != packet::OP_BOOTREPLY || msg.xid != self.xid || msg.chaddr != self.mac {
+            return Step::Idle;
+        }
+
+        match (self.state, msg.message_type()) {
+            (State::Selecting, Some(MessageType::Offer)) => {
+                let server = match msg.options.get_addr(opt::SERVER_ID) {
+                    Some(server) => server,
+                    None => return Step::Idle,
+                };
+
+                if msg.yiaddr.is_unspecified() {
+                    return Step::Idle;
+                }
+
+                // Take the first offer, and request it on the next poll.
+                self.state = State::Requesting {
+                    server,
+                    address: msg.yiaddr,
+                };
+                self.deadline = now;
+                self.retries = 0;
+
+                Step::Idle
+            }
+
+            (
+                State::Requesting { .. } | State::Renewing | State::Rebinding,
+                Some(MessageType::Ack),
+            ) => match Lease::from_ack(&msg, now) {
+                Some(lease) => {
+                    self.lease = Some(lease);
+                    self.state = State::Bound;
+                    self.retries = 0;
+
+                    Step::Configured
+                }
+
+                None => Step::Idle,
+            },
+
+            (
+                State::Requesting { .. } | State::Renewing | State::Rebinding,
+                Some(MessageType::Nak),
+            ) => self.reset(now),
+
+            _ => Step::Idle,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MAC: [u8; 6] = [0x69, 0x42, 0x00, 0x00, 0x00, 0x00];
+    const SERVER: Ipv4Address = Ipv4Address([192, 168, 1, 1]);
+    const ADDRESS: Ipv4Address = Ipv4Address([192, 168, 1, 50]);
+
+    /// Build a server reply to the request in `req`.
+    fn reply(req: &[u8], ty: MessageType, options: &[u8]) -> Vec<u8> {
+        let mut msg = req[..240].to_vec();
+        msg[0] = packet::OP_BOOTREPLY;
+        msg[16..20].copy_from_slice(&ADDRESS.0);
+        msg[20..24].copy_from_slice(&[192, 168, 1, 2]);
+        msg[108..119].copy_from_slice(b"pxelinux.0\0");
+
+        msg.extend_from_slice(&[opt::MESSAGE_TYPE, 1, ty as u8]);
+        msg.extend_from_slice(&[opt::SERVER_ID, 4]);
+        msg.extend_from_slice(&SERVER.0);
+        msg.extend_from_slice(options);
+        msg.push(opt::END);
+        msg
+    }
+
+    fn send(step: Result<Step, Error>) -> Transmit {
+        match step {
+            Ok(Step::Send(t)) => t,
+            s => panic!("expected a message, got {:?}", s),
+        }
+    }
+
+    fn secs(s: u64) -> Instant {
+        Instant::from_millis(s as i64 * 1000)
+    }
+
+    fn acquire(client: &mut Client, out: &mut [u8], options: &[u8]) {
+        let t = send(client.poll(secs(0), out));
+        assert_eq!(t.dst, Ipv4Address::BROADCAST);
+        assert_eq!(t.src, Ipv4Address::UNSPECIFIED);
+
+        let offer = reply(&out[..t.len], MessageType::Offer, &[]);
+        assert_eq!(client.handle(secs(1), &offer), Step::Idle);
+
+        let t = send(client.poll(secs(1), out));
+        let req = Message::parse(&out[..t.len]).unwrap();
+        assert_eq!(req.message_type(), Some(MessageType::Request));
+        assert_eq!(req.options.get_addr(opt::REQUESTED_IP), Some(ADDRESS));
+        assert_eq!(req.options.get_addr(opt::SERVER_ID), Some(SERVER));
+
+        let ack = reply(&out[..t.len], MessageType::Ack, options);
+        assert_eq!(client.handle(secs(1), &ack), Step::Configured);
+    }
+
+    #[test]
+    fn test_acquire() {
+        let mut out = [0u8; MAX_MESSAGE];
+        let mut client = Client::new(MAC, 1234);
+
+        #[rustfmt::skip]
+        let options = [
+            opt::SUBNET_MASK, 4, 255, 255, 0, 0,
+            opt::ROUTER, 4, 192, 168, 1, 1,
+            opt::DNS_SERVER, 8, 1, 1, 1, 1, 8, 8, 8, 8,
+            opt::LEASE_TIME, 4, 0, 0, 0x0E, 0x10,
+            opt::BOOTFILE, 7, b'v', b'm', b'l', b'i', b'n', b'u', b'x',
+        ];
+        acquire(&mut client, &mut out, &options);
+
+        let lease = client.lease().unwrap();
+        assert_eq!(lease.address, Ipv4Cidr::new(ADDRESS, 16));
+        assert_eq!(lease.router, Some(SERVER));
+        assert_eq!(
+            lease.dns_servers,
+            [Ipv4Address::new(1, 1, 1, 1), Ipv4Address::new(8, 8, 8, 8)]
+        );
+        assert_eq!(lease.server, SERVER);
+        assert_eq!(lease.next_server, Some(Ipv4Address::new(192, 168, 1, 2)));
+        assert_eq!(lease.tftp_server(), Some(Ipv4Address::new(192, 168, 1, 2)));
+        assert_eq!(lease.boot_file.as_deref(), Some("vmlinux"));
+        assert_eq!(lease.renew_at, secs(1 + 1800));
+        assert_eq!(lease.rebind_at, secs(1 + 3150));
+        assert_eq!(lease.expires_at, secs(1 + 3600));
+
+        assert_eq!(client.poll(secs(100), &mut out), Ok(Step::Idle));
+    }
+
+    #[test]
+    fn test_renew() {
+        let mut out = [0u8; MAX_MESSAGE];
+        let mut client = Client::new(MAC, 1);
+
+        acquire(
+            &mut client,
+            &mut out,
+            &[opt::LEASE_TIME, 4, 0, 0, 0x0E, 0x10],
+        );
+
+        // The boot file falls back to the `file` field.
+        assert_eq!(
+            client.lease().unwrap().boot_file.as_deref(),
+            Some("pxelinux.0")
+        );
+
+        // Renew by unicast at T1.
+        let t = send(client.poll(secs(1801), &mut out));
+        assert_eq!(t.src, ADDRESS);
+        assert_eq!(t.dst, SERVER);
+
+        let req = Message::parse(&out[..t.len]).unwrap();
+        assert_eq!(req.ciaddr, ADDRESS);
+        assert_eq!(req.options.get(opt::REQUESTED_IP), None);
+
+        // No answer; retransmit after half the time remaining until T2.
+        assert_eq!(client.poll(secs(2400), &mut out), Ok(Step::Idle));
+        send(client.poll(secs(2476), &mut out));
+
+        // ...but no more often than every 60 seconds.
+        send(client.poll(secs(3100), &mut out));
+        assert_eq!(client.poll(secs(3150), &mut out), Ok(Step::Idle));
+
+        // Rebind by broadcast at T2.
+        let t = send(client.poll(secs(3151), &mut out));
+        assert_eq!(t.src, ADDRESS);
+        assert_eq!(t.dst, Ipv4Address::BROADCAST);
+
+        let ack = reply(
+            &out[..t.len],
+            MessageType::Ack,
+            &[opt::LEASE_TIME, 4, 0, 0, 0x0E, 0x10],
+        );
+        assert_eq!(client.handle(secs(3152), &ack), Step::Configured);
+        assert_eq!(client.lease().unwrap().expires_at, secs(3152 + 3600));
+    }
+
+    #[test]
+    fn test_expire() {
+        let mut out = [0u8; MAX_MESSAGE];
+        let mut client = Client::new(MAC, 1);
+
+        acquire(
+            &mut client,
+            &mut out,
+            &[opt::LEASE_TIME, 4, 0, 0, 0x0E, 0x10],
+        );
+
+        send(client.poll(secs(1801), &mut out));
+        send(client.poll(secs(3151), &mut out));
+
+        // The lease is lost, and discovery restarts.
+        assert_eq!(client.poll(secs(3601), &mut out), Ok(Step::Deconfigured));
+        assert!(client.lease().is_none());
+
+        let t = send(client.poll(secs(3601), &mut out));
+        let msg = Message::parse(&out[..t.len]).unwrap();
+        assert_eq!(msg.message_type(), Some(MessageType::Discover));
+    }
+
+    #[test]
+    fn test_nak() {
+        let mut out = [0u8; MAX_MESSAGE];
+        let mut client = Client::new(MAC, 1);
+
+        acquire(&mut client, &mut out, &[]);
+
+        let t = send(client.poll(secs(u32::MAX as u64 / 2 + 2), &mut out));
+        let nak = reply(&out[..t.len], MessageType::Nak, &[]);
+
+        // Replies for other clients are ignored.
+        let mut other = nak.clone();
+        other[4] ^= 1;
+        assert_eq!(client.handle(secs(0), &other), Step::Idle);
+
+        assert_eq!(client.handle(secs(0), &nak), Step::Deconfigured);
+        assert!(client.lease().is_none());
+    }
+}
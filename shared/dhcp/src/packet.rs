@@ -0,0 +1,537 @@
+//! This module contains DHCP message encoding and decoding (RFC 2131, RFC 2132),
+//! along with the IPv4/UDP framing needed to exchange messages before the
+//! interface has an address.
+
+use smoltcp::wire::Ipv4Address;
+
+use crate::Error;
+
+pub const SERVER_PORT: u16 = 67;
+pub const CLIENT_PORT: u16 = 68;
+
+pub const OP_BOOTREQUEST: u8 = 1;
+pub const OP_BOOTREPLY: u8 = 2;
+
+const HTYPE_ETHERNET: u8 = 1;
+const MAGIC_COOKIE: [u8; 4] = [0x63, 0x82, 0x53, 0x63];
+
+/// Asks the server to broadcast its replies, as we cannot receive unicast
+/// datagrams until an address is assigned.
+pub const FLAG_BROADCAST: u16 = 0x8000;
+
+/// The offset of the options field (after the magic cookie).
+const OPTIONS_OFFSET: usize = 240;
+
+/// BOOTP relays may drop messages shorter than this.
+const MIN_MESSAGE_LEN: usize = 300;
+
+/// DHCP option codes, as defined by RFC 2132.
+#[allow(dead_code)]
+pub mod opt {
+    pub const PAD: u8 = 0;
+    pub const SUBNET_MASK: u8 = 1;
+    pub const ROUTER: u8 = 3;
+    pub const DNS_SERVER: u8 = 6;
+    pub const REQUESTED_IP: u8 = 50;
+    pub const LEASE_TIME: u8 = 51;
+    pub const MESSAGE_TYPE: u8 = 53;
+    pub const SERVER_ID: u8 = 54;
+    pub const PARAMETER_LIST: u8 = 55;
+    pub const MAX_MESSAGE_SIZE: u8 = 57;
+    pub const RENEWAL_TIME: u8 = 58;
+    pub const REBINDING_TIME: u8 = 59;
+    pub const CLIENT_ID: u8 = 61;
+    pub const TFTP_SERVER: u8 = 66;
+    pub const BOOTFILE: u8 = 67;
+    pub const END: u8 = 255;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageType {
+    Discover = 1,
+    Offer = 2,
+    Request = 3,
+    Decline = 4,
+    Ack = 5,
+    Nak = 6,
+    Release = 7,
+    Inform = 8,
+}
+
+impl MessageType {
+    fn from_u8(n: u8) -> Option<Self> {
+        Some(match n {
+            1 => Self::Discover,
+            2 => Self::Offer,
+            3 => Self::Request,
+            4 => Self::Decline,
+            5 => Self::Ack,
+            6 => Self::Nak,
+            7 => Self::Release,
+            8 => Self::Inform,
+            _ => return None,
+        })
+    }
+}
+
+/// A received DHCP message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Message<'a> {
+    pub op: u8,
+    pub xid: u32,
+    pub flags: u16,
+    pub ciaddr: Ipv4Address,
+    pub yiaddr: Ipv4Address,
+    pub siaddr: Ipv4Address,
+    pub giaddr: Ipv4Address,
+    pub chaddr: [u8; 6],
+    /// The server host name, with trailing NULs removed.
+    pub sname: &'a [u8],
+    /// The boot file name, with trailing NULs removed.
+    pub file: &'a [u8],
+    pub options: Options<'a>,
+}
+
+fn addr(buf: &[u8]) -> Ipv4Address {
+    Ipv4Address::from_bytes(&buf[..4])
+}
+
+fn trim_nul(buf: &[u8]) -> &[u8] {
+    let end = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    &buf[..end]
+}
+
+impl<'a> Message<'a> {
+    pub fn parse(buf: &'a [u8]) -> Result<Self, Error> {
+        if buf.len() < OPTIONS_OFFSET || buf[236..240] != MAGIC_COOKIE {
+            return Err(Error::Malformed);
+        }
+
+        // Only ethernet hardware addresses are supported.
+        if buf[1] != HTYPE_ETHERNET || buf[2] != 6 {
+            return Err(Error::Malformed);
+        }
+
+        let mut chaddr = [0u8; 6];
+        chaddr.copy_from_slice(&buf[28..34]);
+
+        Ok(Self {
+            op: buf[0],
+            xid: u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]),
+            flags: u16::from_be_bytes([buf[10], buf[11]]),
+            ciaddr: addr(&buf[12..]),
+            yiaddr: addr(&buf[16..]),
+            siaddr: addr(&buf[20..]),
+            giaddr: addr(&buf[24..]),
+            chaddr,
+            sname: trim_nul(&buf[44..108]),
+            file: trim_nul(&buf[108..236]),
+            options: Options(&buf[OPTIONS_OFFSET..]),
+        })
+    }
+
+    pub fn message_type(&self) -> Option<MessageType> {
+        self.options
+            .get(opt::MESSAGE_TYPE)
+            .and_then(|v| v.first().copied())
+            .and_then(MessageType::from_u8)
+    }
+}
+
+/// The options field of a message.
+///
+/// N.B: Option overloading (RFC 2132 section 9.3) is not supported; options
+/// stored in the `sname` and `file` fields are ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Options<'a>(&'a [u8]);
+
+impl<'a> Options<'a> {
+    /// Iterate over all options as `(code, value)` pairs.
+    /// Iteration stops at the end option or the first truncated option.
+    pub fn iter(&self) -> impl Iterator<Item = (u8, &'a [u8])> {
+        let mut buf = self.0;
+
+        core::iter::from_fn(move || loop {
+            match *buf {
+                [opt::PAD, ref rest @ ..] => buf = rest,
+                [opt::END, ..] | [] | [_] => return None,
+                [code, len, ref rest @ ..] => {
+                    let len = len as usize;
+                    if rest.len() < len {
+                        return None;
+                    }
+
+                    let (value, rest) = rest.split_at(len);
+                    buf = rest;
+
+                    return Some((code, value));
+                }
+            }
+        })
+    }
+
+    /// Look up the value of an option.
+    pub fn get(&self, code: u8) -> Option<&'a [u8]> {
+        self.iter().find(|(c, _)| *c == code).map(|(_, v)| v)
+    }
+
+    /// Look up an option holding a single IPv4 address.
+    pub fn get_addr(&self, code: u8) -> Option<Ipv4Address> {
+        self.get(code).filter(|v| v.len() == 4).map(addr)
+    }
+
+    /// Look up an option holding a list of IPv4 addresses.
+    pub fn get_addrs(&self, code: u8) -> impl Iterator<Item = Ipv4Address> + 'a {
+        self.get(code).unwrap_or(&[]).chunks_exact(4).map(addr)
+    }
+
+    /// Look up an option holding a 32-bit integer.
+    pub fn get_u32(&self, code: u8) -> Option<u32> {
+        self.get(code)
+            .filter(|v| v.len() == 4)
+            .map(|v| u32::from_be_bytes([v[0], v[1], v[2], v[3]]))
+    }
+}
+
+/// A message sent by the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Request {
+    pub message_type: MessageType,
+    pub xid: u32,
+    pub broadcast: bool,
+    pub chaddr: [u8; 6],
+    /// Our current address, when renewing or rebinding a lease.
+    pub ciaddr: Ipv4Address,
+    pub requested_ip: Option<Ipv4Address>,
+    pub server_id: Option<Ipv4Address>,
+}
+
+/// The options we ask servers to include in their replies.
+const PARAMETER_LIST: &[u8] = &[
+    opt::SUBNET_MASK,
+    opt::ROUTER,
+    opt::DNS_SERVER,
+    opt::LEASE_TIME,
+    opt::SERVER_ID,
+    opt::RENEWAL_TIME,
+    opt::REBINDING_TIME,
+    opt::TFTP_SERVER,
+    opt::BOOTFILE,
+];
+
+/// Emit a client message into `buf`, returning its length.
+pub fn write_request(buf: &mut [u8], req: &Request) -> Result<usize, Error> {
+    let mut w = Writer::new(buf);
+
+    w.put(&[OP_BOOTREQUEST, HTYPE_ETHERNET, 6, 0])?;
+    w.put(&req.xid.to_be_bytes())?;
+    // secs
+    w.put(&[0, 0])?;
+    w.put(&(if req.broadcast { FLAG_BROADCAST } else { 0 }).to_be_bytes())?;
+    w.put(req.ciaddr.as_bytes())?;
+    // yiaddr, siaddr, giaddr
+    w.zero(12)?;
+    w.put(&req.chaddr)?;
+    // The remainder of chaddr, sname and file.
+    w.zero(10 + 64 + 128)?;
+    w.put(&MAGIC_COOKIE)?;
+
+    w.put_option(opt::MESSAGE_TYPE, &[req.message_type as u8])?;
+
+    let mut client_id = [HTYPE_ETHERNET, 0, 0, 0, 0, 0, 0];
+    client_id[1..].copy_from_slice(&req.chaddr);
+    w.put_option(opt::CLIENT_ID, &client_id)?;
+
+    if let Some(ip) = req.requested_ip {
+        w.put_option(opt::REQUESTED_IP, ip.as_bytes())?;
+    }
+
+    if let Some(ip) = req.server_id {
+        w.put_option(opt::SERVER_ID, ip.as_bytes())?;
+    }
+
+    w.put_option(opt::MAX_MESSAGE_SIZE, &1500u16.to_be_bytes())?;
+    w.put_option(opt::PARAMETER_LIST, PARAMETER_LIST)?;
+    w.put(&[opt::END])?;
+
+    if w.len < MIN_MESSAGE_LEN {
+        w.zero(MIN_MESSAGE_LEN - w.len)?;
+    }
+
+    Ok(w.len)
+}
+
+/// A simple cursor used to emit packets into a buffer.
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Writer<'a> {
+    fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, len: 0 }
+    }
+
+    fn reserve(&mut self, len: usize) -> Result<&mut [u8], Error> {
+        let dst = self
+            .buf
+            .get_mut(self.len..self.len + len)
+            .ok_or(Error::BufferTooSmall)?;
+
+        self.len += len;
+        Ok(dst)
+    }
+
+    fn put(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.reserve(data.len())?.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn zero(&mut self, len: usize) -> Result<(), Error> {
+        self.reserve(len)?.fill(0);
+        Ok(())
+    }
+
+    fn put_option(&mut self, code: u8, value: &[u8]) -> Result<(), Error> {
+        self.put(&[code, value.len() as u8])?;
+        self.put(value)
+    }
+}
+
+/// A UDP datagram extracted from an IPv4 packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Datagram<'a> {
+    pub src: Ipv4Address,
+    pub dst: Ipv4Address,
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub payload: &'a [u8],
+}
+
+const IPV4_HEADER_LEN: usize = 20;
+const UDP_HEADER_LEN: usize = 8;
+const IPPROTO_UDP: u8 = 17;
+
+/// Compute the ones' complement sum used by the IPv4 and UDP checksums.
+fn checksum(mut sum: u32, data: &[u8]) -> u32 {
+    let mut chunks = data.chunks_exact(2);
+    for c in &mut chunks {
+        sum += u16::from_be_bytes([c[0], c[1]]) as u32;
+    }
+
+    if let [b] = chunks.remainder() {
+        sum += (*b as u32) << 8;
+    }
+
+    sum
+}
+
+fn fold(mut sum: u32) -> u16 {
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+
+    !(sum as u16)
+}
+
+fn udp_pseudo_header(src: Ipv4Address, dst: Ipv4Address, len: usize) -> u32 {
+    let sum = checksum(0, src.as_bytes());
+    let sum = checksum(sum, dst.as_bytes());
+    sum + IPPROTO_UDP as u32 + len as u32
+}
+
+/// Wrap `datagram` in UDP and IPv4 headers, returning the length of the packet.
+pub fn write_ipv4_udp(buf: &mut [u8], datagram: &Datagram) -> Result<usize, Error> {
+    let udp_len = UDP_HEADER_LEN + datagram.payload.len();
+    let total_len = IPV4_HEADER_LEN + udp_len;
+    if total_len > buf.len() || total_len > u16::MAX as usize {
+        return Err(Error::BufferTooSmall);
+    }
+
+    let (ip, rest) = buf.split_at_mut(IPV4_HEADER_LEN);
+    let (udp, rest) = rest.split_at_mut(UDP_HEADER_LEN);
+    rest[..datagram.payload.len()].copy_from_slice(datagram.payload);
+
+    // Version 4, 5-word header, no options. Don't fragment, TTL 64.
+    ip[0] = 0x45;
+    ip[1] = 0;
+    ip[2..4].copy_from_slice(&(total_len as u16).to_be_bytes());
+    ip[4..6].fill(0);
+    ip[6..8].copy_from_slice(&0x4000u16.to_be_bytes());
+    ip[8] = 64;
+    ip[9] = IPPROTO_UDP;
+    ip[10..12].fill(0);
+    ip[12..16].copy_from_slice(datagram.src.as_bytes());
+    ip[16..20].copy_from_slice(datagram.dst.as_bytes());
+
+    let sum = fold(checksum(0, ip));
+    ip[10..12].copy_from_slice(&sum.to_be_bytes());
+
+    udp[0..2].copy_from_slice(&datagram.src_port.to_be_bytes());
+    udp[2..4].copy_from_slice(&datagram.dst_port.to_be_bytes());
+    udp[4..6].copy_from_slice(&(udp_len as u16).to_be_bytes());
+    udp[6..8].fill(0);
+
+    let sum = udp_pseudo_header(datagram.src, datagram.dst, udp_len);
+    let sum = checksum(checksum(sum, udp), datagram.payload);
+    // A computed checksum of zero is transmitted as all ones.
+    let sum = match fold(sum) {
+        0 => 0xFFFF,
+        n => n,
+    };
+    udp[6..8].copy_from_slice(&sum.to_be_bytes());
+
+    Ok(total_len)
+}
+
+/// Extract the UDP datagram from an IPv4 packet.
+/// Fragmented packets and packets with bad checksums are rejected.
+pub fn parse_ipv4_udp(buf: &[u8]) -> Result<Datagram<'_>, Error> {
+    if buf.len() < IPV4_HEADER_LEN || buf[0] >> 4 != 4 {
+        return Err(Error::Malformed);
+    }
+
+    let header_len = ((buf[0] & 0xF) as usize) * 4;
+    let total_len = u16::from_be_bytes([buf[2], buf[3]]) as usize;
+    if header_len < IPV4_HEADER_LEN
+        || total_len < header_len + UDP_HEADER_LEN
+        || total_len > buf.len()
+    {
+        return Err(Error::Malformed);
+    }
+
+    // More fragments, or a nonzero fragment offset.
+    if u16::from_be_bytes([buf[6], buf[7]]) & 0x3FFF != 0 {
+        return Err(Error::Malformed);
+    }
+
+    if buf[9] != IPPROTO_UDP || fold(checksum(0, &buf[..header_len])) != 0 {
+        return Err(Error::Malformed);
+    }
+
+    let src = addr(&buf[12..]);
+    let dst = addr(&buf[16..]);
+
+    let udp = &buf[header_len..total_len];
+    let udp_len = u16::from_be_bytes([udp[4], udp[5]]) as usize;
+    if udp_len < UDP_HEADER_LEN || udp_len > udp.len() {
+        return Err(Error::Malformed);
+    }
+
+    let udp = &udp[..udp_len];
+
+    // A zero checksum means the sender did not compute one.
+    if udp[6..8] != [0, 0] {
+        let sum = udp_pseudo_header(src, dst, udp_len);
+        if fold(checksum(sum, udp)) != 0 {
+            return Err(Error::Malformed);
+        }
+    }
+
+    Ok(Datagram {
+        src,
+        dst,
+        src_port: u16::from_be_bytes([udp[0], udp[1]]),
+        dst_port: u16::from_be_bytes([udp[2], udp[3]]),
+        payload: &udp[UDP_HEADER_LEN..],
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MAC: [u8; 6] = [0x69, 0x42, 0x00, 0x00, 0x00, 0x00];
+
+    #[test]
+    fn test_request() {
+        let req = Request {
+            message_type: MessageType::Request,
+            xid: 0x1234_5678,
+            broadcast: true,
+            chaddr: MAC,
+            ciaddr: Ipv4Address::UNSPECIFIED,
+            requested_ip: Some(Ipv4Address::new(192, 168, 1, 50)),
+            server_id: Some(Ipv4Address::new(192, 168, 1, 1)),
+        };
+
+        let mut buf = [0u8; 576];
+        let n = write_request(&mut buf, &req).unwrap();
+
+        assert_eq!(n, MIN_MESSAGE_LEN);
+        assert_eq!(
+            &buf[..12],
+            b"\x01\x01\x06\x00\x12\x34\x56\x78\x00\x00\x80\x00"
+        );
+
+        // Our own messages parse back, apart from the opcode.
+        let msg = Message::parse(&buf[..n]).unwrap();
+        assert_eq!(msg.op, OP_BOOTREQUEST);
+        assert_eq!(msg.xid, 0x1234_5678);
+        assert_eq!(msg.chaddr, MAC);
+        assert_eq!(msg.message_type(), Some(MessageType::Request));
+        assert_eq!(
+            msg.options.get_addr(opt::REQUESTED_IP),
+            Some(Ipv4Address::new(192, 168, 1, 50))
+        );
+        assert_eq!(
+            msg.options.get_addr(opt::SERVER_ID),
+            Some(Ipv4Address::new(192, 168, 1, 1))
+        );
+        assert_eq!(msg.options.get(opt::PARAMETER_LIST), Some(PARAMETER_LIST));
+
+        assert_eq!(
+            write_request(&mut buf[..200], &req),
+            Err(Error::BufferTooSmall)
+        );
+    }
+
+    #[test]
+    fn test_options() {
+        let opts = Options(b"\x00\x00\x01\x04\xff\xff\xff\x00\x06\x08\x08\x08\x08\x08\x01\x01\x01\x01\x43\x03abc\xff\x03\x04\x01\x02\x03\x04");
+
+        assert_eq!(
+            opts.get_addr(opt::SUBNET_MASK),
+            Some(Ipv4Address::new(255, 255, 255, 0))
+        );
+        assert_eq!(opts.get_addrs(opt::DNS_SERVER).count(), 2);
+        assert_eq!(opts.get(opt::BOOTFILE), Some(&b"abc"[..]));
+
+        // Options past the end marker are ignored.
+        assert_eq!(opts.get(opt::ROUTER), None);
+
+        // Truncated options are ignored.
+        assert_eq!(Options(b"\x33\x04\x00\x00").get_u32(opt::LEASE_TIME), None);
+    }
+
+    #[test]
+    fn test_ipv4_udp() {
+        let mut buf = [0u8; 64];
+        let datagram = Datagram {
+            src: Ipv4Address::new(10, 0, 0, 1),
+            dst: Ipv4Address::BROADCAST,
+            src_port: SERVER_PORT,
+            dst_port: CLIENT_PORT,
+            payload: b"hello",
+        };
+
+        let n = write_ipv4_udp(&mut buf, &datagram).unwrap();
+        assert_eq!(n, 33);
+        assert_eq!(
+            &buf[..20],
+            b"\x45\x00\x00\x21\x00\x00\x40\x00\x40\x11\x30\xcc\x0a\x00\x00\x01\xff\xff\xff\xff"
+        );
+
+        assert_eq!(parse_ipv4_udp(&buf[..n]), Ok(datagram));
+
+        // Corrupt the payload.
+        buf[n - 1] ^= 1;
+        assert_eq!(parse_ipv4_udp(&buf[..n]), Err(Error::Malformed));
+
+        // A zero checksum is accepted.
+        buf[26..28].fill(0);
+        assert!(parse_ipv4_udp(&buf[..n]).is_ok());
+
+        assert_eq!(parse_ipv4_udp(&buf[..n - 1]), Err(Error::Malformed));
+    }
+}
@@ -0,0 +1,277 @@
+//! Core file writer.
+//!
+//! A core file is laid out as the file header, a program header table, a `PT_NOTE` segment
+//! holding an `NT_PRSTATUS` note per thread, and then the contents of each memory segment
+//! in order. Everything up to the memory is built in a buffer by [write_header]. Memory is
+//! large, so the caller sends it on afterwards, straight from where it lies.
+//!
+//! The notes follow the Linux ppc64 layout, which is what GDB understands. It may need to be
+//! told to use it with `set osabi GNU/Linux` before loading the file.
+
+use crate::{
+    Error, EHDR_SIZE, ELFCLASS64, ELFDATA2MSB, EM_PPC64, ET_CORE, EV_CURRENT, PF_R, PF_W, PF_X,
+    PHDR_SIZE, PT_LOAD, PT_NOTE,
+};
+
+/// Note type: Thread status (`struct elf_prstatus`).
+pub const NT_PRSTATUS: u32 = 1;
+
+/// The name of every note we write.
+const NOTE_NAME: &[u8] = b"CORE\0";
+
+/// The size of a note header, and of the padded note name.
+const NOTE_HEADER_SIZE: usize = 12;
+const NOTE_NAME_SIZE: usize = 8;
+
+/// The size of `struct elf_prstatus` on ppc64, and the offsets of its fields.
+const PRSTATUS_SIZE: usize = 504;
+const PR_CURSIG: usize = 12;
+const PR_PID: usize = 32;
+const PR_REG: usize = 112;
+
+const NOTE_SIZE: usize = NOTE_HEADER_SIZE + NOTE_NAME_SIZE + PRSTATUS_SIZE;
+
+/// A thread's registers, in the order of the kernel's `struct pt_regs`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Registers {
+    pub gpr: [u64; 32],
+    pub pc: u64,
+    pub msr: u64,
+    pub ctr: u64,
+    pub lr: u64,
+    pub xer: u64,
+    pub cr: u64,
+    /// The exception vector the thread was stopped by.
+    pub trap: u64,
+    pub dar: u64,
+    pub dsisr: u64,
+}
+
+impl Registers {
+    /// The registers as they appear in `pr_reg`.
+    fn to_array(self) -> [u64; 48] {
+        let mut out = [0u64; 48];
+
+        out[..32].copy_from_slice(&self.gpr);
+        out[32] = self.pc;
+        out[33] = self.msr;
+        out[34] = self.gpr[3]; // orig_gpr3
+        out[35] = self.ctr;
+        out[36] = self.lr;
+        out[37] = self.xer;
+        out[38] = self.cr;
+        out[40] = self.trap;
+        out[41] = self.dar;
+        out[42] = self.dsisr;
+
+        out
+    }
+}
+
+/// A thread to describe in the core file.
+#[derive(Debug, Clone, Copy)]
+pub struct Thread {
+    /// The thread ID. GDB shows this as the LWP.
+    pub tid: u32,
+    /// The signal the thread was stopped with, or 0.
+    pub signal: u16,
+    pub regs: Registers,
+}
+
+/// A range of memory to include in the core file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment {
+    /// The address the memory is seen at by the program.
+    pub vaddr: u64,
+    /// The physical address of the memory.
+    pub paddr: u64,
+    pub size: u64,
+}
+
+/// The size of the headers for a core file with `threads` threads and `segments` segments.
+pub fn header_size(threads: usize, segments: usize) -> usize {
+    EHDR_SIZE + PHDR_SIZE * (1 + segments) + NOTE_SIZE * threads
+}
+
+/// The size of a core file, including the contents of every segment.
+pub fn file_size(threads: &[Thread], segments: &[Segment]) -> u64 {
+    let data: u64 = segments.iter().map(|s| s.size).sum();
+    header_size(threads.len(), segments.len()) as u64 + data
+}
+
+/// Write the headers of a core file into `buf`, returning their length. The contents of
+/// `segments` are expected to follow, in order.
+///
+/// The first thread is the one GDB selects when the file is loaded.
+pub fn write_header(
+    buf: &mut [u8],
+    threads: &[Thread],
+    segments: &[Segment],
+) -> Result<usize, Error> {
+    let len = header_size(threads.len(), segments.len());
+    let buf = buf.get_mut(..len).ok_or(Error::NoSpace)?;
+    buf.fill(0);
+
+    let phnum = 1 + segments.len();
+    let notes = EHDR_SIZE + PHDR_SIZE * phnum;
+
+    // File header.
+    buf[0..4].copy_from_slice(b"\x7FELF");
+    buf[4] = ELFCLASS64;
+    buf[5] = ELFDATA2MSB;
+    buf[6] = EV_CURRENT as u8;
+    put_u16(buf, 16, ET_CORE);
+    put_u16(buf, 18, EM_PPC64);
+    put_u32(buf, 20, EV_CURRENT);
+    put_u64(buf, 32, EHDR_SIZE as u64); // e_phoff
+    put_u16(buf, 52, EHDR_SIZE as u16);
+    put_u16(buf, 54, PHDR_SIZE as u16);
+    put_u16(buf, 56, phnum as u16);
+
+    // The notes.
+    let ph = &mut buf[EHDR_SIZE..EHDR_SIZE + PHDR_SIZE];
+    put_u32(ph, 0, PT_NOTE);
+    put_u64(ph, 8, notes as u64);
+    put_u64(ph, 32, (NOTE_SIZE * threads.len()) as u64);
+    put_u64(ph, 48, 4);
+
+    // The memory.
+    let mut offset = len as u64;
+    for (i, seg) in segments.iter().enumerate() {
+        let off = EHDR_SIZE + PHDR_SIZE * (1 + i);
+        let ph = &mut buf[off..off + PHDR_SIZE];
+
+        put_u32(ph, 0, PT_LOAD);
+        put_u32(ph, 4, PF_R | PF_W | PF_X);
+        put_u64(ph, 8, offset);
+        put_u64(ph, 16, seg.vaddr);
+        put_u64(ph, 24, seg.paddr);
+        put_u64(ph, 32, seg.size);
+        put_u64(ph, 40, seg.size);
+        put_u64(ph, 48, 1);
+
+        offset += seg.size;
+    }
+
+    for (i, thread) in threads.iter().enumerate() {
+        let off = notes + NOTE_SIZE * i;
+        let note = &mut buf[off..off + NOTE_SIZE];
+
+        put_u32(note, 0, NOTE_NAME.len() as u32);
+        put_u32(note, 4, PRSTATUS_SIZE as u32);
+        put_u32(note, 8, NT_PRSTATUS);
+        note[NOTE_HEADER_SIZE..NOTE_HEADER_SIZE + NOTE_NAME.len()].copy_from_slice(NOTE_NAME);
+
+        let status = &mut note[NOTE_HEADER_SIZE + NOTE_NAME_SIZE..];
+        put_u32(status, 0, thread.signal as u32); // si_signo
+        put_u16(status, PR_CURSIG, thread.signal);
+        put_u32(status, PR_PID, thread.tid);
+
+        for (j, reg) in thread.regs.to_array().iter().enumerate() {
+            put_u64(status, PR_REG + j * 8, *reg);
+        }
+    }
+
+    Ok(len)
+}
+
+fn put_u16(buf: &mut [u8], off: usize, val: u16) {
+    buf[off..off + 2].copy_from_slice(&val.to_be_bytes());
+}
+
+fn put_u32(buf: &mut [u8], off: usize, val: u32) {
+    buf[off..off + 4].copy_from_slice(&val.to_be_bytes());
+}
+
+fn put_u64(buf: &mut [u8], off: usize, val: u64) {
+    buf[off..off + 8].copy_from_slice(&val.to_be_bytes());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{read_u16, read_u32, read_u64, FileHeader, ProgramHeader};
+
+    #[test]
+    fn test_header() {
+        let mut regs = Registers::default();
+        regs.gpr[1] = 0x1DFF_FF00;
+        regs.gpr[3] = 0x33;
+        regs.pc = 0x8000_0000_0000_1234;
+        regs.trap = 0x300;
+
+        let threads = [
+            Thread {
+                tid: 3,
+                signal: 11,
+                regs,
+            },
+            Thread {
+                tid: 1,
+                signal: 0,
+                regs: Registers::default(),
+            },
+        ];
+        let segments = [
+            Segment {
+                vaddr: 0x8000_0000_0000_0000,
+                paddr: 0,
+                size: 0x1000,
+            },
+            Segment {
+                vaddr: 0x8000_0000_1EFF_0000,
+                paddr: 0x1EFF_0000,
+                size: 0x10000,
+            },
+        ];
+
+        let mut buf = [0xA5u8; 4096];
+        let len = write_header(&mut buf, &threads, &segments).unwrap();
+        assert_eq!(len, header_size(2, 2));
+        assert_eq!(file_size(&threads, &segments), len as u64 + 0x11000);
+
+        let hdr = FileHeader::parse(&buf).unwrap();
+        assert_eq!(hdr.e_type, ET_CORE);
+        assert_eq!(hdr.e_machine, EM_PPC64);
+        assert_eq!(hdr.e_phnum, 3);
+
+        let phdr = |i: usize| {
+            let off = hdr.e_phoff as usize + i * PHDR_SIZE;
+            ProgramHeader::parse(&buf[off..off + PHDR_SIZE])
+        };
+
+        let note = phdr(0);
+        assert_eq!(note.p_type, PT_NOTE);
+        assert_eq!(note.p_filesz as usize, NOTE_SIZE * 2);
+
+        let load = phdr(2);
+        assert!(load.is_load());
+        assert_eq!(load.p_offset, len as u64 + 0x1000);
+        assert_eq!(load.p_vaddr, 0x8000_0000_1EFF_0000);
+        assert_eq!(load.phys_range(), 0x1EFF_0000..0x1F00_0000);
+
+        // The first thread's status.
+        let n = &buf[note.p_offset as usize..];
+        assert_eq!(read_u32(n, 0), 5);
+        assert_eq!(read_u32(n, 4), PRSTATUS_SIZE as u32);
+        assert_eq!(read_u32(n, 8), NT_PRSTATUS);
+        assert_eq!(&n[12..17], b"CORE\0");
+
+        let status = &n[NOTE_HEADER_SIZE + NOTE_NAME_SIZE..];
+        assert_eq!(read_u16(status, PR_CURSIG), 11);
+        assert_eq!(read_u32(status, PR_PID), 3);
+        assert_eq!(read_u64(status, PR_REG + 8), 0x1DFF_FF00);
+        assert_eq!(read_u64(status, PR_REG + 32 * 8), 0x8000_0000_0000_1234);
+        assert_eq!(read_u64(status, PR_REG + 34 * 8), 0x33);
+        assert_eq!(read_u64(status, PR_REG + 40 * 8), 0x300);
+
+        // The second thread follows.
+        assert_eq!(read_u32(&n[NOTE_SIZE..], 8), NT_PRSTATUS);
+        assert_eq!(read_u32(&n[NOTE_SIZE + 20..], PR_PID), 1);
+
+        assert_eq!(
+            write_header(&mut buf[..len - 1], &threads, &segments),
+            Err(Error::NoSpace)
+        );
+    }
+}
@@ -0,0 +1,513 @@
+//! Minimal ELF64 parser for PowerPC64 big-endian executables.
+//!
+//! This only implements what is necessary to validate an executable image and
+//! find the segments that need to be placed in memory. No section headers, symbols
+//! or relocations are interpreted.
+//!
+//! [coredump] writes the headers of a core file, so a crashed system can be examined
+//! offline.
+#![no_std]
+
+pub mod coredump;
+
+use core::fmt;
+
+/// `e_ident[EI_CLASS]`: 64-bit objects.
+pub const ELFCLASS64: u8 = 2;
+/// `e_ident[EI_DATA]`: Big-endian data encoding.
+pub const ELFDATA2MSB: u8 = 2;
+/// `e_ident[EI_VERSION]` and `e_version`: Current version.
+pub const EV_CURRENT: u32 = 1;
+
+/// `e_type`: Executable file.
+pub const ET_EXEC: u16 = 2;
+/// `e_type`: Shared object (position-independent executable).
+pub const ET_DYN: u16 = 3;
+/// `e_type`: Core file.
+pub const ET_CORE: u16 = 4;
+
+/// `e_machine`: 64-bit PowerPC.
+pub const EM_PPC64: u16 = 21;
+
+/// `p_type`: Loadable segment.
+pub const PT_LOAD: u32 = 1;
+/// `p_type`: Auxiliary information (notes).
+pub const PT_NOTE: u32 = 4;
+
+/// `p_flags`: Execute permission.
+pub const PF_X: u32 = 1;
+/// `p_flags`: Write permission.
+pub const PF_W: u32 = 2;
+/// `p_flags`: Read permission.
+pub const PF_R: u32 = 4;
+
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The buffer is too small to contain an ELF header.
+    Truncated,
+    /// The file does not start with `\x7FELF`.
+    BadMagic,
+    /// The file is not a 64-bit object.
+    BadClass,
+    /// The file is not big-endian.
+    BadEndianness,
+    /// The file version is unknown.
+    BadVersion,
+    /// The file is not an executable.
+    BadType(u16),
+    /// The file is not built for PowerPC64.
+    BadMachine(u16),
+    /// The header sizes do not match the ELF64 structures.
+    BadHeaderSize,
+    /// The program header table is out of bounds of the file.
+    BadProgramHeaders,
+    /// A segment's file contents are out of bounds of the file.
+    SegmentOutOfBounds(usize),
+    /// A segment has a file size larger than its memory size.
+    SegmentTooLarge(usize),
+    /// A segment's address range wraps around the address space.
+    SegmentOverflow(usize),
+    /// Two loadable segments occupy the same physical memory.
+    SegmentOverlap(usize, usize),
+    /// The file does not contain any loadable segments.
+    NoLoadableSegments,
+    /// The entry point does not lie within a loadable segment.
+    EntryNotMapped(u64),
+    /// The output buffer is too small.
+    NoSpace,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Truncated => write!(f, "file truncated"),
+            Error::BadMagic => write!(f, "bad ELF magic"),
+            Error::BadClass => write!(f, "not a 64-bit ELF"),
+            Error::BadEndianness => write!(f, "not a big-endian ELF"),
+            Error::BadVersion => write!(f, "unknown ELF version"),
+            Error::BadType(t) => write!(f, "not an executable (e_type {})", t),
+            Error::BadMachine(m) => write!(f, "not a PowerPC64 ELF (e_machine {})", m),
+            Error::BadHeaderSize => write!(f, "bad header size"),
+            Error::BadProgramHeaders => write!(f, "program headers out of bounds"),
+            Error::SegmentOutOfBounds(i) => write!(f, "segment {} out of bounds", i),
+            Error::SegmentTooLarge(i) => write!(f, "segment {} filesz > memsz", i),
+            Error::SegmentOverflow(i) => write!(f, "segment {} wraps the address space", i),
+            Error::SegmentOverlap(a, b) => write!(f, "segments {} and {} overlap", a, b),
+            Error::NoLoadableSegments => write!(f, "no loadable segments"),
+            Error::EntryNotMapped(e) => write!(f, "entry point {:016X} not mapped", e),
+            Error::NoSpace => write!(f, "output buffer too small"),
+        }
+    }
+}
+
+fn read_u16(data: &[u8], off: usize) -> u16 {
+    u16::from_be_bytes([data[off], data[off + 1]])
+}
+
+fn read_u32(data: &[u8], off: usize) -> u32 {
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(&data[off..off + 4]);
+    u32::from_be_bytes(buf)
+}
+
+fn read_u64(data: &[u8], off: usize) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&data[off..off + 8]);
+    u64::from_be_bytes(buf)
+}
+
+/// The ELF file header, decoded into native byte order.
+#[derive(Debug, Clone, Copy)]
+pub struct FileHeader {
+    pub e_type: u16,
+    pub e_machine: u16,
+    pub e_version: u32,
+    pub e_entry: u64,
+    pub e_phoff: u64,
+    pub e_shoff: u64,
+    pub e_flags: u32,
+    pub e_ehsize: u16,
+    pub e_phentsize: u16,
+    pub e_phnum: u16,
+    pub e_shentsize: u16,
+    pub e_shnum: u16,
+    pub e_shstrndx: u16,
+}
+
+impl FileHeader {
+    fn parse(data: &[u8]) -> Result<Self, Error> {
+        if data.len() < EHDR_SIZE {
+            return Err(Error::Truncated);
+        }
+
+        if &data[0..4] != b"\x7FELF" {
+            return Err(Error::BadMagic);
+        }
+
+        if data[4] != ELFCLASS64 {
+            return Err(Error::BadClass);
+        }
+
+        if data[5] != ELFDATA2MSB {
+            return Err(Error::BadEndianness);
+        }
+
+        if data[6] as u32 != EV_CURRENT {
+            return Err(Error::BadVersion);
+        }
+
+        Ok(Self {
+            e_type: read_u16(data, 16),
+            e_machine: read_u16(data, 18),
+            e_version: read_u32(data, 20),
+            e_entry: read_u64(data, 24),
+            e_phoff: read_u64(data, 32),
+            e_shoff: read_u64(data, 40),
+            e_flags: read_u32(data, 48),
+            e_ehsize: read_u16(data, 52),
+            e_phentsize: read_u16(data, 54),
+            e_phnum: read_u16(data, 56),
+            e_shentsize: read_u16(data, 58),
+            e_shnum: read_u16(data, 60),
+            e_shstrndx: read_u16(data, 62),
+        })
+    }
+
+    /// The PowerPC64 ABI version encoded in `e_flags` (1 = ELFv1, 2 = ELFv2, 0 = unspecified).
+    pub fn abi_version(&self) -> u32 {
+        self.e_flags & 0x3
+    }
+}
+
+/// A single program header, decoded into native byte order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramHeader {
+    pub p_type: u32,
+    pub p_flags: u32,
+    pub p_offset: u64,
+    pub p_vaddr: u64,
+    pub p_paddr: u64,
+    pub p_filesz: u64,
+    pub p_memsz: u64,
+    pub p_align: u64,
+}
+
+impl ProgramHeader {
+    fn parse(data: &[u8]) -> Self {
+        Self {
+            p_type: read_u32(data, 0),
+            p_flags: read_u32(data, 4),
+            p_offset: read_u64(data, 8),
+            p_vaddr: read_u64(data, 16),
+            p_paddr: read_u64(data, 24),
+            p_filesz: read_u64(data, 32),
+            p_memsz: read_u64(data, 40),
+            p_align: read_u64(data, 48),
+        }
+    }
+
+    /// Returns true if this segment should be placed in memory.
+    pub fn is_load(&self) -> bool {
+        self.p_type == PT_LOAD && self.p_memsz != 0
+    }
+
+    /// The physical address range occupied by this segment in memory.
+    pub fn phys_range(&self) -> core::ops::Range<u64> {
+        self.p_paddr..self.p_paddr + self.p_memsz
+    }
+
+    /// Returns true if the virtual address `addr` lies within this segment.
+    pub fn contains_vaddr(&self, addr: u64) -> bool {
+        addr >= self.p_vaddr && addr - self.p_vaddr < self.p_memsz
+    }
+}
+
+/// A validated ELF64 big-endian PowerPC executable.
+///
+/// Construction validates the file header, the program header table, and all
+/// loadable segments, so the accessors on this type never fail.
+pub struct ElfFile<'a> {
+    data: &'a [u8],
+    header: FileHeader,
+}
+
+impl<'a> ElfFile<'a> {
+    /// Parse and validate an ELF image contained within `data`.
+    pub fn parse(data: &'a [u8]) -> Result<Self, Error> {
+        let header = FileHeader::parse(data)?;
+
+        if header.e_version != EV_CURRENT {
+            return Err(Error::BadVersion);
+        }
+
+        if header.e_type != ET_EXEC && header.e_type != ET_DYN {
+            return Err(Error::BadType(header.e_type));
+        }
+
+        if header.e_machine != EM_PPC64 {
+            return Err(Error::BadMachine(header.e_machine));
+        }
+
+        if header.e_ehsize as usize != EHDR_SIZE
+            || (header.e_phnum != 0 && header.e_phentsize as usize != PHDR_SIZE)
+        {
+            return Err(Error::BadHeaderSize);
+        }
+
+        // Ensure the program header table lies entirely within the file.
+        let phdr_len = header.e_phnum as u64 * PHDR_SIZE as u64;
+        match header.e_phoff.checked_add(phdr_len) {
+            Some(end) if end <= data.len() as u64 => {}
+            _ => return Err(Error::BadProgramHeaders),
+        }
+
+        let elf = Self { data, header };
+        elf.validate_segments()?;
+
+        Ok(elf)
+    }
+
+    fn validate_segments(&self) -> Result<(), Error> {
+        let mut found = false;
+
+        for (i, ph) in self.program_headers().enumerate() {
+            if !ph.is_load() {
+                continue;
+            }
+
+            found = true;
+
+            if ph.p_filesz > ph.p_memsz {
+                return Err(Error::SegmentTooLarge(i));
+            }
+
+            match ph.p_offset.checked_add(ph.p_filesz) {
+                Some(end) if end <= self.data.len() as u64 => {}
+                _ => return Err(Error::SegmentOutOfBounds(i)),
+            }
+
+            if ph.p_paddr.checked_add(ph.p_memsz).is_none()
+                || ph.p_vaddr.checked_add(ph.p_memsz).is_none()
+            {
+                return Err(Error::SegmentOverflow(i));
+            }
+
+            // Check this segment against all previous loadable segments.
+            for (j, other) in self.program_headers().enumerate().take(i) {
+                if !other.is_load() {
+                    continue;
+                }
+
+                let (a, b) = (ph.phys_range(), other.phys_range());
+                if a.start < b.end && b.start < a.end {
+                    return Err(Error::SegmentOverlap(j, i));
+                }
+            }
+        }
+
+        if !found {
+            return Err(Error::NoLoadableSegments);
+        }
+
+        let entry = self.header.e_entry;
+        if !self.load_segments().any(|ph| ph.contains_vaddr(entry)) {
+            return Err(Error::EntryNotMapped(entry));
+        }
+
+        Ok(())
+    }
+
+    /// The decoded file header.
+    pub fn header(&self) -> &FileHeader {
+        &self.header
+    }
+
+    /// Iterate over all program headers in the file.
+    pub fn program_headers(&self) -> impl Iterator<Item = ProgramHeader> + 'a {
+        let data = self.data;
+        let phoff = self.header.e_phoff as usize;
+
+        (0..self.header.e_phnum as usize).map(move |i| {
+            let off = phoff + i * PHDR_SIZE;
+            ProgramHeader::parse(&data[off..off + PHDR_SIZE])
+        })
+    }
+
+    /// Iterate over all segments that should be placed in memory.
+    pub fn load_segments(&self) -> impl Iterator<Item = ProgramHeader> + 'a {
+        self.program_headers().filter(|ph| ph.is_load())
+    }
+
+    /// Retrieve the file contents of a segment. The remainder of the segment
+    /// (`p_memsz - p_filesz` bytes) is expected to be zero-filled by the loader.
+    pub fn segment_data(&self, ph: &ProgramHeader) -> &'a [u8] {
+        let start = ph.p_offset as usize;
+        &self.data[start..start + ph.p_filesz as usize]
+    }
+
+    /// The virtual address of the entry point.
+    pub fn entry(&self) -> u64 {
+        self.header.e_entry
+    }
+
+    /// The physical address of the entry point, translated through the loadable
+    /// segment that contains it.
+    pub fn entry_phys(&self) -> u64 {
+        let entry = self.header.e_entry;
+
+        // N.B: This was validated during parsing.
+        let ph = self
+            .load_segments()
+            .find(|ph| ph.contains_vaddr(entry))
+            .unwrap();
+
+        ph.p_paddr + (entry - ph.p_vaddr)
+    }
+
+    /// The physical address range spanned by all loadable segments.
+    pub fn phys_extent(&self) -> core::ops::Range<u64> {
+        let start = self.load_segments().map(|ph| ph.p_paddr).min().unwrap();
+        let end = self
+            .load_segments()
+            .map(|ph| ph.p_paddr + ph.p_memsz)
+            .max()
+            .unwrap();
+
+        start..end
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::*;
+    use std::vec::Vec;
+
+    /// Build a minimal big-endian PPC64 executable out of `(vaddr, paddr, contents, memsz)`.
+    fn build_elf(entry: u64, segments: &[(u64, u64, &[u8], u64)]) -> Vec<u8> {
+        let mut out = Vec::new();
+
+        out.extend_from_slice(b"\x7FELF");
+        out.extend_from_slice(&[ELFCLASS64, ELFDATA2MSB, EV_CURRENT as u8, 0]);
+        out.extend_from_slice(&[0u8; 8]);
+        out.extend_from_slice(&ET_EXEC.to_be_bytes());
+        out.extend_from_slice(&EM_PPC64.to_be_bytes());
+        out.extend_from_slice(&EV_CURRENT.to_be_bytes());
+        out.extend_from_slice(&entry.to_be_bytes());
+        out.extend_from_slice(&(EHDR_SIZE as u64).to_be_bytes()); // e_phoff
+        out.extend_from_slice(&0u64.to_be_bytes()); // e_shoff
+        out.extend_from_slice(&1u32.to_be_bytes()); // e_flags
+        out.extend_from_slice(&(EHDR_SIZE as u16).to_be_bytes());
+        out.extend_from_slice(&(PHDR_SIZE as u16).to_be_bytes());
+        out.extend_from_slice(&(segments.len() as u16).to_be_bytes());
+        out.extend_from_slice(&0u16.to_be_bytes());
+        out.extend_from_slice(&0u16.to_be_bytes());
+        out.extend_from_slice(&0u16.to_be_bytes());
+        assert_eq!(out.len(), EHDR_SIZE);
+
+        let mut offset = (EHDR_SIZE + PHDR_SIZE * segments.len()) as u64;
+        for (vaddr, paddr, data, memsz) in segments {
+            out.extend_from_slice(&PT_LOAD.to_be_bytes());
+            out.extend_from_slice(&(PF_R | PF_X).to_be_bytes());
+            out.extend_from_slice(&offset.to_be_bytes());
+            out.extend_from_slice(&vaddr.to_be_bytes());
+            out.extend_from_slice(&paddr.to_be_bytes());
+            out.extend_from_slice(&(data.len() as u64).to_be_bytes());
+            out.extend_from_slice(&memsz.to_be_bytes());
+            out.extend_from_slice(&0x10000u64.to_be_bytes());
+
+            offset += data.len() as u64;
+        }
+
+        for (_, _, data, _) in segments {
+            out.extend_from_slice(data);
+        }
+
+        out
+    }
+
+    #[test]
+    fn test_parse() {
+        let text = [0x60u8, 0x00, 0x00, 0x00];
+        let data = [0xAAu8; 8];
+        let file = build_elf(
+            0xC000_0000_0000_0000,
+            &[
+                (0xC000_0000_0000_0000, 0x0, &text, 0x1000),
+                (0xC000_0000_0001_0000, 0x10000, &data, 0x2000),
+            ],
+        );
+
+        let elf = ElfFile::parse(&file).unwrap();
+        assert_eq!(elf.header().abi_version(), 1);
+        assert_eq!(elf.load_segments().count(), 2);
+        assert_eq!(elf.entry_phys(), 0x0);
+        assert_eq!(elf.phys_extent(), 0x0..0x12000);
+
+        let seg = elf.load_segments().nth(1).unwrap();
+        assert_eq!(elf.segment_data(&seg), &data);
+    }
+
+    #[test]
+    fn test_entry_translation() {
+        let text = [0u8; 0x100];
+        let file = build_elf(
+            0xC000_0000_0000_0060,
+            &[(0xC000_0000_0000_0000, 0x0100_0000, &text, 0x100)],
+        );
+
+        let elf = ElfFile::parse(&file).unwrap();
+        assert_eq!(elf.entry_phys(), 0x0100_0060);
+    }
+
+    #[test]
+    fn test_reject() {
+        let text = [0u8; 0x10];
+        let good = build_elf(0x1000, &[(0x1000, 0x1000, &text, 0x10)]);
+
+        assert_eq!(ElfFile::parse(&good[..32]).err(), Some(Error::Truncated));
+
+        let mut bad = good.clone();
+        bad[5] = 1; // Little-endian
+        assert_eq!(ElfFile::parse(&bad).err(), Some(Error::BadEndianness));
+
+        let mut bad = good.clone();
+        bad[19] = 20; // EM_PPC
+        assert_eq!(ElfFile::parse(&bad).err(), Some(Error::BadMachine(20)));
+
+        let mut bad = good.clone();
+        bad.truncate(bad.len() - 1);
+        assert_eq!(ElfFile::parse(&bad).err(), Some(Error::SegmentOutOfBounds(0)));
+
+        let bad = build_elf(0x2000, &[(0x1000, 0x1000, &text, 0x10)]);
+        assert_eq!(ElfFile::parse(&bad).err(), Some(Error::EntryNotMapped(0x2000)));
+
+        let bad = build_elf(0x1000, &[(0x1000, 0x1000, &text, 0x8)]);
+        assert_eq!(ElfFile::parse(&bad).err(), Some(Error::SegmentTooLarge(0)));
+
+        let bad = build_elf(
+            0x1000,
+            &[(0x1000, 0x1000, &text, 0x100), (0x2000, 0x1080, &text, 0x10)],
+        );
+        assert_eq!(ElfFile::parse(&bad).err(), Some(Error::SegmentOverlap(0, 1)));
+    }
+
+    /// Validate against a real kernel, if one is provided through `XELL_TEST_VMLINUX`.
+    #[test]
+    fn test_vmlinux() {
+        let path = match std::env::var("XELL_TEST_VMLINUX") {
+            Ok(p) => p,
+            Err(_) => return,
+        };
+
+        let file = std::fs::read(path).unwrap();
+        let elf = ElfFile::parse(&file).unwrap();
+
+        assert!(elf.load_segments().count() != 0);
+        assert!(elf.phys_extent().contains(&elf.entry_phys()));
+    }
+}
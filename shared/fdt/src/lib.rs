@@ -0,0 +1,484 @@
+//! Flattened Device Tree (FDT) parser, editor and serializer.
+//!
+//! A blob is parsed into an owned tree of [Node]s, which can be freely edited
+//! and then serialized back into a version 17 blob suitable for passing to Linux.
+//! Serialization follows the same layout as `dtc`, so a blob produced by `dtc`
+//! round-trips to identical bytes.
+#![no_std]
+
+extern crate alloc;
+
+mod node;
+
+pub use node::{Node, Property};
+
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+
+/// The magic number at the beginning of every FDT blob.
+pub const FDT_MAGIC: u32 = 0xD00D_FEED;
+
+/// The blob version we emit.
+const FDT_VERSION: u32 = 17;
+/// The oldest version our emitted blobs are backwards-compatible with.
+const FDT_LAST_COMP_VERSION: u32 = 16;
+
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+const FDT_END: u32 = 0x9;
+
+const HEADER_SIZE: usize = 40;
+
+/// Maximum nesting depth accepted by the parser.
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The blob is smaller than its header claims.
+    Truncated,
+    /// The blob does not start with [FDT_MAGIC].
+    BadMagic,
+    /// The blob version is not supported.
+    BadVersion(u32),
+    /// An unknown token was encountered in the structure block.
+    BadToken(u32),
+    /// The structure block is malformed (e.g. unbalanced nodes).
+    BadStructure,
+    /// A string is not NUL-terminated or not valid UTF-8.
+    BadString,
+    /// Nodes are nested too deeply.
+    TooDeep,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Truncated => write!(f, "blob truncated"),
+            Error::BadMagic => write!(f, "bad magic"),
+            Error::BadVersion(v) => write!(f, "unsupported version {}", v),
+            Error::BadToken(t) => write!(f, "bad token {:#X}", t),
+            Error::BadStructure => write!(f, "malformed structure block"),
+            Error::BadString => write!(f, "malformed string"),
+            Error::TooDeep => write!(f, "nodes nested too deeply"),
+        }
+    }
+}
+
+/// An entry in the memory reservation block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reservation {
+    pub address: u64,
+    pub size: u64,
+}
+
+/// An owned, editable device tree.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceTree {
+    /// Regions of physical memory the client must not use.
+    pub reservations: Vec<Reservation>,
+    /// The physical ID of the boot CPU.
+    pub boot_cpuid_phys: u32,
+    /// The root node.
+    pub root: Node,
+}
+
+fn read_u32(data: &[u8], off: usize) -> Result<u32, Error> {
+    let bytes = data.get(off..off + 4).ok_or(Error::Truncated)?;
+    Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u64(data: &[u8], off: usize) -> Result<u64, Error> {
+    let bytes = data.get(off..off + 8).ok_or(Error::Truncated)?;
+    Ok(u64::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+/// Read a NUL-terminated string starting at `off`, returning it and its length
+/// (excluding the terminator).
+fn read_str(data: &[u8], off: usize) -> Result<(&str, usize), Error> {
+    let rest = data.get(off..).ok_or(Error::Truncated)?;
+    let len = rest.iter().position(|b| *b == 0).ok_or(Error::BadString)?;
+    let s = core::str::from_utf8(&rest[..len]).map_err(|_| Error::BadString)?;
+
+    Ok((s, len))
+}
+
+const fn align4(n: usize) -> usize {
+    (n + 3) & !3
+}
+
+impl DeviceTree {
+    /// Create an empty device tree with a nameless root node.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse a flattened device tree blob.
+    pub fn from_bytes(blob: &[u8]) -> Result<Self, Error> {
+        if read_u32(blob, 0)? != FDT_MAGIC {
+            return Err(Error::BadMagic);
+        }
+
+        let totalsize = read_u32(blob, 4)? as usize;
+        let off_struct = read_u32(blob, 8)? as usize;
+        let off_strings = read_u32(blob, 12)? as usize;
+        let off_rsvmap = read_u32(blob, 16)? as usize;
+        let version = read_u32(blob, 20)?;
+        let last_comp = read_u32(blob, 24)?;
+        let boot_cpuid_phys = read_u32(blob, 28)?;
+
+        // Versions 16 and 17 share the same structure block format.
+        if version < 16 || last_comp > FDT_VERSION {
+            return Err(Error::BadVersion(version));
+        }
+
+        let blob = blob.get(..totalsize).ok_or(Error::Truncated)?;
+
+        let strings = if version >= 17 {
+            let size = read_u32(blob, 32)? as usize;
+            blob.get(off_strings..off_strings + size)
+                .ok_or(Error::Truncated)?
+        } else {
+            blob.get(off_strings..).ok_or(Error::Truncated)?
+        };
+
+        let mut reservations = Vec::new();
+        let mut off = off_rsvmap;
+        loop {
+            let address = read_u64(blob, off)?;
+            let size = read_u64(blob, off + 8)?;
+            off += 16;
+
+            if address == 0 && size == 0 {
+                break;
+            }
+
+            reservations.push(Reservation { address, size });
+        }
+
+        let mut stack: Vec<Node> = Vec::new();
+        let mut root = None;
+        let mut off = off_struct;
+
+        loop {
+            let token = read_u32(blob, off)?;
+            off += 4;
+
+            match token {
+                FDT_BEGIN_NODE => {
+                    if root.is_some() {
+                        return Err(Error::BadStructure);
+                    }
+
+                    if stack.len() >= MAX_DEPTH {
+                        return Err(Error::TooDeep);
+                    }
+
+                    let (name, len) = read_str(blob, off)?;
+                    off += align4(len + 1);
+
+                    stack.push(Node::new(name));
+                }
+
+                FDT_END_NODE => {
+                    let node = stack.pop().ok_or(Error::BadStructure)?;
+                    match stack.last_mut() {
+                        Some(parent) => parent.children.push(node),
+                        None => root = Some(node),
+                    }
+                }
+
+                FDT_PROP => {
+                    let len = read_u32(blob, off)? as usize;
+                    let nameoff = read_u32(blob, off + 4)? as usize;
+                    off += 8;
+
+                    let value = blob.get(off..off + len).ok_or(Error::Truncated)?;
+                    off += align4(len);
+
+                    let (name, _) = read_str(strings, nameoff)?;
+                    let node = stack.last_mut().ok_or(Error::BadStructure)?;
+
+                    node.properties.push(Property {
+                        name: String::from(name),
+                        value: Vec::from(value),
+                    });
+                }
+
+                FDT_NOP => {}
+
+                FDT_END => break,
+
+                t => return Err(Error::BadToken(t)),
+            }
+        }
+
+        if !stack.is_empty() {
+            return Err(Error::BadStructure);
+        }
+
+        Ok(Self {
+            reservations,
+            boot_cpuid_phys,
+            root: root.ok_or(Error::BadStructure)?,
+        })
+    }
+
+    /// Look up a node by its absolute path, e.g. `/chosen`.
+    pub fn node(&self, path: &str) -> Option<&Node> {
+        self.root.find(path)
+    }
+
+    /// Look up a node by its absolute path, e.g. `/chosen`.
+    pub fn node_mut(&mut self, path: &str) -> Option<&mut Node> {
+        self.root.find_mut(path)
+    }
+
+    /// Look up a node by its absolute path, creating any missing nodes.
+    pub fn node_or_insert(&mut self, path: &str) -> &mut Node {
+        self.root.find_or_insert(path)
+    }
+
+    /// Serialize this tree into a flattened device tree blob.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut dt_struct = Vec::new();
+        let mut dt_strings = Vec::new();
+
+        flatten_node(&self.root, &mut dt_struct, &mut dt_strings);
+        dt_struct.extend_from_slice(&FDT_END.to_be_bytes());
+
+        // N.B: The reservation map must be 8-byte aligned, which the header size already is.
+        let off_rsvmap = HEADER_SIZE;
+        let off_struct = off_rsvmap + (self.reservations.len() + 1) * 16;
+        let off_strings = off_struct + dt_struct.len();
+        let totalsize = off_strings + dt_strings.len();
+
+        let mut blob = Vec::with_capacity(totalsize);
+        for word in [
+            FDT_MAGIC,
+            totalsize as u32,
+            off_struct as u32,
+            off_strings as u32,
+            off_rsvmap as u32,
+            FDT_VERSION,
+            FDT_LAST_COMP_VERSION,
+            self.boot_cpuid_phys,
+            dt_strings.len() as u32,
+            dt_struct.len() as u32,
+        ] {
+            blob.extend_from_slice(&word.to_be_bytes());
+        }
+
+        for rsv in self.reservations.iter() {
+            blob.extend_from_slice(&rsv.address.to_be_bytes());
+            blob.extend_from_slice(&rsv.size.to_be_bytes());
+        }
+        blob.extend_from_slice(&[0u8; 16]);
+
+        blob.extend_from_slice(&dt_struct);
+        blob.extend_from_slice(&dt_strings);
+
+        blob
+    }
+}
+
+/// Insert a string into the strings block, reusing any existing string that
+/// ends with the same characters (as `dtc` does).
+fn insert_string(strings: &mut Vec<u8>, s: &str) -> u32 {
+    let needle = s.as_bytes();
+
+    for i in 0..strings.len() {
+        let rest = &strings[i..];
+        if rest.len() > needle.len() && rest.starts_with(needle) && rest[needle.len()] == 0 {
+            return i as u32;
+        }
+    }
+
+    let off = strings.len();
+    strings.extend_from_slice(needle);
+    strings.push(0);
+
+    off as u32
+}
+
+fn pad4(buf: &mut Vec<u8>) {
+    buf.resize(align4(buf.len()), 0);
+}
+
+fn flatten_node(node: &Node, dt_struct: &mut Vec<u8>, dt_strings: &mut Vec<u8>) {
+    dt_struct.extend_from_slice(&FDT_BEGIN_NODE.to_be_bytes());
+    dt_struct.extend_from_slice(node.name.as_bytes());
+    dt_struct.push(0);
+    pad4(dt_struct);
+
+    for prop in node.properties.iter() {
+        let nameoff = insert_string(dt_strings, &prop.name);
+
+        dt_struct.extend_from_slice(&FDT_PROP.to_be_bytes());
+        dt_struct.extend_from_slice(&(prop.value.len() as u32).to_be_bytes());
+        dt_struct.extend_from_slice(&nameoff.to_be_bytes());
+        dt_struct.extend_from_slice(&prop.value);
+        pad4(dt_struct);
+    }
+
+    for child in node.children.iter() {
+        flatten_node(child, dt_struct, dt_strings);
+    }
+
+    dt_struct.extend_from_slice(&FDT_END_NODE.to_be_bytes());
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::*;
+
+    /// The following source, laid out as emitted by `dtc -O dtb`:
+    ///
+    /// ```dts
+    /// /dts-v1/;
+    /// /memreserve/ 0x1c000000 0x40000;
+    /// / {
+    ///     #address-cells = <1>;
+    ///     model = "xenon";
+    ///     chosen {
+    ///         bootargs = "console=ttyS0";
+    ///     };
+    ///     memory@0 {
+    ///         device_type = "memory";
+    ///         reg = <0x0 0x20000000>;
+    ///     };
+    /// };
+    /// ```
+    #[rustfmt::skip]
+    const XENON_DTB: &[u8] = &[
+        // Header
+        0xD0, 0x0D, 0xFE, 0xED, 0x00, 0x00, 0x01, 0x12, // magic, totalsize
+        0x00, 0x00, 0x00, 0x48, 0x00, 0x00, 0x00, 0xE4, // off_dt_struct, off_dt_strings
+        0x00, 0x00, 0x00, 0x28, 0x00, 0x00, 0x00, 0x11, // off_mem_rsvmap, version
+        0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, // last_comp_version, boot_cpuid_phys
+        0x00, 0x00, 0x00, 0x2E, 0x00, 0x00, 0x00, 0x9C, // size_dt_strings, size_dt_struct
+        // Memory reservation block
+        0x00, 0x00, 0x00, 0x00, 0x1C, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        // Structure block
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, // BEGIN_NODE ""
+        0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x04, // PROP len=4
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, // "#address-cells" = <1>
+        0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x06, // PROP len=6
+        0x00, 0x00, 0x00, 0x0F, b'x',  b'e',  b'n',  b'o', // "model" = "xenon"
+        b'n',  0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x01, b'c',  b'h',  b'o',  b's', // BEGIN_NODE "chosen"
+        b'e',  b'n',  0x00, 0x00,
+        0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x0E, // PROP len=14
+        0x00, 0x00, 0x00, 0x15, b'c',  b'o',  b'n',  b's', // "bootargs"
+        b'o',  b'l',  b'e',  b'=', b't',  b't',  b'y',  b'S',
+        b'0',  0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x02,                         // END_NODE
+        0x00, 0x00, 0x00, 0x01, b'm',  b'e',  b'm',  b'o', // BEGIN_NODE "memory@0"
+        b'r',  b'y',  b'@',  b'0', 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x07, // PROP len=7
+        0x00, 0x00, 0x00, 0x1E, b'm',  b'e',  b'm',  b'o', // "device_type" = "memory"
+        b'r',  b'y',  0x00, 0x00,
+        0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x08, // PROP len=8
+        0x00, 0x00, 0x00, 0x2A, 0x00, 0x00, 0x00, 0x00, // "reg"
+        0x20, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x02,                         // END_NODE
+        0x00, 0x00, 0x00, 0x02,                         // END_NODE
+        0x00, 0x00, 0x00, 0x09,                         // END
+        // Strings block
+        b'#', b'a', b'd', b'd', b'r', b'e', b's', b's', b'-', b'c', b'e', b'l', b'l', b's', 0,
+        b'm', b'o', b'd', b'e', b'l', 0,
+        b'b', b'o', b'o', b't', b'a', b'r', b'g', b's', 0,
+        b'd', b'e', b'v', b'i', b'c', b'e', b'_', b't', b'y', b'p', b'e', 0,
+        b'r', b'e', b'g', 0,
+    ];
+
+    #[test]
+    fn test_parse() {
+        let dt = DeviceTree::from_bytes(XENON_DTB).unwrap();
+
+        assert_eq!(
+            dt.reservations,
+            [Reservation {
+                address: 0x1C00_0000,
+                size: 0x4_0000
+            }]
+        );
+
+        assert_eq!(dt.root.property("#address-cells").unwrap().as_u32(), Some(1));
+        assert_eq!(dt.root.property("model").unwrap().as_str(), Some("xenon"));
+        assert_eq!(
+            dt.node("/chosen").unwrap().property("bootargs").unwrap().as_str(),
+            Some("console=ttyS0")
+        );
+        assert_eq!(
+            dt.node("/memory@0").unwrap().property("reg").unwrap().as_u64(),
+            Some(0x2000_0000)
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dt = DeviceTree::from_bytes(XENON_DTB).unwrap();
+        let blob = dt.to_bytes();
+
+        assert_eq!(blob, XENON_DTB);
+        assert_eq!(DeviceTree::from_bytes(&blob).unwrap(), dt);
+    }
+
+    #[test]
+    fn test_edit() {
+        let mut dt = DeviceTree::from_bytes(XENON_DTB).unwrap();
+
+        let chosen = dt.node_or_insert("/chosen");
+        chosen.set_str("bootargs", "root=/dev/sda1");
+        chosen.set_u64("linux,initrd-start", 0x0400_0000);
+
+        dt.node_or_insert("/cpus/cpu@0").set_str("device_type", "cpu");
+        dt.root.remove_child("memory@0");
+
+        let dt = DeviceTree::from_bytes(&dt.to_bytes()).unwrap();
+        let chosen = dt.node("/chosen").unwrap();
+        assert_eq!(chosen.property("bootargs").unwrap().as_str(), Some("root=/dev/sda1"));
+        assert_eq!(
+            chosen.property("linux,initrd-start").unwrap().as_u64(),
+            Some(0x0400_0000)
+        );
+        assert!(dt.node("/cpus/cpu@0").is_some());
+        assert!(dt.node("/memory@0").is_none());
+    }
+
+    #[test]
+    fn test_reject() {
+        assert_eq!(DeviceTree::from_bytes(&XENON_DTB[..16]), Err(Error::Truncated));
+
+        let mut bad = std::vec::Vec::from(XENON_DTB);
+        bad[0] = 0;
+        assert_eq!(DeviceTree::from_bytes(&bad), Err(Error::BadMagic));
+
+        // Replace the final END_NODE with a NOP, leaving the root unterminated.
+        let mut bad = std::vec::Vec::from(XENON_DTB);
+        bad[0xDC..0xE0].copy_from_slice(&FDT_NOP.to_be_bytes());
+        assert_eq!(DeviceTree::from_bytes(&bad), Err(Error::BadStructure));
+    }
+
+    /// Round-trip a blob compiled by `dtc`, if one is provided through `XELL_TEST_DTB`.
+    #[test]
+    fn test_dtc_blob() {
+        let path = match std::env::var("XELL_TEST_DTB") {
+            Ok(p) => p,
+            Err(_) => return,
+        };
+
+        let blob = std::fs::read(path).unwrap();
+        let dt = DeviceTree::from_bytes(&blob).unwrap();
+
+        let totalsize = u32::from_be_bytes(blob[4..8].try_into().unwrap()) as usize;
+        assert_eq!(dt.to_bytes(), &blob[..totalsize]);
+    }
+}
@@ -0,0 +1,159 @@
+//! This module contains the in-memory representation of device tree nodes.
+
+use alloc::{string::String, vec::Vec};
+
+/// A single named property attached to a node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Property {
+    pub name: String,
+    pub value: Vec<u8>,
+}
+
+impl Property {
+    /// Interpret the value as a single big-endian 32-bit cell.
+    pub fn as_u32(&self) -> Option<u32> {
+        let bytes: [u8; 4] = self.value.as_slice().try_into().ok()?;
+        Some(u32::from_be_bytes(bytes))
+    }
+
+    /// Interpret the value as a big-endian 64-bit value (two cells).
+    pub fn as_u64(&self) -> Option<u64> {
+        let bytes: [u8; 8] = self.value.as_slice().try_into().ok()?;
+        Some(u64::from_be_bytes(bytes))
+    }
+
+    /// Interpret the value as a single NUL-terminated string.
+    pub fn as_str(&self) -> Option<&str> {
+        let (last, s) = self.value.split_last()?;
+        if *last != 0 {
+            return None;
+        }
+
+        core::str::from_utf8(s).ok()
+    }
+}
+
+/// A device tree node, containing properties and child nodes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Node {
+    /// The node's name, including the unit address (e.g. `memory@0`).
+    /// The root node has an empty name.
+    pub name: String,
+    pub properties: Vec<Property>,
+    pub children: Vec<Node>,
+}
+
+impl Node {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: String::from(name),
+            properties: Vec::new(),
+            children: Vec::new(),
+        }
+    }
+
+    /// Look up a property by name.
+    pub fn property(&self, name: &str) -> Option<&Property> {
+        self.properties.iter().find(|p| p.name == name)
+    }
+
+    /// Set a property to a raw value, replacing any existing property of the same name.
+    /// New properties are appended after existing ones.
+    pub fn set_property(&mut self, name: &str, value: &[u8]) {
+        match self.properties.iter_mut().find(|p| p.name == name) {
+            Some(p) => {
+                p.value.clear();
+                p.value.extend_from_slice(value);
+            }
+
+            None => self.properties.push(Property {
+                name: String::from(name),
+                value: Vec::from(value),
+            }),
+        }
+    }
+
+    /// Set a property to an empty (boolean) value.
+    pub fn set_empty(&mut self, name: &str) {
+        self.set_property(name, &[]);
+    }
+
+    /// Set a property to a single 32-bit cell.
+    pub fn set_u32(&mut self, name: &str, value: u32) {
+        self.set_property(name, &value.to_be_bytes());
+    }
+
+    /// Set a property to a 64-bit value, encoded as two cells.
+    pub fn set_u64(&mut self, name: &str, value: u64) {
+        self.set_property(name, &value.to_be_bytes());
+    }
+
+    /// Set a property to a list of 32-bit cells.
+    pub fn set_cells(&mut self, name: &str, cells: &[u32]) {
+        let value: Vec<u8> = cells.iter().flat_map(|c| c.to_be_bytes()).collect();
+        self.set_property(name, &value);
+    }
+
+    /// Set a property to a NUL-terminated string.
+    pub fn set_str(&mut self, name: &str, value: &str) {
+        let mut buf = Vec::with_capacity(value.len() + 1);
+        buf.extend_from_slice(value.as_bytes());
+        buf.push(0);
+
+        self.set_property(name, &buf);
+    }
+
+    /// Remove a property, returning it if it existed.
+    pub fn remove_property(&mut self, name: &str) -> Option<Property> {
+        let idx = self.properties.iter().position(|p| p.name == name)?;
+        Some(self.properties.remove(idx))
+    }
+
+    /// Look up a direct child by its full name (including unit address).
+    pub fn child(&self, name: &str) -> Option<&Node> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    /// Look up a direct child by its full name (including unit address).
+    pub fn child_mut(&mut self, name: &str) -> Option<&mut Node> {
+        self.children.iter_mut().find(|c| c.name == name)
+    }
+
+    /// Retrieve a direct child, creating it if it does not exist.
+    pub fn child_or_insert(&mut self, name: &str) -> &mut Node {
+        match self.children.iter().position(|c| c.name == name) {
+            Some(idx) => &mut self.children[idx],
+            None => {
+                self.children.push(Node::new(name));
+                self.children.last_mut().unwrap()
+            }
+        }
+    }
+
+    /// Remove a direct child, returning it if it existed.
+    pub fn remove_child(&mut self, name: &str) -> Option<Node> {
+        let idx = self.children.iter().position(|c| c.name == name)?;
+        Some(self.children.remove(idx))
+    }
+
+    /// Look up a descendant by a path relative to this node, e.g. `cpus/cpu@0`.
+    pub fn find(&self, path: &str) -> Option<&Node> {
+        path.split('/')
+            .filter(|c| !c.is_empty())
+            .try_fold(self, |node, c| node.child(c))
+    }
+
+    /// Look up a descendant by a path relative to this node, e.g. `cpus/cpu@0`.
+    pub fn find_mut(&mut self, path: &str) -> Option<&mut Node> {
+        path.split('/')
+            .filter(|c| !c.is_empty())
+            .try_fold(self, |node, c| node.child_mut(c))
+    }
+
+    /// Look up a descendant by path, creating any missing nodes along the way.
+    pub fn find_or_insert(&mut self, path: &str) -> &mut Node {
+        path.split('/')
+            .filter(|c| !c.is_empty())
+            .fold(self, |node, c| node.child_or_insert(c))
+    }
+}
@@ -0,0 +1,158 @@
+//! 64-bit PowerPC architecture definition for `gdbstub`.
+//!
+//! The register layout matches GDB's `powerpc:common64` description (the `power64-core`,
+//! `power-fpu` and `power-altivec` features), so a stock `powerpc64-linux-gnu-gdb` can
+//! debug the target without any local configuration.
+#![no_std]
+
+use gdbstub::arch::Arch;
+
+mod reg;
+
+pub use reg::{Fpu, PowerPc64Regs, Vmx};
+
+/// The target description sent to GDB.
+///
+/// N.B: The order of the registers here must match [PowerPc64Regs::gdb_serialize].
+pub const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <architecture>powerpc:common64</architecture>
+  <feature name="org.gnu.gdb.power.core">
+    <reg name="r0" bitsize="64" type="uint64" regnum="0"/>
+    <reg name="r1" bitsize="64" type="uint64"/>
+    <reg name="r2" bitsize="64" type="uint64"/>
+    <reg name="r3" bitsize="64" type="uint64"/>
+    <reg name="r4" bitsize="64" type="uint64"/>
+    <reg name="r5" bitsize="64" type="uint64"/>
+    <reg name="r6" bitsize="64" type="uint64"/>
+    <reg name="r7" bitsize="64" type="uint64"/>
+    <reg name="r8" bitsize="64" type="uint64"/>
+    <reg name="r9" bitsize="64" type="uint64"/>
+    <reg name="r10" bitsize="64" type="uint64"/>
+    <reg name="r11" bitsize="64" type="uint64"/>
+    <reg name="r12" bitsize="64" type="uint64"/>
+    <reg name="r13" bitsize="64" type="uint64"/>
+    <reg name="r14" bitsize="64" type="uint64"/>
+    <reg name="r15" bitsize="64" type="uint64"/>
+    <reg name="r16" bitsize="64" type="uint64"/>
+    <reg name="r17" bitsize="64" type="uint64"/>
+    <reg name="r18" bitsize="64" type="uint64"/>
+    <reg name="r19" bitsize="64" type="uint64"/>
+    <reg name="r20" bitsize="64" type="uint64"/>
+    <reg name="r21" bitsize="64" type="uint64"/>
+    <reg name="r22" bitsize="64" type="uint64"/>
+    <reg name="r23" bitsize="64" type="uint64"/>
+    <reg name="r24" bitsize="64" type="uint64"/>
+    <reg name="r25" bitsize="64" type="uint64"/>
+    <reg name="r26" bitsize="64" type="uint64"/>
+    <reg name="r27" bitsize="64" type="uint64"/>
+    <reg name="r28" bitsize="64" type="uint64"/>
+    <reg name="r29" bitsize="64" type="uint64"/>
+    <reg name="r30" bitsize="64" type="uint64"/>
+    <reg name="r31" bitsize="64" type="uint64"/>
+    <reg name="pc" bitsize="64" type="code_ptr" regnum="64"/>
+    <reg name="msr" bitsize="64" type="uint64"/>
+    <reg name="cr" bitsize="32" type="uint32"/>
+    <reg name="lr" bitsize="64" type="code_ptr"/>
+    <reg name="ctr" bitsize="64" type="uint64"/>
+    <reg name="xer" bitsize="32" type="uint32"/>
+  </feature>
+  <feature name="org.gnu.gdb.power.fpu">
+    <reg name="f0" bitsize="64" type="ieee_double" regnum="32"/>
+    <reg name="f1" bitsize="64" type="ieee_double"/>
+    <reg name="f2" bitsize="64" type="ieee_double"/>
+    <reg name="f3" bitsize="64" type="ieee_double"/>
+    <reg name="f4" bitsize="64" type="ieee_double"/>
+    <reg name="f5" bitsize="64" type="ieee_double"/>
+    <reg name="f6" bitsize="64" type="ieee_double"/>
+    <reg name="f7" bitsize="64" type="ieee_double"/>
+    <reg name="f8" bitsize="64" type="ieee_double"/>
+    <reg name="f9" bitsize="64" type="ieee_double"/>
+    <reg name="f10" bitsize="64" type="ieee_double"/>
+    <reg name="f11" bitsize="64" type="ieee_double"/>
+    <reg name="f12" bitsize="64" type="ieee_double"/>
+    <reg name="f13" bitsize="64" type="ieee_double"/>
+    <reg name="f14" bitsize="64" type="ieee_double"/>
+    <reg name="f15" bitsize="64" type="ieee_double"/>
+    <reg name="f16" bitsize="64" type="ieee_double"/>
+    <reg name="f17" bitsize="64" type="ieee_double"/>
+    <reg name="f18" bitsize="64" type="ieee_double"/>
+    <reg name="f19" bitsize="64" type="ieee_double"/>
+    <reg name="f20" bitsize="64" type="ieee_double"/>
+    <reg name="f21" bitsize="64" type="ieee_double"/>
+    <reg name="f22" bitsize="64" type="ieee_double"/>
+    <reg name="f23" bitsize="64" type="ieee_double"/>
+    <reg name="f24" bitsize="64" type="ieee_double"/>
+    <reg name="f25" bitsize="64" type="ieee_double"/>
+    <reg name="f26" bitsize="64" type="ieee_double"/>
+    <reg name="f27" bitsize="64" type="ieee_double"/>
+    <reg name="f28" bitsize="64" type="ieee_double"/>
+    <reg name="f29" bitsize="64" type="ieee_double"/>
+    <reg name="f30" bitsize="64" type="ieee_double"/>
+    <reg name="f31" bitsize="64" type="ieee_double"/>
+    <reg name="fpscr" bitsize="32" group="float" regnum="70"/>
+  </feature>
+  <feature name="org.gnu.gdb.power.altivec">
+    <vector id="v4f" type="ieee_single" count="4"/>
+    <vector id="v4i32" type="int32" count="4"/>
+    <vector id="v8i16" type="int16" count="8"/>
+    <vector id="v16i8" type="int8" count="16"/>
+    <union id="vec128">
+      <field name="uint128" type="uint128"/>
+      <field name="v4_float" type="v4f"/>
+      <field name="v4_int32" type="v4i32"/>
+      <field name="v8_int16" type="v8i16"/>
+      <field name="v16_int8" type="v16i8"/>
+    </union>
+    <reg name="vr0" bitsize="128" type="vec128" regnum="71"/>
+    <reg name="vr1" bitsize="128" type="vec128"/>
+    <reg name="vr2" bitsize="128" type="vec128"/>
+    <reg name="vr3" bitsize="128" type="vec128"/>
+    <reg name="vr4" bitsize="128" type="vec128"/>
+    <reg name="vr5" bitsize="128" type="vec128"/>
+    <reg name="vr6" bitsize="128" type="vec128"/>
+    <reg name="vr7" bitsize="128" type="vec128"/>
+    <reg name="vr8" bitsize="128" type="vec128"/>
+    <reg name="vr9" bitsize="128" type="vec128"/>
+    <reg name="vr10" bitsize="128" type="vec128"/>
+    <reg name="vr11" bitsize="128" type="vec128"/>
+    <reg name="vr12" bitsize="128" type="vec128"/>
+    <reg name="vr13" bitsize="128" type="vec128"/>
+    <reg name="vr14" bitsize="128" type="vec128"/>
+    <reg name="vr15" bitsize="128" type="vec128"/>
+    <reg name="vr16" bitsize="128" type="vec128"/>
+    <reg name="vr17" bitsize="128" type="vec128"/>
+    <reg name="vr18" bitsize="128" type="vec128"/>
+    <reg name="vr19" bitsize="128" type="vec128"/>
+    <reg name="vr20" bitsize="128" type="vec128"/>
+    <reg name="vr21" bitsize="128" type="vec128"/>
+    <reg name="vr22" bitsize="128" type="vec128"/>
+    <reg name="vr23" bitsize="128" type="vec128"/>
+    <reg name="vr24" bitsize="128" type="vec128"/>
+    <reg name="vr25" bitsize="128" type="vec128"/>
+    <reg name="vr26" bitsize="128" type="vec128"/>
+    <reg name="vr27" bitsize="128" type="vec128"/>
+    <reg name="vr28" bitsize="128" type="vec128"/>
+    <reg name="vr29" bitsize="128" type="vec128"/>
+    <reg name="vr30" bitsize="128" type="vec128"/>
+    <reg name="vr31" bitsize="128" type="vec128"/>
+    <reg name="vscr" bitsize="32" type="int" group="vector"/>
+    <reg name="vrsave" bitsize="32" type="int"/>
+  </feature>
+</target>
+"#;
+
+/// 64-bit PowerPC, with the FPU and AltiVec (VMX) register sets.
+pub enum PowerPc64 {}
+
+impl Arch for PowerPc64 {
+    type Usize = u64;
+    type Registers = PowerPc64Regs;
+    type RegId = ();
+    type BreakpointKind = usize;
+
+    fn target_description_xml() -> Option<&'static str> {
+        Some(TARGET_XML)
+    }
+}
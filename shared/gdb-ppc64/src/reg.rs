@@ -0,0 +1,266 @@
+//! This module contains the register file and its wire format.
+
+use core::convert::TryInto;
+
+use gdbstub::arch::Registers;
+
+/// The size of the register file on the wire, in bytes.
+pub const REGS_SIZE: usize = 0x434;
+
+/// Floating-point registers.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Fpu {
+    /// Floating-point registers, as raw IEEE 754 doubles.
+    pub f: [u64; 32],
+    /// Floating-point status and control register
+    pub fpscr: u32,
+}
+
+/// AltiVec (VMX) registers.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Vmx {
+    /// Vector registers
+    pub vr: [u128; 32],
+    /// Vector status and control register
+    pub vscr: u32,
+    /// Vector save/restore register
+    pub vrsave: u32,
+}
+
+/// 64-bit PowerPC registers.
+///
+/// The floating-point and vector registers are optional, since a target may not save them.
+/// They are reported to GDB as unavailable if absent.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PowerPc64Regs {
+    /// General purpose registers
+    pub r: [u64; 32],
+    /// Program counter
+    pub pc: u64,
+    /// Machine state register
+    pub msr: u64,
+    /// Condition register
+    pub cr: u32,
+    /// Link register
+    pub lr: u64,
+    /// Count register
+    pub ctr: u64,
+    /// Fixed-point exception register
+    pub xer: u32,
+    pub fpu: Option<Fpu>,
+    pub vmx: Option<Vmx>,
+}
+
+/// Reads big-endian values from the front of a byte slice.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], ()> {
+        if self.0.len() < N {
+            return Err(());
+        }
+
+        let (bytes, rest) = self.0.split_at(N);
+        self.0 = rest;
+
+        Ok(bytes.try_into().unwrap())
+    }
+
+    fn u32(&mut self) -> Result<u32, ()> {
+        Ok(u32::from_be_bytes(self.take()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, ()> {
+        Ok(u64::from_be_bytes(self.take()?))
+    }
+
+    fn u128(&mut self) -> Result<u128, ()> {
+        Ok(u128::from_be_bytes(self.take()?))
+    }
+}
+
+/// Write a register's bytes, or mark it as unavailable.
+fn write<const N: usize>(write_byte: &mut impl FnMut(Option<u8>), bytes: Option<[u8; N]>) {
+    match bytes {
+        Some(bytes) => bytes.iter().for_each(|b| write_byte(Some(*b))),
+        None => (0..N).for_each(|_| write_byte(None)),
+    }
+}
+
+impl Registers for PowerPc64Regs {
+    type ProgramCounter = u64;
+
+    fn pc(&self) -> Self::ProgramCounter {
+        self.pc
+    }
+
+    fn gdb_serialize(&self, mut write_byte: impl FnMut(Option<u8>)) {
+        let w = &mut write_byte;
+
+        for reg in &self.r {
+            write(w, Some(reg.to_be_bytes()));
+        }
+
+        for i in 0..32 {
+            write(w, self.fpu.as_ref().map(|f| f.f[i].to_be_bytes()));
+        }
+
+        write(w, Some(self.pc.to_be_bytes()));
+        write(w, Some(self.msr.to_be_bytes()));
+        write(w, Some(self.cr.to_be_bytes()));
+        write(w, Some(self.lr.to_be_bytes()));
+        write(w, Some(self.ctr.to_be_bytes()));
+        write(w, Some(self.xer.to_be_bytes()));
+        write(w, self.fpu.as_ref().map(|f| f.fpscr.to_be_bytes()));
+
+        for i in 0..32 {
+            write(w, self.vmx.as_ref().map(|v| v.vr[i].to_be_bytes()));
+        }
+
+        write(w, self.vmx.as_ref().map(|v| v.vscr.to_be_bytes()));
+        write(w, self.vmx.as_ref().map(|v| v.vrsave.to_be_bytes()));
+    }
+
+    fn gdb_deserialize(&mut self, bytes: &[u8]) -> Result<(), ()> {
+        if bytes.len() < REGS_SIZE {
+            return Err(());
+        }
+
+        let mut rd = Reader(bytes);
+        let mut fpu = Fpu::default();
+        let mut vmx = Vmx::default();
+
+        for reg in &mut self.r {
+            *reg = rd.u64()?;
+        }
+
+        for reg in &mut fpu.f {
+            *reg = rd.u64()?;
+        }
+
+        self.pc = rd.u64()?;
+        self.msr = rd.u64()?;
+        self.cr = rd.u32()?;
+        self.lr = rd.u64()?;
+        self.ctr = rd.u64()?;
+        self.xer = rd.u32()?;
+        fpu.fpscr = rd.u32()?;
+
+        for reg in &mut vmx.vr {
+            *reg = rd.u128()?;
+        }
+
+        vmx.vscr = rd.u32()?;
+        vmx.vrsave = rd.u32()?;
+
+        self.fpu = Some(fpu);
+        self.vmx = Some(vmx);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Serialize registers, with unavailable bytes as `None`.
+    fn serialize(regs: &PowerPc64Regs) -> ([Option<u8>; REGS_SIZE], usize) {
+        let mut data = [None; REGS_SIZE];
+        let mut len = 0;
+
+        regs.gdb_serialize(|b| {
+            data[len] = b;
+            len += 1;
+        });
+
+        (data, len)
+    }
+
+    fn sample() -> PowerPc64Regs {
+        let mut r = [0u64; 32];
+        for (i, reg) in r.iter_mut().enumerate() {
+            *reg = 0x8000_0000_0000_0000 | i as u64;
+        }
+
+        PowerPc64Regs {
+            r,
+            pc: 0x8000_0200_0001_0000,
+            msr: 0x9000_0000_0000_1000,
+            cr: 0x2400_0088,
+            lr: 0x8000_0200_0001_0040,
+            ctr: 0xFFFF_FFFF_0000_0001,
+            xer: 0x2000_0000,
+            fpu: Some(Fpu {
+                f: [1.5f64.to_bits(); 32],
+                fpscr: 0x0000_00F8,
+            }),
+            vmx: Some(Vmx {
+                vr: [0x0011_2233_4455_6677_8899_AABB_CCDD_EEFF; 32],
+                vscr: 0x0001_0000,
+                vrsave: 0xFFFF_FFFF,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let regs = sample();
+
+        let (data, len) = serialize(&regs);
+        assert_eq!(len, REGS_SIZE);
+
+        let bytes: [u8; REGS_SIZE] = data.map(|b| b.unwrap());
+        let mut out = PowerPc64Regs::default();
+        out.gdb_deserialize(&bytes).unwrap();
+
+        assert_eq!(out, regs);
+    }
+
+    #[test]
+    fn test_layout() {
+        let (data, _) = serialize(&sample());
+        let at = |off: usize, len: usize| -> u64 {
+            data[off..off + len]
+                .iter()
+                .fold(0, |acc, b| acc << 8 | b.unwrap() as u64)
+        };
+
+        // Offsets follow the register numbers in the target description.
+        assert_eq!(at(0x008, 8), 0x8000_0000_0000_0001);
+        assert_eq!(at(0x100, 8), 1.5f64.to_bits());
+        assert_eq!(at(0x200, 8), 0x8000_0200_0001_0000); // pc
+        assert_eq!(at(0x208, 8), 0x9000_0000_0000_1000); // msr
+        assert_eq!(at(0x210, 4), 0x2400_0088); // cr
+        assert_eq!(at(0x214, 8), 0x8000_0200_0001_0040); // lr
+        assert_eq!(at(0x21C, 8), 0xFFFF_FFFF_0000_0001); // ctr
+        assert_eq!(at(0x224, 4), 0x2000_0000); // xer
+        assert_eq!(at(0x228, 4), 0xF8); // fpscr
+        assert_eq!(at(0x22C, 8), 0x0011_2233_4455_6677); // vr0
+        assert_eq!(at(0x42C, 4), 0x0001_0000); // vscr
+        assert_eq!(at(0x430, 4), 0xFFFF_FFFF); // vrsave
+    }
+
+    #[test]
+    fn test_unavailable() {
+        let regs = PowerPc64Regs {
+            fpu: None,
+            vmx: None,
+            ..sample()
+        };
+
+        let (data, len) = serialize(&regs);
+        assert_eq!(len, REGS_SIZE);
+
+        assert!(data[..0x100].iter().all(|b| b.is_some()));
+        assert!(data[0x100..0x200].iter().all(|b| b.is_none()));
+        assert!(data[0x200..0x228].iter().all(|b| b.is_some()));
+        assert!(data[0x228..].iter().all(|b| b.is_none()));
+    }
+
+    #[test]
+    fn test_short() {
+        let mut regs = PowerPc64Regs::default();
+        assert_eq!(regs.gdb_deserialize(&[0u8; REGS_SIZE - 1]), Err(()));
+    }
+}
@@ -0,0 +1,161 @@
+//! Fixed-size chains of handlers that can be walked from any context, e.g. an exception handler,
+//! while handlers are added and removed.
+//!
+//! Each handler is stored with a mask (e.g. the processors it runs on) and an ID that identifies
+//! it for [Chain::remove]. Handlers are kept packed at the start of the chain in the order they
+//! were added, so the slots freed by removed handlers are reused.
+//!
+//! N.B: Changes to a chain must be serialized by the caller. Walking it needs no lock.
+#![no_std]
+
+use atomic::{Atomic, Ordering};
+use core::sync::atomic::AtomicU32;
+
+struct Slot<H: Copy> {
+    handler: Atomic<Option<H>>,
+    mask: AtomicU32,
+    id: AtomicU32,
+}
+
+impl<H: Copy> Slot<H> {
+    // N.B: This is only used to initialize chains.
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Self = Self {
+        handler: Atomic::new(None),
+        mask: AtomicU32::new(0),
+        id: AtomicU32::new(0),
+    };
+}
+
+/// A chain of up to `N` handlers of type `H`.
+pub struct Chain<H: Copy, const N: usize> {
+    slots: [Slot<H>; N],
+}
+
+impl<H: Copy, const N: usize> Chain<H, N> {
+    /// A chain with no handlers, for initializing statics.
+    #[allow(clippy::declare_interior_mutable_const)]
+    pub const EMPTY: Self = Self {
+        slots: [Slot::EMPTY; N],
+    };
+
+    /// Add `handler` after the last one in the chain. Returns `false` if the chain is full.
+    pub fn insert(&self, id: u32, mask: u32, handler: H) -> bool {
+        let slot = match self
+            .slots
+            .iter()
+            .find(|s| s.handler.load(Ordering::Relaxed).is_none())
+        {
+            Some(free) => free,
+            None => return false,
+        };
+
+        // N.B: The mask must be visible before the handler is.
+        slot.mask.store(mask, Ordering::Relaxed);
+        slot.id.store(id, Ordering::Relaxed);
+        slot.handler.store(Some(handler), Ordering::Release);
+
+        true
+    }
+
+    /// Remove the handler added as `id`, moving the handlers after it up to fill its slot.
+    ///
+    /// N.B: Code walking the chain meanwhile may see a moved handler twice, but never misses one.
+    pub fn remove(&self, id: u32) {
+        let mut hole = match self.slots.iter().position(|s| {
+            s.handler.load(Ordering::Relaxed).is_some() && s.id.load(Ordering::Relaxed) == id
+        }) {
+            Some(slot) => slot,
+            None => return,
+        };
+
+        self.slots[hole].handler.store(None, Ordering::Release);
+
+        while let Some(next) = self.slots.get(hole + 1) {
+            let handler = match next.handler.load(Ordering::Relaxed) {
+                Some(handler) => handler,
+                None => break,
+            };
+
+            let slot = &self.slots[hole];
+            slot.mask
+                .store(next.mask.load(Ordering::Relaxed), Ordering::Relaxed);
+            slot.id
+                .store(next.id.load(Ordering::Relaxed), Ordering::Relaxed);
+            slot.handler.store(Some(handler), Ordering::Release);
+            next.handler.store(None, Ordering::Release);
+
+            hole += 1;
+        }
+    }
+
+    /// The handlers in the chain, in order, with their masks.
+    pub fn iter(&self) -> impl Iterator<Item = (H, u32)> + '_ {
+        self.slots.iter().filter_map(|slot| {
+            let handler = slot.handler.load(Ordering::Acquire)?;
+            Some((handler, slot.mask.load(Ordering::Relaxed)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// The handlers in the chain, in slot order. Free slots are 0.
+    fn handlers<const N: usize>(chain: &Chain<u32, N>) -> [u32; N] {
+        let mut handlers = [0; N];
+        for (h, slot) in handlers.iter_mut().zip(chain.slots.iter()) {
+            *h = slot.handler.load(Ordering::Relaxed).unwrap_or(0);
+        }
+
+        handlers
+    }
+
+    #[test]
+    fn test_reuse_slots() {
+        let chain: Chain<u32, 8> = Chain::EMPTY;
+
+        // Handlers are numbered after their IDs here.
+        for id in 1..=8 {
+            assert!(chain.insert(id, u32::MAX, id));
+        }
+        assert!(!chain.insert(9, u32::MAX, 9));
+
+        // Removing handlers leaves the rest in order, with the free slots at the end.
+        chain.remove(3);
+        chain.remove(1);
+        assert_eq!(handlers(&chain), [2, 4, 5, 6, 7, 8, 0, 0]);
+
+        // The free slots are reused, after the last handler.
+        assert!(chain.insert(9, 0x01, 9));
+        assert!(chain.insert(10, u32::MAX, 10));
+        assert!(!chain.insert(11, u32::MAX, 11));
+        assert_eq!(handlers(&chain), [2, 4, 5, 6, 7, 8, 9, 10]);
+
+        // Moved handlers keep their IDs and masks.
+        chain.remove(8);
+        chain.remove(9);
+        assert_eq!(handlers(&chain), [2, 4, 5, 6, 7, 10, 0, 0]);
+        assert!(chain
+            .iter()
+            .eq([2, 4, 5, 6, 7, 10].iter().map(|&h| (h, u32::MAX))));
+
+        // Unknown IDs are ignored.
+        chain.remove(9);
+        assert_eq!(handlers(&chain), [2, 4, 5, 6, 7, 10, 0, 0]);
+    }
+
+    #[test]
+    fn test_iter_masks() {
+        let chain: Chain<u32, 4> = Chain::EMPTY;
+        assert_eq!(chain.iter().count(), 0);
+
+        assert!(chain.insert(1, 0x01, 100));
+        assert!(chain.insert(2, 0x3E, 200));
+        assert!(chain.iter().eq([(100, 0x01), (200, 0x3E)]));
+
+        chain.remove(1);
+        assert!(chain.iter().eq([(200, 0x3E)]));
+    }
+}
@@ -0,0 +1,502 @@
+//! A minimal `no_std` HTTP/1.1 server.
+//!
+//! [Connection] implements the server side of a single connection, independent
+//! of the network stack: bytes received from the client are fed in, and the
+//! response is read back out. Requests are dispatched to a [Handler], which
+//! receives request bodies as they arrive so that large uploads never need to
+//! be buffered. `multipart/form-data` bodies are decoded on the fly.
+//!
+//! Every response closes the connection; there is no support for keep-alive
+//! or the chunked transfer encoding.
+#![no_std]
+
+extern crate alloc;
+
+pub mod multipart;
+pub mod request;
+
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt::{self, Write};
+
+use multipart::Multipart;
+pub use request::{Head, Method};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The request is malformed.
+    BadRequest,
+    /// The request head is too large.
+    HeadTooLarge,
+    /// The body was rejected by the handler.
+    Aborted,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::BadRequest => write!(f, "bad request"),
+            Error::HeadTooLarge => write!(f, "request head too large"),
+            Error::Aborted => write!(f, "aborted"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    Accepted,
+    SeeOther,
+    BadRequest,
+    NotFound,
+    MethodNotAllowed,
+    Conflict,
+    LengthRequired,
+    PayloadTooLarge,
+    HeaderFieldsTooLarge,
+    InternalServerError,
+}
+
+impl Status {
+    pub fn code(&self) -> u16 {
+        match self {
+            Status::Ok => 200,
+            Status::Accepted => 202,
+            Status::SeeOther => 303,
+            Status::BadRequest => 400,
+            Status::NotFound => 404,
+            Status::MethodNotAllowed => 405,
+            Status::Conflict => 409,
+            Status::LengthRequired => 411,
+            Status::PayloadTooLarge => 413,
+            Status::HeaderFieldsTooLarge => 431,
+            Status::InternalServerError => 500,
+        }
+    }
+
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Status::Ok => "OK",
+            Status::Accepted => "Accepted",
+            Status::SeeOther => "See Other",
+            Status::BadRequest => "Bad Request",
+            Status::NotFound => "Not Found",
+            Status::MethodNotAllowed => "Method Not Allowed",
+            Status::Conflict => "Conflict",
+            Status::LengthRequired => "Length Required",
+            Status::PayloadTooLarge => "Payload Too Large",
+            Status::HeaderFieldsTooLarge => "Request Header Fields Too Large",
+            Status::InternalServerError => "Internal Server Error",
+        }
+    }
+}
+
+impl From<Error> for Status {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::BadRequest => Status::BadRequest,
+            Error::HeadTooLarge => Status::HeaderFieldsTooLarge,
+            Error::Aborted => Status::InternalServerError,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    status: Status,
+    headers: Vec<(&'static str, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: Status) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// A plain text response.
+    pub fn text(status: Status, text: &str) -> Self {
+        Self::new(status).body("text/plain; charset=utf-8", text)
+    }
+
+    /// A redirect to `location`, to be fetched with a GET request.
+    pub fn redirect(location: &str) -> Self {
+        Self::new(Status::SeeOther).header("Location", location)
+    }
+
+    pub fn header(mut self, name: &'static str, value: &str) -> Self {
+        self.headers.push((name, value.to_string()));
+        self
+    }
+
+    pub fn body(mut self, content_type: &str, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self.header("Content-Type", content_type)
+    }
+
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    /// Serialize the response. The body is omitted if `head_only` is set.
+    fn encode(&self, head_only: bool) -> Vec<u8> {
+        let mut head = String::new();
+
+        // N.B: Writing to a string cannot fail.
+        let _ = write!(
+            head,
+            "HTTP/1.1 {} {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+            self.status.code(),
+            self.status.reason(),
+            self.body.len()
+        );
+
+        for (name, value) in self.headers.iter() {
+            let _ = write!(head, "{}: {}\r\n", name, value);
+        }
+
+        head.push_str("\r\n");
+
+        let mut out = head.into_bytes();
+        if !head_only {
+            out.extend_from_slice(&self.body);
+        }
+
+        out
+    }
+}
+
+/// Handles requests on a [Connection].
+pub trait Handler {
+    /// Called once the request head has been received.
+    /// Returning a response rejects the request without reading its body.
+    fn begin(&mut self, head: &Head) -> Result<(), Response>;
+
+    /// Called with the request body as it arrives. For `multipart/form-data`
+    /// requests, only the contents of the first file field are passed.
+    /// Returning a response aborts the request.
+    fn data(&mut self, head: &Head, data: &[u8]) -> Result<(), Response>;
+
+    /// Called once the whole body has been received.
+    fn finish(&mut self, head: &Head) -> Response;
+}
+
+enum State {
+    /// Accumulating the request head.
+    Head(Vec<u8>),
+    /// Receiving the request body.
+    Body {
+        head: Head,
+        remaining: usize,
+        multipart: Option<Multipart>,
+    },
+    /// Sending the response.
+    Respond { out: Vec<u8>, sent: usize },
+}
+
+/// The server side of a single connection.
+pub struct Connection {
+    state: State,
+}
+
+impl Default for Connection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Connection {
+    pub fn new() -> Self {
+        Self {
+            state: State::Head(Vec::new()),
+        }
+    }
+
+    /// Handle data received from the client.
+    /// Anything received after the request (e.g. a pipelined request) is ignored.
+    pub fn feed(&mut self, mut data: &[u8], handler: &mut impl Handler) {
+        while !data.is_empty() {
+            match &mut self.state {
+                State::Head(buf) => {
+                    let old = buf.len();
+
+                    // Anything past the head is the start of the body, so only take as much as
+                    // a head could possibly need.
+                    let take = data.len().min(request::MAX_HEAD - old);
+                    buf.extend_from_slice(&data[..take]);
+
+                    match Head::parse(buf) {
+                        Ok(Some((head, len))) => {
+                            data = &data[len - old..];
+                            self.begin(head, handler);
+                        }
+
+                        Ok(None) => data = &data[take..],
+
+                        Err(e) => {
+                            self.respond(Response::text(e.into(), "Bad request\n"), false);
+                            return;
+                        }
+                    }
+                }
+
+                State::Body { .. } => {
+                    let used = self.body(data, handler);
+                    data = &data[used..];
+                }
+
+                State::Respond { .. } => return,
+            }
+        }
+    }
+
+    fn begin(&mut self, head: Head, handler: &mut impl Handler) {
+        let head_only = head.method == Method::Head;
+
+        let length = match (head.content_length, head.method) {
+            _ if head.chunked => None,
+            (Some(len), _) => Some(len),
+            (None, Method::Post) => None,
+            (None, _) => Some(0),
+        };
+
+        let remaining = match length {
+            Some(len) => len,
+            None => {
+                let resp = Response::text(Status::LengthRequired, "Length required\n");
+                return self.respond(resp, head_only);
+            }
+        };
+
+        if let Err(resp) = handler.begin(&head) {
+            return self.respond(resp, head_only);
+        }
+
+        let multipart = head.boundary().map(Multipart::new);
+        self.state = State::Body {
+            head,
+            remaining,
+            multipart,
+        };
+
+        if remaining == 0 {
+            self.body(&[], handler);
+        }
+    }
+
+    /// Handle body data, returning the number of bytes consumed.
+    fn body(&mut self, data: &[u8], handler: &mut impl Handler) -> usize {
+        let (head, remaining, multipart) = match &mut self.state {
+            State::Body {
+                head,
+                remaining,
+                multipart,
+            } => (head, remaining, multipart),
+            _ => return 0,
+        };
+
+        let take = data.len().min(*remaining);
+        let chunk = &data[..take];
+        *remaining -= take;
+
+        let res = match multipart {
+            Some(mp) => {
+                let mut rejected = None;
+                let res = mp.feed(chunk, &mut |d| {
+                    handler.data(head, d).map_err(|resp| rejected = Some(resp))
+                });
+
+                match (res, rejected) {
+                    (Ok(()), _) if *remaining == 0 && !mp.is_done() => {
+                        Err(Response::text(Status::BadRequest, "Truncated body\n"))
+                    }
+
+                    (Ok(()), _) => Ok(()),
+                    (Err(_), Some(resp)) => Err(resp),
+                    (Err(e), None) => Err(Response::text(e.into(), "Bad request\n")),
+                }
+            }
+
+            None if chunk.is_empty() => Ok(()),
+            None => handler.data(head, chunk),
+        };
+
+        let head_only = head.method == Method::Head;
+
+        match res {
+            Ok(()) if *remaining == 0 => {
+                let resp = handler.finish(head);
+                self.respond(resp, head_only);
+            }
+
+            Ok(()) => {}
+            Err(resp) => self.respond(resp, head_only),
+        }
+
+        take
+    }
+
+    fn respond(&mut self, resp: Response, head_only: bool) {
+        self.state = State::Respond {
+            out: resp.encode(head_only),
+            sent: 0,
+        };
+    }
+
+    /// The response data waiting to be sent.
+    pub fn output(&self) -> &[u8] {
+        match &self.state {
+            State::Respond { out, sent } => &out[*sent..],
+            _ => &[],
+        }
+    }
+
+    /// Mark `n` bytes of [Connection::output] as sent.
+    pub fn consume(&mut self, n: usize) {
+        if let State::Respond { out, sent } = &mut self.state {
+            *sent = (*sent + n).min(out.len());
+        }
+    }
+
+    /// Returns true once the response has been sent, and the connection can be closed.
+    pub fn is_done(&self) -> bool {
+        matches!(&self.state, State::Respond { out, sent } if *sent == out.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Default)]
+    struct Upload {
+        data: Vec<u8>,
+        limit: usize,
+    }
+
+    impl Handler for Upload {
+        fn begin(&mut self, head: &Head) -> Result<(), Response> {
+            match (head.method, head.path.as_str()) {
+                (Method::Get, "/") => Ok(()),
+                (Method::Post, "/upload") => Ok(()),
+                _ => Err(Response::text(Status::NotFound, "Not found\n")),
+            }
+        }
+
+        fn data(&mut self, _head: &Head, data: &[u8]) -> Result<(), Response> {
+            if self.data.len() + data.len() > self.limit {
+                return Err(Response::new(Status::PayloadTooLarge));
+            }
+
+            self.data.extend_from_slice(data);
+            Ok(())
+        }
+
+        fn finish(&mut self, head: &Head) -> Response {
+            match head.method {
+                Method::Post => Response::redirect("/"),
+                _ => Response::new(Status::Ok).body("text/html", "<html></html>"),
+            }
+        }
+    }
+
+    fn run(chunks: &[&[u8]], handler: &mut Upload) -> Vec<u8> {
+        let mut conn = Connection::new();
+
+        for chunk in chunks {
+            conn.feed(chunk, handler);
+        }
+
+        let out = conn.output().to_vec();
+        conn.consume(out.len());
+        assert!(conn.is_done());
+
+        out
+    }
+
+    #[test]
+    fn test_get() {
+        let mut handler = Upload::default();
+        let out = run(&[b"GET / HTTP/1.1\r\nHost: x\r\n", b"\r\n"], &mut handler);
+
+        assert_eq!(
+            out,
+            b"HTTP/1.1 200 OK\r\nContent-Length: 13\r\nConnection: close\r\n\
+            Content-Type: text/html\r\n\r\n<html></html>"
+        );
+
+        let out = run(&[b"GET /nope HTTP/1.1\r\n\r\n"], &mut handler);
+        assert!(out.starts_with(b"HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn test_upload() {
+        let body = b"--b\r\nContent-Disposition: form-data; name=\"f\"; filename=\"a\"\r\n\r\n\
+            hello\r\n--b--\r\n";
+        let mut req = alloc::format!(
+            "POST /upload HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=b\r\n\
+            Content-Length: {}\r\n\r\n",
+            body.len()
+        )
+        .into_bytes();
+        req.extend_from_slice(body);
+
+        for i in 0..req.len() {
+            let mut handler = Upload {
+                limit: 16,
+                ..Default::default()
+            };
+
+            let out = run(&[&req[..i], &req[i..]], &mut handler);
+            assert!(
+                out.starts_with(b"HTTP/1.1 303 See Other\r\n"),
+                "split at {}",
+                i
+            );
+            assert_eq!(handler.data, b"hello");
+        }
+
+        // A raw upload that is too large.
+        let mut handler = Upload {
+            limit: 4,
+            ..Default::default()
+        };
+        let out = run(
+            &[b"POST /upload HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello"],
+            &mut handler,
+        );
+        assert!(out.starts_with(b"HTTP/1.1 413 Payload Too Large\r\n"));
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut handler = Upload::default();
+
+        let out = run(&[b"POST /upload HTTP/1.1\r\n\r\n"], &mut handler);
+        assert!(out.starts_with(b"HTTP/1.1 411 Length Required\r\n"));
+
+        let out = run(
+            &[b"POST /upload HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n"],
+            &mut handler,
+        );
+        assert!(out.starts_with(b"HTTP/1.1 411 Length Required\r\n"));
+
+        let out = run(&[b"garbage\r\n\r\n"], &mut handler);
+        assert!(out.starts_with(b"HTTP/1.1 400 Bad Request\r\n"));
+
+        // The multipart body ends before the closing delimiter.
+        let out = run(
+            &[
+                b"POST /upload HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=b\r\n\
+                Content-Length: 3\r\n\r\n--b",
+            ],
+            &mut handler,
+        );
+        assert!(out.starts_with(b"HTTP/1.1 400 Bad Request\r\n"));
+    }
+}
@@ -0,0 +1,249 @@
+//! This module contains a streaming `multipart/form-data` decoder (RFC 7578).
+//!
+//! Only the contents of the first file field are passed on; other fields are
+//! skipped. The body is decoded incrementally, so uploads of any size can be
+//! written straight to their destination.
+
+use alloc::{vec, vec::Vec};
+
+use crate::Error;
+
+/// The largest part header block we will accept.
+const MAX_PART_HEAD: usize = 1024;
+
+enum State {
+    /// Searching for a delimiter. Data is passed on if `emit` is set.
+    Body { emit: bool },
+    /// A delimiter was found. Waiting for either `--` (the end of the body) or CRLF.
+    Delimiter { buf: [u8; 2], len: usize },
+    /// Accumulating the headers of a part.
+    PartHead(Vec<u8>),
+    /// The closing delimiter was found. Anything that follows is ignored.
+    Done,
+}
+
+pub struct Multipart {
+    /// `CRLF--boundary`
+    delimiter: Vec<u8>,
+    /// The number of delimiter bytes matched so far.
+    matched: usize,
+    state: State,
+    /// Set once a file field has been found.
+    found_file: bool,
+}
+
+impl Multipart {
+    pub fn new(boundary: &str) -> Self {
+        let mut delimiter = vec![b'\r', b'\n', b'-', b'-'];
+        delimiter.extend_from_slice(boundary.as_bytes());
+
+        Self {
+            delimiter,
+            // The first delimiter is not preceded by a line break.
+            matched: 2,
+            state: State::Body { emit: false },
+            found_file: false,
+        }
+    }
+
+    /// Returns true once the closing delimiter has been received.
+    pub fn is_done(&self) -> bool {
+        matches!(self.state, State::Done)
+    }
+
+    /// Returns true if a file field was found.
+    pub fn found_file(&self) -> bool {
+        self.found_file
+    }
+
+    /// Decode a chunk of the body, passing file contents to `sink`.
+    /// If `sink` fails, decoding stops with [Error::Aborted].
+    pub fn feed(
+        &mut self,
+        mut data: &[u8],
+        sink: &mut impl FnMut(&[u8]) -> Result<(), ()>,
+    ) -> Result<(), Error> {
+        while !data.is_empty() {
+            let used = match &mut self.state {
+                State::Body { emit } => {
+                    let emit = *emit;
+                    self.body(data, emit, sink)?
+                }
+
+                State::Delimiter { buf, len } => {
+                    buf[*len] = data[0];
+                    *len += 1;
+
+                    if *len == 2 {
+                        self.state = match *buf {
+                            [b'-', b'-'] => State::Done,
+                            [b'\r', b'\n'] => State::PartHead(Vec::new()),
+                            _ => return Err(Error::BadRequest),
+                        };
+                    }
+
+                    1
+                }
+
+                State::PartHead(head) => {
+                    head.push(data[0]);
+
+                    if head.ends_with(b"\r\n\r\n") {
+                        let emit = !self.found_file && is_file_field(head);
+                        self.found_file |= emit;
+                        self.state = State::Body { emit };
+                    } else if head.len() > MAX_PART_HEAD {
+                        return Err(Error::HeadTooLarge);
+                    }
+
+                    1
+                }
+
+                State::Done => data.len(),
+            };
+
+            data = &data[used..];
+        }
+
+        Ok(())
+    }
+
+    /// Scan body data for the delimiter, returning the number of bytes consumed.
+    fn body(
+        &mut self,
+        data: &[u8],
+        emit: bool,
+        sink: &mut impl FnMut(&[u8]) -> Result<(), ()>,
+    ) -> Result<usize, Error> {
+        let mut write = |d: &[u8]| match emit && !d.is_empty() {
+            true => sink(d).map_err(|_| Error::Aborted),
+            false => Ok(()),
+        };
+
+        // The start of the pending run of data bytes in `data`.
+        let mut start = 0;
+
+        for (i, &b) in data.iter().enumerate() {
+            if b == self.delimiter[self.matched] {
+                if self.matched == 0 {
+                    write(&data[start..i])?;
+                }
+
+                self.matched += 1;
+                if self.matched == self.delimiter.len() {
+                    self.matched = 0;
+                    self.state = State::Delimiter {
+                        buf: [0; 2],
+                        len: 0,
+                    };
+
+                    return Ok(i + 1);
+                }
+
+                continue;
+            }
+
+            if self.matched != 0 {
+                // A false start. The bytes we matched are data after all.
+                // N.B: The delimiter cannot match again until the next CR, which
+                // only appears at its start.
+                write(&self.delimiter[..self.matched])?;
+                self.matched = 0;
+                start = i;
+
+                if b == self.delimiter[0] {
+                    self.matched = 1;
+                }
+            }
+        }
+
+        if self.matched == 0 {
+            write(&data[start..])?;
+        }
+
+        Ok(data.len())
+    }
+}
+
+/// Returns true if the part headers describe a file upload.
+fn is_file_field(head: &[u8]) -> bool {
+    let head = match core::str::from_utf8(head) {
+        Ok(h) => h,
+        Err(_) => return false,
+    };
+
+    head.split("\r\n")
+        .filter_map(|line| line.split_once(':'))
+        .any(|(name, value)| {
+            name.eq_ignore_ascii_case("content-disposition")
+                && value
+                    .split(';')
+                    .any(|p| p.trim_start().starts_with("filename="))
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const BODY: &[u8] = b"preamble\r\n--XyZ\r\n\
+        Content-Disposition: form-data; name=\"note\"\r\n\r\n\
+        not a file\r\n--XyZ\r\n\
+        Content-Disposition: form-data; name=\"image\"; filename=\"vmlinux\"\r\n\
+        Content-Type: application/octet-stream\r\n\r\n\
+        \x7fELF\r\n--X\r\r\n--XyY\r\nend\r\n--XyZ--\r\nepilogue";
+
+    const FILE: &[u8] = b"\x7fELF\r\n--X\r\r\n--XyY\r\nend";
+
+    fn decode(chunks: &[&[u8]]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut mp = Multipart::new("XyZ");
+
+        for chunk in chunks {
+            mp.feed(chunk, &mut |d: &[u8]| {
+                out.extend_from_slice(d);
+                Ok(())
+            })
+            .unwrap();
+        }
+
+        assert!(mp.is_done());
+        assert!(mp.found_file());
+        out
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode(&[BODY]), FILE);
+
+        // Delimiters split across chunks.
+        for i in 0..BODY.len() {
+            assert_eq!(decode(&[&BODY[..i], &BODY[i..]]), FILE, "split at {}", i);
+        }
+
+        let bytes: Vec<&[u8]> = BODY.chunks(1).collect();
+        assert_eq!(decode(&bytes), FILE);
+    }
+
+    #[test]
+    fn test_no_preamble() {
+        let body = b"--b\r\nContent-Disposition: form-data; name=\"f\"; filename=\"a\"\r\n\r\nabc\r\n--b--";
+        let mut out = Vec::new();
+        let mut mp = Multipart::new("b");
+
+        mp.feed(body, &mut |d: &[u8]| {
+            out.extend_from_slice(d);
+            Ok(())
+        })
+        .unwrap();
+
+        assert!(mp.is_done());
+        assert_eq!(out, b"abc");
+    }
+
+    #[test]
+    fn test_sink_error() {
+        let mut mp = Multipart::new("XyZ");
+        assert_eq!(mp.feed(BODY, &mut |_: &[u8]| Err(())), Err(Error::Aborted));
+    }
+}
@@ -0,0 +1,134 @@
+//! This module contains the request head parser.
+
+use alloc::string::{String, ToString};
+
+use crate::Error;
+
+/// The largest request head we will accept.
+pub const MAX_HEAD: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Other,
+}
+
+/// A parsed request line and the headers we care about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Head {
+    pub method: Method,
+    /// The request target, without any query string.
+    pub path: String,
+    pub content_length: Option<usize>,
+    pub content_type: Option<String>,
+    /// Set if the body uses the chunked transfer encoding.
+    pub chunked: bool,
+}
+
+impl Head {
+    /// Parse a request head from the start of `buf`.
+    ///
+    /// Returns `Ok(None)` if the head is incomplete, or the head and its length
+    /// (including the terminating blank line) otherwise.
+    pub fn parse(buf: &[u8]) -> Result<Option<(Self, usize)>, Error> {
+        let len = match buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            Some(pos) => pos + 4,
+            None if buf.len() >= MAX_HEAD => return Err(Error::HeadTooLarge),
+            None => return Ok(None),
+        };
+
+        let head = core::str::from_utf8(&buf[..len - 4]).map_err(|_| Error::BadRequest)?;
+        let mut lines = head.split("\r\n");
+
+        let mut request = lines.next().ok_or(Error::BadRequest)?.split(' ');
+        let method = match request.next() {
+            Some("GET") => Method::Get,
+            Some("HEAD") => Method::Head,
+            Some("POST") => Method::Post,
+            Some(_) => Method::Other,
+            None => return Err(Error::BadRequest),
+        };
+
+        let target = request.next().ok_or(Error::BadRequest)?;
+        match request.next() {
+            Some(v) if v.starts_with("HTTP/1.") => {}
+            _ => return Err(Error::BadRequest),
+        }
+
+        let path = target.split('?').next().unwrap_or(target).to_string();
+
+        let mut head = Self {
+            method,
+            path,
+            content_length: None,
+            content_type: None,
+            chunked: false,
+        };
+
+        for line in lines {
+            let (name, value) = line.split_once(':').ok_or(Error::BadRequest)?;
+            let value = value.trim();
+
+            if name.eq_ignore_ascii_case("content-length") {
+                head.content_length = Some(value.parse().map_err(|_| Error::BadRequest)?);
+            } else if name.eq_ignore_ascii_case("content-type") {
+                head.content_type = Some(value.to_string());
+            } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                head.chunked = value.eq_ignore_ascii_case("chunked");
+            }
+        }
+
+        Ok(Some((head, len)))
+    }
+
+    /// The multipart boundary, if the body is `multipart/form-data`.
+    pub fn boundary(&self) -> Option<&str> {
+        let content_type = self.content_type.as_deref()?;
+        let mut params = content_type.split(';');
+
+        let mime = params.next()?.trim();
+        if !mime.eq_ignore_ascii_case("multipart/form-data") {
+            return None;
+        }
+
+        params.find_map(|p| {
+            let (name, value) = p.split_once('=')?;
+            if name.trim().eq_ignore_ascii_case("boundary") {
+                Some(value.trim().trim_matches('"'))
+            } else {
+                None
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let req = b"POST /upload?x=1 HTTP/1.1\r\nHost: xenon\r\nContent-Length: 12\r\n\
+            Content-Type: multipart/form-data; boundary=\"----abc\"\r\n\r\nbody";
+
+        let (head, len) = Head::parse(req).unwrap().unwrap();
+        assert_eq!(len, req.len() - 4);
+        assert_eq!(head.method, Method::Post);
+        assert_eq!(head.path, "/upload");
+        assert_eq!(head.content_length, Some(12));
+        assert_eq!(head.boundary(), Some("----abc"));
+        assert!(!head.chunked);
+
+        // Incomplete.
+        assert_eq!(Head::parse(&req[..20]), Ok(None));
+
+        assert_eq!(Head::parse(b"GET /\r\n\r\n"), Err(Error::BadRequest));
+        assert_eq!(
+            Head::parse(b"GET / HTTP/1.1\r\nContent-Length: x\r\n\r\n"),
+            Err(Error::BadRequest)
+        );
+        assert_eq!(Head::parse(&[b'a'; MAX_HEAD]), Err(Error::HeadTooLarge));
+    }
+}
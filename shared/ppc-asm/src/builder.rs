@@ -0,0 +1,130 @@
+use crate::*;
+
+/// Builds a fixed-length instruction sequence. All methods are `const`, so sequences with
+/// constant operands can be built at compile time.
+pub struct Builder<const N: usize> {
+    code: [u32; N],
+    len: usize,
+}
+
+impl<const N: usize> Builder<N> {
+    pub const fn new() -> Self {
+        Self {
+            code: [0; N],
+            len: 0,
+        }
+    }
+
+    /// Append an instruction.
+    pub const fn push(mut self, insn: u32) -> Self {
+        assert!(self.len < N, "too many instructions");
+
+        self.code[self.len] = insn;
+        self.len += 1;
+        self
+    }
+
+    /// Append the load of a 64-bit constant into `rt`. This always takes five instructions, so
+    /// the value can be patched later.
+    pub const fn load_u64(self, rt: Gpr, value: u64) -> Self {
+        self.push(lis(rt, (value >> 48) as u16))
+            .push(ori(rt, rt, (value >> 32) as u16))
+            .push(rldicr(rt, rt, 32, 31))
+            .push(oris(rt, rt, (value >> 16) as u16))
+            .push(ori(rt, rt, value as u16))
+    }
+
+    /// Append an absolute jump to `target`, through `scratch` and CTR.
+    pub const fn jump(self, scratch: Gpr, target: u64) -> Self {
+        self.load_u64(scratch, target)
+            .push(mtctr(scratch))
+            .push(bctr())
+    }
+
+    /// Return the finished sequence, which must fill the builder exactly.
+    pub const fn finish(self) -> [u32; N] {
+        assert!(self.len == N, "sequence is incomplete");
+        self.code
+    }
+}
+
+impl<const N: usize> Default for Builder<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_longjmp() {
+        let target: u64 = 0x8000_0200_1C00_0100;
+        let code = Builder::<17>::new()
+            .load_u64(R3, target)
+            .push(mtspr(Spr::SRR0, R3))
+            .push(lis(R4, 0))
+            .push(ori(R4, R4, 0x8030))
+            .push(mfmsr(R3))
+            .push(andc(R3, R3, R4))
+            .push(mtspr(Spr::SRR1, R3))
+            .load_u64(R3, 0x1234_5678_9ABC_DEF0)
+            .push(rfid())
+            .finish();
+
+        // The sequence as it used to be written out by hand.
+        let expected = [
+            0x3C600000 | (target >> 48) as u32 & 0xFFFF,
+            0x60630000 | (target >> 32) as u32 & 0xFFFF,
+            0x786307C6,
+            0x64630000 | (target >> 16) as u32 & 0xFFFF,
+            0x60630000 | target as u32 & 0xFFFF,
+            0x7C7A03A6,
+            0x3c800000,
+            0x60848030,
+            0x7C6000A6,
+            0x7C632078,
+            0x7C7B03A6,
+            0x3C601234,
+            0x60635678,
+            0x786307C6,
+            0x64639ABC,
+            0x6063DEF0,
+            0x4C000024,
+        ];
+
+        assert_eq!(code, expected);
+    }
+
+    #[test]
+    fn test_jump() {
+        const CODE: [u32; 11] = Builder::new()
+            .push(mtspr(Spr::HSPRG0, R3))
+            .push(mtspr(Spr::HSPRG1, R4))
+            .load_u64(R3, 0x8000_0000_0000_1234)
+            .push(mfctr(R4))
+            .push(mtctr(R3))
+            .push(li(R3, 0x700))
+            .push(bctr())
+            .finish();
+
+        assert_eq!(
+            CODE,
+            [
+                0x7C704BA6, 0x7C914BA6, 0x3C608000, 0x60630000, 0x786307C6, 0x64630000, 0x60631234,
+                0x7C8902A6, 0x7C6903A6, 0x38600700, 0x4E800420,
+            ]
+        );
+
+        let code = Builder::<7>::new().jump(R12, 0x100).finish();
+        assert_eq!(code[4], ori(R12, R12, 0x100));
+        assert_eq!(code[5..], [0x7D8903A6, 0x4E800420]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_incomplete() {
+        Builder::<2>::new().push(nop()).finish();
+    }
+}
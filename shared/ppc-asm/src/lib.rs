@@ -0,0 +1,349 @@
+//! A `no_std` encoder for the 64-bit PowerPC instructions that bootloader code needs to generate
+//! at runtime, e.g. jump stubs placed at exception vectors.
+//!
+//! Every encoder is a `const fn`, so code can be built at compile time:
+//!
+//! ```
+//! use ppc_asm::*;
+//!
+//! const STUB: [u32; 7] = Builder::new().jump(R3, 0x8000_0000_1C00_0100).finish();
+//!
+//! assert_eq!(STUB[5], mtctr(R3));
+//! assert_eq!(STUB[6], bctr());
+//! ```
+//!
+//! Operands are typed ([Gpr], [Spr]) so they can't be swapped by accident. Immediates are
+//! truncated to their field, as an assembler's `@l` and friends would.
+#![no_std]
+
+mod builder;
+
+pub use builder::Builder;
+
+/// A general purpose register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gpr(u8);
+
+impl Gpr {
+    pub const fn new(n: u8) -> Self {
+        assert!(n < 32, "no such register");
+        Self(n)
+    }
+
+    const fn bits(self) -> u32 {
+        self.0 as u32
+    }
+}
+
+macro_rules! gprs {
+    ($($name:ident = $n:literal),*) => {
+        $(pub const $name: Gpr = Gpr($n);)*
+    };
+}
+
+gprs! {
+    R0 = 0, R1 = 1, R2 = 2, R3 = 3, R4 = 4, R5 = 5, R6 = 6, R7 = 7,
+    R8 = 8, R9 = 9, R10 = 10, R11 = 11, R12 = 12, R13 = 13, R14 = 14, R15 = 15,
+    R16 = 16, R17 = 17, R18 = 18, R19 = 19, R20 = 20, R21 = 21, R22 = 22, R23 = 23,
+    R24 = 24, R25 = 25, R26 = 26, R27 = 27, R28 = 28, R29 = 29, R30 = 30, R31 = 31
+}
+
+/// A special purpose register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Spr(pub u16);
+
+impl Spr {
+    pub const XER: Spr = Spr(1);
+    pub const LR: Spr = Spr(8);
+    pub const CTR: Spr = Spr(9);
+    pub const DSISR: Spr = Spr(18);
+    pub const DAR: Spr = Spr(19);
+    pub const DEC: Spr = Spr(22);
+    pub const SRR0: Spr = Spr(26);
+    pub const SRR1: Spr = Spr(27);
+    pub const HSPRG0: Spr = Spr(304);
+    pub const HSPRG1: Spr = Spr(305);
+    pub const HDSISR: Spr = Spr(306);
+    pub const HDAR: Spr = Spr(307);
+    pub const HDEC: Spr = Spr(310);
+    pub const HRMOR: Spr = Spr(313);
+    pub const HSRR0: Spr = Spr(314);
+    pub const HSRR1: Spr = Spr(315);
+    pub const LPCR: Spr = Spr(318);
+    pub const LPIDR: Spr = Spr(319);
+    pub const HID0: Spr = Spr(1008);
+    pub const HID1: Spr = Spr(1009);
+    pub const HID4: Spr = Spr(1012);
+    pub const HID6: Spr = Spr(1017);
+    pub const PIR: Spr = Spr(1023);
+
+    /// The SPR field, which holds the number with its halves swapped.
+    const fn bits(self) -> u32 {
+        let n = self.0 as u32;
+        (n & 0x1F) << 5 | (n >> 5) & 0x1F
+    }
+}
+
+const fn d_form(op: u32, rt: Gpr, ra: Gpr, imm: u16) -> u32 {
+    op << 26 | rt.bits() << 21 | ra.bits() << 16 | imm as u32
+}
+
+const fn x_form(rt: u32, ra: u32, rb: u32, xo: u32) -> u32 {
+    31 << 26 | rt << 21 | ra << 16 | rb << 11 | xo << 1
+}
+
+const fn md_form(ra: Gpr, rs: Gpr, sh: u32, mask: u32, xo: u32) -> u32 {
+    assert!(sh < 64 && mask < 64, "shift or mask out of range");
+
+    // The high bits of the shift and the mask are kept apart from the rest.
+    30 << 26
+        | rs.bits() << 21
+        | ra.bits() << 16
+        | (sh & 0x1F) << 11
+        | (mask & 0x1F) << 6
+        | (mask >> 5) << 5
+        | xo << 2
+        | (sh >> 5) << 1
+}
+
+/// `addi rt, ra, si`
+pub const fn addi(rt: Gpr, ra: Gpr, si: i16) -> u32 {
+    d_form(14, rt, ra, si as u16)
+}
+
+/// `addis rt, ra, si`
+pub const fn addis(rt: Gpr, ra: Gpr, si: i16) -> u32 {
+    d_form(15, rt, ra, si as u16)
+}
+
+/// `li rt, si`
+pub const fn li(rt: Gpr, si: i16) -> u32 {
+    addi(rt, R0, si)
+}
+
+/// `lis rt, ui`, with the immediate given as the high half of a value.
+pub const fn lis(rt: Gpr, ui: u16) -> u32 {
+    d_form(15, rt, R0, ui)
+}
+
+/// `ori ra, rs, ui`
+pub const fn ori(ra: Gpr, rs: Gpr, ui: u16) -> u32 {
+    d_form(24, rs, ra, ui)
+}
+
+/// `oris ra, rs, ui`
+pub const fn oris(ra: Gpr, rs: Gpr, ui: u16) -> u32 {
+    d_form(25, rs, ra, ui)
+}
+
+/// `ld rt, ds(ra)`
+pub const fn ld(rt: Gpr, ds: i16, ra: Gpr) -> u32 {
+    assert!(ds & 3 == 0, "misaligned displacement");
+    d_form(58, rt, ra, ds as u16)
+}
+
+/// `std rs, ds(ra)`
+pub const fn std(rs: Gpr, ds: i16, ra: Gpr) -> u32 {
+    assert!(ds & 3 == 0, "misaligned displacement");
+    d_form(62, rs, ra, ds as u16)
+}
+
+/// `rldicl ra, rs, sh, mb`
+pub const fn rldicl(ra: Gpr, rs: Gpr, sh: u32, mb: u32) -> u32 {
+    md_form(ra, rs, sh, mb, 0)
+}
+
+/// `rldicr ra, rs, sh, me`
+pub const fn rldicr(ra: Gpr, rs: Gpr, sh: u32, me: u32) -> u32 {
+    md_form(ra, rs, sh, me, 1)
+}
+
+/// `sldi ra, rs, n`
+pub const fn sldi(ra: Gpr, rs: Gpr, n: u32) -> u32 {
+    rldicr(ra, rs, n, 63 - n)
+}
+
+/// `rotldi ra, rs, n`
+pub const fn rotldi(ra: Gpr, rs: Gpr, n: u32) -> u32 {
+    rldicl(ra, rs, n, 0)
+}
+
+/// `or ra, rs, rb`
+pub const fn or(ra: Gpr, rs: Gpr, rb: Gpr) -> u32 {
+    x_form(rs.bits(), ra.bits(), rb.bits(), 444)
+}
+
+/// `mr ra, rs`
+pub const fn mr(ra: Gpr, rs: Gpr) -> u32 {
+    or(ra, rs, rs)
+}
+
+/// `andc ra, rs, rb`
+pub const fn andc(ra: Gpr, rs: Gpr, rb: Gpr) -> u32 {
+    x_form(rs.bits(), ra.bits(), rb.bits(), 60)
+}
+
+/// `mfspr rt, spr`
+pub const fn mfspr(rt: Gpr, spr: Spr) -> u32 {
+    let spr = spr.bits();
+    x_form(rt.bits(), spr >> 5, spr & 0x1F, 339)
+}
+
+/// `mtspr spr, rs`
+pub const fn mtspr(spr: Spr, rs: Gpr) -> u32 {
+    let spr = spr.bits();
+    x_form(rs.bits(), spr >> 5, spr & 0x1F, 467)
+}
+
+/// `mflr rt`
+pub const fn mflr(rt: Gpr) -> u32 {
+    mfspr(rt, Spr::LR)
+}
+
+/// `mtlr rs`
+pub const fn mtlr(rs: Gpr) -> u32 {
+    mtspr(Spr::LR, rs)
+}
+
+/// `mfctr rt`
+pub const fn mfctr(rt: Gpr) -> u32 {
+    mfspr(rt, Spr::CTR)
+}
+
+/// `mtctr rs`
+pub const fn mtctr(rs: Gpr) -> u32 {
+    mtspr(Spr::CTR, rs)
+}
+
+/// `mfmsr rt`
+pub const fn mfmsr(rt: Gpr) -> u32 {
+    x_form(rt.bits(), 0, 0, 83)
+}
+
+/// `mtmsrd rs`
+pub const fn mtmsrd(rs: Gpr) -> u32 {
+    x_form(rs.bits(), 0, 0, 178)
+}
+
+/// `b` to `offset` bytes from the branch.
+pub const fn b(offset: i64) -> u32 {
+    assert!(offset & 3 == 0, "misaligned branch");
+    assert!(
+        offset >= -0x200_0000 && offset < 0x200_0000,
+        "branch out of range"
+    );
+
+    18 << 26 | (offset as u32 & 0x03FF_FFFC)
+}
+
+/// `bl` to `offset` bytes from the branch.
+pub const fn bl(offset: i64) -> u32 {
+    b(offset) | 1
+}
+
+/// `blr`
+pub const fn blr() -> u32 {
+    0x4E80_0020
+}
+
+/// `bctr`
+pub const fn bctr() -> u32 {
+    0x4E80_0420
+}
+
+/// `bctrl`
+pub const fn bctrl() -> u32 {
+    bctr() | 1
+}
+
+/// `rfid`
+pub const fn rfid() -> u32 {
+    0x4C00_0024
+}
+
+/// `isync`
+pub const fn isync() -> u32 {
+    0x4C00_012C
+}
+
+/// `sync`
+pub const fn sync() -> u32 {
+    x_form(0, 0, 0, 598)
+}
+
+/// `nop`
+pub const fn nop() -> u32 {
+    ori(R0, R0, 0)
+}
+
+/// `trap`
+pub const fn trap() -> u32 {
+    x_form(31, 0, 0, 4)
+}
+
+/// Split an address into the halves loaded by `addis` (`@ha`) and `addi` (`@l`). The high half
+/// is adjusted for the low half being sign-extended.
+pub const fn split_ha(addr: u32) -> (u16, u16) {
+    let lo = addr as u16;
+    let hi = ((addr >> 16) as u16).wrapping_add((lo >> 15) & 1);
+
+    (hi, lo)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_encodings() {
+        assert_eq!(lis(R3, 0x8000), 0x3C608000);
+        assert_eq!(ori(R3, R3, 0x1234), 0x60631234);
+        assert_eq!(oris(R3, R3, 0x1C00), 0x64631C00);
+        assert_eq!(rldicr(R3, R3, 32, 31), 0x786307C6);
+        assert_eq!(sldi(R3, R3, 41), 0x78634D86);
+        assert_eq!(rotldi(R4, R4, 32), 0x78840002);
+        assert_eq!(addi(R4, R4, -0x7FE8), 0x38848018);
+        assert_eq!(li(R3, 0x70), 0x38600070);
+        assert_eq!(mr(R4, R3), 0x7C641B78);
+        assert_eq!(andc(R3, R3, R4), 0x7C632078);
+        assert_eq!(mtspr(Spr::SRR0, R3), 0x7C7A03A6);
+        assert_eq!(mtspr(Spr::SRR1, R3), 0x7C7B03A6);
+        assert_eq!(mtspr(Spr::HSPRG0, R3), 0x7C704BA6);
+        assert_eq!(mtspr(Spr::HSPRG1, R4), 0x7C914BA6);
+        assert_eq!(mfspr(R3, Spr::PIR), 0x7C7FFAA6);
+        assert_eq!(mfctr(R4), 0x7C8902A6);
+        assert_eq!(mtctr(R3), 0x7C6903A6);
+        assert_eq!(mflr(R0), 0x7C0802A6);
+        assert_eq!(mfmsr(R3), 0x7C6000A6);
+        assert_eq!(mtmsrd(R3), 0x7C600164);
+        assert_eq!(std(R1, -0x80, R1), 0xF821FF80);
+        assert_eq!(ld(R0, 0x90, R1), 0xE8010090);
+        assert_eq!(bctr(), 0x4E800420);
+        assert_eq!(rfid(), 0x4C000024);
+        assert_eq!(nop(), 0x60000000);
+        assert_eq!(trap(), 0x7FE00008);
+        assert_eq!(sync(), 0x7C0004AC);
+    }
+
+    #[test]
+    fn test_branches() {
+        assert_eq!(b(0x10), 0x48000010);
+        assert_eq!(b(-4), 0x4BFFFFFC);
+        assert_eq!(b(0x1FF_FFFC), 0x49FFFFFC);
+        assert_eq!(b(-0x200_0000), 0x4A000000);
+        assert_eq!(bl(-0x10), 0x4BFFFFF1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_branch_range() {
+        b(0x200_0000);
+    }
+
+    #[test]
+    fn test_split_ha() {
+        assert_eq!(split_ha(0x0B0B_8018), (0x0B0C, 0x8018));
+        assert_eq!(split_ha(0x0B0B_7FFF), (0x0B0B, 0x7FFF));
+        assert_eq!(split_ha(0xFFFF_8000), (0x0000, 0x8000));
+    }
+}
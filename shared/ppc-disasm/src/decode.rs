@@ -0,0 +1,1013 @@
+//! Instruction decoding.
+//!
+//! Most instructions are looked up in tables of their extended opcodes, which give their name and
+//! operands. The instructions with extended mnemonics (branches, `mtspr`, `or` and the like) are
+//! picked apart by hand.
+
+use Field::*;
+
+/// An operand, which knows where it is in the instruction word and how it's written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    /// GPRs in the RT (or RS), RA and RB fields.
+    Rt,
+    Ra,
+    Rb,
+    /// RA as the base of an address, where r0 stands for 0.
+    Ra0,
+    /// FPRs in the FRT, FRA, FRB and FRC fields.
+    Ft,
+    Fa,
+    Fb,
+    Fc,
+    /// Vector registers in the VRT, VRA, VRB and VRC fields.
+    Vt,
+    Va,
+    Vb,
+    Vc,
+    /// VMX128 vector registers, whose numbers are split across several fields.
+    Vt128,
+    Va128,
+    Vb128,
+    /// `vperm128`'s VC, which only reaches v0-v7.
+    Vc128,
+    /// Signed and unsigned 16-bit immediates.
+    Si,
+    Ui,
+    /// A D-form (or DS-form) load/store address, `d(rA)`, where r0 stands for 0.
+    D,
+    Ds,
+    /// Condition register fields in the BF and BFA fields.
+    Crf,
+    Crfs,
+    /// Condition register bits in the BT, BA and BB fields.
+    Crbt,
+    Crba,
+    Crbb,
+    Bo,
+    Bi,
+    To,
+    /// 32-bit rotate shift and mask.
+    Sh,
+    Mb,
+    Me,
+    /// 64-bit rotate shift and mask, whose high bits are kept elsewhere.
+    Sh64,
+    Mb64,
+    Nb,
+    Spr,
+    Fxm,
+    Flm,
+    Sr,
+    L,
+    U,
+    Lev,
+    /// The VRA field as a signed or unsigned 5-bit immediate.
+    Uimm,
+    Simm,
+    /// `vsldoi`'s byte shift.
+    Vsh,
+    /// `vpermwi128`'s permutation.
+    Perm128,
+    /// The rotation of `vrlimi128` and `vpkd3d128`.
+    Z128,
+    /// A branch target.
+    Target,
+}
+
+/// A mnemonic, assembled from a base name and suffixes.
+#[derive(Clone, Copy)]
+pub struct Name {
+    buf: [u8; 16],
+    len: usize,
+}
+
+impl Name {
+    fn new(name: &str) -> Self {
+        let mut this = Self {
+            buf: [0; 16],
+            len: 0,
+        };
+
+        this.push(name);
+        this
+    }
+
+    fn push(&mut self, s: &str) {
+        let end = self.len + s.len();
+        self.buf[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+    }
+
+    pub fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.buf[..self.len]).unwrap()
+    }
+}
+
+pub struct Decoded {
+    pub name: Name,
+    pub fields: &'static [Field],
+}
+
+fn decoded(name: &str, fields: &'static [Field]) -> Option<Decoded> {
+    Some(Decoded {
+        name: Name::new(name),
+        fields,
+    })
+}
+
+/// The record bit (bit 31) appends `.`.
+const RC: u8 = 1 << 0;
+/// The overflow bit (bit 21) appends `o`.
+const OE: u8 = 1 << 1;
+/// VMX compares record with bit 21.
+const RC_VC: u8 = 1 << 2;
+/// VMX128 compares record with bit 25.
+const RC_128: u8 = 1 << 3;
+
+/// An entry in a table of extended opcodes.
+struct Op {
+    xo: u32,
+    name: &'static str,
+    fields: &'static [Field],
+    flags: u8,
+}
+
+const fn op(xo: u32, name: &'static str, fields: &'static [Field]) -> Op {
+    Op {
+        xo,
+        name,
+        fields,
+        flags: 0,
+    }
+}
+
+const fn rc(xo: u32, name: &'static str, fields: &'static [Field]) -> Op {
+    Op {
+        xo,
+        name,
+        fields,
+        flags: RC,
+    }
+}
+
+const fn oe(xo: u32, name: &'static str, fields: &'static [Field]) -> Op {
+    Op {
+        xo,
+        name,
+        fields,
+        flags: OE | RC,
+    }
+}
+
+const fn flags(xo: u32, name: &'static str, fields: &'static [Field], flags: u8) -> Op {
+    Op {
+        xo,
+        name,
+        fields,
+        flags,
+    }
+}
+
+fn lookup(table: &[Op], xo: u32, word: u32) -> Option<Decoded> {
+    let op = table.iter().find(|op| op.xo == xo)?;
+    let mut name = Name::new(op.name);
+
+    if op.flags & OE != 0 && word & 0x400 != 0 {
+        name.push("o");
+    }
+
+    let record = match op.flags {
+        f if f & RC != 0 => word & 0x1,
+        f if f & RC_VC != 0 => word & 0x400,
+        f if f & RC_128 != 0 => word & 0x40,
+        _ => 0,
+    };
+
+    if record != 0 {
+        name.push(".");
+    }
+
+    Some(Decoded {
+        name,
+        fields: op.fields,
+    })
+}
+
+/// Decode an instruction word.
+pub fn decode(word: u32) -> Option<Decoded> {
+    let ra = (word >> 16) & 0x1F;
+
+    match word >> 26 {
+        2 => decoded("tdi", &[To, Ra, Si]),
+        3 => decoded("twi", &[To, Ra, Si]),
+        4 => vmx(word),
+        5 => vmx128_5(word),
+        6 => vmx128_6(word),
+        7 => decoded("mulli", &[Rt, Ra, Si]),
+        8 => decoded("subfic", &[Rt, Ra, Si]),
+        10 | 11 => compare(word),
+        12 => decoded("addic", &[Rt, Ra, Si]),
+        13 => decoded("addic.", &[Rt, Ra, Si]),
+        14 if ra == 0 => decoded("li", &[Rt, Si]),
+        14 => decoded("addi", &[Rt, Ra, Si]),
+        15 if ra == 0 => decoded("lis", &[Rt, Ui]),
+        15 => decoded("addis", &[Rt, Ra, Si]),
+        16 | 18 => branch(word),
+        17 if word & 2 == 0 => None,
+        17 if word & 0x0FE0 == 0 => decoded("sc", &[]),
+        17 => decoded("sc", &[Lev]),
+        19 => match (word >> 1) & 0x3FF {
+            16 | 528 => branch(word),
+            xo => lookup(X19, xo, word),
+        },
+        20 => lookup(&[rc(20, "rlwimi", &[Ra, Rt, Sh, Mb, Me])], 20, word),
+        21 => lookup(&[rc(21, "rlwinm", &[Ra, Rt, Sh, Mb, Me])], 21, word),
+        23 => lookup(&[rc(23, "rlwnm", &[Ra, Rt, Rb, Mb, Me])], 23, word),
+        24 if word == 0x6000_0000 => decoded("nop", &[]),
+        24 => decoded("ori", &[Ra, Rt, Ui]),
+        25 => decoded("oris", &[Ra, Rt, Ui]),
+        26 => decoded("xori", &[Ra, Rt, Ui]),
+        27 => decoded("xoris", &[Ra, Rt, Ui]),
+        28 => decoded("andi.", &[Ra, Rt, Ui]),
+        29 => decoded("andis.", &[Ra, Rt, Ui]),
+        30 => match (word >> 2) & 0x7 {
+            4 => lookup(MDS30, (word >> 1) & 0xF, word),
+            xo => lookup(MD30, xo, word),
+        },
+        31 => x31(word),
+        op @ 32..=55 => {
+            let (name, fpr) = LOAD_STORE[op as usize - 32];
+            decoded(name, if fpr { &[Ft, D] } else { &[Rt, D] })
+        }
+        58 => lookup(DS58, word & 0x3, word),
+        59 => lookup(A59, (word >> 1) & 0x1F, word),
+        62 => lookup(DS62, word & 0x3, word),
+        63 if word & 0x20 != 0 => lookup(A63, (word >> 1) & 0x1F, word),
+        63 => lookup(X63, (word >> 1) & 0x3FF, word),
+        _ => None,
+    }
+}
+
+/// `cmpi`, `cmpli`, `cmp` and `cmpl`, with the width in the mnemonic and `cr0` left out.
+fn compare(word: u32) -> Option<Decoded> {
+    let op = word >> 26;
+    let wide = word & 0x0020_0000 != 0;
+    let logical = match op {
+        10 => true,
+        11 => false,
+        _ => (word >> 1) & 0x3FF == 32,
+    };
+
+    let name = match (logical, wide) {
+        (false, false) => "cmpw",
+        (false, true) => "cmpd",
+        (true, false) => "cmplw",
+        (true, true) => "cmpld",
+    };
+
+    let mut name = Name::new(name);
+    let cr0 = (word >> 23) & 0x7 == 0;
+
+    let fields: &'static [Field] = match op {
+        10 | 11 => {
+            name.push("i");
+
+            match (logical, cr0) {
+                (false, true) => &[Ra, Si],
+                (false, false) => &[Crf, Ra, Si],
+                (true, true) => &[Ra, Ui],
+                (true, false) => &[Crf, Ra, Ui],
+            }
+        }
+        _ if cr0 => &[Ra, Rb],
+        _ => &[Crf, Ra, Rb],
+    };
+
+    Some(Decoded { name, fields })
+}
+
+/// `b`, `bc`, `bclr` and `bcctr`, using the extended mnemonics (e.g. `beq`, `bdnz`, `blr`) where
+/// there is one.
+fn branch(word: u32) -> Option<Decoded> {
+    let bo = (word >> 21) & 0x1F;
+    let bi = (word >> 16) & 0x1F;
+    let link = word & 1 != 0;
+    let absolute = word & 2 != 0;
+
+    let (to, relative) = match (word >> 26, (word >> 1) & 0x3FF) {
+        (18, _) => {
+            let mut name = Name::new("b");
+            push_link(&mut name, link, absolute);
+            return Some(Decoded {
+                name,
+                fields: &[Target],
+            });
+        }
+        (16, _) => ("", true),
+        (_, 16) => ("lr", false),
+        (_, 528) => ("ctr", false),
+        _ => return None,
+    };
+
+    const CONDITIONS: [&str; 4] = ["lt", "gt", "eq", "so"];
+    const NEGATED: [&str; 4] = ["ge", "le", "ne", "ns"];
+
+    // BO says whether the condition is tested (and for what), and whether CTR is decremented
+    // (and tested for zero). The low bit is a branch prediction hint.
+    let (mut name, test) = match bo & 0x14 {
+        0x14 => (Name::new("b"), false),
+        0x04 => (Name::new("b"), true),
+        0x10 if bo & 0x02 != 0 => (Name::new("bdz"), false),
+        0x10 => (Name::new("bdnz"), false),
+        // Both: Fall back on the basic mnemonic.
+        _ => {
+            let mut name = Name::new("bc");
+            name.push(to);
+            push_link(&mut name, link, absolute && relative);
+
+            let fields: &'static [Field] = if relative {
+                &[Bo, Bi, Target]
+            } else {
+                &[Bo, Bi]
+            };
+
+            return Some(Decoded { name, fields });
+        }
+    };
+
+    if test {
+        let conditions = if bo & 0x08 != 0 {
+            &CONDITIONS
+        } else {
+            &NEGATED
+        };
+
+        name.push(conditions[bi as usize % 4]);
+    }
+
+    name.push(to);
+    push_link(&mut name, link, absolute && relative);
+
+    // The condition register field is left out if it is cr0.
+    let crf = test && bi / 4 != 0;
+    let fields: &'static [Field] = match (crf, relative) {
+        (false, false) => &[],
+        (true, false) => &[Crfs],
+        (false, true) => &[Target],
+        (true, true) => &[Crfs, Target],
+    };
+
+    Some(Decoded { name, fields })
+}
+
+fn push_link(name: &mut Name, link: bool, absolute: bool) {
+    if link {
+        name.push("l");
+    }
+
+    if absolute {
+        name.push("a");
+    }
+}
+
+/// The names of the special purpose registers that `mtspr` and `mfspr` have extended mnemonics
+/// for.
+const SPRS: &[(u32, &str)] = &[
+    (1, "xer"),
+    (8, "lr"),
+    (9, "ctr"),
+    (18, "dsisr"),
+    (19, "dar"),
+    (22, "dec"),
+    (25, "sdr1"),
+    (26, "srr0"),
+    (27, "srr1"),
+    (136, "ctrl"),
+    (152, "ctrl"),
+    (256, "vrsave"),
+    (268, "tbl"),
+    (269, "tbu"),
+    (272, "sprg0"),
+    (273, "sprg1"),
+    (274, "sprg2"),
+    (275, "sprg3"),
+    (284, "tbl"),
+    (285, "tbu"),
+    (287, "pvr"),
+    (304, "hsprg0"),
+    (305, "hsprg1"),
+    (306, "hdsisr"),
+    (307, "hdar"),
+    (310, "hdec"),
+    (312, "rmor"),
+    (313, "hrmor"),
+    (314, "hsrr0"),
+    (315, "hsrr1"),
+    (318, "lpcr"),
+    (319, "lpidr"),
+    (1008, "hid0"),
+    (1009, "hid1"),
+    (1012, "hid4"),
+    (1017, "hid6"),
+    (1023, "pir"),
+];
+
+fn x31(word: u32) -> Option<Decoded> {
+    let rt = (word >> 21) & 0x1F;
+    let rb = (word >> 11) & 0x1F;
+    let xo = (word >> 1) & 0x3FF;
+
+    match xo {
+        0 | 32 => return compare(word),
+        4 if rt == 31 && word & 0x001F_F800 == 0 => return decoded("trap", &[]),
+        // N.B: `or rA,rS,rS` is `mr rA,rS`.
+        444 if rt == rb => return lookup(&[rc(444, "mr", &[Ra, Rt])], 444, word),
+        339 | 467 => {
+            let spr = ((word >> 16) & 0x1F) | ((word >> 11) & 0x1F) << 5;
+            let (prefix, fields): (_, &'static [Field]) = match xo {
+                339 => ("mf", &[Rt]),
+                _ => ("mt", &[Rt]),
+            };
+
+            return match SPRS.iter().find(|&&(n, _)| n == spr) {
+                Some(&(_, spr)) => {
+                    let mut name = Name::new(prefix);
+                    name.push(spr);
+                    Some(Decoded { name, fields })
+                }
+                None if xo == 339 => decoded("mfspr", &[Rt, Spr]),
+                None => decoded("mtspr", &[Spr, Rt]),
+            };
+        }
+        371 => {
+            let tbr = ((word >> 16) & 0x1F) | ((word >> 11) & 0x1F) << 5;
+            return match tbr {
+                268 => decoded("mftb", &[Rt]),
+                269 => decoded("mftbu", &[Rt]),
+                _ => decoded("mftb", &[Rt, Spr]),
+            };
+        }
+        598 => {
+            return match (word >> 21) & 0x3 {
+                1 => decoded("lwsync", &[]),
+                2 => decoded("ptesync", &[]),
+                _ => decoded("sync", &[]),
+            };
+        }
+        178 if word & 0x0001_0000 != 0 => return decoded("mtmsrd", &[Rt, L]),
+        // Xenon's 128-byte `dcbz`.
+        1014 if rt == 1 => return decoded("dcbz128", &[Ra0, Rb]),
+        _ => {}
+    }
+
+    // `sradi` is the only XS-form instruction, with the high bit of its shift in its opcode.
+    if (word >> 2) & 0x1FF == 413 {
+        return lookup(&[rc(413, "sradi", &[Ra, Rt, Sh64])], 413, word);
+    }
+
+    lookup(X31, xo, word).or_else(|| lookup(XO31, (word >> 1) & 0x1FF, word))
+}
+
+/// Opcode 19 (XL-form), by extended opcode.
+const X19: &[Op] = &[
+    op(0, "mcrf", &[Crf, Crfs]),
+    op(18, "rfid", &[]),
+    op(33, "crnor", &[Crbt, Crba, Crbb]),
+    op(129, "crandc", &[Crbt, Crba, Crbb]),
+    op(150, "isync", &[]),
+    op(193, "crxor", &[Crbt, Crba, Crbb]),
+    op(225, "crnand", &[Crbt, Crba, Crbb]),
+    op(257, "crand", &[Crbt, Crba, Crbb]),
+    op(274, "hrfid", &[]),
+    op(289, "creqv", &[Crbt, Crba, Crbb]),
+    op(417, "crorc", &[Crbt, Crba, Crbb]),
+    op(449, "cror", &[Crbt, Crba, Crbb]),
+];
+
+/// Opcode 30 (MD-form), by extended opcode.
+const MD30: &[Op] = &[
+    rc(0, "rldicl", &[Ra, Rt, Sh64, Mb64]),
+    rc(1, "rldicr", &[Ra, Rt, Sh64, Mb64]),
+    rc(2, "rldic", &[Ra, Rt, Sh64, Mb64]),
+    rc(3, "rldimi", &[Ra, Rt, Sh64, Mb64]),
+];
+
+/// Opcode 30 (MDS-form), by extended opcode.
+const MDS30: &[Op] = &[
+    rc(8, "rldcl", &[Ra, Rt, Rb, Mb64]),
+    rc(9, "rldcr", &[Ra, Rt, Rb, Mb64]),
+];
+
+/// Opcode 31 (X-form), by extended opcode.
+const X31: &[Op] = &[
+    op(4, "tw", &[To, Ra, Rb]),
+    op(6, "lvsl", &[Vt, Ra0, Rb]),
+    op(7, "lvebx", &[Vt, Ra0, Rb]),
+    op(19, "mfcr", &[Rt]),
+    op(20, "lwarx", &[Rt, Ra0, Rb]),
+    op(21, "ldx", &[Rt, Ra0, Rb]),
+    op(23, "lwzx", &[Rt, Ra0, Rb]),
+    rc(24, "slw", &[Ra, Rt, Rb]),
+    rc(26, "cntlzw", &[Ra, Rt]),
+    rc(27, "sld", &[Ra, Rt, Rb]),
+    rc(28, "and", &[Ra, Rt, Rb]),
+    op(38, "lvsr", &[Vt, Ra0, Rb]),
+    op(39, "lvehx", &[Vt, Ra0, Rb]),
+    op(53, "ldux", &[Rt, Ra0, Rb]),
+    op(54, "dcbst", &[Ra0, Rb]),
+    op(55, "lwzux", &[Rt, Ra0, Rb]),
+    rc(58, "cntlzd", &[Ra, Rt]),
+    rc(60, "andc", &[Ra, Rt, Rb]),
+    op(68, "td", &[To, Ra, Rb]),
+    op(71, "lvewx", &[Vt, Ra0, Rb]),
+    op(83, "mfmsr", &[Rt]),
+    op(84, "ldarx", &[Rt, Ra0, Rb]),
+    op(86, "dcbf", &[Ra0, Rb]),
+    op(87, "lbzx", &[Rt, Ra0, Rb]),
+    op(103, "lvx", &[Vt, Ra0, Rb]),
+    op(119, "lbzux", &[Rt, Ra0, Rb]),
+    rc(124, "nor", &[Ra, Rt, Rb]),
+    op(135, "stvebx", &[Vt, Ra0, Rb]),
+    op(144, "mtcrf", &[Fxm, Rt]),
+    op(146, "mtmsr", &[Rt]),
+    op(149, "stdx", &[Rt, Ra0, Rb]),
+    op(150, "stwcx.", &[Rt, Ra0, Rb]),
+    op(151, "stwx", &[Rt, Ra0, Rb]),
+    op(167, "stvehx", &[Vt, Ra0, Rb]),
+    op(178, "mtmsrd", &[Rt]),
+    op(181, "stdux", &[Rt, Ra0, Rb]),
+    op(183, "stwux", &[Rt, Ra0, Rb]),
+    op(199, "stvewx", &[Vt, Ra0, Rb]),
+    op(210, "mtsr", &[Sr, Rt]),
+    op(214, "stdcx.", &[Rt, Ra0, Rb]),
+    op(215, "stbx", &[Rt, Ra0, Rb]),
+    op(231, "stvx", &[Vt, Ra0, Rb]),
+    op(246, "dcbtst", &[Ra0, Rb]),
+    op(247, "stbux", &[Rt, Ra0, Rb]),
+    op(274, "tlbiel", &[Rb]),
+    op(278, "dcbt", &[Ra0, Rb]),
+    op(279, "lhzx", &[Rt, Ra0, Rb]),
+    rc(284, "eqv", &[Ra, Rt, Rb]),
+    op(306, "tlbie", &[Rb]),
+    op(311, "lhzux", &[Rt, Ra0, Rb]),
+    rc(316, "xor", &[Ra, Rt, Rb]),
+    op(341, "lwax", &[Rt, Ra0, Rb]),
+    op(343, "lhax", &[Rt, Ra0, Rb]),
+    op(359, "lvxl", &[Vt, Ra0, Rb]),
+    op(370, "tlbia", &[]),
+    op(373, "lwaux", &[Rt, Ra0, Rb]),
+    op(375, "lhaux", &[Rt, Ra0, Rb]),
+    op(402, "slbmte", &[Rt, Rb]),
+    op(407, "sthx", &[Rt, Ra0, Rb]),
+    rc(412, "orc", &[Ra, Rt, Rb]),
+    op(434, "slbie", &[Rb]),
+    op(439, "sthux", &[Rt, Ra0, Rb]),
+    rc(444, "or", &[Ra, Rt, Rb]),
+    op(470, "dcbi", &[Ra0, Rb]),
+    rc(476, "nand", &[Ra, Rt, Rb]),
+    op(487, "stvxl", &[Vt, Ra0, Rb]),
+    op(498, "slbia", &[]),
+    op(512, "mcrxr", &[Crf]),
+    op(519, "lvlx", &[Vt, Ra0, Rb]),
+    op(533, "lswx", &[Rt, Ra0, Rb]),
+    op(534, "lwbrx", &[Rt, Ra0, Rb]),
+    op(535, "lfsx", &[Ft, Ra0, Rb]),
+    rc(536, "srw", &[Ra, Rt, Rb]),
+    rc(539, "srd", &[Ra, Rt, Rb]),
+    op(551, "lvrx", &[Vt, Ra0, Rb]),
+    op(566, "tlbsync", &[]),
+    op(567, "lfsux", &[Ft, Ra0, Rb]),
+    op(595, "mfsr", &[Rt, Sr]),
+    op(597, "lswi", &[Rt, Ra, Nb]),
+    op(599, "lfdx", &[Ft, Ra0, Rb]),
+    op(631, "lfdux", &[Ft, Ra0, Rb]),
+    op(647, "stvlx", &[Vt, Ra0, Rb]),
+    op(659, "mfsrin", &[Rt, Rb]),
+    op(662, "stwbrx", &[Rt, Ra0, Rb]),
+    op(663, "stfsx", &[Ft, Ra0, Rb]),
+    op(679, "stvrx", &[Vt, Ra0, Rb]),
+    op(695, "stfsux", &[Ft, Ra0, Rb]),
+    op(725, "stswi", &[Rt, Ra, Nb]),
+    op(727, "stfdx", &[Ft, Ra0, Rb]),
+    op(759, "stfdux", &[Ft, Ra0, Rb]),
+    op(775, "lvlxl", &[Vt, Ra0, Rb]),
+    op(790, "lhbrx", &[Rt, Ra0, Rb]),
+    rc(792, "sraw", &[Ra, Rt, Rb]),
+    rc(794, "srad", &[Ra, Rt, Rb]),
+    op(807, "lvrxl", &[Vt, Ra0, Rb]),
+    rc(824, "srawi", &[Ra, Rt, Sh]),
+    op(851, "slbmfev", &[Rt, Rb]),
+    op(854, "eieio", &[]),
+    op(903, "stvlxl", &[Vt, Ra0, Rb]),
+    op(915, "slbmfee", &[Rt, Rb]),
+    op(918, "sthbrx", &[Rt, Ra0, Rb]),
+    rc(922, "extsh", &[Ra, Rt]),
+    op(935, "stvrxl", &[Vt, Ra0, Rb]),
+    rc(954, "extsb", &[Ra, Rt]),
+    op(982, "icbi", &[Ra0, Rb]),
+    op(983, "stfiwx", &[Ft, Ra0, Rb]),
+    rc(986, "extsw", &[Ra, Rt]),
+    op(1014, "dcbz", &[Ra0, Rb]),
+];
+
+/// Opcode 31 (XO-form), by extended opcode.
+const XO31: &[Op] = &[
+    oe(8, "subfc", &[Rt, Ra, Rb]),
+    rc(9, "mulhdu", &[Rt, Ra, Rb]),
+    oe(10, "addc", &[Rt, Ra, Rb]),
+    rc(11, "mulhwu", &[Rt, Ra, Rb]),
+    oe(40, "subf", &[Rt, Ra, Rb]),
+    rc(73, "mulhd", &[Rt, Ra, Rb]),
+    rc(75, "mulhw", &[Rt, Ra, Rb]),
+    oe(104, "neg", &[Rt, Ra]),
+    oe(136, "subfe", &[Rt, Ra, Rb]),
+    oe(138, "adde", &[Rt, Ra, Rb]),
+    oe(200, "subfze", &[Rt, Ra]),
+    oe(202, "addze", &[Rt, Ra]),
+    oe(232, "subfme", &[Rt, Ra]),
+    oe(233, "mulld", &[Rt, Ra, Rb]),
+    oe(234, "addme", &[Rt, Ra]),
+    oe(235, "mullw", &[Rt, Ra, Rb]),
+    oe(266, "add", &[Rt, Ra, Rb]),
+    oe(457, "divdu", &[Rt, Ra, Rb]),
+    oe(459, "divwu", &[Rt, Ra, Rb]),
+    oe(489, "divd", &[Rt, Ra, Rb]),
+    oe(491, "divw", &[Rt, Ra, Rb]),
+];
+
+/// The D-form loads and stores, opcodes 32 to 55, and whether they move an FPR.
+const LOAD_STORE: [(&str, bool); 24] = [
+    ("lwz", false),
+    ("lwzu", false),
+    ("lbz", false),
+    ("lbzu", false),
+    ("stw", false),
+    ("stwu", false),
+    ("stb", false),
+    ("stbu", false),
+    ("lhz", false),
+    ("lhzu", false),
+    ("lha", false),
+    ("lhau", false),
+    ("sth", false),
+    ("sthu", false),
+    ("lmw", false),
+    ("stmw", false),
+    ("lfs", true),
+    ("lfsu", true),
+    ("lfd", true),
+    ("lfdu", true),
+    ("stfs", true),
+    ("stfsu", true),
+    ("stfd", true),
+    ("stfdu", true),
+];
+
+/// Opcode 58 (DS-form loads), by extended opcode.
+const DS58: &[Op] = &[
+    op(0, "ld", &[Rt, Ds]),
+    op(1, "ldu", &[Rt, Ds]),
+    op(2, "lwa", &[Rt, Ds]),
+];
+
+/// Opcode 62 (DS-form stores), by extended opcode.
+const DS62: &[Op] = &[op(0, "std", &[Rt, Ds]), op(1, "stdu", &[Rt, Ds])];
+
+/// Opcode 59 (single precision A-form), by extended opcode.
+const A59: &[Op] = &[
+    rc(18, "fdivs", &[Ft, Fa, Fb]),
+    rc(20, "fsubs", &[Ft, Fa, Fb]),
+    rc(21, "fadds", &[Ft, Fa, Fb]),
+    rc(22, "fsqrts", &[Ft, Fb]),
+    rc(24, "fres", &[Ft, Fb]),
+    rc(25, "fmuls", &[Ft, Fa, Fc]),
+    rc(28, "fmsubs", &[Ft, Fa, Fc, Fb]),
+    rc(29, "fmadds", &[Ft, Fa, Fc, Fb]),
+    rc(30, "fnmsubs", &[Ft, Fa, Fc, Fb]),
+    rc(31, "fnmadds", &[Ft, Fa, Fc, Fb]),
+];
+
+/// Opcode 63 (double precision A-form), by extended opcode.
+const A63: &[Op] = &[
+    rc(18, "fdiv", &[Ft, Fa, Fb]),
+    rc(20, "fsub", &[Ft, Fa, Fb]),
+    rc(21, "fadd", &[Ft, Fa, Fb]),
+    rc(22, "fsqrt", &[Ft, Fb]),
+    rc(23, "fsel", &[Ft, Fa, Fc, Fb]),
+    rc(25, "fmul", &[Ft, Fa, Fc]),
+    rc(26, "frsqrte", &[Ft, Fb]),
+    rc(28, "fmsub", &[Ft, Fa, Fc, Fb]),
+    rc(29, "fmadd", &[Ft, Fa, Fc, Fb]),
+    rc(30, "fnmsub", &[Ft, Fa, Fc, Fb]),
+    rc(31, "fnmadd", &[Ft, Fa, Fc, Fb]),
+];
+
+/// Opcode 63 (X-form), by extended opcode.
+const X63: &[Op] = &[
+    op(0, "fcmpu", &[Crf, Fa, Fb]),
+    rc(12, "frsp", &[Ft, Fb]),
+    rc(14, "fctiw", &[Ft, Fb]),
+    rc(15, "fctiwz", &[Ft, Fb]),
+    op(32, "fcmpo", &[Crf, Fa, Fb]),
+    rc(38, "mtfsb1", &[Crbt]),
+    rc(40, "fneg", &[Ft, Fb]),
+    op(64, "mcrfs", &[Crf, Crfs]),
+    rc(70, "mtfsb0", &[Crbt]),
+    rc(72, "fmr", &[Ft, Fb]),
+    rc(134, "mtfsfi", &[Crf, U]),
+    rc(136, "fnabs", &[Ft, Fb]),
+    rc(264, "fabs", &[Ft, Fb]),
+    rc(583, "mffs", &[Ft]),
+    rc(711, "mtfsf", &[Flm, Fb]),
+    rc(814, "fctid", &[Ft, Fb]),
+    rc(815, "fctidz", &[Ft, Fb]),
+    rc(846, "fcfid", &[Ft, Fb]),
+];
+
+/// Opcode 4: VMX, and the VMX128 loads, stores and `vsldoi128`, which use encodings that VMX
+/// leaves free.
+fn vmx(word: u32) -> Option<Decoded> {
+    if let 32..=47 = word & 0x3F {
+        return lookup(VA4, word & 0x3F, word);
+    }
+
+    if word & 0x3 == 0x3 {
+        return lookup(VX128_LOAD_STORE, word & 0x7F3, word);
+    }
+
+    if word & 0x10 != 0 {
+        return decoded("vsldoi128", &[Vt128, Va128, Vb128, Vsh]);
+    }
+
+    lookup(VX4, word & 0x7FF, word).or_else(|| lookup(VC4, word & 0x3FF, word))
+}
+
+/// Opcode 4 (VA-form), by extended opcode.
+const VA4: &[Op] = &[
+    op(32, "vmhaddshs", &[Vt, Va, Vb, Vc]),
+    op(33, "vmhraddshs", &[Vt, Va, Vb, Vc]),
+    op(34, "vmladduhm", &[Vt, Va, Vb, Vc]),
+    op(36, "vmsumubm", &[Vt, Va, Vb, Vc]),
+    op(37, "vmsummbm", &[Vt, Va, Vb, Vc]),
+    op(38, "vmsumuhm", &[Vt, Va, Vb, Vc]),
+    op(39, "vmsumuhs", &[Vt, Va, Vb, Vc]),
+    op(40, "vmsumshm", &[Vt, Va, Vb, Vc]),
+    op(41, "vmsumshs", &[Vt, Va, Vb, Vc]),
+    op(42, "vsel", &[Vt, Va, Vb, Vc]),
+    op(43, "vperm", &[Vt, Va, Vb, Vc]),
+    op(44, "vsldoi", &[Vt, Va, Vb, Vsh]),
+    op(46, "vmaddfp", &[Vt, Va, Vc, Vb]),
+    op(47, "vnmsubfp", &[Vt, Va, Vc, Vb]),
+];
+
+/// Opcode 4 (VC-form compares), by extended opcode.
+const VC4: &[Op] = &[
+    flags(6, "vcmpequb", &[Vt, Va, Vb], RC_VC),
+    flags(70, "vcmpequh", &[Vt, Va, Vb], RC_VC),
+    flags(134, "vcmpequw", &[Vt, Va, Vb], RC_VC),
+    flags(198, "vcmpeqfp", &[Vt, Va, Vb], RC_VC),
+    flags(454, "vcmpgefp", &[Vt, Va, Vb], RC_VC),
+    flags(518, "vcmpgtub", &[Vt, Va, Vb], RC_VC),
+    flags(582, "vcmpgtuh", &[Vt, Va, Vb], RC_VC),
+    flags(646, "vcmpgtuw", &[Vt, Va, Vb], RC_VC),
+    flags(710, "vcmpgtfp", &[Vt, Va, Vb], RC_VC),
+    flags(774, "vcmpgtsb", &[Vt, Va, Vb], RC_VC),
+    flags(838, "vcmpgtsh", &[Vt, Va, Vb], RC_VC),
+    flags(902, "vcmpgtsw", &[Vt, Va, Vb], RC_VC),
+    flags(966, "vcmpbfp", &[Vt, Va, Vb], RC_VC),
+];
+
+/// Opcode 4 (VX-form), by extended opcode.
+const VX4: &[Op] = &[
+    op(0, "vaddubm", &[Vt, Va, Vb]),
+    op(2, "vmaxub", &[Vt, Va, Vb]),
+    op(4, "vrlb", &[Vt, Va, Vb]),
+    op(8, "vmuloub", &[Vt, Va, Vb]),
+    op(10, "vaddfp", &[Vt, Va, Vb]),
+    op(12, "vmrghb", &[Vt, Va, Vb]),
+    op(14, "vpkuhum", &[Vt, Va, Vb]),
+    op(64, "vadduhm", &[Vt, Va, Vb]),
+    op(66, "vmaxuh", &[Vt, Va, Vb]),
+    op(68, "vrlh", &[Vt, Va, Vb]),
+    op(72, "vmulouh", &[Vt, Va, Vb]),
+    op(74, "vsubfp", &[Vt, Va, Vb]),
+    op(76, "vmrghh", &[Vt, Va, Vb]),
+    op(78, "vpkuwum", &[Vt, Va, Vb]),
+    op(128, "vadduwm", &[Vt, Va, Vb]),
+    op(130, "vmaxuw", &[Vt, Va, Vb]),
+    op(132, "vrlw", &[Vt, Va, Vb]),
+    op(140, "vmrghw", &[Vt, Va, Vb]),
+    op(142, "vpkuhus", &[Vt, Va, Vb]),
+    op(206, "vpkuwus", &[Vt, Va, Vb]),
+    op(258, "vmaxsb", &[Vt, Va, Vb]),
+    op(260, "vslb", &[Vt, Va, Vb]),
+    op(264, "vmulosb", &[Vt, Va, Vb]),
+    op(266, "vrefp", &[Vt, Vb]),
+    op(268, "vmrglb", &[Vt, Va, Vb]),
+    op(270, "vpkshus", &[Vt, Va, Vb]),
+    op(322, "vmaxsh", &[Vt, Va, Vb]),
+    op(324, "vslh", &[Vt, Va, Vb]),
+    op(328, "vmulosh", &[Vt, Va, Vb]),
+    op(330, "vrsqrtefp", &[Vt, Vb]),
+    op(332, "vmrglh", &[Vt, Va, Vb]),
+    op(334, "vpkswus", &[Vt, Va, Vb]),
+    op(384, "vaddcuw", &[Vt, Va, Vb]),
+    op(386, "vmaxsw", &[Vt, Va, Vb]),
+    op(388, "vslw", &[Vt, Va, Vb]),
+    op(394, "vexptefp", &[Vt, Vb]),
+    op(396, "vmrglw", &[Vt, Va, Vb]),
+    op(398, "vpkshss", &[Vt, Va, Vb]),
+    op(452, "vsl", &[Vt, Va, Vb]),
+    op(458, "vlogefp", &[Vt, Vb]),
+    op(462, "vpkswss", &[Vt, Va, Vb]),
+    op(512, "vaddubs", &[Vt, Va, Vb]),
+    op(514, "vminub", &[Vt, Va, Vb]),
+    op(516, "vsrb", &[Vt, Va, Vb]),
+    op(520, "vmuleub", &[Vt, Va, Vb]),
+    op(522, "vrfin", &[Vt, Vb]),
+    op(524, "vspltb", &[Vt, Vb, Uimm]),
+    op(526, "vupkhsb", &[Vt, Vb]),
+    op(576, "vadduhs", &[Vt, Va, Vb]),
+    op(578, "vminuh", &[Vt, Va, Vb]),
+    op(580, "vsrh", &[Vt, Va, Vb]),
+    op(584, "vmuleuh", &[Vt, Va, Vb]),
+    op(586, "vrfiz", &[Vt, Vb]),
+    op(588, "vsplth", &[Vt, Vb, Uimm]),
+    op(590, "vupkhsh", &[Vt, Vb]),
+    op(640, "vadduws", &[Vt, Va, Vb]),
+    op(642, "vminuw", &[Vt, Va, Vb]),
+    op(644, "vsrw", &[Vt, Va, Vb]),
+    op(650, "vrfip", &[Vt, Vb]),
+    op(652, "vspltw", &[Vt, Vb, Uimm]),
+    op(654, "vupklsb", &[Vt, Vb]),
+    op(708, "vsr", &[Vt, Va, Vb]),
+    op(714, "vrfim", &[Vt, Vb]),
+    op(718, "vupklsh", &[Vt, Vb]),
+    op(768, "vaddsbs", &[Vt, Va, Vb]),
+    op(770, "vminsb", &[Vt, Va, Vb]),
+    op(772, "vsrab", &[Vt, Va, Vb]),
+    op(776, "vmulesb", &[Vt, Va, Vb]),
+    op(778, "vcfux", &[Vt, Vb, Uimm]),
+    op(780, "vspltisb", &[Vt, Simm]),
+    op(782, "vpkpx", &[Vt, Va, Vb]),
+    op(832, "vaddshs", &[Vt, Va, Vb]),
+    op(834, "vminsh", &[Vt, Va, Vb]),
+    op(836, "vsrah", &[Vt, Va, Vb]),
+    op(840, "vmulesh", &[Vt, Va, Vb]),
+    op(842, "vcfsx", &[Vt, Vb, Uimm]),
+    op(844, "vspltish", &[Vt, Simm]),
+    op(846, "vupkhpx", &[Vt, Vb]),
+    op(896, "vaddsws", &[Vt, Va, Vb]),
+    op(898, "vminsw", &[Vt, Va, Vb]),
+    op(900, "vsraw", &[Vt, Va, Vb]),
+    op(906, "vctuxs", &[Vt, Vb, Uimm]),
+    op(908, "vspltisw", &[Vt, Simm]),
+    op(970, "vctsxs", &[Vt, Vb, Uimm]),
+    op(974, "vupklpx", &[Vt, Vb]),
+    op(1024, "vsububm", &[Vt, Va, Vb]),
+    op(1026, "vavgub", &[Vt, Va, Vb]),
+    op(1028, "vand", &[Vt, Va, Vb]),
+    op(1034, "vmaxfp", &[Vt, Va, Vb]),
+    op(1036, "vslo", &[Vt, Va, Vb]),
+    op(1088, "vsubuhm", &[Vt, Va, Vb]),
+    op(1090, "vavguh", &[Vt, Va, Vb]),
+    op(1092, "vandc", &[Vt, Va, Vb]),
+    op(1098, "vminfp", &[Vt, Va, Vb]),
+    op(1100, "vsro", &[Vt, Va, Vb]),
+    op(1152, "vsubuwm", &[Vt, Va, Vb]),
+    op(1154, "vavguw", &[Vt, Va, Vb]),
+    op(1156, "vor", &[Vt, Va, Vb]),
+    op(1220, "vxor", &[Vt, Va, Vb]),
+    op(1282, "vavgsb", &[Vt, Va, Vb]),
+    op(1284, "vnor", &[Vt, Va, Vb]),
+    op(1346, "vavgsh", &[Vt, Va, Vb]),
+    op(1408, "vsubcuw", &[Vt, Va, Vb]),
+    op(1410, "vavgsw", &[Vt, Va, Vb]),
+    op(1536, "vsububs", &[Vt, Va, Vb]),
+    op(1540, "mfvscr", &[Vt]),
+    op(1544, "vsum4ubs", &[Vt, Va, Vb]),
+    op(1600, "vsubuhs", &[Vt, Va, Vb]),
+    op(1604, "mtvscr", &[Vb]),
+    op(1608, "vsum4shs", &[Vt, Va, Vb]),
+    op(1664, "vsubuws", &[Vt, Va, Vb]),
+    op(1672, "vsum2sws", &[Vt, Va, Vb]),
+    op(1792, "vsubsbs", &[Vt, Va, Vb]),
+    op(1800, "vsum4sbs", &[Vt, Va, Vb]),
+    op(1856, "vsubshs", &[Vt, Va, Vb]),
+    op(1920, "vsubsws", &[Vt, Va, Vb]),
+    op(1928, "vsumsws", &[Vt, Va, Vb]),
+];
+
+/// The VMX128 loads and stores (VX128_1-form), by extended opcode.
+const VX128_LOAD_STORE: &[Op] = &[
+    op(0x003, "lvsl128", &[Vt128, Ra0, Rb]),
+    op(0x043, "lvsr128", &[Vt128, Ra0, Rb]),
+    op(0x083, "lvewx128", &[Vt128, Ra0, Rb]),
+    op(0x0C3, "lvx128", &[Vt128, Ra0, Rb]),
+    op(0x183, "stvewx128", &[Vt128, Ra0, Rb]),
+    op(0x1C3, "stvx128", &[Vt128, Ra0, Rb]),
+    op(0x2C3, "lvxl128", &[Vt128, Ra0, Rb]),
+    op(0x3C3, "stvxl128", &[Vt128, Ra0, Rb]),
+    op(0x403, "lvlx128", &[Vt128, Ra0, Rb]),
+    op(0x443, "lvrx128", &[Vt128, Ra0, Rb]),
+    op(0x503, "stvlx128", &[Vt128, Ra0, Rb]),
+    op(0x543, "stvrx128", &[Vt128, Ra0, Rb]),
+    op(0x603, "lvlxl128", &[Vt128, Ra0, Rb]),
+    op(0x643, "lvrxl128", &[Vt128, Ra0, Rb]),
+    op(0x703, "stvlxl128", &[Vt128, Ra0, Rb]),
+    op(0x743, "stvrxl128", &[Vt128, Ra0, Rb]),
+];
+
+/// Opcode 5: VMX128 arithmetic and logic.
+fn vmx128_5(word: u32) -> Option<Decoded> {
+    if word & 0x210 == 0 {
+        return decoded("vperm128", &[Vt128, Va128, Vb128, Vc128]);
+    }
+
+    lookup(VX128_5, word & 0x3D0, word)
+}
+
+/// Opcode 5 (VX128-form), by extended opcode.
+const VX128_5: &[Op] = &[
+    op(0x010, "vaddfp128", &[Vt128, Va128, Vb128]),
+    op(0x050, "vsubfp128", &[Vt128, Va128, Vb128]),
+    op(0x090, "vmulfp128", &[Vt128, Va128, Vb128]),
+    op(0x0D0, "vmaddfp128", &[Vt128, Va128, Vb128]),
+    op(0x110, "vmaddcfp128", &[Vt128, Va128, Vb128]),
+    op(0x150, "vnmsubfp128", &[Vt128, Va128, Vb128]),
+    op(0x190, "vmsum3fp128", &[Vt128, Va128, Vb128]),
+    op(0x1D0, "vmsum4fp128", &[Vt128, Va128, Vb128]),
+    op(0x200, "vpkshss128", &[Vt128, Va128, Vb128]),
+    op(0x210, "vand128", &[Vt128, Va128, Vb128]),
+    op(0x240, "vpkshus128", &[Vt128, Va128, Vb128]),
+    op(0x250, "vandc128", &[Vt128, Va128, Vb128]),
+    op(0x280, "vpkswss128", &[Vt128, Va128, Vb128]),
+    op(0x290, "vnor128", &[Vt128, Va128, Vb128]),
+    op(0x2C0, "vpkswus128", &[Vt128, Va128, Vb128]),
+    op(0x2D0, "vor128", &[Vt128, Va128, Vb128]),
+    op(0x300, "vpkuhum128", &[Vt128, Va128, Vb128]),
+    op(0x310, "vxor128", &[Vt128, Va128, Vb128]),
+    op(0x340, "vpkuhus128", &[Vt128, Va128, Vb128]),
+    op(0x350, "vsel128", &[Vt128, Va128, Vb128]),
+    op(0x380, "vpkuwum128", &[Vt128, Va128, Vb128]),
+    op(0x390, "vslo128", &[Vt128, Va128, Vb128]),
+    op(0x3C0, "vpkuwus128", &[Vt128, Va128, Vb128]),
+    op(0x3D0, "vsro128", &[Vt128, Va128, Vb128]),
+];
+
+/// Opcode 6: VMX128 conversions, permutes and compares. Its extended opcodes are different
+/// lengths, so it is looked up in order from the longest.
+fn vmx128_6(word: u32) -> Option<Decoded> {
+    lookup(VX128_6_PERMUTE, word & 0x730, word)
+        .or_else(|| lookup(VX128_6_UNARY, word & 0x7F0, word))
+        .or_else(|| lookup(VX128_6_BINARY, word & 0x3D0, word))
+        .or_else(|| lookup(VX128_6_COMPARE, word & 0x390, word))
+}
+
+const VX128_6_PERMUTE: &[Op] = &[
+    op(0x210, "vpermwi128", &[Vt128, Vb128, Perm128]),
+    op(0x610, "vpkd3d128", &[Vt128, Vb128, Uimm, Z128]),
+    op(0x710, "vrlimi128", &[Vt128, Vb128, Uimm, Z128]),
+];
+
+const VX128_6_UNARY: &[Op] = &[
+    op(0x230, "vcfpsxws128", &[Vt128, Vb128, Uimm]),
+    op(0x270, "vcfpuxws128", &[Vt128, Vb128, Uimm]),
+    op(0x2B0, "vcsxwfp128", &[Vt128, Vb128, Uimm]),
+    op(0x2F0, "vcuxwfp128", &[Vt128, Vb128, Uimm]),
+    op(0x330, "vrfim128", &[Vt128, Vb128]),
+    op(0x370, "vrfin128", &[Vt128, Vb128]),
+    op(0x3B0, "vrfip128", &[Vt128, Vb128]),
+    op(0x3F0, "vrfiz128", &[Vt128, Vb128]),
+    op(0x630, "vrefp128", &[Vt128, Vb128]),
+    op(0x670, "vrsqrtefp128", &[Vt128, Vb128]),
+    op(0x6B0, "vexptefp128", &[Vt128, Vb128]),
+    op(0x6F0, "vlogefp128", &[Vt128, Vb128]),
+    op(0x730, "vspltw128", &[Vt128, Vb128, Uimm]),
+    op(0x770, "vspltisw128", &[Vt128, Simm]),
+    op(0x7F0, "vupkd3d128", &[Vt128, Vb128, Uimm]),
+];
+
+const VX128_6_BINARY: &[Op] = &[
+    op(0x050, "vrlw128", &[Vt128, Va128, Vb128]),
+    op(0x0D0, "vslw128", &[Vt128, Va128, Vb128]),
+    op(0x150, "vsraw128", &[Vt128, Va128, Vb128]),
+    op(0x1D0, "vsrw128", &[Vt128, Va128, Vb128]),
+    op(0x280, "vmaxfp128", &[Vt128, Va128, Vb128]),
+    op(0x2C0, "vminfp128", &[Vt128, Va128, Vb128]),
+    op(0x300, "vmrghw128", &[Vt128, Va128, Vb128]),
+    op(0x340, "vmrglw128", &[Vt128, Va128, Vb128]),
+    op(0x380, "vupkhsb128", &[Vt128, Vb128]),
+    op(0x3C0, "vupklsb128", &[Vt128, Vb128]),
+];
+
+/// The VMX128 compares. Their record bit is in the middle of the extended opcode, and is left
+/// out of the lookup.
+const VX128_6_COMPARE: &[Op] = &[
+    flags(0x000, "vcmpeqfp128", &[Vt128, Va128, Vb128], RC_128),
+    flags(0x080, "vcmpgefp128", &[Vt128, Va128, Vb128], RC_128),
+    flags(0x100, "vcmpgtfp128", &[Vt128, Va128, Vb128], RC_128),
+    flags(0x180, "vcmpbfp128", &[Vt128, Va128, Vb128], RC_128),
+    flags(0x200, "vcmpequw128", &[Vt128, Va128, Vb128], RC_128),
+];
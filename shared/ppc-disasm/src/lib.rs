@@ -0,0 +1,313 @@
+//! A `no_std` disassembler for 64-bit PowerPC, including the Xenon's VMX128 extensions.
+//!
+//! An [Instruction] formats as assembly in the style of `objdump`, e.g. `lis     r3,0x8000`.
+//! Common extended mnemonics such as `li`, `mr`, `nop`, `blr`, `beq` and `mtsrr0` are used in
+//! place of the instructions they stand for. A word which doesn't decode formats as `.long`.
+//!
+//! Immediates and displacements are printed in hex, and shift counts, bit numbers and the like
+//! in decimal. Branch targets are absolute, so that callers can look them up with
+//! [Instruction::target].
+#![no_std]
+
+mod decode;
+
+use core::fmt;
+
+use decode::{Decoded, Field};
+
+/// An instruction, and the address it is at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction {
+    addr: u64,
+    word: u32,
+}
+
+impl Instruction {
+    pub const fn new(addr: u64, word: u32) -> Self {
+        Self { addr, word }
+    }
+
+    pub const fn addr(&self) -> u64 {
+        self.addr
+    }
+
+    pub const fn word(&self) -> u32 {
+        self.word
+    }
+
+    /// True if the word decodes as an instruction.
+    pub fn is_valid(&self) -> bool {
+        decode::decode(self.word).is_some()
+    }
+
+    /// The address a relative or absolute branch (`b` or `bc`) goes to.
+    pub fn target(&self) -> Option<u64> {
+        let word = self.word;
+
+        let offset = match word >> 26 {
+            // Sign-extend the 24-bit LI field.
+            18 => (((word & 0x03FF_FFFC) << 6) as i32 >> 6) as i64,
+            // Sign-extend the 14-bit BD field.
+            16 => (word & 0xFFFC) as u16 as i16 as i64,
+            _ => return None,
+        };
+
+        // AA: The target is absolute.
+        if word & 2 != 0 {
+            Some(offset as u64)
+        } else {
+            Some(self.addr.wrapping_add(offset as u64))
+        }
+    }
+
+    fn field(&self, field: Field, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let word = self.word;
+        let bits = |shift: u32, n: u32| (word >> shift) & ((1 << n) - 1);
+
+        match field {
+            Field::Rt => write!(f, "r{}", bits(21, 5)),
+            Field::Ra => write!(f, "r{}", bits(16, 5)),
+            Field::Rb => write!(f, "r{}", bits(11, 5)),
+            Field::Ra0 => base(f, bits(16, 5)),
+            Field::Ft => write!(f, "f{}", bits(21, 5)),
+            Field::Fa => write!(f, "f{}", bits(16, 5)),
+            Field::Fb => write!(f, "f{}", bits(11, 5)),
+            Field::Fc => write!(f, "f{}", bits(6, 5)),
+            Field::Vt => write!(f, "v{}", bits(21, 5)),
+            Field::Va => write!(f, "v{}", bits(16, 5)),
+            Field::Vb => write!(f, "v{}", bits(11, 5)),
+            Field::Vc => write!(f, "v{}", bits(6, 5)),
+            Field::Vt128 => write!(f, "v{}", bits(21, 5) | bits(2, 2) << 5),
+            Field::Va128 => write!(f, "v{}", bits(16, 5) | bits(5, 1) << 5 | bits(10, 1) << 6),
+            Field::Vb128 => write!(f, "v{}", bits(11, 5) | bits(0, 2) << 5),
+            Field::Vc128 => write!(f, "v{}", bits(6, 3)),
+            Field::Si => signed(f, word as u16 as i16 as i64),
+            Field::Ui => write!(f, "0x{:X}", word & 0xFFFF),
+            Field::D => {
+                signed(f, word as u16 as i16 as i64)?;
+                f.write_str("(")?;
+                base(f, bits(16, 5))?;
+                f.write_str(")")
+            }
+            Field::Ds => {
+                signed(f, (word & 0xFFFC) as u16 as i16 as i64)?;
+                f.write_str("(")?;
+                base(f, bits(16, 5))?;
+                f.write_str(")")
+            }
+            Field::Crf => write!(f, "cr{}", bits(23, 3)),
+            Field::Crfs => write!(f, "cr{}", bits(18, 3)),
+            Field::Crbt | Field::Bo | Field::To => write!(f, "{}", bits(21, 5)),
+            Field::Crba | Field::Bi | Field::Uimm => write!(f, "{}", bits(16, 5)),
+            Field::Crbb | Field::Sh | Field::Nb => write!(f, "{}", bits(11, 5)),
+            Field::Mb => write!(f, "{}", bits(6, 5)),
+            Field::Me => write!(f, "{}", bits(1, 5)),
+            Field::Sh64 => write!(f, "{}", bits(11, 5) | bits(1, 1) << 5),
+            Field::Mb64 => write!(f, "{}", bits(6, 5) | bits(5, 1) << 5),
+            Field::Spr => write!(f, "{}", bits(16, 5) | bits(11, 5) << 5),
+            Field::Fxm => write!(f, "0x{:X}", bits(12, 8)),
+            Field::Flm => write!(f, "0x{:X}", bits(17, 8)),
+            Field::Sr => write!(f, "{}", bits(16, 4)),
+            Field::L => write!(f, "{}", bits(16, 1)),
+            Field::U => write!(f, "{}", bits(12, 4)),
+            Field::Lev => write!(f, "{}", bits(5, 7)),
+            // Sign-extend the 5-bit field.
+            Field::Simm => write!(f, "{}", ((bits(16, 5) << 27) as i32) >> 27),
+            Field::Vsh => write!(f, "{}", bits(6, 4)),
+            Field::Perm128 => write!(f, "0x{:X}", bits(16, 5) | bits(6, 3) << 5),
+            Field::Z128 => write!(f, "{}", bits(6, 2)),
+            Field::Target => write!(f, "0x{:X}", self.target().unwrap_or(0)),
+        }
+    }
+}
+
+/// An address's base register, where r0 stands for 0.
+fn base(f: &mut fmt::Formatter<'_>, ra: u32) -> fmt::Result {
+    match ra {
+        0 => f.write_str("0"),
+        ra => write!(f, "r{}", ra),
+    }
+}
+
+fn signed(f: &mut fmt::Formatter<'_>, value: i64) -> fmt::Result {
+    if value < 0 {
+        write!(f, "-0x{:X}", value.unsigned_abs())
+    } else {
+        write!(f, "0x{:X}", value)
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Decoded { name, fields } = match decode::decode(self.word) {
+            Some(decoded) => decoded,
+            None => return write!(f, ".long   0x{:08X}", self.word),
+        };
+
+        if fields.is_empty() {
+            return f.write_str(name.as_str());
+        }
+
+        write!(f, "{:<7} ", name.as_str())?;
+        for (i, &field) in fields.iter().enumerate() {
+            if i != 0 {
+                f.write_str(",")?;
+            }
+
+            self.field(field, f)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::*;
+    use std::string::{String, ToString};
+
+    fn dis(word: u32) -> String {
+        Instruction::new(0x8000_0000_1C00_0000, word).to_string()
+    }
+
+    #[test]
+    fn test_longjmp() {
+        // The sequence built by stage1's `util::make_longjmp`, for a target of
+        // 0x8000_0000_1C00_1234 and a parameter of 0x0123_4567_89AB_CDEF.
+        let code = [
+            (0x3C608000, "lis     r3,0x8000"),
+            (0x60630000, "ori     r3,r3,0x0"),
+            (0x786307C6, "rldicr  r3,r3,32,31"),
+            (0x64631C00, "oris    r3,r3,0x1C00"),
+            (0x60631234, "ori     r3,r3,0x1234"),
+            (0x7C7A03A6, "mtsrr0  r3"),
+            (0x3C800000, "lis     r4,0x0"),
+            (0x60848030, "ori     r4,r4,0x8030"),
+            (0x7C6000A6, "mfmsr   r3"),
+            (0x7C632078, "andc    r3,r3,r4"),
+            (0x7C7B03A6, "mtsrr1  r3"),
+            (0x3C600123, "lis     r3,0x123"),
+            (0x60634567, "ori     r3,r3,0x4567"),
+            (0x786307C6, "rldicr  r3,r3,32,31"),
+            (0x646389AB, "oris    r3,r3,0x89AB"),
+            (0x6063CDEF, "ori     r3,r3,0xCDEF"),
+            (0x4C000024, "rfid"),
+        ];
+
+        for (word, text) in code {
+            assert_eq!(dis(word), text, "{:08X}", word);
+        }
+    }
+
+    #[test]
+    fn test_integer() {
+        assert_eq!(dis(0x60000000), "nop");
+        assert_eq!(dis(0x38600010), "li      r3,0x10");
+        assert_eq!(dis(0x3821FF80), "addi    r1,r1,-0x80");
+        assert_eq!(dis(0x7C641B78), "mr      r4,r3");
+        assert_eq!(dis(0x7C632214), "add     r3,r3,r4");
+        assert_eq!(dis(0x7C632615), "addo.   r3,r3,r4");
+        assert_eq!(dis(0x7C6300D0), "neg     r3,r3");
+        assert_eq!(dis(0x2C030000), "cmpwi   r3,0x0");
+        assert_eq!(dis(0x2FA30001), "cmpdi   cr7,r3,0x1");
+        assert_eq!(dis(0x7C232040), "cmpld   r3,r4");
+        assert_eq!(dis(0x5463103A), "rlwinm  r3,r3,2,0,29");
+        assert_eq!(dis(0x7C630734), "extsh   r3,r3");
+        assert_eq!(dis(0x7C6AFE76), "sradi   r10,r3,63");
+        assert_eq!(dis(0x7FE00008), "trap");
+    }
+
+    #[test]
+    fn test_memory() {
+        assert_eq!(dis(0xF821FF81), "stdu    r1,-0x80(r1)");
+        assert_eq!(dis(0xE8010090), "ld      r0,0x90(r1)");
+        assert_eq!(dis(0x80630004), "lwz     r3,0x4(r3)");
+        assert_eq!(dis(0x7C6020A8), "ldarx   r3,0,r4");
+        assert_eq!(dis(0x7C6021AD), "stdcx.  r3,0,r4");
+        assert_eq!(dis(0x7C0004AC), "sync");
+        assert_eq!(dis(0x7C2004AC), "lwsync");
+        assert_eq!(dis(0x4C00012C), "isync");
+        assert_eq!(dis(0x7C001FEC), "dcbz    0,r3");
+        assert_eq!(dis(0x7C201FEC), "dcbz128 0,r3");
+    }
+
+    #[test]
+    fn test_branches() {
+        let at = |addr: u64, word: u32| Instruction::new(addr, word).to_string();
+
+        assert_eq!(at(0x1000, 0x48000010), "b       0x1010");
+        assert_eq!(at(0x1000, 0x4BFFFFF1), "bl      0xFF0");
+        assert_eq!(at(0x1000, 0x48000102), "ba      0x100");
+        assert_eq!(at(0x1000, 0x4182000C), "beq     0x100C");
+        assert_eq!(at(0x1000, 0x409EFFF8), "bne     cr7,0xFF8");
+        assert_eq!(at(0x1000, 0x4200FFFC), "bdnz    0xFFC");
+        assert_eq!(at(0x1000, 0x4E800020), "blr");
+        assert_eq!(at(0x1000, 0x4E800421), "bctrl");
+        assert_eq!(at(0x1000, 0x4D820020), "beqlr");
+        assert_eq!(at(0x1000, 0x44000002), "sc");
+
+        assert_eq!(Instruction::new(0x1000, 0x4BFFFFF1).target(), Some(0xFF0));
+        assert_eq!(Instruction::new(0x1000, 0x4E800020).target(), None);
+    }
+
+    #[test]
+    fn test_spr() {
+        assert_eq!(dis(0x7C0802A6), "mflr    r0");
+        assert_eq!(dis(0x7C0803A6), "mtlr    r0");
+        assert_eq!(dis(0x7C724AA6), "mfhdsisr r3");
+        assert_eq!(dis(0x7C7E4AA6), "mflpcr  r3");
+        assert_eq!(dis(0x7C70FBA6), "mthid0  r3");
+        assert_eq!(dis(0x7C7FFAA6), "mfpir   r3");
+        assert_eq!(dis(0x7C6C42E6), "mftb    r3");
+        assert_eq!(dis(0x7C6102A6), "mfxer   r3");
+        assert_eq!(dis(0x7C7A1BA6), "mtspr   122,r3");
+        assert_eq!(dis(0x7C600164), "mtmsrd  r3");
+        assert_eq!(dis(0x7C610164), "mtmsrd  r3,1");
+    }
+
+    #[test]
+    fn test_float() {
+        assert_eq!(dis(0xFC20102A), "fadd    f1,f0,f2");
+        assert_eq!(dis(0xEC2100B2), "fmuls   f1,f1,f2");
+        assert_eq!(dis(0xFC2110FA), "fmadd   f1,f1,f3,f2");
+        assert_eq!(dis(0xFC200890), "fmr     f1,f1");
+        assert_eq!(dis(0xC8230008), "lfd     f1,0x8(r3)");
+    }
+
+    #[test]
+    fn test_vmx() {
+        assert_eq!(dis(0x10011080), "vadduwm v0,v1,v2");
+        assert_eq!(dis(0x10011484), "vor     v0,v1,v2");
+        assert_eq!(dis(0x1001108C), "vmrghw  v0,v1,v2");
+        assert_eq!(dis(0x100110C6), "vcmpeqfp v0,v1,v2");
+        assert_eq!(dis(0x100114C6), "vcmpeqfp. v0,v1,v2");
+        assert_eq!(dis(0x100110EC), "vsldoi  v0,v1,v2,3");
+        assert_eq!(dis(0x100110EE), "vmaddfp v0,v1,v3,v2");
+        assert_eq!(dis(0x100110EB), "vperm   v0,v1,v2,v3");
+        assert_eq!(dis(0x101F038C), "vspltisw v0,-1");
+        assert_eq!(dis(0x7C0018CE), "lvx     v0,0,r3");
+    }
+
+    #[test]
+    fn test_vmx128() {
+        // vD = v64 (VD128h = 2), rA = r3, rB = r4.
+        assert_eq!(dis(0x100320CB), "lvx128  v64,r3,r4");
+        assert_eq!(dis(0x100321C3), "stvx128 v0,r3,r4");
+        // vD = v1, vA = v2, vB = v3, with VA128H set for v66.
+        assert_eq!(dis(0x14221C10), "vaddfp128 v1,v66,v3");
+        assert_eq!(dis(0x14221890), "vmulfp128 v1,v2,v3");
+        assert_eq!(dis(0x18201F30), "vspltw128 v1,v3,0");
+        assert_eq!(dis(0x182218D0), "vslw128 v1,v2,v3");
+        assert_eq!(dis(0x18221840), "vcmpeqfp128. v1,v2,v3");
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert_eq!(dis(0x00000000), ".long   0x00000000");
+        assert_eq!(dis(0x04000000), ".long   0x04000000");
+        assert!(!Instruction::new(0, 0).is_valid());
+        assert!(Instruction::new(0, 0x60000000).is_valid());
+    }
+}
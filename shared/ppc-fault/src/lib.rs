@@ -0,0 +1,444 @@
+//! Diagnoses of 64-bit PowerPC storage, alignment and program exceptions.
+//!
+//! A [Fault] is the state the processor leaves behind when it takes an exception: the vector,
+//! SRR0/SRR1, DAR/DSISR (or HDAR/HDSISR for a hypervisor data storage interrupt) and the
+//! faulting instruction. [Fault::diagnose] turns it into a [Diagnosis], which says what went
+//! wrong in words, e.g. `store to unmapped address 0x0000000012345678` or
+//! `trap instruction (tw)`.
+#![no_std]
+
+use core::fmt;
+
+/// Data storage interrupt.
+pub const VECTOR_DSI: u16 = 0x300;
+/// Data segment interrupt.
+pub const VECTOR_DATA_SEGMENT: u16 = 0x380;
+/// Instruction storage interrupt.
+pub const VECTOR_ISI: u16 = 0x400;
+/// Instruction segment interrupt.
+pub const VECTOR_INSTRUCTION_SEGMENT: u16 = 0x480;
+/// Alignment interrupt.
+pub const VECTOR_ALIGNMENT: u16 = 0x600;
+/// Program interrupt.
+pub const VECTOR_PROGRAM: u16 = 0x700;
+/// Floating-point unavailable interrupt.
+pub const VECTOR_FP_UNAVAILABLE: u16 = 0x800;
+/// Hypervisor data storage interrupt.
+pub const VECTOR_HDSI: u16 = 0xE00;
+
+// DSISR (and HDSISR) bits.
+const DSISR_NO_TRANSLATION: u32 = 0x4000_0000;
+const DSISR_PROTECTION: u32 = 0x0800_0000;
+const DSISR_STORE: u32 = 0x0200_0000;
+const DSISR_DABR_MATCH: u32 = 0x0040_0000;
+const DSISR_NO_SEGMENT: u32 = 0x0020_0000;
+
+// SRR1 bits for an instruction storage interrupt.
+const SRR1_ISI_NO_TRANSLATION: u64 = 0x4000_0000;
+const SRR1_ISI_NO_EXECUTE: u64 = 0x1000_0000;
+const SRR1_ISI_PROTECTION: u64 = 0x0800_0000;
+
+// SRR1 bits for a program interrupt.
+const SRR1_PROGRAM_FP: u64 = 0x0010_0000;
+const SRR1_PROGRAM_ILLEGAL: u64 = 0x0008_0000;
+const SRR1_PROGRAM_PRIVILEGED: u64 = 0x0004_0000;
+const SRR1_PROGRAM_TRAP: u64 = 0x0002_0000;
+
+// Linux signal numbers, as reported to debuggers.
+pub const SIGILL: u8 = 4;
+pub const SIGTRAP: u8 = 5;
+pub const SIGBUS: u8 = 7;
+pub const SIGFPE: u8 = 8;
+pub const SIGSEGV: u8 = 11;
+
+/// The state captured when an exception is taken.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Fault {
+    /// The exception vector, e.g. `0x300`.
+    pub vector: u16,
+    /// SRR0: the faulting instruction's address, for the exceptions diagnosed here.
+    pub pc: u64,
+    /// SRR1: the MSR, along with the reason bits for some exceptions.
+    pub srr1: u64,
+    pub dar: u64,
+    pub dsisr: u32,
+    pub hdar: u64,
+    pub hdsisr: u32,
+    /// The instruction at [Fault::pc], if it could be read.
+    pub insn: Option<u32>,
+}
+
+/// The kind of memory access that faulted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Load,
+    Store,
+    /// An instruction fetch.
+    Fetch,
+    /// A load or store. The instruction could not be read to tell which.
+    Data,
+}
+
+/// Why a memory access faulted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cause {
+    /// No translation exists for the address.
+    Unmapped,
+    /// The page protection forbids the access.
+    Protected,
+    /// The page is guarded or no-execute.
+    NoExecute,
+    /// The address has no segment.
+    NoSegment,
+    /// The access matched the data address breakpoint.
+    Breakpoint,
+    /// None of the above.
+    Other,
+}
+
+/// What went wrong, in words.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Diagnosis {
+    /// A memory access failed.
+    Storage {
+        access: Access,
+        addr: u64,
+        cause: Cause,
+    },
+    /// A load or store was not aligned as its instruction requires.
+    Alignment { access: Access, addr: u64 },
+    /// An instruction that doesn't exist.
+    Illegal { insn: Option<u32> },
+    /// An instruction that isn't allowed at the current privilege level.
+    Privileged { insn: Option<u32> },
+    /// A trap instruction whose condition was met.
+    Trap { insn: Option<u32> },
+    /// A floating-point exception, with the exception enabled in the FPSCR.
+    FloatingPoint,
+    /// A floating-point instruction, with MSR[FP] clear.
+    FloatingPointUnavailable,
+}
+
+impl Fault {
+    /// Say what went wrong, if this is a fault that can be diagnosed.
+    pub fn diagnose(&self) -> Option<Diagnosis> {
+        let data = |dar: u64, dsisr: u32| {
+            let access = if dsisr & DSISR_STORE != 0 {
+                Access::Store
+            } else {
+                Access::Load
+            };
+
+            let cause = if dsisr & DSISR_NO_TRANSLATION != 0 {
+                Cause::Unmapped
+            } else if dsisr & DSISR_PROTECTION != 0 {
+                Cause::Protected
+            } else if dsisr & DSISR_DABR_MATCH != 0 {
+                Cause::Breakpoint
+            } else if dsisr & DSISR_NO_SEGMENT != 0 {
+                Cause::NoSegment
+            } else {
+                Cause::Other
+            };
+
+            Diagnosis::Storage {
+                access,
+                addr: dar,
+                cause,
+            }
+        };
+
+        let diagnosis = match self.vector {
+            VECTOR_DSI => data(self.dar, self.dsisr),
+            VECTOR_HDSI => data(self.hdar, self.hdsisr),
+
+            VECTOR_DATA_SEGMENT => Diagnosis::Storage {
+                access: self.data_access(),
+                addr: self.dar,
+                cause: Cause::NoSegment,
+            },
+
+            VECTOR_ISI => {
+                let cause = if self.srr1 & SRR1_ISI_NO_TRANSLATION != 0 {
+                    Cause::Unmapped
+                } else if self.srr1 & SRR1_ISI_NO_EXECUTE != 0 {
+                    Cause::NoExecute
+                } else if self.srr1 & SRR1_ISI_PROTECTION != 0 {
+                    Cause::Protected
+                } else {
+                    Cause::Other
+                };
+
+                Diagnosis::Storage {
+                    access: Access::Fetch,
+                    addr: self.pc,
+                    cause,
+                }
+            }
+
+            VECTOR_INSTRUCTION_SEGMENT => Diagnosis::Storage {
+                access: Access::Fetch,
+                addr: self.pc,
+                cause: Cause::NoSegment,
+            },
+
+            VECTOR_ALIGNMENT => Diagnosis::Alignment {
+                access: self.data_access(),
+                addr: self.dar,
+            },
+
+            VECTOR_PROGRAM => {
+                let insn = self.insn;
+
+                if self.srr1 & SRR1_PROGRAM_FP != 0 {
+                    Diagnosis::FloatingPoint
+                } else if self.srr1 & SRR1_PROGRAM_ILLEGAL != 0 {
+                    Diagnosis::Illegal { insn }
+                } else if self.srr1 & SRR1_PROGRAM_PRIVILEGED != 0 {
+                    Diagnosis::Privileged { insn }
+                } else if self.srr1 & SRR1_PROGRAM_TRAP != 0 {
+                    Diagnosis::Trap { insn }
+                } else {
+                    return None;
+                }
+            }
+
+            VECTOR_FP_UNAVAILABLE => Diagnosis::FloatingPointUnavailable,
+
+            _ => return None,
+        };
+
+        Some(diagnosis)
+    }
+
+    /// The kind of data access the faulting instruction makes.
+    fn data_access(&self) -> Access {
+        self.insn.map(data_access).unwrap_or(Access::Data)
+    }
+}
+
+impl Diagnosis {
+    /// The signal a debugger should see the fault as.
+    pub fn signal(&self) -> u8 {
+        match self {
+            Diagnosis::Storage { .. } => SIGSEGV,
+            Diagnosis::Alignment { .. } => SIGBUS,
+            Diagnosis::Illegal { .. } | Diagnosis::Privileged { .. } => SIGILL,
+            Diagnosis::Trap { .. } => SIGTRAP,
+            Diagnosis::FloatingPoint | Diagnosis::FloatingPointUnavailable => SIGFPE,
+        }
+    }
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Access::Load => write!(f, "load from"),
+            Access::Store => write!(f, "store to"),
+            Access::Fetch => write!(f, "instruction fetch from"),
+            Access::Data => write!(f, "access to"),
+        }
+    }
+}
+
+impl fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Diagnosis::Storage {
+                access,
+                addr,
+                cause,
+            } => match cause {
+                Cause::Unmapped => write!(f, "{} unmapped address {:#018x}", access, addr),
+                Cause::Protected => write!(f, "{} protected address {:#018x}", access, addr),
+                Cause::NoExecute => write!(f, "{} no-execute address {:#018x}", access, addr),
+                Cause::NoSegment => write!(f, "{} address {:#018x} with no segment", access, addr),
+                Cause::Breakpoint => write!(f, "{} {:#018x} hit a breakpoint", access, addr),
+                Cause::Other => write!(f, "{} address {:#018x} failed", access, addr),
+            },
+            Diagnosis::Alignment { access, addr } => {
+                write!(f, "misaligned {} {:#018x}", access, addr)
+            }
+            Diagnosis::Illegal { insn } => {
+                write!(f, "illegal instruction")?;
+                write_word(f, insn)
+            }
+            Diagnosis::Privileged { insn } => {
+                write!(f, "privileged instruction")?;
+                write_word(f, insn)
+            }
+            Diagnosis::Trap { insn } => match insn.and_then(trap_mnemonic) {
+                Some(name) => write!(f, "trap instruction ({})", name),
+                None => write!(f, "trap instruction"),
+            },
+            Diagnosis::FloatingPoint => write!(f, "floating-point exception"),
+            Diagnosis::FloatingPointUnavailable => {
+                write!(f, "floating-point instruction with floating point disabled")
+            }
+        }
+    }
+}
+
+fn write_word(f: &mut fmt::Formatter<'_>, insn: Option<u32>) -> fmt::Result {
+    match insn {
+        Some(word) => write!(f, " {:08X}", word),
+        None => Ok(()),
+    }
+}
+
+/// The name of a trap instruction.
+fn trap_mnemonic(insn: u32) -> Option<&'static str> {
+    match (insn >> 26, (insn >> 1) & 0x3FF) {
+        (2, _) => Some("tdi"),
+        (3, _) => Some("twi"),
+        (31, 4) => Some("tw"),
+        (31, 68) => Some("td"),
+        _ => None,
+    }
+}
+
+/// Extended opcodes (primary opcode 31) of the indexed stores, including `dcbz`.
+const STORES_X: [u32; 25] = [
+    135, 149, 150, 151, 167, 181, 183, 199, 214, 215, 231, 247, 407, 439, 487, 661, 662, 663, 695,
+    725, 727, 759, 918, 983, 1014,
+];
+
+/// Extended opcodes (primary opcode 31) of the indexed loads.
+const LOADS_X: [u32; 27] = [
+    7, 20, 21, 23, 39, 53, 55, 71, 84, 87, 103, 119, 279, 311, 341, 343, 359, 373, 375, 533, 534,
+    535, 567, 597, 599, 631, 790,
+];
+
+/// Whether `insn` loads or stores.
+fn data_access(insn: u32) -> Access {
+    match insn >> 26 {
+        32..=35 | 40..=43 | 46 | 48..=51 | 58 => Access::Load,
+        36..=39 | 44 | 45 | 47 | 52..=55 | 62 => Access::Store,
+        31 => {
+            let xo = (insn >> 1) & 0x3FF;
+
+            if STORES_X.contains(&xo) {
+                Access::Store
+            } else if LOADS_X.contains(&xo) {
+                Access::Load
+            } else {
+                Access::Data
+            }
+        }
+        _ => Access::Data,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::*;
+    use std::string::ToString;
+
+    fn fault(vector: u16) -> Fault {
+        Fault {
+            vector,
+            pc: 0x8000_0000_0000_1000,
+            ..Fault::default()
+        }
+    }
+
+    #[test]
+    fn test_storage() {
+        let f = Fault {
+            dar: 0x1234_5678,
+            dsisr: DSISR_NO_TRANSLATION | DSISR_STORE,
+            ..fault(VECTOR_DSI)
+        };
+        let d = f.diagnose().unwrap();
+        assert_eq!(d.signal(), SIGSEGV);
+        assert_eq!(
+            d.to_string(),
+            "store to unmapped address 0x0000000012345678"
+        );
+
+        let f = Fault {
+            hdar: 0x20,
+            hdsisr: DSISR_PROTECTION,
+            ..fault(VECTOR_HDSI)
+        };
+        assert_eq!(
+            f.diagnose().unwrap().to_string(),
+            "load from protected address 0x0000000000000020"
+        );
+
+        let f = Fault {
+            srr1: SRR1_ISI_NO_EXECUTE,
+            ..fault(VECTOR_ISI)
+        };
+        assert_eq!(
+            f.diagnose().unwrap().to_string(),
+            "instruction fetch from no-execute address 0x8000000000001000"
+        );
+    }
+
+    #[test]
+    fn test_alignment() {
+        // std r3,0x10(r4)
+        let f = Fault {
+            dar: 0x1003,
+            insn: Some(0xF864_0010),
+            ..fault(VECTOR_ALIGNMENT)
+        };
+        let d = f.diagnose().unwrap();
+        assert_eq!(d.signal(), SIGBUS);
+        assert_eq!(d.to_string(), "misaligned store to 0x0000000000001003");
+
+        // lwarx r3,0,r4
+        let f = Fault {
+            insn: Some(0x7C60_2028),
+            ..f
+        };
+        assert_eq!(
+            f.diagnose(),
+            Some(Diagnosis::Alignment {
+                access: Access::Load,
+                addr: 0x1003
+            })
+        );
+
+        let f = Fault { insn: None, ..f };
+        assert_eq!(
+            f.diagnose().unwrap().to_string(),
+            "misaligned access to 0x0000000000001003"
+        );
+    }
+
+    #[test]
+    fn test_program() {
+        let program = |srr1, insn| {
+            Fault {
+                srr1,
+                insn: Some(insn),
+                ..fault(VECTOR_PROGRAM)
+            }
+            .diagnose()
+        };
+
+        // tw 31,r0,r0 (trap)
+        let d = program(SRR1_PROGRAM_TRAP, 0x7FE0_0008).unwrap();
+        assert_eq!(d.signal(), SIGTRAP);
+        assert_eq!(d.to_string(), "trap instruction (tw)");
+        assert_eq!(
+            program(SRR1_PROGRAM_TRAP, 0x0C03_0000).unwrap().to_string(),
+            "trap instruction (twi)"
+        );
+
+        let d = program(SRR1_PROGRAM_ILLEGAL, 0).unwrap();
+        assert_eq!(d.signal(), SIGILL);
+        assert_eq!(d.to_string(), "illegal instruction 00000000");
+
+        // mtmsrd r3
+        let d = program(SRR1_PROGRAM_PRIVILEGED, 0x7C60_0164).unwrap();
+        assert_eq!(d.to_string(), "privileged instruction 7C600164");
+
+        assert_eq!(program(0, 0), None);
+        assert_eq!(fault(0x500).diagnose(), None);
+    }
+}
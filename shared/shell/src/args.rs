@@ -0,0 +1,144 @@
+//! Command argument parsing.
+
+use core::str::FromStr;
+
+use crate::{
+    expr::{self, Resolve},
+    Error,
+};
+
+/// The arguments following a command's name, separated by whitespace.
+///
+/// The parsing helpers report a missing argument as [Error::Usage], and one that can't be parsed
+/// as [Error::Invalid], naming it as the caller describes it (e.g. "address").
+#[derive(Clone)]
+pub struct Args<'a> {
+    rest: &'a str,
+    names: &'a dyn Resolve,
+}
+
+impl<'a> Args<'a> {
+    pub fn new(args: &'a str) -> Self {
+        Self::with_names(args, &())
+    }
+
+    /// Arguments whose expressions may refer to `names`.
+    pub fn with_names(args: &'a str, names: &'a dyn Resolve) -> Self {
+        Self {
+            rest: args.trim_start(),
+            names,
+        }
+    }
+
+    /// True if every argument has been taken.
+    pub fn is_empty(&self) -> bool {
+        self.rest.is_empty()
+    }
+
+    /// Take the remainder of the line as a single argument, e.g. a kernel command line.
+    pub fn rest(&mut self) -> &'a str {
+        core::mem::take(&mut self.rest).trim_end()
+    }
+
+    /// Take the next argument, which must be present.
+    pub fn arg(&mut self) -> Result<&'a str, Error> {
+        self.next().ok_or(Error::Usage)
+    }
+
+    /// Take the next argument as a number, given as an [expression](crate::expr), e.g. `$lr-0x40`.
+    pub fn num(&mut self, what: &'static str) -> Result<u64, Error> {
+        self.opt_num(what)?.ok_or(Error::Usage)
+    }
+
+    /// Take the next argument, if there is one, as a number.
+    pub fn opt_num(&mut self, what: &'static str) -> Result<Option<u64>, Error> {
+        let names = self.names;
+
+        self.next()
+            .map(|arg| expr::eval(arg, names).map_err(|_| Error::Invalid(what)))
+            .transpose()
+    }
+
+    /// Take the next argument, parsed with [FromStr].
+    pub fn parse<F: FromStr>(&mut self, what: &'static str) -> Result<F, Error> {
+        self.opt_parse(what)?.ok_or(Error::Usage)
+    }
+
+    /// Take the next argument, if there is one, parsed with [FromStr].
+    pub fn opt_parse<F: FromStr>(&mut self, what: &'static str) -> Result<Option<F>, Error> {
+        self.next()
+            .map(|arg| arg.parse().map_err(|_| Error::Invalid(what)))
+            .transpose()
+    }
+}
+
+impl<'a> Iterator for Args<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        if self.rest.is_empty() {
+            return None;
+        }
+
+        let (arg, rest) = self
+            .rest
+            .split_once(char::is_whitespace)
+            .unwrap_or((self.rest, ""));
+
+        self.rest = rest.trim_start();
+        Some(arg)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_args() {
+        let mut args = Args::new("  8000 0x1F  zz  42 ");
+
+        assert_eq!(args.clone().count(), 4);
+        assert_eq!(args.num("address"), Ok(0x8000));
+        assert_eq!(args.num("length"), Ok(0x1F));
+        assert_eq!(args.num("value"), Err(Error::Invalid("value")));
+        assert_eq!(args.parse::<u16>("port"), Ok(42));
+        assert!(args.is_empty());
+        assert_eq!(args.num("address"), Err(Error::Usage));
+        assert_eq!(args.opt_num("address"), Ok(None));
+        assert_eq!(args.arg(), Err(Error::Usage));
+    }
+
+    #[test]
+    fn test_names() {
+        struct Names;
+
+        impl Resolve for Names {
+            fn variable(&self, name: &str) -> Option<u64> {
+                match name {
+                    "lr" => Some(0x1C00_2040),
+                    _ => None,
+                }
+            }
+
+            fn symbol(&self, _name: &str) -> Option<u64> {
+                None
+            }
+        }
+
+        let mut args = Args::with_names("$lr-0x40 0n128 $pc", &Names);
+
+        assert_eq!(args.num("address"), Ok(0x1C00_2000));
+        assert_eq!(args.num("length"), Ok(0x80));
+        assert_eq!(args.num("address"), Err(Error::Invalid("address")));
+    }
+
+    #[test]
+    fn test_rest() {
+        let mut args = Args::new("console=ttyS0  root=/dev/sda1 ");
+
+        assert_eq!(args.rest(), "console=ttyS0  root=/dev/sda1");
+        assert!(args.is_empty());
+        assert_eq!(args.rest(), "");
+    }
+}
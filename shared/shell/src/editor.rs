@@ -0,0 +1,645 @@
+//! An interactive line editor.
+//!
+//! [Editor] turns the bytes typed on a terminal into command lines. It understands the ANSI
+//! escape sequences sent by the arrow, Home, End and Delete keys, and the usual control keys:
+//!
+//! | Key                | Action                                        |
+//! |--------------------|-----------------------------------------------|
+//! | Left / Ctrl-B      | Move back one character                       |
+//! | Right / Ctrl-F     | Move forward one character                    |
+//! | Home / Ctrl-A      | Move to the start of the line                 |
+//! | End / Ctrl-E       | Move to the end of the line                   |
+//! | Backspace          | Erase the character before the cursor         |
+//! | Delete / Ctrl-D    | Erase the character under the cursor          |
+//! | Ctrl-U             | Erase everything before the cursor            |
+//! | Ctrl-K             | Erase everything after the cursor             |
+//! | Ctrl-W             | Erase the word before the cursor              |
+//! | Ctrl-C             | Abandon the line                              |
+//! | Up / Down          | Recall earlier (or later) lines from history  |
+//! | Tab                | Complete the command name                     |
+//!
+//! Input is decoded as UTF-8, and the cursor always moves by whole characters. Each character is
+//! assumed to take up one column on the screen.
+
+use alloc::{collections::VecDeque, string::String, vec::Vec};
+use core::fmt::{self, Write};
+
+/// The longest line the editor accepts, in bytes.
+pub const LINE_MAX: usize = 1024;
+
+/// A key press.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    /// A control character, given as the key pressed with Ctrl (e.g. `b'A'` for Ctrl-A).
+    Ctrl(u8),
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Seq {
+    None,
+    /// After `ESC`.
+    Esc,
+    /// Within a control sequence (`ESC [`), with its first numeric parameter so far.
+    Csi {
+        param: u16,
+        more: bool,
+    },
+    /// After `ESC O`, as sent by some terminals for the cursor keys.
+    Ss3,
+}
+
+/// Decodes the bytes sent by a terminal into key presses.
+#[derive(Debug, Clone)]
+pub struct KeyDecoder {
+    seq: Seq,
+    /// The UTF-8 sequence being received.
+    utf8: [u8; 4],
+    utf8_len: usize,
+    utf8_need: usize,
+    /// Set after a carriage return, so a following line feed isn't a second Enter.
+    cr: bool,
+}
+
+impl Default for KeyDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KeyDecoder {
+    pub const fn new() -> Self {
+        Self {
+            seq: Seq::None,
+            utf8: [0; 4],
+            utf8_len: 0,
+            utf8_need: 0,
+            cr: false,
+        }
+    }
+
+    /// Decode the next byte, returning a key once one is complete.
+    pub fn feed(&mut self, byte: u8) -> Option<Key> {
+        let after_cr = core::mem::replace(&mut self.cr, false);
+
+        match self.seq {
+            Seq::None => {}
+            Seq::Esc => {
+                self.seq = match byte {
+                    b'[' => Seq::Csi {
+                        param: 0,
+                        more: false,
+                    },
+                    b'O' => Seq::Ss3,
+                    0x1B => Seq::Esc,
+                    // Alt and a key. Nothing uses these.
+                    _ => Seq::None,
+                };
+
+                return None;
+            }
+            Seq::Csi { param, more } => {
+                match byte {
+                    b'0'..=b'9' if !more => {
+                        let param = param
+                            .saturating_mul(10)
+                            .saturating_add((byte - b'0') as u16);
+                        self.seq = Seq::Csi { param, more };
+                    }
+                    b';' => self.seq = Seq::Csi { param, more: true },
+                    // The final byte.
+                    0x40..=0x7E => {
+                        self.seq = Seq::None;
+                        return Self::csi(param, byte);
+                    }
+                    _ => {}
+                }
+
+                return None;
+            }
+            Seq::Ss3 => {
+                self.seq = Seq::None;
+                return Self::csi(0, byte);
+            }
+        }
+
+        if self.utf8_need != 0 {
+            if byte & 0xC0 == 0x80 {
+                self.utf8[self.utf8_len] = byte;
+                self.utf8_len += 1;
+
+                if self.utf8_len < self.utf8_need {
+                    return None;
+                }
+
+                let need = core::mem::replace(&mut self.utf8_need, 0);
+                return core::str::from_utf8(&self.utf8[..need])
+                    .ok()
+                    .and_then(|s| s.chars().next())
+                    .map(Key::Char);
+            }
+
+            // The sequence was cut short. Drop it, and take this byte on its own.
+            self.utf8_need = 0;
+        }
+
+        match byte {
+            b'\r' => {
+                self.cr = true;
+                Some(Key::Enter)
+            }
+            b'\n' if after_cr => None,
+            b'\n' => Some(Key::Enter),
+            b'\t' => Some(Key::Tab),
+            0x08 | 0x7F => Some(Key::Backspace),
+            0x1B => {
+                self.seq = Seq::Esc;
+                None
+            }
+            0x00..=0x1F => Some(Key::Ctrl(byte | 0x40)),
+            0x20..=0x7E => Some(Key::Char(byte as char)),
+            _ => {
+                self.utf8_need = match byte {
+                    0xC2..=0xDF => 2,
+                    0xE0..=0xEF => 3,
+                    0xF0..=0xF4 => 4,
+                    _ => return None,
+                };
+
+                self.utf8[0] = byte;
+                self.utf8_len = 1;
+                None
+            }
+        }
+    }
+
+    fn csi(param: u16, byte: u8) -> Option<Key> {
+        match (byte, param) {
+            (b'A', _) => Some(Key::Up),
+            (b'B', _) => Some(Key::Down),
+            (b'C', _) => Some(Key::Right),
+            (b'D', _) => Some(Key::Left),
+            (b'H', _) | (b'~', 1 | 7) => Some(Key::Home),
+            (b'F', _) | (b'~', 4 | 8) => Some(Key::End),
+            (b'~', 3) => Some(Key::Delete),
+            _ => None,
+        }
+    }
+}
+
+/// A source of completions for the first word on the line.
+pub trait Complete {
+    /// Pass every word that begins with `prefix` to `f`.
+    fn complete(&self, prefix: &str, f: &mut dyn FnMut(&str));
+}
+
+impl Complete for [&str] {
+    fn complete(&self, prefix: &str, f: &mut dyn FnMut(&str)) {
+        for word in self.iter().filter(|w| w.starts_with(prefix)) {
+            f(word);
+        }
+    }
+}
+
+/// A line editor with history.
+#[derive(Debug, Clone)]
+pub struct Editor {
+    prompt: &'static str,
+    keys: KeyDecoder,
+    line: String,
+    /// The cursor's byte offset into `line`.
+    cursor: usize,
+    /// Earlier lines, oldest first.
+    history: VecDeque<String>,
+    history_len: usize,
+    /// The history entry being shown, counting back from the newest.
+    browsing: Option<usize>,
+    /// The line that was being edited before the history was recalled.
+    scratch: String,
+}
+
+impl Editor {
+    /// Create an editor showing `prompt`, which remembers up to `history_len` lines.
+    pub fn new(prompt: &'static str, history_len: usize) -> Self {
+        Self {
+            prompt,
+            keys: KeyDecoder::new(),
+            line: String::new(),
+            cursor: 0,
+            history: VecDeque::with_capacity(history_len),
+            history_len,
+            browsing: None,
+            scratch: String::new(),
+        }
+    }
+
+    /// Start a new line, showing the prompt.
+    pub fn start(&mut self, out: &mut (impl Write + ?Sized)) {
+        self.line.clear();
+        self.cursor = 0;
+        self.browsing = None;
+
+        let _ = out.write_str(self.prompt);
+    }
+
+    /// The earlier lines, oldest first.
+    pub fn history(&self) -> impl Iterator<Item = &str> {
+        self.history.iter().map(|l| l.as_str())
+    }
+
+    /// Feed a byte typed on the terminal, echoing its effects to `out`.
+    /// Returns the line once Enter is pressed.
+    pub fn feed(
+        &mut self,
+        byte: u8,
+        out: &mut (impl Write + ?Sized),
+        complete: &(impl Complete + ?Sized),
+    ) -> Option<String> {
+        let key = self.keys.feed(byte)?;
+        self.key(key, out, complete).unwrap_or(None)
+    }
+
+    fn key(
+        &mut self,
+        key: Key,
+        out: &mut (impl Write + ?Sized),
+        complete: &(impl Complete + ?Sized),
+    ) -> Result<Option<String>, fmt::Error> {
+        let len = self.line.len();
+
+        match key {
+            Key::Enter => {
+                out.write_char('\n')?;
+                return Ok(Some(self.finish()));
+            }
+
+            Key::Ctrl(b'C') => {
+                out.write_str("^C\n")?;
+                self.line.clear();
+                return Ok(Some(self.finish()));
+            }
+
+            Key::Char(c) => self.insert(out, c.encode_utf8(&mut [0; 4]))?,
+            Key::Tab => self.complete(out, complete)?,
+
+            Key::Backspace if self.cursor != 0 => self.erase(out, self.prev(), self.cursor)?,
+            Key::Delete | Key::Ctrl(b'D') if self.cursor != len => {
+                self.erase(out, self.cursor, self.next())?
+            }
+            Key::Ctrl(b'U') => self.erase(out, 0, self.cursor)?,
+            Key::Ctrl(b'K') => self.erase(out, self.cursor, len)?,
+            Key::Ctrl(b'W') => {
+                let before = self.line[..self.cursor].trim_end();
+                let start = before.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+
+                self.erase(out, start, self.cursor)?
+            }
+
+            Key::Left | Key::Ctrl(b'B') if self.cursor != 0 => self.move_to(out, self.prev())?,
+            Key::Right | Key::Ctrl(b'F') if self.cursor != len => self.move_to(out, self.next())?,
+            Key::Home | Key::Ctrl(b'A') => self.move_to(out, 0)?,
+            Key::End | Key::Ctrl(b'E') => self.move_to(out, len)?,
+
+            Key::Up => self.recall(out, self.browsing.map_or(0, |i| i + 1))?,
+            Key::Down => match self.browsing {
+                Some(0) => {
+                    self.browsing = None;
+                    let line = core::mem::take(&mut self.scratch);
+                    self.replace(out, line)?;
+                }
+                Some(i) => self.recall(out, i - 1)?,
+                None => out.write_char('\x07')?,
+            },
+
+            // Nothing to do, e.g. moving past the end of the line.
+            Key::Backspace
+            | Key::Delete
+            | Key::Left
+            | Key::Right
+            | Key::Ctrl(b'D' | b'B' | b'F') => out.write_char('\x07')?,
+
+            Key::Ctrl(_) => {}
+        }
+
+        Ok(None)
+    }
+
+    /// Take the finished line, and remember it.
+    fn finish(&mut self) -> String {
+        let line = core::mem::take(&mut self.line);
+        self.cursor = 0;
+        self.browsing = None;
+
+        let repeated = self.history.back() == Some(&line);
+        if self.history_len != 0 && !line.trim().is_empty() && !repeated {
+            if self.history.len() == self.history_len {
+                self.history.pop_front();
+            }
+
+            self.history.push_back(line.clone());
+        }
+
+        line
+    }
+
+    /// The offset of the character before the cursor.
+    fn prev(&self) -> usize {
+        self.line[..self.cursor]
+            .char_indices()
+            .next_back()
+            .map_or(0, |(i, _)| i)
+    }
+
+    /// The offset of the character after the cursor.
+    fn next(&self) -> usize {
+        self.line[self.cursor..]
+            .chars()
+            .next()
+            .map_or(self.cursor, |c| self.cursor + c.len_utf8())
+    }
+
+    fn move_to(&mut self, out: &mut (impl Write + ?Sized), pos: usize) -> fmt::Result {
+        if pos < self.cursor {
+            back(out, self.line[pos..self.cursor].chars().count())?;
+        } else if pos > self.cursor {
+            let n = self.line[self.cursor..pos].chars().count();
+            write!(out, "\x1b[{}C", n)?;
+        }
+
+        self.cursor = pos;
+        Ok(())
+    }
+
+    /// Redraw the line from the cursor onwards, leaving the cursor where it is.
+    fn redraw(&mut self, out: &mut (impl Write + ?Sized), clear: bool) -> fmt::Result {
+        let tail = &self.line[self.cursor..];
+        out.write_str(tail)?;
+
+        if clear {
+            out.write_str("\x1b[K")?;
+        }
+
+        back(out, tail.chars().count())
+    }
+
+    fn insert(&mut self, out: &mut (impl Write + ?Sized), s: &str) -> fmt::Result {
+        if self.line.len() + s.len() > LINE_MAX {
+            return out.write_char('\x07');
+        }
+
+        self.line.insert_str(self.cursor, s);
+        self.cursor += s.len();
+
+        out.write_str(s)?;
+        self.redraw(out, false)
+    }
+
+    /// Erase the text from `start` to `end`, where `start` is at or before the cursor.
+    fn erase(&mut self, out: &mut (impl Write + ?Sized), start: usize, end: usize) -> fmt::Result {
+        if start == end {
+            return Ok(());
+        }
+
+        self.move_to(out, start)?;
+        self.line.replace_range(start..end, "");
+        self.redraw(out, true)
+    }
+
+    /// Replace the whole line, leaving the cursor at its end.
+    fn replace(&mut self, out: &mut (impl Write + ?Sized), line: String) -> fmt::Result {
+        self.move_to(out, 0)?;
+        self.line = line;
+        self.redraw(out, true)?;
+        self.move_to(out, self.line.len())
+    }
+
+    /// Show the history entry `idx` lines back.
+    fn recall(&mut self, out: &mut (impl Write + ?Sized), idx: usize) -> fmt::Result {
+        let entry = match self.history.len().checked_sub(idx + 1) {
+            Some(entry) => self.history[entry].clone(),
+            None => return out.write_char('\x07'),
+        };
+
+        if self.browsing.is_none() {
+            self.scratch = self.line.clone();
+        }
+
+        self.browsing = Some(idx);
+        self.replace(out, entry)
+    }
+
+    /// Complete the word before the cursor, if it is the first on the line.
+    fn complete(
+        &mut self,
+        out: &mut (impl Write + ?Sized),
+        complete: &(impl Complete + ?Sized),
+    ) -> fmt::Result {
+        let prefix = &self.line[..self.cursor];
+        if prefix.contains(char::is_whitespace) {
+            return out.write_char('\x07');
+        }
+
+        let mut matches = Vec::new();
+        complete.complete(prefix, &mut |word| matches.push(String::from(word)));
+
+        let typed = prefix.len();
+
+        let (first, rest) = match matches.split_first() {
+            Some(m) => m,
+            None => return out.write_char('\x07'),
+        };
+
+        // Extend the word as far as every match agrees.
+        let common = rest
+            .iter()
+            .fold(first.len(), |len, word| common_prefix(&first[..len], word));
+
+        if rest.is_empty() {
+            let at_word_end = self.line[self.cursor..].starts_with(char::is_whitespace);
+            self.insert(out, &first[typed..])?;
+            if !at_word_end {
+                self.insert(out, " ")?;
+            }
+        } else if common > typed {
+            self.insert(out, &first[typed..common])?;
+        } else {
+            // Ambiguous. List the matches, and start over below them.
+            out.write_char('\n')?;
+            for (i, word) in matches.iter().enumerate() {
+                if i != 0 {
+                    out.write_str("  ")?;
+                }
+
+                out.write_str(word)?;
+            }
+
+            write!(out, "\n{}{}", self.prompt, self.line)?;
+            back(out, self.line[self.cursor..].chars().count())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Move the cursor back by `n` columns.
+fn back(out: &mut (impl Write + ?Sized), n: usize) -> fmt::Result {
+    if n != 0 {
+        write!(out, "\x1b[{}D", n)?;
+    }
+
+    Ok(())
+}
+
+/// The length of the common prefix of two strings, in bytes.
+fn common_prefix(a: &str, b: &str) -> usize {
+    a.char_indices()
+        .zip(b.chars())
+        .find(|((_, x), y)| x != y)
+        .map_or(a.len().min(b.len()), |((i, _), _)| i)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const COMMANDS: &[&str] = &["boot", "bootargs", "dhcp", "help", "reboot"];
+
+    /// Type `input`, returning the finished lines and the output.
+    fn type_keys(editor: &mut Editor, input: &[u8]) -> (Vec<String>, String) {
+        let mut lines = Vec::new();
+        let mut out = String::new();
+
+        for &b in input {
+            if let Some(line) = editor.feed(b, &mut out, COMMANDS) {
+                lines.push(line);
+            }
+        }
+
+        (lines, out)
+    }
+
+    fn line(input: &[u8]) -> String {
+        let (mut lines, _) = type_keys(&mut Editor::new("> ", 8), input);
+        assert_eq!(lines.len(), 1);
+
+        lines.remove(0)
+    }
+
+    #[test]
+    fn test_keys() {
+        let mut keys = KeyDecoder::new();
+        let input = b"a\x1b[A\x1bOD\x1b[3~\x1b[1;5H\x01\r\n\n\xc3\xa9\xe2\x82";
+
+        let decoded: Vec<_> = input.iter().filter_map(|&b| keys.feed(b)).collect();
+        assert_eq!(
+            decoded,
+            [
+                Key::Char('a'),
+                Key::Up,
+                Key::Left,
+                Key::Delete,
+                Key::Home,
+                Key::Ctrl(b'A'),
+                Key::Enter,
+                Key::Enter,
+                Key::Char('é'),
+            ]
+        );
+
+        // A truncated character is dropped.
+        assert_eq!(keys.feed(b'x'), Some(Key::Char('x')));
+    }
+
+    #[test]
+    fn test_editing() {
+        assert_eq!(line(b"r64 8000\r"), "r64 8000");
+
+        // Backspace and delete erase whole characters.
+        assert_eq!(line("caf\u{e9}\x7f\x08e\r".as_bytes()), "cae");
+        assert_eq!(line("\u{1F346}x\x1b[D\x1b[D\x1b[3~\r".as_bytes()), "x");
+
+        // Insert in the middle of the line.
+        assert_eq!(line(b"w 8000\x1b[H\x1b[C64\x1b[F 1\r"), "w64 8000 1");
+        assert_eq!(line(b"acd\x1b[D\x1b[Db\r"), "abcd");
+
+        // Ctrl-A/E/U/K/W.
+        assert_eq!(line(b"ab cd ef  \x17\x17xy\r"), "ab xy");
+        assert_eq!(line(b"abc def\x1b[D\x1b[D\x15\x05!\r"), "ef!");
+        assert_eq!(line(b"abc def\x01\x1b[C\x0b\r"), "a");
+
+        // Ctrl-C abandons the line.
+        assert_eq!(line(b"abc\x03"), "");
+    }
+
+    #[test]
+    fn test_output() {
+        let mut editor = Editor::new("> ", 8);
+        let mut out = String::new();
+        editor.start(&mut out);
+        assert_eq!(out, "> ");
+
+        // Inserting redraws the rest of the line; erasing clears what's left behind.
+        let (_, out) = type_keys(&mut editor, b"ac\x1b[Db\x7f");
+        assert_eq!(out, "ac\x1b[1Dbc\x1b[1D\x1b[1Dc\x1b[K\x1b[1D");
+
+        // Moving past either end of the line rings the bell.
+        let (_, out) = type_keys(&mut editor, b"\x01\x1b[D");
+        assert_eq!(out, "\x1b[1D\x07");
+    }
+
+    #[test]
+    fn test_history() {
+        let mut editor = Editor::new("> ", 2);
+
+        let (lines, _) = type_keys(&mut editor, b"one\rtwo\rtwo\r\rthree\r");
+        assert_eq!(lines, ["one", "two", "two", "", "three"]);
+        assert_eq!(editor.history().collect::<Vec<_>>(), ["two", "three"]);
+
+        // Up past the oldest entry stays there. Down past the newest returns to the edited line.
+        let (lines, _) = type_keys(&mut editor, b"x\x1b[A\x1b[A\x1b[A\r");
+        assert_eq!(lines, ["two"]);
+
+        let (lines, _) = type_keys(&mut editor, b"x\x1b[A\x1b[A\x1b[B\x1b[B\x1b[By\r");
+        assert_eq!(lines, ["xy"]);
+
+        // A recalled line can be edited.
+        let (lines, _) = type_keys(&mut editor, b"\x1b[A\x08o\r");
+        assert_eq!(lines, ["xo"]);
+    }
+
+    #[test]
+    fn test_completion() {
+        // A unique match is completed.
+        assert_eq!(line(b"dh\t\r"), "dhcp ");
+        assert_eq!(line(b"re\t8\r"), "reboot 8");
+
+        // Ambiguous matches are completed as far as they agree, then listed.
+        let mut editor = Editor::new("> ", 8);
+        let (_, out) = type_keys(&mut editor, b"bo");
+        assert_eq!(out, "bo");
+
+        let (_, out) = type_keys(&mut editor, b"\t");
+        assert_eq!(out, "ot");
+
+        let (_, out) = type_keys(&mut editor, b"\t");
+        assert_eq!(out, "\nboot  bootargs\n> boot");
+
+        // Only the command name is completed.
+        let (lines, out) = type_keys(&mut editor, b" he\t\r");
+        assert_eq!(out, " he\x07\n");
+        assert_eq!(lines, ["boot he"]);
+
+        let (_, out) = type_keys(&mut editor, b"x\t");
+        assert_eq!(out, "x\x07");
+    }
+}
@@ -0,0 +1,246 @@
+//! Arithmetic expressions, for command arguments.
+//!
+//! Expressions combine numbers and names with C's binary operators, in C's order of precedence:
+//! `* /`, then `+ -`, then `<< >>`, then `&`, then `|`. Unary `-` and `~`, and parentheses, are
+//! also supported. Arithmetic is on 64-bit unsigned values, and wraps around.
+//!
+//! * Numbers are hexadecimal, with or without a `0x` prefix. Decimal numbers take a `0n` prefix.
+//! * `$name` is a variable, such as a register or an environment variable.
+//! * Any other name is a symbol. If there is no such symbol, a name made up of hexadecimal
+//!   digits (e.g. `beef`) is taken as a number.
+//!
+//! For example, `$lr-0x40`, `(start+0n4096)&~fff` or `$r3<<2|1`.
+
+/// Looks up the values of the names in an expression.
+pub trait Resolve {
+    /// The value of the variable `$name`.
+    fn variable(&self, name: &str) -> Option<u64>;
+
+    /// The address of the symbol `name`.
+    fn symbol(&self, name: &str) -> Option<u64>;
+}
+
+/// Nothing has a name.
+impl Resolve for () {
+    fn variable(&self, _name: &str) -> Option<u64> {
+        None
+    }
+
+    fn symbol(&self, _name: &str) -> Option<u64> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The expression is malformed.
+    Syntax,
+    /// A name has no value.
+    Unknown,
+    DivideByZero,
+}
+
+/// The binary operators, from the lowest precedence to the highest.
+const OPERATORS: [&[&str]; 5] = [&["|"], &["&"], &["<<", ">>"], &["+", "-"], &["*", "/"]];
+
+/// Evaluate an expression, looking up names with `names`.
+pub fn eval(expr: &str, names: &(impl Resolve + ?Sized)) -> Result<u64, Error> {
+    let mut parser = Parser { rest: expr, names };
+
+    let value = parser.binary(0)?;
+    if !parser.rest.trim_start().is_empty() {
+        return Err(Error::Syntax);
+    }
+
+    Ok(value)
+}
+
+struct Parser<'a, R: ?Sized> {
+    rest: &'a str,
+    names: &'a R,
+}
+
+impl<'a, R: Resolve + ?Sized> Parser<'a, R> {
+    /// Take `token` if it comes next.
+    fn take(&mut self, token: &str) -> bool {
+        self.rest = self.rest.trim_start();
+
+        match self.rest.strip_prefix(token) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Take a name or number, made up of letters, digits, `_`, `.` and `:`.
+    fn word(&mut self) -> &'a str {
+        let end = self
+            .rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | ':')))
+            .unwrap_or(self.rest.len());
+
+        let (word, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        word
+    }
+
+    /// Parse the operators from precedence `level` upwards.
+    fn binary(&mut self, level: usize) -> Result<u64, Error> {
+        let ops = match OPERATORS.get(level) {
+            Some(ops) => ops,
+            None => return self.unary(),
+        };
+
+        let mut value = self.binary(level + 1)?;
+
+        while let Some(&op) = ops.iter().find(|op| self.take(op)) {
+            let rhs = self.binary(level + 1)?;
+
+            value = match op {
+                "|" => value | rhs,
+                "&" => value & rhs,
+                "<<" if rhs < 64 => value << rhs,
+                ">>" if rhs < 64 => value >> rhs,
+                "<<" | ">>" => 0,
+                "+" => value.wrapping_add(rhs),
+                "-" => value.wrapping_sub(rhs),
+                "*" => value.wrapping_mul(rhs),
+                _ => value.checked_div(rhs).ok_or(Error::DivideByZero)?,
+            };
+        }
+
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<u64, Error> {
+        if self.take("-") {
+            return Ok(self.unary()?.wrapping_neg());
+        }
+
+        if self.take("~") {
+            return Ok(!self.unary()?);
+        }
+
+        if self.take("(") {
+            let value = self.binary(0)?;
+
+            if !self.take(")") {
+                return Err(Error::Syntax);
+            }
+
+            return Ok(value);
+        }
+
+        if self.take("$") {
+            return match self.word() {
+                "" => Err(Error::Syntax),
+                name => self.names.variable(name).ok_or(Error::Unknown),
+            };
+        }
+
+        let word = self.word();
+        match word.chars().next() {
+            None => Err(Error::Syntax),
+            Some('0'..='9') => number(word),
+            Some(_) => match self.names.symbol(word) {
+                Some(value) => Ok(value),
+                None => u64::from_str_radix(word, 16).map_err(|_| Error::Unknown),
+            },
+        }
+    }
+}
+
+fn number(word: &str) -> Result<u64, Error> {
+    let (digits, radix) = if let Some(digits) = word.strip_prefix("0n") {
+        (digits, 10)
+    } else {
+        let digits = word.strip_prefix("0x").or_else(|| word.strip_prefix("0X"));
+        (digits.unwrap_or(word), 16)
+    };
+
+    u64::from_str_radix(digits, radix).map_err(|_| Error::Syntax)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Names;
+
+    impl Resolve for Names {
+        fn variable(&self, name: &str) -> Option<u64> {
+            match name {
+                "pc" => Some(0x8000_0000_1C00_1000),
+                "lr" => Some(0x8000_0000_1C00_2040),
+                "r3" => Some(3),
+                _ => None,
+            }
+        }
+
+        fn symbol(&self, name: &str) -> Option<u64> {
+            match name {
+                "start" => Some(0x1000),
+                "face" => Some(0x2000),
+                "stage1::main" => Some(0x3000),
+                _ => None,
+            }
+        }
+    }
+
+    fn eval(expr: &str) -> Result<u64, Error> {
+        super::eval(expr, &Names)
+    }
+
+    #[test]
+    fn test_numbers() {
+        assert_eq!(eval("10"), Ok(0x10));
+        assert_eq!(eval("0x10"), Ok(0x10));
+        assert_eq!(eval("0n10"), Ok(10));
+        assert_eq!(eval("800000001C000000"), Ok(0x8000_0000_1C00_0000));
+        assert_eq!(eval("FFFFFFFFFFFFFFFF"), Ok(u64::MAX));
+        assert_eq!(eval("10000000000000000"), Err(Error::Syntax));
+        assert_eq!(eval("0n1A"), Err(Error::Syntax));
+    }
+
+    #[test]
+    fn test_operators() {
+        assert_eq!(eval("1+2*3"), Ok(7));
+        assert_eq!(eval("(1+2)*3"), Ok(9));
+        assert_eq!(eval("10-1-1"), Ok(0xE));
+        assert_eq!(eval("100/2/2"), Ok(0x40));
+        assert_eq!(eval("1<<4+1"), Ok(0x20));
+        assert_eq!(eval("f0|f&3"), Ok(0xF3));
+        assert_eq!(eval("ff>>4<<8"), Ok(0xF00));
+        assert_eq!(eval("1<<0n64"), Ok(0));
+        assert_eq!(eval("0-1"), Ok(u64::MAX));
+        assert_eq!(eval("-1"), Ok(u64::MAX));
+        assert_eq!(eval("~0xF & FF"), Ok(0xF0));
+        assert_eq!(eval(" ( 2 + 3 ) * - 1 "), Ok(5u64.wrapping_neg()));
+        assert_eq!(eval("1/0"), Err(Error::DivideByZero));
+    }
+
+    #[test]
+    fn test_names() {
+        assert_eq!(eval("$lr-0x40"), Ok(0x8000_0000_1C00_2000));
+        assert_eq!(eval("$r3<<2|1"), Ok(0xD));
+        assert_eq!(eval("start+0n4096"), Ok(0x2000));
+        assert_eq!(eval("stage1::main+4"), Ok(0x3004));
+
+        // Symbols win over hex numbers.
+        assert_eq!(eval("face"), Ok(0x2000));
+        assert_eq!(eval("beef"), Ok(0xBEEF));
+
+        assert_eq!(eval("$sp"), Err(Error::Unknown));
+        assert_eq!(eval("main"), Err(Error::Unknown));
+        assert_eq!(super::eval("$pc", &()), Err(Error::Unknown));
+    }
+
+    #[test]
+    fn test_syntax() {
+        for expr in ["", "1+", "(1", "1)", "$", "1 2", "*1", "1+*2", "0xg"] {
+            assert_eq!(eval(expr), Err(Error::Syntax), "{}", expr);
+        }
+    }
+}
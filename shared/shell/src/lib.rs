@@ -0,0 +1,370 @@
+//! A `no_std` command registry for interactive terminals.
+//!
+//! Each command implements [Command], which carries its name, usage and help text along with the
+//! handler that runs it. A [Registry] dispatches command lines to the registered commands, and
+//! provides `help` and the reporting of bad arguments, so that every command behaves the same.
+//!
+//! Commands are generic over the terminal `T` they run on. The terminal is passed to their
+//! handler, and is where their output goes.
+//!
+//! Numeric arguments are [expressions](expr), which may refer to names the caller provides
+//! through [Resolve].
+//!
+//! Command lines are typed into an [Editor], which completes command names from the registry.
+#![no_std]
+
+extern crate alloc;
+
+pub mod args;
+pub mod editor;
+pub mod expr;
+
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+
+pub use args::Args;
+pub use editor::{Complete, Editor};
+pub use expr::Resolve;
+
+/// Why a command couldn't run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The arguments don't match the command's usage (e.g. one is missing).
+    Usage,
+    /// An argument is malformed. This names the argument, e.g. "address".
+    Invalid(&'static str),
+}
+
+/// A terminal command.
+pub trait Command<T: ?Sized> {
+    /// The name the command is invoked by.
+    fn name(&self) -> &'static str;
+
+    /// The arguments the command takes, e.g. `<address> [len]`.
+    fn usage(&self) -> &'static str {
+        ""
+    }
+
+    /// A one-line description of the command. Commands without one are left out of `help`.
+    fn help(&self) -> &'static str;
+
+    /// Run the command.
+    fn run(&self, term: &mut T, args: &mut Args) -> Result<(), Error>;
+}
+
+/// A command implemented by a plain function. This is convenient for declaring commands as
+/// statics.
+pub struct FnCommand<T: ?Sized> {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub help: &'static str,
+    pub handler: fn(&mut T, &mut Args) -> Result<(), Error>,
+}
+
+impl<T: ?Sized> Command<T> for FnCommand<T> {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn usage(&self) -> &'static str {
+        self.usage
+    }
+
+    fn help(&self) -> &'static str {
+        self.help
+    }
+
+    fn run(&self, term: &mut T, args: &mut Args) -> Result<(), Error> {
+        (self.handler)(term, args)
+    }
+}
+
+/// A reference to a registered command.
+pub type CommandRef<T> = &'static (dyn Command<T> + Sync);
+
+/// A set of commands, sorted by name.
+pub struct Registry<T: ?Sized + 'static> {
+    commands: Vec<CommandRef<T>>,
+}
+
+impl<T: ?Sized + 'static> Clone for Registry<T> {
+    fn clone(&self) -> Self {
+        Self {
+            commands: self.commands.clone(),
+        }
+    }
+}
+
+impl<T: ?Sized + 'static> Default for Registry<T> {
+    fn default() -> Self {
+        Self::EMPTY
+    }
+}
+
+impl<T: ?Sized + 'static> Registry<T> {
+    /// An empty registry, for initializing statics.
+    pub const EMPTY: Self = Self {
+        commands: Vec::new(),
+    };
+
+    /// Register a command. This replaces any command already registered with the same name.
+    pub fn register(&mut self, command: CommandRef<T>) {
+        match self
+            .commands
+            .binary_search_by(|c| c.name().cmp(command.name()))
+        {
+            Ok(idx) => self.commands[idx] = command,
+            Err(idx) => self.commands.insert(idx, command),
+        }
+    }
+
+    /// Register several commands at once.
+    pub fn register_all(&mut self, commands: &[CommandRef<T>]) {
+        for &command in commands {
+            self.register(command);
+        }
+    }
+
+    /// Look up a command by name.
+    pub fn find(&self, name: &str) -> Option<CommandRef<T>> {
+        self.commands
+            .binary_search_by(|c| c.name().cmp(name))
+            .ok()
+            .map(|idx| self.commands[idx])
+    }
+
+    /// The registered commands, sorted by name.
+    pub fn commands(&self) -> &[CommandRef<T>] {
+        &self.commands
+    }
+}
+
+/// Completes the names of the commands listed by `help`.
+impl<T: ?Sized + 'static> Complete for Registry<T> {
+    fn complete(&self, prefix: &str, f: &mut dyn FnMut(&str)) {
+        let builtin = match self.find("help") {
+            Some(_) => None,
+            None => Some("help"),
+        };
+        let listed = self
+            .commands
+            .iter()
+            .filter(|c| !c.help().is_empty())
+            .map(|c| c.name());
+
+        for name in listed.chain(builtin).filter(|n| n.starts_with(prefix)) {
+            f(name);
+        }
+    }
+}
+
+impl<T: Write + ?Sized + 'static> Registry<T> {
+    /// Run a command line, reporting any errors to the terminal.
+    ///
+    /// `help` is built in, unless a command of that name has been registered. Expressions in the
+    /// arguments are evaluated with `names`.
+    pub fn run(&self, term: &mut T, line: &str, names: &dyn Resolve) {
+        let mut args = Args::with_names(line, names);
+        let name = match args.next() {
+            Some(name) => name,
+            None => return,
+        };
+
+        let command = match self.find(name) {
+            Some(command) => command,
+            None if name == "help" => {
+                let _ = self.help(term, args.next());
+                return;
+            }
+            None => {
+                let _ = writeln!(
+                    term,
+                    "Unknown command \"{}\"! Type \"help\" for a list of commands.",
+                    name
+                );
+                return;
+            }
+        };
+
+        let _ = match command.run(term, &mut args) {
+            Ok(()) => Ok(()),
+            Err(Error::Usage) => Self::usage(term, command),
+            Err(Error::Invalid(what)) => writeln!(term, "invalid {}", what),
+        };
+    }
+
+    fn usage(term: &mut T, command: CommandRef<T>) -> fmt::Result {
+        write!(term, "usage: {}", command.name())?;
+
+        if !command.usage().is_empty() {
+            write!(term, " {}", command.usage())?;
+        }
+
+        writeln!(term)
+    }
+
+    /// Describe one command, or list them all.
+    fn help(&self, term: &mut T, name: Option<&str>) -> fmt::Result {
+        if let Some(name) = name {
+            return match self.find(name) {
+                Some(command) => {
+                    Self::usage(term, command)?;
+                    writeln!(term, "{}", command.help())
+                }
+                None => writeln!(term, "Unknown command \"{}\"!", name),
+            };
+        }
+
+        let listed = || self.commands.iter().filter(|c| !c.help().is_empty());
+        let width = listed().map(|c| c.name().len()).max().unwrap_or(0);
+
+        writeln!(term, "Commands:")?;
+        for command in listed() {
+            writeln!(
+                term,
+                "  {:width$}  {}",
+                command.name(),
+                command.help(),
+                width = width
+            )?;
+        }
+
+        writeln!(term, "Type \"help <command>\" for its usage.")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::string::String;
+
+    /// A terminal which records its output, and a value for commands to work on.
+    #[derive(Default)]
+    struct Term {
+        out: String,
+        value: u64,
+    }
+
+    impl Write for Term {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            self.out.write_str(s)
+        }
+    }
+
+    static SET: FnCommand<Term> = FnCommand {
+        name: "set",
+        usage: "<value>",
+        help: "Set the value",
+        handler: |term, args| {
+            term.value = args.num("value")?;
+            Ok(())
+        },
+    };
+
+    static SHOW: FnCommand<Term> = FnCommand {
+        name: "show",
+        usage: "",
+        help: "Show the value",
+        handler: |term, _| {
+            let value = term.value;
+            writeln!(term, "{:X}", value).unwrap();
+            Ok(())
+        },
+    };
+
+    static SECRET: FnCommand<Term> = FnCommand {
+        name: "secret",
+        usage: "",
+        help: "",
+        handler: |_, _| Ok(()),
+    };
+
+    fn registry() -> Registry<Term> {
+        let mut registry = Registry::default();
+        registry.register_all(&[&SHOW, &SET, &SECRET]);
+        registry
+    }
+
+    fn run(registry: &Registry<Term>, term: &mut Term, line: &str) -> String {
+        term.out.clear();
+        registry.run(term, line, &());
+        core::mem::take(&mut term.out)
+    }
+
+    #[test]
+    fn test_dispatch() {
+        let registry = registry();
+        let mut term = Term::default();
+
+        assert_eq!(run(&registry, &mut term, "  set   1f0 "), "");
+        assert_eq!(run(&registry, &mut term, "show"), "1F0\n");
+        assert_eq!(run(&registry, &mut term, ""), "");
+        assert_eq!(
+            run(&registry, &mut term, "get"),
+            "Unknown command \"get\"! Type \"help\" for a list of commands.\n"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let registry = registry();
+        let mut term = Term::default();
+
+        assert_eq!(run(&registry, &mut term, "set"), "usage: set <value>\n");
+        assert_eq!(run(&registry, &mut term, "set xyz"), "invalid value\n");
+        assert_eq!(term.value, 0);
+    }
+
+    #[test]
+    fn test_help() {
+        let registry = registry();
+        let mut term = Term::default();
+
+        assert_eq!(
+            run(&registry, &mut term, "help"),
+            "Commands:\n  set   Set the value\n  show  Show the value\n\
+             Type \"help <command>\" for its usage.\n"
+        );
+
+        assert_eq!(
+            run(&registry, &mut term, "help set"),
+            "usage: set <value>\nSet the value\n"
+        );
+    }
+
+    #[test]
+    fn test_replace() {
+        static SHOW_DEC: FnCommand<Term> = FnCommand {
+            name: "show",
+            usage: "",
+            help: "Show the value in decimal",
+            handler: |term, _| {
+                let value = term.value;
+                writeln!(term, "{}", value).unwrap();
+                Ok(())
+            },
+        };
+
+        let mut registry = registry();
+        registry.register(&SHOW_DEC);
+
+        let mut term = Term {
+            value: 0x10,
+            ..Default::default()
+        };
+
+        assert_eq!(registry.commands().len(), 3);
+        assert_eq!(run(&registry, &mut term, "show"), "16\n");
+    }
+
+    #[test]
+    fn test_complete() {
+        let registry = registry();
+        let mut names = Vec::new();
+
+        // Hidden commands aren't offered, but the built-in help is.
+        registry.complete("s", &mut |name| names.push(String::from(name)));
+        registry.complete("h", &mut |name| names.push(String::from(name)));
+        assert_eq!(names, ["set", "show", "help"]);
+    }
+}
@@ -0,0 +1,322 @@
+//! Compact symbol table embedded in an image, for address-to-symbol lookup.
+//!
+//! A table is built on the host from an ELF's function symbols (see [build]) and patched
+//! into space the image reserves for it (see [placeholder]). The image can then resolve
+//! addresses to `symbol+offset` without any allocation.
+//!
+//! All fields are big-endian:
+//!
+//! | Offset | Size     | Field                                              |
+//! |--------|----------|----------------------------------------------------|
+//! | 0      | 4        | Magic, `SYMT`                                      |
+//! | 4      | 2        | Version ([VERSION])                                |
+//! | 6      | 2        | Reserved, zero                                     |
+//! | 8      | 4        | Number of symbols                                  |
+//! | 12     | 4        | Size of the string table                           |
+//! | 16     | 8        | Base address                                       |
+//! | 24     | 12 * n   | Symbols sorted by address: offset from the base,   |
+//! |        |          | size, and offset of the name in the string table   |
+//! | ...    | ...      | String table of NUL-terminated names               |
+#![no_std]
+
+extern crate alloc;
+
+mod write;
+
+pub use write::build;
+
+use core::fmt;
+
+/// The magic number at the beginning of every table.
+pub const MAGIC: [u8; 4] = *b"SYMT";
+
+/// The table version we read and write.
+pub const VERSION: u16 = 1;
+
+const HEADER_SIZE: usize = 24;
+const ENTRY_SIZE: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The buffer is smaller than the table's header claims.
+    Truncated,
+    /// The table does not start with [MAGIC].
+    BadMagic,
+    /// The table version is unknown.
+    BadVersion(u16),
+    /// A symbol is too far from the lowest one to be stored.
+    OutOfRange(u64),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Truncated => write!(f, "table truncated"),
+            Error::BadMagic => write!(f, "bad table magic"),
+            Error::BadVersion(v) => write!(f, "unknown table version {}", v),
+            Error::OutOfRange(a) => write!(f, "symbol at {:016X} out of range", a),
+        }
+    }
+}
+
+/// A function symbol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Symbol<'a> {
+    pub name: &'a str,
+    pub addr: u64,
+    pub size: u64,
+}
+
+/// An address resolved to an offset into a symbol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location<'a> {
+    pub symbol: Symbol<'a>,
+    pub offset: u64,
+}
+
+impl fmt::Display for Location<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.offset == 0 {
+            write!(f, "{}", self.symbol.name)
+        } else {
+            write!(f, "{}+{:#x}", self.symbol.name, self.offset)
+        }
+    }
+}
+
+fn read_u16(data: &[u8], off: usize) -> u16 {
+    u16::from_be_bytes([data[off], data[off + 1]])
+}
+
+fn read_u32(data: &[u8], off: usize) -> u32 {
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(&data[off..off + 4]);
+    u32::from_be_bytes(buf)
+}
+
+fn read_u64(data: &[u8], off: usize) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&data[off..off + 8]);
+    u64::from_be_bytes(buf)
+}
+
+/// Create the contents of the space reserved for a table in an image: an empty table followed
+/// by zeroes.
+///
+/// N.B: The header also keeps the space out of `.bss`, so it can be patched in the file.
+pub const fn placeholder<const N: usize>() -> [u8; N] {
+    assert!(N >= HEADER_SIZE, "reserved space is too small");
+
+    let mut buf = [0u8; N];
+    let mut i = 0;
+    while i < MAGIC.len() {
+        buf[i] = MAGIC[i];
+        i += 1;
+    }
+
+    let version = VERSION.to_be_bytes();
+    buf[4] = version[0];
+    buf[5] = version[1];
+    buf
+}
+
+/// A parsed symbol table.
+#[derive(Debug, Clone, Copy)]
+pub struct Table<'a> {
+    base: u64,
+    entries: &'a [u8],
+    strings: &'a [u8],
+}
+
+impl<'a> Table<'a> {
+    /// Parse a table at the start of `data`. Anything after the table is ignored.
+    pub fn parse(data: &'a [u8]) -> Result<Self, Error> {
+        if data.len() < HEADER_SIZE {
+            return Err(Error::Truncated);
+        }
+
+        if data[..4] != MAGIC {
+            return Err(Error::BadMagic);
+        }
+
+        let version = read_u16(data, 4);
+        if version != VERSION {
+            return Err(Error::BadVersion(version));
+        }
+
+        let count = read_u32(data, 8) as usize;
+        let strings_len = read_u32(data, 12) as usize;
+
+        let entries_end = count
+            .checked_mul(ENTRY_SIZE)
+            .and_then(|n| n.checked_add(HEADER_SIZE))
+            .ok_or(Error::Truncated)?;
+        let strings_end = entries_end
+            .checked_add(strings_len)
+            .ok_or(Error::Truncated)?;
+        if strings_end > data.len() {
+            return Err(Error::Truncated);
+        }
+
+        Ok(Self {
+            base: read_u64(data, 16),
+            entries: &data[HEADER_SIZE..entries_end],
+            strings: &data[entries_end..strings_end],
+        })
+    }
+
+    /// The number of symbols in the table.
+    pub fn len(&self) -> usize {
+        self.entries.len() / ENTRY_SIZE
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The symbol at index `idx`, in address order.
+    pub fn get(&self, idx: usize) -> Option<Symbol<'a>> {
+        if idx >= self.len() {
+            return None;
+        }
+
+        let off = idx * ENTRY_SIZE;
+        Some(Symbol {
+            name: self.name(read_u32(self.entries, off + 8) as usize),
+            addr: self.base.wrapping_add(read_u32(self.entries, off) as u64),
+            size: read_u32(self.entries, off + 4) as u64,
+        })
+    }
+
+    /// Iterate over the symbols in address order.
+    pub fn iter(&self) -> impl Iterator<Item = Symbol<'a>> + '_ {
+        (0..self.len()).filter_map(move |idx| self.get(idx))
+    }
+
+    /// Resolve `addr` to the symbol containing it.
+    pub fn lookup(&self, addr: u64) -> Option<Location<'a>> {
+        let offset = addr.checked_sub(self.base)?;
+        if offset > u32::MAX as u64 {
+            return None;
+        }
+
+        // Find the last symbol starting at or below the address.
+        let (mut lo, mut hi) = (0, self.len());
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if read_u32(self.entries, mid * ENTRY_SIZE) as u64 <= offset {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+
+        let symbol = self.get(lo.checked_sub(1)?)?;
+        let offset = addr.wrapping_sub(symbol.addr);
+        if offset != 0 && offset >= symbol.size {
+            return None;
+        }
+
+        Some(Location { symbol, offset })
+    }
+
+    /// Find the address of the symbol called `name`.
+    pub fn find(&self, name: &str) -> Option<u64> {
+        self.iter().find(|s| s.name == name).map(|s| s.addr)
+    }
+
+    fn name(&self, off: usize) -> &'a str {
+        let bytes = self.strings.get(off..).unwrap_or(&[]);
+        let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+
+        core::str::from_utf8(&bytes[..len]).unwrap_or("?")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SYMBOLS: [Symbol; 4] = [
+        Symbol {
+            name: "stage1::main",
+            addr: 0x8000_0000_1C00_1000,
+            size: 0x200,
+        },
+        Symbol {
+            name: "_start",
+            addr: 0x8000_0000_1C00_0000,
+            size: 0,
+        },
+        Symbol {
+            name: "memcpy",
+            addr: 0x8000_0000_1C00_2000,
+            size: 0x40,
+        },
+        Symbol {
+            name: "__start_rust",
+            addr: 0x8000_0000_1C00_2000,
+            size: 0x40,
+        },
+    ];
+
+    #[test]
+    fn test_lookup() {
+        let data = build(&SYMBOLS).unwrap();
+        let table = Table::parse(&data).unwrap();
+
+        // Sorted, with the duplicate dropped.
+        assert_eq!(table.len(), 3);
+        assert_eq!(table.get(0).unwrap().name, "_start");
+
+        let loc = table.lookup(0x8000_0000_1C00_0010).unwrap();
+        assert_eq!(loc.symbol.name, "_start");
+        assert_eq!(loc.symbol.size, 0x1000);
+        assert_eq!(loc.offset, 0x10);
+
+        let loc = table.lookup(0x8000_0000_1C00_1040).unwrap();
+        assert_eq!(alloc::format!("{}", loc), "stage1::main+0x40");
+
+        let loc = table.lookup(0x8000_0000_1C00_2000).unwrap();
+        assert_eq!(alloc::format!("{}", loc), "memcpy");
+
+        // Between symbols, below the first, and past the last.
+        assert_eq!(table.lookup(0x8000_0000_1C00_1200), None);
+        assert_eq!(table.lookup(0x8000_0000_1BFF_FFFC), None);
+        assert_eq!(table.lookup(0x8000_0000_1C00_2040), None);
+        assert_eq!(table.lookup(0), None);
+
+        assert_eq!(table.find("memcpy"), Some(0x8000_0000_1C00_2000));
+        assert_eq!(table.find("memset"), None);
+    }
+
+    #[test]
+    fn test_placeholder() {
+        let data: [u8; 64] = placeholder();
+        let table = Table::parse(&data).unwrap();
+
+        assert!(table.is_empty());
+        assert_eq!(table.lookup(0x8000_0000_1C00_0000), None);
+
+        // A table that doesn't fit its buffer.
+        let mut data = build(&SYMBOLS).unwrap();
+        data.pop();
+        assert_eq!(Table::parse(&data).unwrap_err(), Error::Truncated);
+
+        assert_eq!(Table::parse(&[0u8; 64]).unwrap_err(), Error::BadMagic);
+    }
+
+    #[test]
+    fn test_range() {
+        let far = [
+            SYMBOLS[0],
+            Symbol {
+                name: "far",
+                addr: 0x8000_0002_1C00_0000,
+                size: 4,
+            },
+        ];
+
+        assert_eq!(build(&far), Err(Error::OutOfRange(0x8000_0002_1C00_0000)));
+    }
+}
@@ -0,0 +1,50 @@
+use alloc::vec::Vec;
+
+use crate::{Error, Symbol, ENTRY_SIZE, HEADER_SIZE, MAGIC, VERSION};
+
+/// Build a table from a list of symbols in any order.
+///
+/// If several symbols share an address, the first one listed is kept. Symbols without a size
+/// are assumed to extend up to the next symbol.
+pub fn build(symbols: &[Symbol]) -> Result<Vec<u8>, Error> {
+    let mut sorted: Vec<Symbol> = symbols.to_vec();
+    // N.B: The sort is stable, so the first of several symbols at an address stays first.
+    sorted.sort_by_key(|s| s.addr);
+    sorted.dedup_by_key(|s| s.addr);
+
+    let base = sorted.first().map(|s| s.addr).unwrap_or(0);
+
+    let mut entries = Vec::with_capacity(sorted.len() * ENTRY_SIZE);
+    let mut strings = Vec::new();
+
+    for (idx, sym) in sorted.iter().enumerate() {
+        let offset = sym.addr - base;
+        if offset > u32::MAX as u64 {
+            return Err(Error::OutOfRange(sym.addr));
+        }
+
+        let size = match (sym.size, sorted.get(idx + 1)) {
+            (0, Some(next)) => next.addr - sym.addr,
+            (size, _) => size,
+        };
+
+        entries.extend_from_slice(&(offset as u32).to_be_bytes());
+        entries.extend_from_slice(&(size.min(u32::MAX as u64) as u32).to_be_bytes());
+        entries.extend_from_slice(&(strings.len() as u32).to_be_bytes());
+
+        strings.extend_from_slice(sym.name.as_bytes());
+        strings.push(0);
+    }
+
+    let mut data = Vec::with_capacity(HEADER_SIZE + entries.len() + strings.len());
+    data.extend_from_slice(&MAGIC);
+    data.extend_from_slice(&VERSION.to_be_bytes());
+    data.extend_from_slice(&0u16.to_be_bytes());
+    data.extend_from_slice(&(sorted.len() as u32).to_be_bytes());
+    data.extend_from_slice(&(strings.len() as u32).to_be_bytes());
+    data.extend_from_slice(&base.to_be_bytes());
+    data.extend_from_slice(&entries);
+    data.extend_from_slice(&strings);
+
+    Ok(data)
+}
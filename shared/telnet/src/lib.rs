@@ -0,0 +1,327 @@
+//! A minimal `no_std` telnet (RFC 854) server codec.
+//!
+//! [Decoder] strips commands and option negotiation out of the byte stream received from a
+//! client, and produces the replies the server should send back. [Encoder] prepares terminal
+//! output for the wire.
+//!
+//! The server offers to echo input and to suppress go-ahead, and refuses the client's line mode
+//! (RFC 1184). Together, these make `telnet` clients send each character as it is typed and leave
+//! the echo to the server, as a serial terminal would. Raw clients such as `nc` ignore the
+//! negotiation and still work, a line at a time.
+#![no_std]
+
+/// Interpret as command
+pub const IAC: u8 = 255;
+pub const DONT: u8 = 254;
+pub const DO: u8 = 253;
+pub const WONT: u8 = 252;
+pub const WILL: u8 = 251;
+/// Subnegotiation begin
+pub const SB: u8 = 250;
+/// Subnegotiation end
+pub const SE: u8 = 240;
+
+/// Telnet options.
+pub mod option {
+    pub const ECHO: u8 = 1;
+    pub const SUPPRESS_GO_AHEAD: u8 = 3;
+    pub const LINEMODE: u8 = 34;
+}
+
+/// The negotiation the server sends when a client connects.
+pub const GREETING: [u8; 9] = [
+    IAC,
+    WILL,
+    option::ECHO,
+    IAC,
+    WILL,
+    option::SUPPRESS_GO_AHEAD,
+    IAC,
+    DONT,
+    option::LINEMODE,
+];
+
+/// The result of decoding one byte from the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// Nothing to do (e.g. the byte is part of a command).
+    None,
+    /// A byte of terminal input.
+    Data(u8),
+    /// A reply to send back to the client.
+    Reply([u8; 3]),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Data,
+    /// After a carriage return, which may be followed by a line feed or NUL.
+    Cr,
+    /// After `IAC`.
+    Command,
+    /// After `IAC` and a negotiation verb (`WILL`, `WONT`, `DO` or `DONT`).
+    Option(u8),
+    /// Within a subnegotiation.
+    Sub,
+    /// After `IAC` within a subnegotiation.
+    SubIac,
+}
+
+/// Decodes the byte stream received from a client.
+///
+/// Line endings (CR LF, CR NUL, or a bare LF from raw clients) are all decoded as a single
+/// carriage return, which is what a terminal sends for the Enter key.
+#[derive(Debug, Clone)]
+pub struct Decoder {
+    state: State,
+    /// Set once we have agreed to the client suppressing go-ahead.
+    client_sga: bool,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder {
+    pub const fn new() -> Self {
+        Self {
+            state: State::Data,
+            client_sga: false,
+        }
+    }
+
+    /// Decode the next byte received from the client.
+    pub fn feed(&mut self, byte: u8) -> Event {
+        match self.state {
+            State::Data | State::Cr => {
+                let after_cr = self.state == State::Cr;
+                self.state = State::Data;
+
+                match byte {
+                    IAC => {
+                        self.state = State::Command;
+                        Event::None
+                    }
+                    b'\r' => {
+                        self.state = State::Cr;
+                        Event::Data(b'\r')
+                    }
+                    b'\n' | 0 if after_cr => Event::None,
+                    b'\n' => Event::Data(b'\r'),
+                    _ => Event::Data(byte),
+                }
+            }
+
+            State::Command => {
+                self.state = State::Data;
+
+                match byte {
+                    // An escaped 0xFF data byte.
+                    IAC => Event::Data(IAC),
+                    WILL | WONT | DO | DONT => {
+                        self.state = State::Option(byte);
+                        Event::None
+                    }
+                    SB => {
+                        self.state = State::Sub;
+                        Event::None
+                    }
+                    // Everything else (NOP, GA, AYT...) is ignored.
+                    _ => Event::None,
+                }
+            }
+
+            State::Option(verb) => {
+                self.state = State::Data;
+                self.negotiate(verb, byte)
+            }
+
+            State::Sub => {
+                if byte == IAC {
+                    self.state = State::SubIac;
+                }
+
+                Event::None
+            }
+
+            State::SubIac => {
+                self.state = if byte == SE { State::Data } else { State::Sub };
+                Event::None
+            }
+        }
+    }
+
+    /// Answer a request to enable or disable an option.
+    ///
+    /// Requests to disable options are never answered, since everything but our own echo and
+    /// go-ahead suppression is already disabled. This also avoids negotiation loops.
+    fn negotiate(&mut self, verb: u8, opt: u8) -> Event {
+        match (verb, opt) {
+            // Acknowledgements of the greeting.
+            (DO, option::ECHO | option::SUPPRESS_GO_AHEAD) => Event::None,
+
+            (DO, _) => Event::Reply([IAC, WONT, opt]),
+
+            // The client may suppress go-ahead too. Agree once.
+            (WILL, option::SUPPRESS_GO_AHEAD) => {
+                if core::mem::replace(&mut self.client_sga, true) {
+                    Event::None
+                } else {
+                    Event::Reply([IAC, DO, opt])
+                }
+            }
+
+            (WILL, _) => Event::Reply([IAC, DONT, opt]),
+
+            _ => Event::None,
+        }
+    }
+}
+
+/// Encodes terminal output for a client.
+///
+/// Bare line feeds are sent as CR LF, as the client expects, and `0xFF` bytes are escaped.
+#[derive(Debug, Default, Clone)]
+pub struct Encoder {
+    last: u8,
+}
+
+impl Encoder {
+    pub const fn new() -> Self {
+        Self { last: 0 }
+    }
+
+    /// Encode the next output byte, passing the bytes to send to `out`.
+    pub fn encode(&mut self, byte: u8, mut out: impl FnMut(u8)) {
+        match byte {
+            b'\n' if self.last != b'\r' => {
+                out(b'\r');
+                out(b'\n');
+            }
+            IAC => {
+                out(IAC);
+                out(IAC);
+            }
+            _ => out(byte),
+        }
+
+        self.last = byte;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Decode a stream, returning the terminal input and the replies.
+    fn decode(decoder: &mut Decoder, bytes: &[u8]) -> ([u8; 64], usize, [u8; 64], usize) {
+        let (mut data, mut data_len) = ([0u8; 64], 0);
+        let (mut reply, mut reply_len) = ([0u8; 64], 0);
+
+        for &b in bytes {
+            match decoder.feed(b) {
+                Event::None => {}
+                Event::Data(b) => {
+                    data[data_len] = b;
+                    data_len += 1;
+                }
+                Event::Reply(r) => {
+                    reply[reply_len..reply_len + 3].copy_from_slice(&r);
+                    reply_len += 3;
+                }
+            }
+        }
+
+        (data, data_len, reply, reply_len)
+    }
+
+    fn encode(bytes: &[u8]) -> ([u8; 64], usize) {
+        let mut encoder = Encoder::new();
+        let (mut out, mut len) = ([0u8; 64], 0);
+
+        for &b in bytes {
+            encoder.encode(b, |b| {
+                out[len] = b;
+                len += 1;
+            });
+        }
+
+        (out, len)
+    }
+
+    #[test]
+    fn test_line_endings() {
+        let mut decoder = Decoder::new();
+
+        let (data, len, _, _) = decode(&mut decoder, b"ab\r\ncd\r\0ef\ngh\r\r\n");
+        assert_eq!(&data[..len], b"ab\rcd\ref\rgh\r\r");
+    }
+
+    #[test]
+    fn test_commands() {
+        let mut decoder = Decoder::new();
+
+        // An escaped 0xFF, a NOP, and a subnegotiation (NAWS, 80x24) are skipped.
+        let (data, len, _, reply_len) = decode(
+            &mut decoder,
+            &[
+                b'a', IAC, IAC, IAC, 241, IAC, SB, 31, 0, 80, 0, 24, IAC, SE, b'b',
+            ],
+        );
+
+        assert_eq!(&data[..len], &[b'a', IAC, b'b']);
+        assert_eq!(reply_len, 0);
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_negotiation() {
+        let mut decoder = Decoder::new();
+
+        // A typical client's response to the greeting, plus a few offers of its own.
+        let (_, len, reply, reply_len) = decode(
+            &mut decoder,
+            &[
+                IAC, DO, option::ECHO,
+                IAC, DO, option::SUPPRESS_GO_AHEAD,
+                IAC, WONT, option::LINEMODE,
+                IAC, WILL, option::SUPPRESS_GO_AHEAD,
+                IAC, WILL, 31,
+                IAC, DO, 24,
+                IAC, DONT, 5,
+            ],
+        );
+
+        assert_eq!(len, 0);
+        assert_eq!(
+            &reply[..reply_len],
+            &[IAC, DO, option::SUPPRESS_GO_AHEAD, IAC, DONT, 31, IAC, WONT, 24]
+        );
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_sga_offer() {
+        let mut decoder = Decoder::new();
+
+        // The client repeats its offer to suppress go-ahead.
+        let (_, _, reply, reply_len) = decode(
+            &mut decoder,
+            &[
+                IAC, WILL, option::SUPPRESS_GO_AHEAD,
+                IAC, DO, option::SUPPRESS_GO_AHEAD,
+                IAC, WILL, option::SUPPRESS_GO_AHEAD,
+            ],
+        );
+
+        assert_eq!(&reply[..reply_len], &[IAC, DO, option::SUPPRESS_GO_AHEAD]);
+    }
+
+    #[test]
+    fn test_encode() {
+        let (out, len) = encode(b"a\nb\r\n\xFF");
+        assert_eq!(&out[..len], b"a\r\nb\r\n\xFF\xFF");
+    }
+}
@@ -0,0 +1,858 @@
+//! A `no_std` TFTP client (RFC 1350) built on smoltcp.
+//!
+//! Supports octet-mode reads and writes with block size (RFC 2348) and transfer size
+//! (RFC 2349) negotiation, retransmission on timeout, and block number rollover.
+//!
+//! The protocol itself is implemented by [Transfer] (reads) and [Upload] (writes), which are
+//! independent of the network stack. [download] and [upload] drive them over a UDP socket on
+//! any smoltcp [Device], and [Download] drives a read a poll at a time.
+#![no_std]
+
+pub mod packet;
+mod upload;
+
+pub use upload::{upload, Source, Upload};
+
+use core::fmt;
+
+use packet::{code, Packet};
+use smoltcp::{
+    iface::EthernetInterface,
+    phy::Device,
+    socket::{SocketHandle, SocketSet, UdpSocket},
+    time::{Duration, Instant},
+    wire::IpEndpoint,
+};
+
+/// The well-known TFTP server port.
+pub const SERVER_PORT: u16 = 69;
+
+/// The block size used if the server does not support option negotiation.
+pub const DEFAULT_BLKSIZE: u16 = 512;
+
+/// The largest block size that fits in a single Ethernet frame.
+pub const MAX_BLKSIZE: u16 = 1468;
+
+/// The largest packet we will send or receive.
+pub const MAX_PACKET: usize = 4 + MAX_BLKSIZE as usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The network stack returned an error.
+    Network(smoltcp::Error),
+    /// The server stopped responding.
+    Timeout,
+    /// The server aborted the transfer with an error code.
+    Remote(u16),
+    /// The server sent a malformed or unexpected packet.
+    Protocol,
+    /// The sink could not accept the file.
+    SinkFull,
+    /// The request does not fit in a single packet.
+    RequestTooLong,
+}
+
+impl From<smoltcp::Error> for Error {
+    fn from(e: smoltcp::Error) -> Self {
+        Self::Network(e)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Network(e) => write!(f, "network error: {}", e),
+            Error::Timeout => write!(f, "timed out"),
+            Error::Remote(code::FILE_NOT_FOUND) => write!(f, "file not found"),
+            Error::Remote(code::ACCESS_VIOLATION) => write!(f, "access violation"),
+            Error::Remote(c) => write!(f, "server error {}", c),
+            Error::Protocol => write!(f, "protocol error"),
+            Error::SinkFull => write!(f, "file too large"),
+            Error::RequestTooLong => write!(f, "file name too long"),
+        }
+    }
+}
+
+/// Returned by a [Sink] that cannot accept any more data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SinkFull;
+
+/// A destination for downloaded data. Data is delivered in order.
+pub trait Sink {
+    /// Called with the size of the file, if the server reported it.
+    /// Returning an error aborts the transfer before any data is received.
+    fn set_size(&mut self, _size: usize) -> Result<(), SinkFull> {
+        Ok(())
+    }
+
+    /// Append data to the sink. Returning an error aborts the transfer.
+    fn write(&mut self, data: &[u8]) -> Result<(), SinkFull>;
+}
+
+/// A sink that writes into a caller-provided buffer.
+pub struct BufferSink<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> BufferSink<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, len: 0 }
+    }
+
+    /// The number of bytes received so far.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<'a> Sink for BufferSink<'a> {
+    fn set_size(&mut self, size: usize) -> Result<(), SinkFull> {
+        if size > self.buf.len() {
+            Err(SinkFull)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), SinkFull> {
+        let dst = self
+            .buf
+            .get_mut(self.len..self.len + data.len())
+            .ok_or(SinkFull)?;
+        dst.copy_from_slice(data);
+        self.len += data.len();
+
+        Ok(())
+    }
+}
+
+/// Transfer parameters.
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    /// The block size to request. `None` to use the default of 512 bytes.
+    pub blksize: Option<u16>,
+    /// Whether to request the transfer size from the server, or to announce it when uploading.
+    pub tsize: bool,
+    /// How long to wait for a response before retransmitting.
+    pub timeout: Duration,
+    /// The number of retransmissions before giving up.
+    pub retries: u32,
+    /// The local UDP port to use.
+    pub local_port: u16,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            blksize: Some(MAX_BLKSIZE),
+            tsize: true,
+            timeout: Duration::from_millis(1000),
+            retries: 5,
+            local_port: 50069,
+        }
+    }
+}
+
+/// The action to take after handling a packet.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Step {
+    /// Send `len` bytes of the output buffer and continue.
+    Reply(usize),
+    /// Send `len` bytes of the output buffer, if any. The transfer is complete.
+    Done(usize),
+    /// Send `len` bytes of the output buffer (an error packet), then fail with `error`.
+    Abort(usize, Error),
+    /// Nothing to do.
+    Ignore,
+}
+
+/// The state of a single read transfer, independent of the network stack.
+pub struct Transfer {
+    config: Config,
+    blksize: usize,
+    /// Set once the server has responded with either an OACK or the first block.
+    started: bool,
+    /// The last block number acknowledged (wrapping).
+    block: u16,
+    /// The number of bytes received.
+    received: usize,
+    /// The transfer size reported by the server, if any.
+    tsize: Option<usize>,
+}
+
+impl Transfer {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            blksize: DEFAULT_BLKSIZE as usize,
+            started: false,
+            block: 0,
+            received: 0,
+            tsize: None,
+        }
+    }
+
+    /// The number of bytes received so far.
+    pub fn received(&self) -> usize {
+        self.received
+    }
+
+    /// The transfer size reported by the server, if any.
+    pub fn tsize(&self) -> Option<usize> {
+        self.tsize
+    }
+
+    /// The negotiated block size.
+    pub fn blksize(&self) -> usize {
+        self.blksize
+    }
+
+    /// Emit the initial read request into `out`.
+    pub fn request(&self, filename: &str, out: &mut [u8]) -> Result<usize, Error> {
+        packet::write_rrq(
+            out,
+            filename,
+            self.config.blksize.map(|b| b.min(MAX_BLKSIZE)),
+            self.config.tsize,
+        )
+    }
+
+    /// Handle a packet from the server, writing any reply into `out`.
+    pub fn handle(
+        &mut self,
+        pkt: &[u8],
+        sink: &mut impl Sink,
+        out: &mut [u8],
+    ) -> Result<Step, Error> {
+        // Malformed packets are dropped, as they may just be stray traffic.
+        let pkt = match Packet::parse(pkt) {
+            Ok(p) => p,
+            Err(_) => return Ok(Step::Ignore),
+        };
+
+        match pkt {
+            Packet::OptionAck { options } => {
+                // An OACK is only valid as the first response.
+                if self.started {
+                    return Ok(Step::Ignore);
+                }
+
+                if let Some(blksize) = options.get_usize("blksize") {
+                    // The server may only lower the block size we asked for.
+                    match self.config.blksize {
+                        Some(req) if blksize >= 8 && blksize <= req as usize => {}
+                        _ => return abort(code::OPTION_REJECTED, out, Error::Protocol),
+                    }
+
+                    self.blksize = blksize;
+                }
+
+                if let Some(tsize) = options.get_usize("tsize") {
+                    if sink.set_size(tsize).is_err() {
+                        return abort(code::DISK_FULL, out, Error::SinkFull);
+                    }
+
+                    self.tsize = Some(tsize);
+                }
+
+                self.started = true;
+                Ok(Step::Reply(packet::write_ack(out, 0)?))
+            }
+
+            Packet::Data { block, data } => {
+                if !self.started {
+                    // The server ignored our options and started sending data.
+                    self.started = true;
+                    self.blksize = DEFAULT_BLKSIZE as usize;
+                }
+
+                if block == self.block {
+                    // Duplicate of the previous block; our ACK was probably lost.
+                    return Ok(Step::Reply(packet::write_ack(out, block)?));
+                }
+
+                if block != self.block.wrapping_add(1) {
+                    return Ok(Step::Ignore);
+                }
+
+                if data.len() > self.blksize {
+                    return abort(code::ILLEGAL_OPERATION, out, Error::Protocol);
+                }
+
+                if sink.write(data).is_err() {
+                    return abort(code::DISK_FULL, out, Error::SinkFull);
+                }
+
+                self.block = block;
+                self.received += data.len();
+
+                let len = packet::write_ack(out, block)?;
+                if data.len() < self.blksize {
+                    Ok(Step::Done(len))
+                } else {
+                    Ok(Step::Reply(len))
+                }
+            }
+
+            Packet::Error { code, .. } => Err(Error::Remote(code)),
+
+            Packet::Ack { .. } => Ok(Step::Ignore),
+        }
+    }
+}
+
+/// Abort a transfer, notifying the server with an error packet.
+fn abort(code: u16, out: &mut [u8], err: Error) -> Result<Step, Error> {
+    let len = packet::write_error(out, code, "transfer aborted")?;
+    Ok(Step::Abort(len, err))
+}
+
+/// A download in progress, driven by calling [Download::poll] after each poll of the interface.
+/// This lets the caller service other sockets (or release the interface) between polls.
+pub struct Download<'s, S: Sink> {
+    transfer: Transfer,
+    session: Session,
+    sink: &'s mut S,
+}
+
+impl<'s, S: Sink> Download<'s, S> {
+    /// Start downloading `filename` from `server` into `sink`.
+    ///
+    /// `handle` must refer to a freshly created [UdpSocket] in `sockets`, with enough buffer
+    /// space for at least one packet of [MAX_PACKET] bytes in each direction. It's bound to
+    /// [Config::local_port], and should be removed from the set once the download ends.
+    pub fn new(
+        sockets: &mut SocketSet<'_>,
+        handle: SocketHandle,
+        server: IpEndpoint,
+        filename: &str,
+        config: &Config,
+        sink: &'s mut S,
+    ) -> Result<Self, Error> {
+        let transfer = Transfer::new(*config);
+
+        let mut request = [0u8; MAX_PACKET];
+        let len = transfer.request(filename, &mut request)?;
+
+        Ok(Self {
+            transfer,
+            session: Session::new(sockets, handle, server, &request[..len], config)?,
+            sink,
+        })
+    }
+
+    /// Process the packets received since the last call. Returns the number of bytes received
+    /// once the download is complete.
+    pub fn poll(
+        &mut self,
+        sockets: &mut SocketSet<'_>,
+        now: Instant,
+    ) -> Result<Option<usize>, Error> {
+        let Self {
+            transfer,
+            session,
+            sink,
+        } = self;
+
+        let done = session.poll(sockets, now, &mut |pkt, out| {
+            transfer.handle(pkt, &mut **sink, out)
+        })?;
+
+        Ok(done.then(|| transfer.received()))
+    }
+}
+
+/// Download `filename` from `server` into `sink` over `socket`, returning the number of bytes
+/// received.
+///
+/// `socket` must be a freshly created [UdpSocket]. See [Download::new] for its requirements. It's
+/// added to `sockets` for the duration of the transfer and dropped afterwards. `clock` must
+/// return the current time.
+#[allow(clippy::too_many_arguments)]
+pub fn download<'a, DeviceT, S>(
+    iface: &mut EthernetInterface<'_, DeviceT>,
+    sockets: &mut SocketSet<'a>,
+    socket: UdpSocket<'a>,
+    server: IpEndpoint,
+    filename: &str,
+    config: &Config,
+    sink: &mut S,
+    clock: &mut dyn FnMut() -> Instant,
+) -> Result<usize, Error>
+where
+    DeviceT: for<'d> Device<'d>,
+    S: Sink,
+{
+    let handle = sockets.add(socket);
+
+    let res =
+        Download::new(sockets, handle, server, filename, config, sink).and_then(|mut download| {
+            drive(iface, sockets, clock, |sockets, now| {
+                download.poll(sockets, now)
+            })
+        });
+
+    // Flush out any final ACK or error before dropping the socket.
+    let _ = iface.poll(sockets, clock());
+    sockets.remove(handle);
+
+    res
+}
+
+/// Poll the interface and `poll` in turn, until `poll` returns a result.
+fn drive<DeviceT, R>(
+    iface: &mut EthernetInterface<'_, DeviceT>,
+    sockets: &mut SocketSet<'_>,
+    clock: &mut dyn FnMut() -> Instant,
+    mut poll: impl FnMut(&mut SocketSet<'_>, Instant) -> Result<Option<R>, Error>,
+) -> Result<R, Error>
+where
+    DeviceT: for<'d> Device<'d>,
+{
+    loop {
+        let now = clock();
+
+        // Errors here are transient (e.g. a malformed frame on the wire).
+        let _ = iface.poll(sockets, now);
+
+        if let Some(r) = poll(sockets, now)? {
+            return Ok(r);
+        }
+    }
+}
+
+/// Handles a packet from the server, writing any reply into the second buffer.
+type Handler<'a> = dyn FnMut(&[u8], &mut [u8]) -> Result<Step, Error> + 'a;
+
+/// The network side of a transfer. A request is sent to the server over a UDP socket, and each
+/// packet from the server is passed to a handler, whose replies are sent back. Replies are
+/// retransmitted if the server goes quiet.
+struct Session {
+    handle: SocketHandle,
+    server: IpEndpoint,
+    config: Config,
+    /// The server replies from a new port (its transfer ID), which we lock onto.
+    peer: Option<IpEndpoint>,
+    /// The last packet sent, kept for retransmission.
+    tx: [u8; MAX_PACKET],
+    tx_len: usize,
+    /// Set if the last packet is waiting to be (re)sent.
+    pending: bool,
+    retries: u32,
+    deadline: Option<Instant>,
+}
+
+impl Session {
+    /// Bind the socket `handle` and queue `request` for `server`. See [Download::new] for the
+    /// requirements on the socket.
+    fn new(
+        sockets: &mut SocketSet<'_>,
+        handle: SocketHandle,
+        server: IpEndpoint,
+        request: &[u8],
+        config: &Config,
+    ) -> Result<Self, Error> {
+        sockets.get::<UdpSocket>(handle).bind(config.local_port)?;
+
+        let mut tx = [0u8; MAX_PACKET];
+        tx[..request.len()].copy_from_slice(request);
+
+        Ok(Self {
+            handle,
+            server,
+            config: *config,
+            peer: None,
+            tx,
+            tx_len: request.len(),
+            pending: true,
+            retries: 0,
+            deadline: None,
+        })
+    }
+
+    /// Send any pending packet and handle the packets received since the last call. Returns
+    /// `true` once `handler` completes the transfer.
+    fn poll(
+        &mut self,
+        sockets: &mut SocketSet<'_>,
+        now: Instant,
+        handler: &mut Handler<'_>,
+    ) -> Result<bool, Error> {
+        let mut socket = sockets.get::<UdpSocket>(self.handle);
+        let mut rx = [0u8; MAX_PACKET];
+
+        if self.pending && socket.can_send() {
+            socket.send_slice(&self.tx[..self.tx_len], self.peer.unwrap_or(self.server))?;
+            self.pending = false;
+            self.deadline = Some(now + self.config.timeout);
+        }
+
+        while socket.can_recv() {
+            let (len, from) = socket.recv_slice(&mut rx)?;
+
+            match self.peer {
+                Some(p) if p != from => {
+                    // Not part of this transfer. Tell the sender to go away.
+                    let mut err = [0u8; 32];
+                    let n = packet::write_error(&mut err, code::UNKNOWN_TID, "unknown TID")?;
+                    let _ = socket.send_slice(&err[..n], from);
+                    continue;
+                }
+
+                None if from.addr != self.server.addr => continue,
+                _ => {}
+            }
+
+            let step = handler(&rx[..len], &mut self.tx)?;
+            self.peer = Some(from);
+
+            match step {
+                Step::Reply(n) => {
+                    self.tx_len = n;
+                    self.retries = 0;
+                    socket.send_slice(&self.tx[..n], from)?;
+                    self.deadline = Some(now + self.config.timeout);
+                }
+
+                Step::Done(n) => {
+                    if n != 0 {
+                        socket.send_slice(&self.tx[..n], from)?;
+                    }
+
+                    return Ok(true);
+                }
+
+                Step::Abort(n, e) => {
+                    let _ = socket.send_slice(&self.tx[..n], from);
+                    return Err(e);
+                }
+
+                Step::Ignore => {}
+            }
+        }
+
+        match self.deadline {
+            Some(deadline) if !self.pending && now >= deadline => {
+                if self.retries >= self.config.retries {
+                    return Err(Error::Timeout);
+                }
+
+                self.retries += 1;
+                self.pending = true;
+            }
+
+            _ => {}
+        }
+
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::*;
+    use smoltcp::{
+        iface::{EthernetInterfaceBuilder, NeighborCache},
+        phy::{self, DeviceCapabilities},
+        socket::{UdpPacketMetadata, UdpSocketBuffer},
+        wire::{EthernetAddress, IpAddress, IpCidr},
+    };
+    use std::{
+        collections::{BTreeMap, VecDeque},
+        sync::{Arc, Mutex},
+        thread, vec,
+        vec::Vec,
+    };
+
+    fn data(block: u16, payload: &[u8]) -> [u8; 4 + 16] {
+        let mut pkt = [0u8; 20];
+        pkt[..2].copy_from_slice(&packet::OP_DATA.to_be_bytes());
+        pkt[2..4].copy_from_slice(&block.to_be_bytes());
+        pkt[4..4 + payload.len()].copy_from_slice(payload);
+        pkt
+    }
+
+    fn config(blksize: Option<u16>) -> Config {
+        Config {
+            blksize,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn test_negotiated() {
+        let mut buf = [0u8; 64];
+        let mut sink = BufferSink::new(&mut buf);
+        let mut out = [0u8; 64];
+
+        let mut xfer = Transfer::new(config(Some(8)));
+
+        let step = xfer
+            .handle(b"\x00\x06blksize\x008\x00tsize\x0012\x00", &mut sink, &mut out)
+            .unwrap();
+        assert_eq!(step, Step::Reply(4));
+        assert_eq!(&out[..4], b"\x00\x04\x00\x00");
+        assert_eq!(xfer.blksize(), 8);
+        assert_eq!(xfer.tsize(), Some(12));
+
+        let pkt = data(1, b"01234567");
+        assert_eq!(xfer.handle(&pkt[..12], &mut sink, &mut out), Ok(Step::Reply(4)));
+        assert_eq!(&out[..4], b"\x00\x04\x00\x01");
+
+        // A duplicate block is re-acknowledged but not written twice.
+        assert_eq!(xfer.handle(&pkt[..12], &mut sink, &mut out), Ok(Step::Reply(4)));
+
+        let pkt = data(2, b"89AB");
+        assert_eq!(xfer.handle(&pkt[..8], &mut sink, &mut out), Ok(Step::Done(4)));
+        assert_eq!(&out[..4], b"\x00\x04\x00\x02");
+
+        assert_eq!(xfer.received(), 12);
+        assert_eq!(sink.len(), 12);
+        assert_eq!(&buf[..12], b"0123456789AB");
+    }
+
+    #[test]
+    fn test_no_options() {
+        let mut buf = [0u8; 64];
+        let mut sink = BufferSink::new(&mut buf);
+        let mut out = [0u8; 64];
+
+        // The server ignores our options and starts sending 512-byte blocks.
+        let mut xfer = Transfer::new(config(Some(1024)));
+        let pkt = data(1, b"hello");
+        assert_eq!(xfer.handle(&pkt[..9], &mut sink, &mut out), Ok(Step::Done(4)));
+        assert_eq!(xfer.blksize(), 512);
+        assert_eq!(&buf[..5], b"hello");
+    }
+
+    #[test]
+    fn test_rollover() {
+        struct Counter(usize);
+        impl Sink for Counter {
+            fn write(&mut self, data: &[u8]) -> Result<(), SinkFull> {
+                self.0 += data.len();
+                Ok(())
+            }
+        }
+
+        let mut sink = Counter(0);
+        let mut out = [0u8; 64];
+        let mut xfer = Transfer::new(config(None));
+
+        let mut pkt = [0u8; 4 + 512];
+        pkt[1] = packet::OP_DATA as u8;
+
+        for i in 1..=65536 + 2 {
+            pkt[2..4].copy_from_slice(&(i as u16).to_be_bytes());
+            assert_eq!(xfer.handle(&pkt, &mut sink, &mut out), Ok(Step::Reply(4)));
+        }
+
+        assert_eq!(sink.0, (65536 + 2) * 512);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut buf = [0u8; 4];
+        let mut sink = BufferSink::new(&mut buf);
+        let mut out = [0u8; 64];
+
+        let mut xfer = Transfer::new(config(Some(8)));
+        assert_eq!(
+            xfer.handle(b"\x00\x05\x00\x01nope\x00", &mut sink, &mut out),
+            Err(Error::Remote(code::FILE_NOT_FOUND))
+        );
+
+        // The file does not fit in the sink.
+        let mut xfer = Transfer::new(config(Some(8)));
+        assert_eq!(
+            xfer.handle(b"\x00\x06tsize\x00100\x00", &mut sink, &mut out),
+            Ok(Step::Abort(21, Error::SinkFull))
+        );
+        assert_eq!(&out[..4], b"\x00\x05\x00\x03");
+
+        // The server tried to raise the block size.
+        let mut xfer = Transfer::new(config(Some(8)));
+        assert_eq!(
+            xfer.handle(b"\x00\x06blksize\x001024\x00", &mut sink, &mut out),
+            Ok(Step::Abort(21, Error::Protocol))
+        );
+        assert_eq!(&out[..4], b"\x00\x05\x00\x08");
+    }
+
+    type Queue = Arc<Mutex<VecDeque<Vec<u8>>>>;
+
+    /// One end of an ethernet link between two interfaces.
+    struct Link {
+        rx: Queue,
+        tx: Queue,
+    }
+
+    struct RxToken(Vec<u8>);
+    struct TxToken(Queue);
+
+    impl phy::RxToken for RxToken {
+        fn consume<R, F>(mut self, _: Instant, f: F) -> smoltcp::Result<R>
+        where
+            F: FnOnce(&mut [u8]) -> smoltcp::Result<R>,
+        {
+            f(&mut self.0)
+        }
+    }
+
+    impl phy::TxToken for TxToken {
+        fn consume<R, F>(self, _: Instant, len: usize, f: F) -> smoltcp::Result<R>
+        where
+            F: FnOnce(&mut [u8]) -> smoltcp::Result<R>,
+        {
+            let mut frame = vec![0u8; len];
+            let r = f(&mut frame)?;
+            self.0.lock().unwrap().push_back(frame);
+            Ok(r)
+        }
+    }
+
+    impl<'d> Device<'d> for Link {
+        type RxToken = RxToken;
+        type TxToken = TxToken;
+
+        fn receive(&'d mut self) -> Option<(RxToken, TxToken)> {
+            let frame = self.rx.lock().unwrap().pop_front()?;
+            Some((RxToken(frame), TxToken(self.tx.clone())))
+        }
+
+        fn transmit(&'d mut self) -> Option<TxToken> {
+            Some(TxToken(self.tx.clone()))
+        }
+
+        fn capabilities(&self) -> DeviceCapabilities {
+            let mut caps = DeviceCapabilities::default();
+            caps.max_transmission_unit = 1514;
+            caps
+        }
+    }
+
+    fn iface(link: Link, mac: u8, addr: [u8; 4]) -> EthernetInterface<'static, Link> {
+        EthernetInterfaceBuilder::new(link)
+            .ethernet_addr(EthernetAddress([2, 0, 0, 0, 0, mac]))
+            .neighbor_cache(NeighborCache::new(BTreeMap::new()))
+            .ip_addrs(vec![IpCidr::new(
+                IpAddress::v4(addr[0], addr[1], addr[2], addr[3]),
+                24,
+            )])
+            .finalize()
+    }
+
+    fn udp_socket() -> UdpSocket<'static> {
+        let buffer =
+            || UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; 4], vec![0u8; 4 * MAX_PACKET]);
+
+        UdpSocket::new(buffer(), buffer())
+    }
+
+    /// Serve `file` to the first client that asks for it, from a new port and without option
+    /// negotiation. Returns the blocks the client acknowledged.
+    fn serve(link: Link, file: &'static [u8]) -> Vec<u16> {
+        let mut iface = iface(link, 2, [10, 0, 0, 2]);
+        let mut sockets = SocketSet::new(Vec::new());
+
+        let listen = sockets.add(udp_socket());
+        sockets.get::<UdpSocket>(listen).bind(SERVER_PORT).unwrap();
+
+        let xfer = sockets.add(udp_socket());
+        sockets.get::<UdpSocket>(xfer).bind(3000).unwrap();
+
+        let mut client = None;
+        let mut acked = Vec::new();
+        let mut rx = [0u8; MAX_PACKET];
+        let mut tx = [0u8; MAX_PACKET];
+        let mut blocks = file.chunks(DEFAULT_BLKSIZE as usize);
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while Instant::now() < deadline {
+            let _ = iface.poll(&mut sockets, Instant::now());
+
+            let request = {
+                let mut socket = sockets.get::<UdpSocket>(listen);
+                socket.recv_slice(&mut rx).ok()
+            };
+
+            if let Some((len, from)) = request {
+                assert!(rx[..len].starts_with(b"\x00\x01boot.elf\x00octet\x00"));
+                client = Some(from);
+
+                let n = packet::write_data(&mut tx, 1, blocks.next().unwrap()).unwrap();
+                sockets
+                    .get::<UdpSocket>(xfer)
+                    .send_slice(&tx[..n], from)
+                    .unwrap();
+            }
+
+            let mut socket = sockets.get::<UdpSocket>(xfer);
+            if socket.can_recv() {
+                let (len, from) = socket.recv_slice(&mut rx).unwrap();
+                assert_eq!(Some(from), client);
+
+                let block = match Packet::parse(&rx[..len]) {
+                    Ok(Packet::Ack { block }) => block,
+                    _ => panic!("expected an ACK"),
+                };
+                acked.push(block);
+
+                match blocks.next() {
+                    Some(data) => {
+                        let n = packet::write_data(&mut tx, block + 1, data).unwrap();
+                        socket.send_slice(&tx[..n], from).unwrap();
+                    }
+                    None => break,
+                }
+            }
+        }
+
+        acked
+    }
+
+    #[test]
+    fn test_download() {
+        static FILE: [u8; 1100] = [0x5A; 1100];
+
+        let a = Queue::default();
+        let b = Queue::default();
+        let server = thread::spawn({
+            let link = Link {
+                rx: b.clone(),
+                tx: a.clone(),
+            };
+            move || serve(link, &FILE)
+        });
+
+        let mut iface = iface(Link { rx: a, tx: b }, 1, [10, 0, 0, 1]);
+        let mut sockets = SocketSet::new(Vec::new());
+        let mut buf = [0u8; 2048];
+        let mut sink = BufferSink::new(&mut buf);
+
+        let res = download(
+            &mut iface,
+            &mut sockets,
+            udp_socket(),
+            (IpAddress::v4(10, 0, 0, 2), SERVER_PORT).into(),
+            "boot.elf",
+            &Config::default(),
+            &mut sink,
+            &mut Instant::now,
+        );
+
+        assert_eq!(res, Ok(FILE.len()));
+        assert_eq!(&buf[..FILE.len()], &FILE[..]);
+        assert_eq!(server.join().unwrap(), [1, 2, 3]);
+
+        // The socket is gone, so another transfer can bind the same port.
+        assert_eq!(sockets.iter().count(), 0);
+    }
+}
@@ -0,0 +1,317 @@
+//! This module contains TFTP packet encoding and decoding (RFC 1350, RFC 2347).
+
+use crate::Error;
+
+pub const OP_RRQ: u16 = 1;
+pub const OP_WRQ: u16 = 2;
+pub const OP_DATA: u16 = 3;
+pub const OP_ACK: u16 = 4;
+pub const OP_ERROR: u16 = 5;
+pub const OP_OACK: u16 = 6;
+
+/// TFTP error codes, as defined by RFC 1350 and RFC 2347.
+#[allow(dead_code)]
+pub mod code {
+    pub const NOT_DEFINED: u16 = 0;
+    pub const FILE_NOT_FOUND: u16 = 1;
+    pub const ACCESS_VIOLATION: u16 = 2;
+    pub const DISK_FULL: u16 = 3;
+    pub const ILLEGAL_OPERATION: u16 = 4;
+    pub const UNKNOWN_TID: u16 = 5;
+    pub const FILE_EXISTS: u16 = 6;
+    pub const NO_SUCH_USER: u16 = 7;
+    pub const OPTION_REJECTED: u16 = 8;
+}
+
+/// A packet received from a server.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Packet<'a> {
+    Data { block: u16, data: &'a [u8] },
+    Ack { block: u16 },
+    Error { code: u16, message: &'a [u8] },
+    OptionAck { options: Options<'a> },
+}
+
+impl<'a> Packet<'a> {
+    pub fn parse(buf: &'a [u8]) -> Result<Self, Error> {
+        if buf.len() < 2 {
+            return Err(Error::Protocol);
+        }
+
+        let opcode = u16::from_be_bytes([buf[0], buf[1]]);
+        let rest = &buf[2..];
+
+        match opcode {
+            OP_DATA if rest.len() >= 2 => Ok(Packet::Data {
+                block: u16::from_be_bytes([rest[0], rest[1]]),
+                data: &rest[2..],
+            }),
+
+            OP_ACK if rest.len() >= 2 => Ok(Packet::Ack {
+                block: u16::from_be_bytes([rest[0], rest[1]]),
+            }),
+
+            OP_ERROR if rest.len() >= 2 => {
+                let message = &rest[2..];
+                let end = message.iter().position(|b| *b == 0).unwrap_or(message.len());
+
+                Ok(Packet::Error {
+                    code: u16::from_be_bytes([rest[0], rest[1]]),
+                    message: &message[..end],
+                })
+            }
+
+            OP_OACK => Ok(Packet::OptionAck {
+                options: Options(rest),
+            }),
+
+            _ => Err(Error::Protocol),
+        }
+    }
+}
+
+/// A list of NUL-terminated option name and value pairs.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Options<'a>(&'a [u8]);
+
+impl<'a> Options<'a> {
+    /// Iterate over all options. Iteration stops at the first malformed pair.
+    pub fn iter(&self) -> impl Iterator<Item = (&'a str, &'a str)> {
+        let mut fields = self
+            .0
+            .split(|b| *b == 0)
+            .map(|f| core::str::from_utf8(f).unwrap_or(""));
+
+        core::iter::from_fn(move || {
+            let name = fields.next().filter(|n| !n.is_empty())?;
+            let value = fields.next()?;
+
+            Some((name, value))
+        })
+    }
+
+    /// Look up an option by name (case-insensitive) and parse it as an integer.
+    pub fn get_usize(&self, name: &str) -> Option<usize> {
+        self.iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .and_then(|(_, v)| v.parse().ok())
+    }
+}
+
+/// A simple cursor used to emit packets into a buffer.
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Writer<'a> {
+    fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, len: 0 }
+    }
+
+    fn put(&mut self, data: &[u8]) -> Result<(), Error> {
+        let dst = self
+            .buf
+            .get_mut(self.len..self.len + data.len())
+            .ok_or(Error::RequestTooLong)?;
+
+        dst.copy_from_slice(data);
+        self.len += data.len();
+
+        Ok(())
+    }
+
+    fn put_u16(&mut self, val: u16) -> Result<(), Error> {
+        self.put(&val.to_be_bytes())
+    }
+
+    fn put_str(&mut self, s: &str) -> Result<(), Error> {
+        self.put(s.as_bytes())?;
+        self.put(&[0])
+    }
+
+    fn put_usize(&mut self, mut val: usize) -> Result<(), Error> {
+        let mut digits = [0u8; 20];
+        let mut i = digits.len();
+
+        loop {
+            i -= 1;
+            digits[i] = b'0' + (val % 10) as u8;
+            val /= 10;
+
+            if val == 0 {
+                break;
+            }
+        }
+
+        self.put(&digits[i..])?;
+        self.put(&[0])
+    }
+}
+
+/// Emit a read request for `filename` in octet mode.
+/// The `blksize` (RFC 2348) and `tsize` (RFC 2349) options are requested if specified.
+pub fn write_rrq(
+    buf: &mut [u8],
+    filename: &str,
+    blksize: Option<u16>,
+    tsize: bool,
+) -> Result<usize, Error> {
+    write_request(
+        buf,
+        OP_RRQ,
+        filename,
+        blksize,
+        if tsize { Some(0) } else { None },
+    )
+}
+
+/// Emit a write request for `filename` in octet mode.
+/// The `blksize` (RFC 2348) option is requested, and the size of the file announced with
+/// `tsize` (RFC 2349), if specified.
+pub fn write_wrq(
+    buf: &mut [u8],
+    filename: &str,
+    blksize: Option<u16>,
+    tsize: Option<usize>,
+) -> Result<usize, Error> {
+    write_request(buf, OP_WRQ, filename, blksize, tsize)
+}
+
+fn write_request(
+    buf: &mut [u8],
+    op: u16,
+    filename: &str,
+    blksize: Option<u16>,
+    tsize: Option<usize>,
+) -> Result<usize, Error> {
+    let mut w = Writer::new(buf);
+
+    w.put_u16(op)?;
+    w.put_str(filename)?;
+    w.put_str("octet")?;
+
+    if let Some(blksize) = blksize {
+        w.put_str("blksize")?;
+        w.put_usize(blksize as usize)?;
+    }
+
+    if let Some(tsize) = tsize {
+        w.put_str("tsize")?;
+        w.put_usize(tsize)?;
+    }
+
+    Ok(w.len)
+}
+
+/// Emit a data block.
+pub fn write_data(buf: &mut [u8], block: u16, data: &[u8]) -> Result<usize, Error> {
+    let mut w = Writer::new(buf);
+
+    w.put_u16(OP_DATA)?;
+    w.put_u16(block)?;
+    w.put(data)?;
+
+    Ok(w.len)
+}
+
+/// Emit an acknowledgement for a data block.
+pub fn write_ack(buf: &mut [u8], block: u16) -> Result<usize, Error> {
+    let mut w = Writer::new(buf);
+
+    w.put_u16(OP_ACK)?;
+    w.put_u16(block)?;
+
+    Ok(w.len)
+}
+
+/// Emit an error packet.
+pub fn write_error(buf: &mut [u8], code: u16, message: &str) -> Result<usize, Error> {
+    let mut w = Writer::new(buf);
+
+    w.put_u16(OP_ERROR)?;
+    w.put_u16(code)?;
+    w.put_str(message)?;
+
+    Ok(w.len)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rrq() {
+        let mut buf = [0u8; 64];
+
+        let n = write_rrq(&mut buf, "vmlinux", Some(1428), true).unwrap();
+        assert_eq!(
+            &buf[..n],
+            b"\x00\x01vmlinux\x00octet\x00blksize\x001428\x00tsize\x000\x00"
+        );
+
+        let n = write_rrq(&mut buf, "a", None, false).unwrap();
+        assert_eq!(&buf[..n], b"\x00\x01a\x00octet\x00");
+
+        assert_eq!(
+            write_rrq(&mut buf[..8], "vmlinux", None, false),
+            Err(Error::RequestTooLong)
+        );
+    }
+
+    #[test]
+    fn test_wrq() {
+        let mut buf = [0u8; 64];
+
+        let n = write_wrq(&mut buf, "core", Some(1468), Some(123456)).unwrap();
+        assert_eq!(
+            &buf[..n],
+            b"\x00\x02core\x00octet\x00blksize\x001468\x00tsize\x00123456\x00"
+        );
+
+        let n = write_data(&mut buf, 0x102, b"abc").unwrap();
+        assert_eq!(&buf[..n], b"\x00\x03\x01\x02abc");
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Packet::parse(b"\x00\x03\x00\x01abc"),
+            Ok(Packet::Data {
+                block: 1,
+                data: b"abc"
+            })
+        );
+
+        assert_eq!(
+            Packet::parse(b"\x00\x05\x00\x01File not found\x00"),
+            Ok(Packet::Error {
+                code: code::FILE_NOT_FOUND,
+                message: b"File not found"
+            })
+        );
+
+        let oack = Packet::parse(b"\x00\x06BLKSIZE\x001024\x00tsize\x0012345\x00").unwrap();
+        match oack {
+            Packet::OptionAck { options } => {
+                assert_eq!(options.get_usize("blksize"), Some(1024));
+                assert_eq!(options.get_usize("tsize"), Some(12345));
+                assert_eq!(options.get_usize("timeout"), None);
+            }
+
+            _ => panic!("expected OACK"),
+        }
+
+        assert_eq!(
+            Packet::parse(b"\x00\x04\x01\x02"),
+            Ok(Packet::Ack { block: 0x102 })
+        );
+
+        assert_eq!(Packet::parse(b"\x00"), Err(Error::Protocol));
+        assert_eq!(Packet::parse(b"\x00\x04\x00"), Err(Error::Protocol));
+        assert_eq!(
+            Packet::parse(b"\x00\x01a\x00octet\x00"),
+            Err(Error::Protocol)
+        );
+    }
+}
@@ -0,0 +1,303 @@
+//! This module implements write transfers (uploads).
+
+use smoltcp::{
+    iface::EthernetInterface,
+    phy::Device,
+    socket::{SocketSet, UdpSocket},
+    time::Instant,
+    wire::IpEndpoint,
+};
+
+use crate::{
+    abort, drive,
+    packet::{self, code, Packet},
+    Config, Error, Session, Step, DEFAULT_BLKSIZE, MAX_BLKSIZE, MAX_PACKET,
+};
+
+/// The origin of uploaded data. Data is read in order.
+pub trait Source {
+    /// Fill `buf` with the next part of the file, returning the length read. Reading less than
+    /// `buf.len()` ends the file.
+    fn read(&mut self, buf: &mut [u8]) -> usize;
+}
+
+impl Source for &[u8] {
+    fn read(&mut self, buf: &mut [u8]) -> usize {
+        let len = buf.len().min(self.len());
+        buf[..len].copy_from_slice(&self[..len]);
+        *self = &self[len..];
+
+        len
+    }
+}
+
+/// The state of a single write transfer, independent of the network stack.
+pub struct Upload {
+    config: Config,
+    blksize: usize,
+    /// Set once the server has responded with either an OACK or the first ACK.
+    started: bool,
+    /// The last block number sent (wrapping).
+    block: u16,
+    /// Set once the final (short) block has been sent.
+    last: bool,
+    /// The number of bytes sent.
+    sent: usize,
+}
+
+impl Upload {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            blksize: DEFAULT_BLKSIZE as usize,
+            started: false,
+            block: 0,
+            last: false,
+            sent: 0,
+        }
+    }
+
+    /// The number of bytes sent so far.
+    pub fn sent(&self) -> usize {
+        self.sent
+    }
+
+    /// The negotiated block size.
+    pub fn blksize(&self) -> usize {
+        self.blksize
+    }
+
+    /// Emit the initial write request into `out`. The size of the file is announced to the
+    /// server if it's given and [Config::tsize] is set.
+    pub fn request(
+        &self,
+        filename: &str,
+        size: Option<usize>,
+        out: &mut [u8],
+    ) -> Result<usize, Error> {
+        packet::write_wrq(
+            out,
+            filename,
+            self.config.blksize.map(|b| b.min(MAX_BLKSIZE)),
+            size.filter(|_| self.config.tsize),
+        )
+    }
+
+    /// Handle a packet from the server, writing any reply into `out`.
+    pub fn handle(
+        &mut self,
+        pkt: &[u8],
+        source: &mut impl Source,
+        out: &mut [u8],
+    ) -> Result<Step, Error> {
+        // Malformed packets are dropped, as they may just be stray traffic.
+        let pkt = match Packet::parse(pkt) {
+            Ok(p) => p,
+            Err(_) => return Ok(Step::Ignore),
+        };
+
+        match pkt {
+            Packet::OptionAck { options } => {
+                // An OACK is only valid as the first response.
+                if self.started {
+                    return Ok(Step::Ignore);
+                }
+
+                if let Some(blksize) = options.get_usize("blksize") {
+                    // The server may only lower the block size we asked for.
+                    match self.config.blksize {
+                        Some(req) if blksize >= 8 && blksize <= req as usize => {}
+                        _ => return abort(code::OPTION_REJECTED, out, Error::Protocol),
+                    }
+
+                    self.blksize = blksize;
+                }
+
+                self.started = true;
+                self.send_next(source, out)
+            }
+
+            Packet::Ack { block } => {
+                if !self.started {
+                    // The server ignored our options, and acknowledged the request itself.
+                    if block != 0 {
+                        return Ok(Step::Ignore);
+                    }
+
+                    self.started = true;
+                    self.blksize = DEFAULT_BLKSIZE as usize;
+                    return self.send_next(source, out);
+                }
+
+                // N.B: Duplicate ACKs are not answered, or every block would be sent twice from
+                // then on. A lost block is sent again when the ACK for it times out.
+                if block != self.block {
+                    return Ok(Step::Ignore);
+                }
+
+                if self.last {
+                    return Ok(Step::Done(0));
+                }
+
+                self.send_next(source, out)
+            }
+
+            Packet::Error { code, .. } => Err(Error::Remote(code)),
+
+            Packet::Data { .. } => Ok(Step::Ignore),
+        }
+    }
+
+    /// Read the next block from `source` and emit it into `out`.
+    fn send_next(&mut self, source: &mut impl Source, out: &mut [u8]) -> Result<Step, Error> {
+        let mut data = [0u8; MAX_BLKSIZE as usize];
+        let len = source.read(&mut data[..self.blksize]);
+
+        self.block = self.block.wrapping_add(1);
+        self.last = len < self.blksize;
+        self.sent += len;
+
+        Ok(Step::Reply(packet::write_data(
+            out,
+            self.block,
+            &data[..len],
+        )?))
+    }
+}
+
+/// Upload `source` to `server` as `filename` over `socket`, returning the number of bytes sent.
+/// The size of the file is announced to the server if it's given.
+///
+/// The requirements on the socket are the same as for [crate::download].
+#[allow(clippy::too_many_arguments)]
+pub fn upload<'a, DeviceT, S>(
+    iface: &mut EthernetInterface<'_, DeviceT>,
+    sockets: &mut SocketSet<'a>,
+    socket: UdpSocket<'a>,
+    server: IpEndpoint,
+    filename: &str,
+    size: Option<usize>,
+    config: &Config,
+    source: &mut S,
+    clock: &mut dyn FnMut() -> Instant,
+) -> Result<usize, Error>
+where
+    DeviceT: for<'d> Device<'d>,
+    S: Source,
+{
+    let mut upload = Upload::new(*config);
+
+    let mut request = [0u8; MAX_PACKET];
+    let len = upload.request(filename, size, &mut request)?;
+
+    let handle = sockets.add(socket);
+
+    let res =
+        Session::new(sockets, handle, server, &request[..len], config).and_then(|mut session| {
+            drive(iface, sockets, clock, |sockets, now| {
+                let done = session.poll(sockets, now, &mut |pkt, out| {
+                    upload.handle(pkt, source, out)
+                })?;
+                Ok(done.then(|| upload.sent()))
+            })
+        });
+
+    // Flush out any final packet before dropping the socket.
+    let _ = iface.poll(sockets, clock());
+    sockets.remove(handle);
+
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ack(block: u16) -> [u8; 4] {
+        let mut pkt = [0u8; 4];
+        pkt[..2].copy_from_slice(&packet::OP_ACK.to_be_bytes());
+        pkt[2..].copy_from_slice(&block.to_be_bytes());
+        pkt
+    }
+
+    fn config(blksize: Option<u16>) -> Config {
+        Config {
+            blksize,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn test_negotiated() {
+        let mut source: &[u8] = b"0123456789AB";
+        let mut out = [0u8; 64];
+
+        let mut up = Upload::new(config(Some(8)));
+        let n = up.request("core", Some(12), &mut out).unwrap();
+        assert_eq!(
+            &out[..n],
+            b"\x00\x02core\x00octet\x00blksize\x008\x00tsize\x0012\x00"
+        );
+
+        let step = up.handle(b"\x00\x06blksize\x008\x00", &mut source, &mut out);
+        assert_eq!(step, Ok(Step::Reply(12)));
+        assert_eq!(&out[..12], b"\x00\x03\x00\x0101234567");
+        assert_eq!(up.blksize(), 8);
+
+        // A duplicate ACK is not answered.
+        assert_eq!(up.handle(&ack(0), &mut source, &mut out), Ok(Step::Ignore));
+
+        assert_eq!(
+            up.handle(&ack(1), &mut source, &mut out),
+            Ok(Step::Reply(8))
+        );
+        assert_eq!(&out[..8], b"\x00\x03\x00\x0289AB");
+
+        assert_eq!(up.handle(&ack(2), &mut source, &mut out), Ok(Step::Done(0)));
+        assert_eq!(up.sent(), 12);
+    }
+
+    #[test]
+    fn test_no_options() {
+        let data = [0x55u8; 1024];
+        let mut source: &[u8] = &data;
+        let mut out = [0u8; 1024];
+
+        // The server ignores our options. A file that fills its last block ends with an
+        // empty one.
+        let mut up = Upload::new(config(Some(1024)));
+        assert_eq!(
+            up.handle(&ack(0), &mut source, &mut out),
+            Ok(Step::Reply(516))
+        );
+        assert_eq!(
+            up.handle(&ack(1), &mut source, &mut out),
+            Ok(Step::Reply(516))
+        );
+        assert_eq!(
+            up.handle(&ack(2), &mut source, &mut out),
+            Ok(Step::Reply(4))
+        );
+        assert_eq!(up.handle(&ack(3), &mut source, &mut out), Ok(Step::Done(0)));
+        assert_eq!(up.sent(), 1024);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut source: &[u8] = b"";
+        let mut out = [0u8; 64];
+
+        let mut up = Upload::new(config(Some(8)));
+        assert_eq!(
+            up.handle(b"\x00\x05\x00\x02denied\x00", &mut source, &mut out),
+            Err(Error::Remote(code::ACCESS_VIOLATION))
+        );
+
+        // The server tried to raise the block size.
+        let mut up = Upload::new(config(Some(8)));
+        assert_eq!(
+            up.handle(b"\x00\x06blksize\x001024\x00", &mut source, &mut out),
+            Ok(Step::Abort(21, Error::Protocol))
+        );
+    }
+}
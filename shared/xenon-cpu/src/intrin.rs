@@ -82,3 +82,32 @@ macro_rules! mfspr {
         }
     };
 }
+
+/// The size of a cache line on Xenon, in bytes.
+pub const CACHE_LINE_SIZE: usize = 128;
+
+/// Make the instruction cache coherent with memory in the range `[start, start + len)`.
+/// This must be called after writing instructions to memory and before executing them.
+///
+/// # Safety
+/// The range must be mapped memory.
+pub unsafe fn sync_icache(start: usize, len: usize) {
+    let first = start & !(CACHE_LINE_SIZE - 1);
+    let end = start + len;
+
+    let mut line = first;
+    while line < end {
+        asm!("dcbst 0, {}", in(reg) line);
+        line += CACHE_LINE_SIZE;
+    }
+
+    asm!("sync");
+
+    let mut line = first;
+    while line < end {
+        asm!("icbi 0, {}", in(reg) line);
+        line += CACHE_LINE_SIZE;
+    }
+
+    asm!("sync", "isync");
+}
@@ -1,4 +1,4 @@
-const TIMEBASE_FREQ: u64 = 3192000000 / 64;
+pub const TIMEBASE_FREQ: u64 = 3192000000 / 64;
 
 use crate::intrin::mftb;
 use core::time::Duration;
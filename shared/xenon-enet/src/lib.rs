@@ -119,6 +119,9 @@ impl Default for HwDescriptor {
     }
 }
 
+/// The MAC address programmed into the controller on reset.
+pub const MAC_ADDRESS: [u8; 6] = [0x69, 0x42, 0x00, 0x00, 0x00, 0x00];
+
 #[repr(align(16))]
 pub struct EthernetDevice<const N: usize, const M: usize> {
     mmio: core::ptr::NonNull<u8>,
@@ -127,6 +130,10 @@ pub struct EthernetDevice<const N: usize, const M: usize> {
     tx_ring: Ring<TxRing, M>,
 }
 
+// SAFETY: There is only one instance of the device (see [EthernetDevice::new]),
+// so it may be handed to whichever thread owns it.
+unsafe impl<const N: usize, const M: usize> Send for EthernetDevice<N, M> {}
+
 impl<const N: usize, const M: usize> EthernetDevice<N, M> {
     /// Constructs a new [EthernetDevice].
     ///
@@ -178,7 +185,7 @@ impl<const N: usize, const M: usize> EthernetDevice<N, M> {
 
         self.write(Register::MulticastFilterControl, 0x0E38);
 
-        self.write(Register::Address0, MacAddress::from(0x69_42_00_00_00_00));
+        self.write(Register::Address0, MacAddress(MAC_ADDRESS));
         self.write(Register::Address1, MacAddress::from(0x69_42_00_00_00_01));
 
         self.write(Register::TxConfig, 0x0000_1C00);
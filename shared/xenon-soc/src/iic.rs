@@ -91,6 +91,14 @@ impl Iic {
         self.write::<u64>(Register::Eoi, (raw_int as u64) << 2);
     }
 
+    /// Send an inter-processor interrupt to the threads set in the bitmap `targets` (bit N = PIR N).
+    pub fn send_ipi(&self, targets: u8, int: Interrupt) {
+        self.write::<u64>(
+            Register::IpiDispatch,
+            ((targets as u64 & 0x3F) << 16) | ((int as u64) << 2),
+        );
+    }
+
     pub fn set_priority(&self, prio: Interrupt) {
         self.write(Register::CurrentTaskPriority, (prio as u64) << 2);
         self.read::<u64>(Register::CurrentTaskPriority);
@@ -49,6 +49,15 @@ impl UART {
         unsafe { (core::ptr::read_volatile(UART_BASE.offset(4)) >> 24) as u8 }
     }
 
+    /// Read a byte if one is available, without waiting.
+    pub fn try_read_byte(&mut self) -> Option<u8> {
+        if self.data_pending() {
+            Some(unsafe { (core::ptr::read_volatile(UART_BASE.offset(4)) >> 24) as u8 })
+        } else {
+            None
+        }
+    }
+
     pub fn write_byte(&mut self, byte: u8) {
         unsafe {
             // Wait for the SMC to be ready.
@@ -0,0 +1,43 @@
+//! Prints the crash records in a crash log downloaded from stage1.
+//!
+//! Usage: `crashreport <file>`, where the file was saved from `http://<address>/crashlog`.
+
+use std::{env, fs, process};
+
+use crashlog::{Error, Log};
+
+fn run(path: &str) -> Result<(), String> {
+    let data = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    let log = Log::new(data);
+
+    for idx in 0..log.slots() {
+        match log.get(idx) {
+            Ok(_) | Err(Error::Empty) => {}
+            Err(e) => eprintln!("warning: slot {}: {}", idx, e),
+        }
+    }
+
+    if log.is_empty() {
+        println!("No crashes recorded");
+    }
+
+    for entry in log.iter() {
+        println!("==== Crash record {}:", entry.sequence);
+        print!("{}", entry.record);
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 2 {
+        eprintln!("usage: {} <file>", args[0]);
+        process::exit(2);
+    }
+
+    if let Err(e) = run(&args[1]) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,104 @@
+//! Just enough of the legacy Rust symbol demangling scheme to make names readable.
+
+/// Escapes used by the legacy scheme for characters that can't appear in a symbol.
+const ESCAPES: [(&str, &str); 16] = [
+    ("$SP$", "@"),
+    ("$BP$", "*"),
+    ("$RF$", "&"),
+    ("$LT$", "<"),
+    ("$GT$", ">"),
+    ("$LP$", "("),
+    ("$RP$", ")"),
+    ("$C$", ","),
+    ("$u20$", " "),
+    ("$u22$", "\""),
+    ("$u27$", "'"),
+    ("$u2b$", "+"),
+    ("$u3b$", ";"),
+    ("$u5b$", "["),
+    ("$u5d$", "]"),
+    ("$u7e$", "~"),
+];
+
+/// Demangle `name`, without its hash. Names that aren't mangled are returned as-is.
+pub fn demangle(name: &str) -> String {
+    let mut rest = match name.strip_prefix("_ZN") {
+        Some(rest) => rest,
+        None => return name.to_string(),
+    };
+
+    let mut parts = Vec::new();
+    while !rest.starts_with('E') {
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        let len = match rest[..digits].parse::<usize>() {
+            Ok(len) if digits + len <= rest.len() => len,
+            _ => return name.to_string(),
+        };
+
+        parts.push(&rest[digits..digits + len]);
+        rest = &rest[digits + len..];
+    }
+
+    // The last component is a hash of the crate and signature.
+    if let Some(last) = parts.last() {
+        if last.len() == 17
+            && last.starts_with('h')
+            && last[1..].bytes().all(|b| b.is_ascii_hexdigit())
+        {
+            parts.pop();
+        }
+    }
+
+    let parts: Vec<String> = parts.iter().map(|p| unescape(p)).collect();
+    parts.join("::")
+}
+
+fn unescape(part: &str) -> String {
+    // Components can't start with `$`, so escapes at the start are prefixed with `_`.
+    let part = part
+        .strip_prefix('_')
+        .filter(|p| p.starts_with('$'))
+        .unwrap_or(part);
+
+    let mut out = String::new();
+    let mut rest = part;
+
+    while !rest.is_empty() {
+        if let Some(tail) = rest.strip_prefix("..") {
+            out.push_str("::");
+            rest = tail;
+        } else if let Some((esc, s)) = ESCAPES.iter().find(|(esc, _)| rest.starts_with(esc)) {
+            out.push_str(s);
+            rest = &rest[esc.len()..];
+        } else {
+            let c = rest.chars().next().unwrap();
+            out.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::demangle;
+
+    #[test]
+    fn test_demangle() {
+        assert_eq!(
+            demangle("_ZN6stage18terminal4exit17h0123456789abcdefE"),
+            "stage1::terminal::exit"
+        );
+        assert_eq!(
+            demangle("_ZN64_$LT$stage1..terminal..Names$u20$as$u20$shell..expr..Resolve$GT$6symbol17h0123456789abcdefE"),
+            "<stage1::terminal::Names as shell::expr::Resolve>::symbol"
+        );
+        assert_eq!(
+            demangle("_ZN4core3ptr13drop_in_placeE"),
+            "core::ptr::drop_in_place"
+        );
+        assert_eq!(demangle("memcpy"), "memcpy");
+        assert_eq!(demangle("_ZN99bogusE"), "_ZN99bogusE");
+    }
+}
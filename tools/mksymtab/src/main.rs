@@ -0,0 +1,199 @@
+//! Build step that embeds a symbol table into the stage1 ELF.
+//!
+//! The function symbols are read from the ELF's `.symtab`, demangled, and written in the
+//! `symtab` format over the space stage1 reserves in its `.symbols` section. The ELF must be
+//! patched before it is converted into a flat image.
+//!
+//! Usage: `mksymtab <elf>`
+
+mod demangle;
+
+use std::{env, fs, ops::Range, process};
+
+use symtab::Symbol;
+
+/// The section stage1 reserves for the table.
+const SECTION: &str = ".symbols";
+
+const SHT_SYMTAB: u32 = 2;
+const SHF_EXECINSTR: u64 = 4;
+
+const STT_NOTYPE: u8 = 0;
+const STT_FUNC: u8 = 2;
+const STB_LOCAL: u8 = 0;
+
+const SHDR_SIZE: usize = 64;
+const SYM_SIZE: usize = 24;
+
+fn read_u16(data: &[u8], off: usize) -> u16 {
+    u16::from_be_bytes([data[off], data[off + 1]])
+}
+
+fn read_u32(data: &[u8], off: usize) -> u32 {
+    u32::from_be_bytes(data[off..off + 4].try_into().unwrap())
+}
+
+fn read_u64(data: &[u8], off: usize) -> u64 {
+    u64::from_be_bytes(data[off..off + 8].try_into().unwrap())
+}
+
+/// The fields we need from a section header.
+struct Section {
+    name: u32,
+    kind: u32,
+    flags: u64,
+    offset: usize,
+    size: usize,
+    link: u32,
+}
+
+impl Section {
+    fn range(&self) -> Range<usize> {
+        self.offset..self.offset + self.size
+    }
+}
+
+fn sections(data: &[u8]) -> Result<Vec<Section>, String> {
+    let shoff = read_u64(data, 40) as usize;
+    let shnum = read_u16(data, 60) as usize;
+
+    if read_u16(data, 58) as usize != SHDR_SIZE || shoff + shnum * SHDR_SIZE > data.len() {
+        return Err("bad section headers".to_string());
+    }
+
+    let sections: Vec<Section> = (0..shnum)
+        .map(|i| {
+            let sh = &data[shoff + i * SHDR_SIZE..];
+            Section {
+                name: read_u32(sh, 0),
+                kind: read_u32(sh, 4),
+                flags: read_u64(sh, 8),
+                offset: read_u64(sh, 24) as usize,
+                size: read_u64(sh, 32) as usize,
+                link: read_u32(sh, 40),
+            }
+        })
+        .collect();
+
+    if sections.iter().any(|s| s.range().end > data.len()) {
+        return Err("section out of bounds".to_string());
+    }
+
+    Ok(sections)
+}
+
+/// Read a NUL-terminated string at `off` in a string table.
+fn string(strtab: &[u8], off: usize) -> &str {
+    let bytes = strtab.get(off..).unwrap_or(&[]);
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+
+    std::str::from_utf8(&bytes[..len]).unwrap_or("")
+}
+
+/// Collect the function symbols, with global symbols ahead of local ones so they are
+/// preferred when several share an address.
+fn functions(data: &[u8], sections: &[Section]) -> Result<Vec<(String, u64, u64)>, String> {
+    let symtab = sections
+        .iter()
+        .find(|s| s.kind == SHT_SYMTAB)
+        .ok_or("no symbol table (was the ELF stripped?)")?;
+    let strtab = sections
+        .get(symtab.link as usize)
+        .ok_or("bad string table link")?;
+    let strtab = &data[strtab.range()];
+
+    let mut globals = Vec::new();
+    let mut locals = Vec::new();
+
+    for sym in data[symtab.range()].chunks_exact(SYM_SIZE) {
+        let info = sym[4];
+        let shndx = read_u16(sym, 6) as usize;
+        let addr = read_u64(sym, 8);
+        let size = read_u64(sym, 16);
+
+        // Labels from assembly have no type, so also take anything in executable sections.
+        let is_code = matches!(sections.get(shndx), Some(s) if s.flags & SHF_EXECINSTR != 0);
+        let is_function = match info & 0xF {
+            STT_FUNC => true,
+            STT_NOTYPE => is_code,
+            _ => false,
+        };
+
+        let name = string(strtab, read_u32(sym, 0) as usize);
+        if !is_function || shndx == 0 || addr == 0 || name.is_empty() {
+            continue;
+        }
+
+        let entry = (demangle::demangle(name), addr, size);
+        if info >> 4 == STB_LOCAL {
+            locals.push(entry);
+        } else {
+            globals.push(entry);
+        }
+    }
+
+    globals.extend(locals);
+    Ok(globals)
+}
+
+fn run(path: &str) -> Result<(), String> {
+    let mut data = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    elf::ElfFile::parse(&data).map_err(|e| e.to_string())?;
+
+    let sections = sections(&data)?;
+    let functions = functions(&data, &sections)?;
+    let symbols: Vec<Symbol> = functions
+        .iter()
+        .map(|(name, addr, size)| Symbol {
+            name,
+            addr: *addr,
+            size: *size,
+        })
+        .collect();
+
+    let table = symtab::build(&symbols).map_err(|e| e.to_string())?;
+
+    let shstrtab = sections
+        .get(read_u16(&data, 62) as usize)
+        .ok_or("bad section name table")?;
+    let target = sections
+        .iter()
+        .find(|s| string(&data[shstrtab.range()], s.name as usize) == SECTION)
+        .ok_or(format!("no {} section", SECTION))?;
+
+    if table.len() > target.size {
+        return Err(format!(
+            "table is {} bytes, but {} only has room for {}",
+            table.len(),
+            SECTION,
+            target.size
+        ));
+    }
+
+    let range = target.offset..target.offset + table.len();
+    data[range].copy_from_slice(&table);
+    fs::write(path, &data).map_err(|e| format!("{}: {}", path, e))?;
+
+    println!(
+        "{}: {} symbols, {} of {} bytes",
+        path,
+        symtab::Table::parse(&table).map_or(0, |t| t.len()),
+        table.len(),
+        target.size
+    );
+
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 2 {
+        eprintln!("usage: {} <elf>", args[0]);
+        process::exit(2);
+    }
+
+    if let Err(e) = run(&args[1]) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}